@@ -0,0 +1,339 @@
+// Keeper reference implementation
+// In Solana Playground, web3, anchor, pg, BN are globally available.
+//
+// Watches markets, clears each batch once it is due, then cranks what a keeper can:
+// settles the keeper wallet's own orders once settlement opens (settle_order must be
+// signed by the order owner) and releases its clear bonds after the dispute window.
+// Every transaction is simulated first and retried with a rising priority fee.
+//
+// clear_batch takes the whole batch in one transaction, so without an address lookup
+// table only a handful of orders fit; production keepers should extend a lookup table
+// with the batch's orders before submitting.
+
+import * as splToken from "@solana/spl-token";
+
+// Markets to crank; empty = every market this wallet may clear.
+const MARKETS: web3.PublicKey[] = [];
+const POLL_MS = 2_000;
+const MAX_ATTEMPTS = 4;
+const BASE_PRIORITY_MICRO_LAMPORTS = 1_000;
+const COMPUTE_UNIT_LIMIT = 1_400_000;
+const ORDER_INDEX_PAGE_SIZE = 64;
+
+const program = pg.program;
+const programId = program.programId;
+const connection = pg.connection;
+const keeper = pg.wallet.publicKey;
+
+const pda = (...seeds: Buffer[]) =>
+  web3.PublicKey.findProgramAddressSync(seeds, programId)[0];
+const u64 = (n: anchor.BN) => n.toArrayLike(Buffer, "le", 8);
+const u32 = (n: number) => new BN(n).toArrayLike(Buffer, "le", 4);
+
+// Bond-carrying batches this keeper cleared: [market, batchState].
+const pendingBonds: [web3.PublicKey, web3.PublicKey][] = [];
+// Batches this keeper cleared with order tips to claim: [market, batchState, quoteMint].
+const pendingTips: [web3.PublicKey, web3.PublicKey, web3.PublicKey][] = [];
+// The keeper wallet's own orders in cleared batches, awaiting settlement.
+const pendingSettles: {
+  marketPk: web3.PublicKey;
+  batchStatePda: web3.PublicKey;
+  orderPk: web3.PublicKey;
+  order: any;
+}[] = [];
+
+const sleep = (ms: number) => new Promise((r) => setTimeout(r, ms));
+
+// Simulate, then send; on failure retry with double the priority fee.
+async function submit(label: string, build: () => any): Promise<string | null> {
+  let priority = BASE_PRIORITY_MICRO_LAMPORTS;
+  for (let attempt = 1; attempt <= MAX_ATTEMPTS; attempt++) {
+    const builder = build().preInstructions([
+      web3.ComputeBudgetProgram.setComputeUnitLimit({ units: COMPUTE_UNIT_LIMIT }),
+      web3.ComputeBudgetProgram.setComputeUnitPrice({ microLamports: priority }),
+    ]);
+    try {
+      await builder.simulate();
+    } catch (err) {
+      // A failing simulation won't land either; don't pay for it.
+      console.log(`${label}: simulation failed`, err?.message ?? err);
+      return null;
+    }
+    try {
+      const sig = await builder.rpc();
+      console.log(`${label}: ${sig}`);
+      return sig;
+    } catch (err) {
+      console.log(`${label}: attempt ${attempt} failed`, err?.message ?? err);
+      priority *= 2;
+      await sleep(500 * attempt);
+    }
+  }
+  return null;
+}
+
+async function batchOrders(marketPk: web3.PublicKey, market: any) {
+  const pages = Math.ceil(market.globalOrdersInBatch / ORDER_INDEX_PAGE_SIZE);
+  const pagePdas = [...Array(pages).keys()].map((page) =>
+    pda(
+      Buffer.from("order_index"),
+      marketPk.toBuffer(),
+      u64(market.currentBatchId),
+      u32(page)
+    )
+  );
+  const indexPages = await program.account.orderIndex.fetchMultiple(pagePdas);
+  const orderPks: web3.PublicKey[] = indexPages.flatMap((p: any) => (p ? p.orders : []));
+  const orders = await program.account.order.fetchMultiple(orderPks);
+  return orderPks
+    .map((pubkey, i) => ({ pubkey, account: orders[i] as any }))
+    .filter((o) => o.account !== null);
+}
+
+async function clearMarket(marketPk: web3.PublicKey, market: any) {
+  const batchId: anchor.BN = market.currentBatchId;
+  const orders = await batchOrders(marketPk, market);
+
+  // One [Order, owner base ATA, owner quote ATA] triplet per order.
+  const remaining = orders.flatMap((o) => {
+    const owner: web3.PublicKey = o.account.user;
+    return [
+      { pubkey: o.pubkey, isSigner: false, isWritable: true },
+      {
+        pubkey: splToken.getAssociatedTokenAddressSync(market.baseMint, owner, true),
+        isSigner: false,
+        isWritable: false,
+      },
+      {
+        pubkey: splToken.getAssociatedTokenAddressSync(market.quoteMint, owner, true),
+        isSigner: false,
+        isWritable: false,
+      },
+    ];
+  });
+
+  const batchStatePda = pda(Buffer.from("batch_state"), marketPk.toBuffer(), u64(batchId));
+  const sig = await submit(`clear ${marketPk.toBase58()} #${batchId}`, () =>
+    program.methods
+      .clearBatch()
+      .accounts({
+        authority: keeper,
+        market: marketPk,
+        baseMint: market.baseMint,
+        quoteMint: market.quoteMint,
+        vaultBase: market.vaultBase,
+        vaultQuote: market.vaultQuote,
+        batchState: batchStatePda,
+        tokenProgram: splToken.TOKEN_PROGRAM_ID,
+        systemProgram: web3.SystemProgram.programId,
+        triggerMarket: null,
+        clearIntent: null,
+        instructionsSysvar: null,
+        liquidityAdapter: null,
+        adapterProgram: null,
+        keeperRecord: null,
+        reimbursementVault: null,
+        slotHashes: web3.SYSVAR_SLOT_HASHES_PUBKEY,
+        eventLog: pda(Buffer.from("event_log"), marketPk.toBuffer()),
+        priceHistory: pda(Buffer.from("price_history"), marketPk.toBuffer()),
+        cluster: null,
+        clusterKeeperShare: null,
+        workQueue: null,
+        clearingState: null,
+      })
+      .remainingAccounts(remaining)
+  );
+  if (sig === null) return;
+
+  if (market.clearBondLamports.gtn(0)) {
+    pendingBonds.push([marketPk, batchStatePda]);
+  }
+  if (market.batchKeeperTipsFp.gtn(0)) {
+    pendingTips.push([marketPk, batchStatePda, market.quoteMint]);
+  }
+  for (const o of orders.filter((o) => o.account.user.equals(keeper))) {
+    pendingSettles.push({ marketPk, batchStatePda, orderPk: o.pubkey, order: o.account });
+  }
+}
+
+// Returns true once the order no longer needs this keeper: settled, or in a batch that
+// will not settle (uncrossed, disputed, or already closed).
+async function settleOwnOrder(
+  slot: number,
+  marketPk: web3.PublicKey,
+  batchStatePda: web3.PublicKey,
+  orderPk: web3.PublicKey,
+  order: any
+): Promise<boolean> {
+  const batchState: any = await program.account.batchState.fetchNullable(batchStatePda);
+  if (batchState === null || batchState.clearingPriceFp.isZero() || batchState.disputed) {
+    return true;
+  }
+  // Held by the volume breaker or the settlement delay; retried on a later poll.
+  if (batchState.awaitingConfirmation || batchState.settlementOpenSlot.gtn(slot)) {
+    return false;
+  }
+  const market: any = await program.account.market.fetch(marketPk);
+
+  const vesting =
+    batchState.vestingTranches > 0 && order.side.bid !== undefined
+      ? pda(Buffer.from("vesting"), orderPk.toBuffer())
+      : null;
+  // Large fills need the travel-rule record, if the keeper attached one.
+  const travelRulePda = pda(Buffer.from("travel_rule"), orderPk.toBuffer());
+  const travelRule =
+    (await program.account.travelRuleRecord.fetchNullable(travelRulePda)) === null
+      ? null
+      : travelRulePda;
+  // Keep the keeper's claimables index current, if it opened one.
+  const claimablesPda = pda(Buffer.from("claimables"), marketPk.toBuffer(), keeper.toBuffer());
+  const claimables =
+    (await program.account.claimables.fetchNullable(claimablesPda)) === null
+      ? null
+      : claimablesPda;
+  // Chunked clears leave fills on the clearing state until settlement copies them.
+  const clearingState = batchState.clearingState.equals(web3.PublicKey.default)
+    ? null
+    : batchState.clearingState;
+  const sig = await submit(`settle ${orderPk.toBase58()}`, () =>
+    program.methods.settleOrder(order.settleNonce).accounts({
+      user: keeper,
+      market: marketPk,
+      batchState: batchStatePda,
+      subAccount: null,
+      order: orderPk,
+      orderFill: pda(Buffer.from("order_fill"), orderPk.toBuffer()),
+      userBatchStats: pda(
+        Buffer.from("user_batch"),
+        marketPk.toBuffer(),
+        keeper.toBuffer(),
+        u64(order.batchId)
+      ),
+      userStats: pda(Buffer.from("user_stats"), marketPk.toBuffer(), keeper.toBuffer()),
+      vaultBase: market.vaultBase,
+      vaultQuote: market.vaultQuote,
+      orderOwner: keeper,
+      baseMint: market.baseMint,
+      quoteMint: market.quoteMint,
+      userBaseAta: splToken.getAssociatedTokenAddressSync(market.baseMint, keeper),
+      userQuoteAta: splToken.getAssociatedTokenAddressSync(market.quoteMint, keeper),
+      vesting,
+      travelRule,
+      claimables,
+      clearingState,
+      tokenProgram: splToken.TOKEN_PROGRAM_ID,
+      associatedTokenProgram: splToken.ASSOCIATED_TOKEN_PROGRAM_ID,
+      systemProgram: web3.SystemProgram.programId,
+      eventLog: pda(Buffer.from("event_log"), marketPk.toBuffer()),
+    })
+  );
+  return sig !== null;
+}
+
+async function settleOwnOrders(slot: number) {
+  for (let i = pendingSettles.length - 1; i >= 0; i--) {
+    const { marketPk, batchStatePda, orderPk, order } = pendingSettles[i];
+    if (await settleOwnOrder(slot, marketPk, batchStatePda, orderPk, order)) {
+      pendingSettles.splice(i, 1);
+    }
+  }
+}
+
+async function releaseBonds(slot: number) {
+  for (let i = pendingBonds.length - 1; i >= 0; i--) {
+    const [marketPk, batchStatePda] = pendingBonds[i];
+    const batchState: any = await program.account.batchState.fetchNullable(batchStatePda);
+    if (batchState === null || batchState.keeperBondLamports.isZero()) {
+      pendingBonds.splice(i, 1);
+      continue;
+    }
+    if (batchState.disputeDeadlineSlot.gten(slot)) continue;
+    const sig = await submit(`release bond ${batchStatePda.toBase58()}`, () =>
+      program.methods
+        .releaseClearBond()
+        .accounts({ market: marketPk, batchState: batchStatePda, keeper })
+    );
+    if (sig !== null) pendingBonds.splice(i, 1);
+  }
+}
+
+async function claimTips(slot: number) {
+  for (let i = pendingTips.length - 1; i >= 0; i--) {
+    const [marketPk, batchStatePda, quoteMint] = pendingTips[i];
+    const batchState: any = await program.account.batchState.fetchNullable(batchStatePda);
+    if (batchState === null || batchState.keeperTipsQuoteFp.isZero()) {
+      pendingTips.splice(i, 1);
+      continue;
+    }
+    if (batchState.disputeDeadlineSlot.gten(slot)) continue;
+    const market: any = await program.account.market.fetch(marketPk);
+    const sig = await submit(`claim tips ${batchStatePda.toBase58()}`, () =>
+      program.methods.claimKeeperTips().accounts({
+        market: marketPk,
+        batchState: batchStatePda,
+        quoteMint,
+        vaultQuote: market.vaultQuote,
+        recipientQuoteAta: splToken.getAssociatedTokenAddressSync(quoteMint, keeper),
+        tokenProgram: splToken.TOKEN_PROGRAM_ID,
+      })
+    );
+    if (sig !== null) pendingTips.splice(i, 1);
+  }
+}
+
+// Mirrors Market::may_clear: thin batches are extended, not cleared, until the
+// extension cap is used up.
+function mayClear(market: any): boolean {
+  const minOrders: number = market.minBatchOrders;
+  const minNotional: anchor.BN = market.minBatchNotionalQuoteFp;
+  return (
+    (minOrders === 0 && minNotional.isZero()) ||
+    (minOrders > 0 && market.globalOrdersInBatch >= minOrders) ||
+    (!minNotional.isZero() && market.batchNotionalQuoteFp.gte(minNotional)) ||
+    market.batchExtensions >= market.maxBatchExtensions
+  );
+}
+
+async function extendBatch(marketPk: web3.PublicKey, market: any) {
+  const workQueue = market.workQueue.equals(web3.PublicKey.default) ? null : market.workQueue;
+  await submit(`extend ${marketPk.toBase58()}`, () =>
+    program.methods.extendBatch().accounts({ market: marketPk, workQueue })
+  );
+}
+
+async function watchedMarkets(): Promise<{ publicKey: web3.PublicKey; account: any }[]> {
+  if (MARKETS.length > 0) {
+    const accounts = await program.account.market.fetchMultiple(MARKETS);
+    return MARKETS.map((publicKey, i) => ({ publicKey, account: accounts[i] })).filter(
+      (m) => m.account !== null
+    );
+  }
+  // Restricted markets (only_keeper set) are skipped unless this wallet is the keeper.
+  const all = await program.account.market.all();
+  return all.filter(
+    (m: any) =>
+      m.account.onlyKeeper.equals(web3.PublicKey.default) ||
+      m.account.onlyKeeper.equals(keeper)
+  );
+}
+
+console.log("Keeper:", keeper.toBase58());
+while (true) {
+  const slot = await connection.getSlot();
+  for (const { publicKey, account } of await watchedMarkets()) {
+    const nextClearSlot = account.lastBatchSlot.add(
+      BN.max(account.batchDurationSlots, account.minSlotsBetweenClears)
+    );
+    if (account.globalOrdersInBatch > 0 && nextClearSlot.lten(slot)) {
+      if (mayClear(account)) {
+        await clearMarket(publicKey, account);
+      } else {
+        await extendBatch(publicKey, account);
+      }
+    }
+  }
+  await settleOwnOrders(slot);
+  await releaseBonds(slot);
+  await claimTips(slot);
+  await sleep(POLL_MS);
+}
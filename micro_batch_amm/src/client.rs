@@ -0,0 +1,318 @@
+//! Off-chain helpers for Rust keepers and integrators (`client` feature).
+//!
+//! PDA derivation and instruction builders that share the program's own seed constants
+//! and generated account structs, so bots don't re-implement account ordering by hand.
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+use anchor_lang::solana_program::sysvar::{self, slot_hashes};
+use anchor_lang::{InstructionData, ToAccountMetas};
+use anchor_spl::associated_token::get_associated_token_address;
+
+use crate::{
+    accounts, instruction, Market, Order, OrderSide, OrderTrigger, ACTIVITY_SNAPSHOT_SEED,
+    BATCH_STATE_SEED, CLAIMABLES_SEED, CLEARING_STATE_SEED, EVENT_LOG_SEED, MARKET_SEED,
+    ORDER_FILL_SEED, ORDER_INDEX_PAGE_SIZE, ORDER_INDEX_SEED, ORDER_SEED, PRICE_HISTORY_SEED,
+    PROGRAM_CONFIG_SEED, TRAVEL_RULE_SEED, USER_BATCH_SEED, USER_STATS_SEED, VAULT_BASE_SEED,
+    VAULT_QUOTE_SEED, VESTING_SEED,
+};
+
+fn pda(seeds: &[&[u8]]) -> Pubkey {
+    Pubkey::find_program_address(seeds, &crate::ID).0
+}
+
+pub fn market_address(authority: &Pubkey, base_mint: &Pubkey, quote_mint: &Pubkey) -> Pubkey {
+    pda(&[
+        MARKET_SEED,
+        authority.as_ref(),
+        base_mint.as_ref(),
+        quote_mint.as_ref(),
+    ])
+}
+
+pub fn program_config_address() -> Pubkey {
+    pda(&[PROGRAM_CONFIG_SEED])
+}
+
+pub fn vault_base_address(market: &Pubkey) -> Pubkey {
+    pda(&[VAULT_BASE_SEED, market.as_ref()])
+}
+
+pub fn vault_quote_address(market: &Pubkey) -> Pubkey {
+    pda(&[VAULT_QUOTE_SEED, market.as_ref()])
+}
+
+pub fn batch_state_address(market: &Pubkey, batch_id: u64) -> Pubkey {
+    pda(&[BATCH_STATE_SEED, market.as_ref(), &batch_id.to_le_bytes()])
+}
+
+pub fn event_log_address(market: &Pubkey) -> Pubkey {
+    pda(&[EVENT_LOG_SEED, market.as_ref()])
+}
+
+pub fn price_history_address(market: &Pubkey) -> Pubkey {
+    pda(&[PRICE_HISTORY_SEED, market.as_ref()])
+}
+
+pub fn user_stats_address(market: &Pubkey, trader: &Pubkey) -> Pubkey {
+    pda(&[USER_STATS_SEED, market.as_ref(), trader.as_ref()])
+}
+
+pub fn user_batch_stats_address(market: &Pubkey, trader: &Pubkey, batch_id: u64) -> Pubkey {
+    pda(&[
+        USER_BATCH_SEED,
+        market.as_ref(),
+        trader.as_ref(),
+        &batch_id.to_le_bytes(),
+    ])
+}
+
+/// `batch_salt` is the market's salt while the order's batch is open.
+pub fn order_address(
+    market: &Pubkey,
+    trader: &Pubkey,
+    order_nonce: u64,
+    batch_salt: &[u8; 32],
+) -> Pubkey {
+    pda(&[
+        ORDER_SEED,
+        market.as_ref(),
+        trader.as_ref(),
+        &order_nonce.to_le_bytes(),
+        batch_salt,
+    ])
+}
+
+pub fn order_fill_address(order: &Pubkey) -> Pubkey {
+    pda(&[ORDER_FILL_SEED, order.as_ref()])
+}
+
+pub fn vesting_address(order: &Pubkey) -> Pubkey {
+    pda(&[VESTING_SEED, order.as_ref()])
+}
+
+pub fn travel_rule_address(order: &Pubkey) -> Pubkey {
+    pda(&[TRAVEL_RULE_SEED, order.as_ref()])
+}
+
+pub fn claimables_address(market: &Pubkey, user: &Pubkey) -> Pubkey {
+    pda(&[CLAIMABLES_SEED, market.as_ref(), user.as_ref()])
+}
+
+pub fn order_index_address(market: &Pubkey, batch_id: u64, page: u32) -> Pubkey {
+    pda(&[
+        ORDER_INDEX_SEED,
+        market.as_ref(),
+        &batch_id.to_le_bytes(),
+        &page.to_le_bytes(),
+    ])
+}
+
+pub fn clearing_state_address(market: &Pubkey, batch_id: u64) -> Pubkey {
+    pda(&[
+        CLEARING_STATE_SEED,
+        market.as_ref(),
+        &batch_id.to_le_bytes(),
+    ])
+}
+
+pub fn activity_snapshot_address(user_stats: &Pubkey, epoch: u64) -> Pubkey {
+    pda(&[
+        ACTIVITY_SNAPSHOT_SEED,
+        user_stats.as_ref(),
+        &epoch.to_le_bytes(),
+    ])
+}
+
+/// Order parameters for `build_place_order`.
+#[derive(Clone, Copy)]
+pub struct PlaceOrderParams {
+    pub side: OrderSide,
+    pub limit_price_fp: u64,
+    pub amount_base_fp: u64,
+    pub trigger: Option<OrderTrigger>,
+    pub allow_external_fill: bool,
+    /// Tag echoed in `OrderPlaced` / `OrderSettled`, e.g. an internal order id.
+    pub memo: Option<[u8; 32]>,
+}
+
+/// `place_order` for `user`'s own wallet in the market's open batch. `order_nonce` is the
+/// user's `UserStats::order_nonce` (0 before their first order); `market` must be fresh,
+/// since the order PDA and index page depend on its current batch.
+pub fn build_place_order(
+    market_pk: &Pubkey,
+    market: &Market,
+    user: &Pubkey,
+    order_nonce: u64,
+    params: PlaceOrderParams,
+) -> Instruction {
+    let batch_id = market.current_batch_id;
+    let index_page = market.global_orders_in_batch / ORDER_INDEX_PAGE_SIZE as u32;
+    let accounts = accounts::PlaceOrder {
+        user: *user,
+        market: *market_pk,
+        program_config: program_config_address(),
+        base_mint: market.base_mint,
+        quote_mint: market.quote_mint,
+        sub_account: None,
+        vault_base: market.vault_base,
+        vault_quote: market.vault_quote,
+        user_base_ata: get_associated_token_address(user, &market.base_mint),
+        user_quote_ata: get_associated_token_address(user, &market.quote_mint),
+        user_stats: user_stats_address(market_pk, user),
+        order: order_address(market_pk, user, order_nonce, &market.batch_salt),
+        user_batch_stats: user_batch_stats_address(market_pk, user, batch_id),
+        order_index: order_index_address(market_pk, batch_id, index_page),
+        system_program: anchor_lang::system_program::ID,
+        token_program: anchor_spl::token::ID,
+        rent: sysvar::rent::ID,
+        rfq_whitelist: None,
+        event_log: event_log_address(market_pk),
+    };
+    let data = instruction::PlaceOrder {
+        side: params.side,
+        limit_price_fp: params.limit_price_fp,
+        amount_base_fp: params.amount_base_fp,
+        trigger: params.trigger,
+        allow_external_fill: params.allow_external_fill,
+        memo: params.memo,
+    };
+    Instruction {
+        program_id: crate::ID,
+        accounts: accounts.to_account_metas(None),
+        data: data.data(),
+    }
+}
+
+/// An order to pass to `build_clear_batch`, with the wallet (or sub-account) owning it.
+#[derive(Clone, Copy)]
+pub struct ClearOrder {
+    pub order: Pubkey,
+    pub owner: Pubkey,
+}
+
+/// `clear_batch` for the market's open batch, signed by `keeper`. Orders are packed into
+/// the `[Order, owner base ATA, owner quote ATA]` remaining-account triplets; pass every
+/// order in the batch (e.g. from its `OrderIndex` pages). `trigger_market` is needed
+/// only to activate conditional orders.
+pub fn build_clear_batch(
+    market_pk: &Pubkey,
+    market: &Market,
+    keeper: &Pubkey,
+    orders: &[ClearOrder],
+    trigger_market: Option<Pubkey>,
+) -> Instruction {
+    let accounts = accounts::ClearBatch {
+        authority: *keeper,
+        market: *market_pk,
+        program_config: program_config_address(),
+        base_mint: market.base_mint,
+        quote_mint: market.quote_mint,
+        vault_base: market.vault_base,
+        vault_quote: market.vault_quote,
+        batch_state: batch_state_address(market_pk, market.current_batch_id),
+        token_program: anchor_spl::token::ID,
+        system_program: anchor_lang::system_program::ID,
+        trigger_market,
+        clear_intent: None,
+        instructions_sysvar: None,
+        liquidity_adapter: None,
+        slot_hashes: slot_hashes::ID,
+        keeper_record: None,
+        reimbursement_vault: None,
+        adapter_program: None,
+        event_log: event_log_address(market_pk),
+        price_history: price_history_address(market_pk),
+        cluster: None,
+        cluster_keeper_share: None,
+        work_queue: None,
+        clearing_state: None,
+    };
+    let mut metas = accounts.to_account_metas(None);
+    for o in orders {
+        metas.push(AccountMeta::new(o.order, false));
+        metas.push(AccountMeta::new_readonly(
+            get_associated_token_address(&o.owner, &market.base_mint),
+            false,
+        ));
+        metas.push(AccountMeta::new_readonly(
+            get_associated_token_address(&o.owner, &market.quote_mint),
+            false,
+        ));
+    }
+    Instruction {
+        program_id: crate::ID,
+        accounts: metas,
+        data: instruction::ClearBatch {}.data(),
+    }
+}
+
+/// One order to settle, with the optional accounts its settlement needs.
+#[derive(Clone, Copy)]
+pub struct SettleLeg<'a> {
+    pub market_pk: Pubkey,
+    pub market: &'a Market,
+    pub order_pk: Pubkey,
+    pub order: &'a Order,
+    /// The sub-account that owns the order, if `user` trades through one.
+    pub sub_account: Option<Pubkey>,
+    /// The fill vests (a bid in a vesting launch batch).
+    pub vesting: bool,
+    /// Pass the order's `TravelRuleRecord` (fills above the travel-rule threshold).
+    pub travel_rule: bool,
+    /// Drop the order from its owner's `Claimables` index (it must be open).
+    pub claimables: bool,
+    /// The order's batch was cleared in chunks; pass its `ClearingState`.
+    pub staged: bool,
+}
+
+/// `settle_order` for `leg`, signed by `user` (the order's owner, or the master of its
+/// sub-account).
+pub fn build_settle_order(user: &Pubkey, leg: &SettleLeg) -> Instruction {
+    let owner = leg.order.user;
+    let accounts = accounts::SettleOrder {
+        user: *user,
+        market: leg.market_pk,
+        program_config: program_config_address(),
+        batch_state: batch_state_address(&leg.market_pk, leg.order.batch_id),
+        sub_account: leg.sub_account,
+        order: leg.order_pk,
+        order_fill: order_fill_address(&leg.order_pk),
+        user_batch_stats: user_batch_stats_address(&leg.market_pk, &owner, leg.order.batch_id),
+        user_stats: user_stats_address(&leg.market_pk, &owner),
+        vault_base: leg.market.vault_base,
+        vault_quote: leg.market.vault_quote,
+        order_owner: owner,
+        base_mint: leg.market.base_mint,
+        quote_mint: leg.market.quote_mint,
+        user_base_ata: get_associated_token_address(&owner, &leg.market.base_mint),
+        user_quote_ata: get_associated_token_address(&owner, &leg.market.quote_mint),
+        vesting: leg.vesting.then(|| vesting_address(&leg.order_pk)),
+        travel_rule: leg.travel_rule.then(|| travel_rule_address(&leg.order_pk)),
+        claimables: leg
+            .claimables
+            .then(|| claimables_address(&leg.market_pk, &owner)),
+        clearing_state: leg
+            .staged
+            .then(|| clearing_state_address(&leg.market_pk, leg.order.batch_id)),
+        token_program: anchor_spl::token::ID,
+        associated_token_program: anchor_spl::associated_token::ID,
+        system_program: anchor_lang::system_program::ID,
+        event_log: event_log_address(&leg.market_pk),
+    };
+    Instruction {
+        program_id: crate::ID,
+        accounts: accounts.to_account_metas(None),
+        data: instruction::SettleOrder {
+            settle_nonce: leg.order.settle_nonce,
+        }
+        .data(),
+    }
+}
+
+/// Both legs of a spread (e.g. one order on each of two correlated markets), to send in
+/// one transaction so they settle atomically. Each leg pays out of its own market's
+/// vaults, so there is nothing to net on-chain; the pair saves the second transaction.
+pub fn build_settle_pair(user: &Pubkey, a: &SettleLeg, b: &SettleLeg) -> [Instruction; 2] {
+    [build_settle_order(user, a), build_settle_order(user, b)]
+}
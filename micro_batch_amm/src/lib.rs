@@ -1,11 +1,174 @@
 use anchor_lang::prelude::*;
-use anchor_lang::AccountDeserialize;
-use anchor_spl::token::{self, Mint, Token, TokenAccount, Transfer};
+use anchor_lang::solana_program::entrypoint::MAX_PERMITTED_DATA_INCREASE;
+use anchor_lang::solana_program::hash::hashv;
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+use anchor_lang::solana_program::program::invoke_signed;
+use anchor_lang::solana_program::sysvar::instructions as sysvar_instructions;
+use anchor_lang::solana_program::sysvar::slot_hashes;
+use anchor_lang::system_program;
+use anchor_lang::{AccountDeserialize, Discriminator};
+use anchor_spl::associated_token::{self, AssociatedToken};
+use anchor_spl::token_2022::spl_token_2022::extension::ExtensionType;
+use anchor_spl::token_2022::{self, spl_token_2022, Token2022};
+use anchor_spl::token_2022_extensions::spl_pod::optional_keys::OptionalNonZeroPubkey;
+use anchor_spl::token_2022_extensions::spl_token_metadata_interface::state::{
+    Field, TokenMetadata,
+};
+use anchor_spl::token_2022_extensions::{
+    metadata_pointer_initialize, non_transferable_mint_initialize, token_metadata_initialize,
+    token_metadata_update_field, MetadataPointerInitialize, NonTransferableMintInitialize,
+    TokenMetadataInitialize, TokenMetadataUpdateField,
+};
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
 
 declare_id!("8puhCTsdk8w61XfXTFVjr623BQWkq5NiBx4nyZ8FNffw");
 
-const PRICE_SCALE: u64 = 1_000_000; // fixed-point scale for prices (1e6)
-const BPS_DENOM: u64 = 10_000;      // basis points denominator
+#[cfg(feature = "client")]
+pub mod client;
+
+#[constant]
+pub const PRICE_SCALE: u64 = 1_000_000; // fixed-point scale for prices (1e6)
+#[constant]
+pub const BPS_DENOM: u64 = 10_000;      // basis points denominator
+#[constant]
+pub const PROGRAM_VERSION: u16 = 1; // program state version this build expects
+const MAX_RFQ_RESPONDERS: usize = 8; // whitelist size for RFQ markets
+const MAX_ADAPTER_ACCOUNTS: usize = 8; // accounts registered for a liquidity adapter
+const MAX_CLUSTER_MARKETS: usize = 8; // member markets per MarketCluster
+const MAX_WORK_ITEMS: usize = 32; // scheduled items per WorkQueue
+const MAX_WORK_QUEUE_MARKETS: usize = 16; // markets per WorkQueue (one clear item each)
+const MAX_TRANSFER_HOOK_PROGRAMS: usize = 4; // allowed transfer-hook programs per market
+const MAX_CLAIMABLES: usize = 32; // pending orders listed per Claimables account
+const MAX_STAGED_ORDERS: usize = 80; // orders a ClearingState can stage for one batch
+const ORDER_INDEX_PAGE_SIZE: usize = 64; // order pubkeys per OrderIndex page
+const EVENT_LOG_CAPACITY: usize = 32; // entries in the per-market EventLog ring
+const PRICE_HISTORY_CAPACITY: usize = 64; // cleared batches kept in the PriceHistory ring
+const MAX_MARKET_NAME_LEN: usize = 32; // MarketMetadata.name bytes
+const MAX_MARKET_TAGS: usize = 4; // MarketMetadata.tags entries
+const MAX_MARKET_TAG_LEN: usize = 16; // bytes per tag
+const MAX_MARKET_URI_LEN: usize = 200; // MarketMetadata.uri bytes
+const MAX_PORTFOLIO_MARKETS: usize = 10; // keeps UserPortfolio within return-data limits
+const PAUSE_REASON_CONSERVATION: u8 = u8::MAX; // auto-pause after a conservation violation
+const RECOVERY_TIMELOCK_SLOTS: u64 = 1_512_000; // ~7 days of 400ms slots
+const VOLUME_AVERAGE_WINDOW: u64 = 16; // batches in the trailing volume average
+
+// PDA seed prefixes, exported in the IDL so clients need not hardcode them.
+#[constant]
+pub const MARKET_SEED: &[u8] = b"market";
+#[constant]
+pub const VAULT_BASE_SEED: &[u8] = b"vault_base";
+#[constant]
+pub const VAULT_QUOTE_SEED: &[u8] = b"vault_quote";
+#[constant]
+pub const BATCH_STATE_SEED: &[u8] = b"batch_state";
+#[constant]
+pub const ORDER_SEED: &[u8] = b"order";
+#[constant]
+pub const ORDER_FILL_SEED: &[u8] = b"order_fill";
+#[constant]
+pub const ORDER_INDEX_SEED: &[u8] = b"order_index";
+#[constant]
+pub const USER_STATS_SEED: &[u8] = b"user_stats";
+#[constant]
+pub const USER_BATCH_SEED: &[u8] = b"user_batch";
+#[constant]
+pub const SUB_ACCOUNT_SEED: &[u8] = b"sub_account";
+#[constant]
+pub const EVENT_LOG_SEED: &[u8] = b"event_log";
+#[constant]
+pub const PRICE_HISTORY_SEED: &[u8] = b"price_history";
+#[constant]
+pub const PROGRAM_CONFIG_SEED: &[u8] = b"program_config";
+#[constant]
+pub const KEEPER_SEED: &[u8] = b"keeper";
+#[constant]
+pub const CLEAR_INTENT_SEED: &[u8] = b"clear_intent";
+#[constant]
+pub const RFQ_SEED: &[u8] = b"rfq";
+#[constant]
+pub const LIQUIDITY_ADAPTER_SEED: &[u8] = b"liquidity_adapter";
+#[constant]
+pub const MARKET_METADATA_SEED: &[u8] = b"market_metadata";
+#[constant]
+pub const RECEIPT_SEED: &[u8] = b"receipt";
+#[constant]
+pub const RECOVERY_SEED: &[u8] = b"recovery";
+#[constant]
+pub const REIMBURSEMENT_VAULT_SEED: &[u8] = b"reimbursement_vault";
+#[constant]
+pub const VESTING_SEED: &[u8] = b"vesting";
+#[constant]
+pub const TRAVEL_RULE_SEED: &[u8] = b"travel_rule";
+#[constant]
+pub const CLUSTER_SEED: &[u8] = b"cluster";
+#[constant]
+pub const CLUSTER_KEEPER_SEED: &[u8] = b"cluster_keeper";
+#[constant]
+pub const WORK_QUEUE_SEED: &[u8] = b"work_queue";
+#[constant]
+pub const CLAIMABLES_SEED: &[u8] = b"claimables";
+#[constant]
+pub const ACTIVITY_SNAPSHOT_SEED: &[u8] = b"activity_snapshot";
+#[constant]
+pub const CLEARING_STATE_SEED: &[u8] = b"clearing_state";
+
+/// Vetted parameter preset for `create_market_from_template`.
+pub struct MarketTemplate {
+    pub name: &'static str,
+    pub batch_duration_slots: u64,
+    pub fee_bps: u16,
+    pub keeper_fee_bps: u16,
+    pub max_price_move_bps: u16,
+    pub max_orders_per_user_per_batch: u32,
+    pub min_base_order_fp: u64,
+    pub min_quote_order_fp: u64,
+}
+
+/// On-chain preset table, indexed by `template_id`.
+pub const MARKET_TEMPLATES: [MarketTemplate; 3] = [
+    // Deep, liquid pairs: fast batches, tight fees, 5% price band.
+    MarketTemplate {
+        name: "major pair",
+        batch_duration_slots: 10,
+        fee_bps: 10,
+        keeper_fee_bps: 2,
+        max_price_move_bps: 500,
+        max_orders_per_user_per_batch: 32,
+        min_base_order_fp: 1_000,
+        min_quote_order_fp: 1_000_000,
+    },
+    // Thin, volatile tokens: longer batches to aggregate flow, wide 20% band.
+    MarketTemplate {
+        name: "long-tail",
+        batch_duration_slots: 25,
+        fee_bps: 50,
+        keeper_fee_bps: 5,
+        max_price_move_bps: 2_000,
+        max_orders_per_user_per_batch: 16,
+        min_base_order_fp: 1_000_000,
+        min_quote_order_fp: 1_000_000,
+    },
+    // Pegged assets: minimal fees, 0.5% band catches depegs.
+    MarketTemplate {
+        name: "stable-stable",
+        batch_duration_slots: 5,
+        fee_bps: 2,
+        keeper_fee_bps: 1,
+        max_price_move_bps: 50,
+        max_orders_per_user_per_batch: 32,
+        min_base_order_fp: 1_000_000,
+        min_quote_order_fp: 1_000_000,
+    },
+];
+
+/// Emit an event and record its digest in the market's `EventLog`.
+macro_rules! emit_logged {
+    ($log:expr, $event:expr) => {{
+        let event = $event;
+        $log.record(&event)?;
+        emit!(event);
+    }};
+}
 
 #[program]
 pub mod micro_batch_amm {
@@ -20,67 +183,45 @@ pub mod micro_batch_amm {
         fee_bps: u16,
         max_orders_per_user_per_batch: u32,
     ) -> Result<()> {
-        require!(fee_bps as u64 <= BPS_DENOM, AmmError::InvalidFeeBps);
-
-        let market = &mut ctx.accounts.market;
-        market.authority = ctx.accounts.authority.key();
-        market.base_mint = ctx.accounts.base_mint.key();
-        market.quote_mint = ctx.accounts.quote_mint.key();
-        market.vault_base = ctx.accounts.vault_base.key();
-        market.vault_quote = ctx.accounts.vault_quote.key();
-        market.batch_duration_slots = batch_duration_slots;
-        market.last_batch_slot = 0;
-        market.current_batch_id = 0;
-        market.next_order_id = 0;
-        market.fee_bps = fee_bps;
-        market.max_orders_per_user_per_batch = max_orders_per_user_per_batch;
-        market.paused = false;
-
-        market.bump = ctx.bumps.market;
-        market.vault_base_bump = ctx.bumps.vault_base;
-        market.vault_quote_bump = ctx.bumps.vault_quote;
-
-        // --- New risk / fee / keeper defaults ---
-
-        // Notional caps (quote-side, fixed point 1e6)
-        market.max_notional_per_batch_quote_fp = u128::MAX;
-        market.max_notional_per_user_per_batch_quote_fp = u128::MAX;
-        market.batch_notional_quote_fp = 0;
-
-        market.max_orders_global_per_batch = u32::MAX;
-        market.global_orders_in_batch = 0;
-
-        // Price band (bps) & last price
-        market.max_price_move_bps = 0; // 0 = disabled
-        market.last_clearing_price_fp = 0;
-
-        // Keeper incentives
-        market.keeper_fee_bps = 0;
-        market.keeper_treasury = ctx.accounts.authority.key();
-        market.min_slots_between_clears = batch_duration_slots;
-        market.keeper_restricted = false;
-        market.only_keeper = Pubkey::default();
-
-        // Protocol treasury / fees
-        market.protocol_treasury = ctx.accounts.authority.key();
-        market.referral_fee_bps = 0;
-        market.protocol_fee_bps = fee_bps;
-        market.protocol_fees_accrued_fp = 0;
+        init_market(
+            ctx.accounts,
+            &ctx.bumps,
+            batch_duration_slots,
+            fee_bps,
+            max_orders_per_user_per_batch,
+        )
+    }
 
-        // Dust / min order sizes
-        market.min_base_order_fp = 1;
-        market.min_quote_order_fp = 1;
+    /// Initialize a market from one of the vetted `MARKET_TEMPLATES` presets, so
+    /// permissionless creators don't have to hand-tune every risk parameter.
+    pub fn create_market_from_template(
+        ctx: Context<InitializeMarket>,
+        template_id: u8,
+    ) -> Result<()> {
+        let template = MARKET_TEMPLATES
+            .get(template_id as usize)
+            .ok_or(AmmError::UnknownMarketTemplate)?;
+        ctx.accounts
+            .program_config
+            .check_fees(template.fee_bps, template.keeper_fee_bps)?;
+        init_market(
+            ctx.accounts,
+            &ctx.bumps,
+            template.batch_duration_slots,
+            template.fee_bps,
+            template.max_orders_per_user_per_batch,
+        )?;
 
-        // Pause reason code
-        market.pause_reason = 0;
+        let market = &mut ctx.accounts.market;
+        market.keeper_fee_bps = template.keeper_fee_bps;
+        market.max_price_move_bps = template.max_price_move_bps;
+        market.min_base_order_fp = template.min_base_order_fp;
+        market.min_quote_order_fp = template.min_quote_order_fp;
 
-        emit!(MarketInitialized {
+        emit!(MarketTemplateApplied {
             market: market.key(),
-            authority: market.authority,
-            base_mint: market.base_mint,
-            quote_mint: market.quote_mint,
-            batch_duration_slots,
-            fee_bps,
+            template_id,
+            name: template.name.to_string(),
         });
 
         Ok(())
@@ -92,23 +233,67 @@ pub mod micro_batch_amm {
     /// - For Asks: user deposits **base** tokens into the base vault.
     ///
     /// `amount_base_fp` is the **amount of base** the user wants to trade, in fixed-point (1e6).
-    /// For Bids we compute a max quote deposit = amount_base_fp * limit_price_fp / PRICE_SCALE.
-    pub fn place_order(
-        ctx: Context<PlaceOrder>,
+    /// For Bids we compute a max quote deposit = amount_base_fp * limit_price_fp / PRICE_SCALE
+    /// (rescaled by `Market::quote_scale_fp` when quote normalization is on).
+    ///
+    /// `trigger` (optional) makes this a conditional order: it only takes part in the clear
+    /// if the trigger market's latest clearing price satisfies the condition at clear time.
+    ///
+    /// `allow_external_fill` opts the order into best-efforts residual filling via
+    /// `route_residual`; when false it only ever executes in the auction.
+    ///
+    /// `memo` (optional) is an opaque tag, e.g. the caller's internal order id, echoed in
+    /// `OrderPlaced` and `OrderSettled`.
+    pub fn place_order<'info>(
+        ctx: Context<'_, '_, '_, 'info, PlaceOrder<'info>>,
         side: OrderSide,
         limit_price_fp: u64,
         amount_base_fp: u64,
+        trigger: Option<OrderTrigger>,
+        allow_external_fill: bool,
+        memo: Option<[u8; 32]>,
     ) -> Result<()> {
         let market = &mut ctx.accounts.market;
-        require!(!market.paused, AmmError::MarketPaused);
+        require!(!market.is_paused(PauseModes::PLACEMENT), AmmError::MarketPaused);
+        // A chunked clear has snapshotted the batch: later orders wait for the next batch.
+        require!(market.clearing_state == Pubkey::default(), AmmError::ClearInProgress);
+        // Clustered batches close at the boundary, even before their clear lands.
+        require!(
+            !market.awaiting_cluster_clear(market.clock()?.slot),
+            AmmError::BatchAwaitingClusterClear
+        );
         require!(limit_price_fp > 0, AmmError::InvalidPrice);
         require!(amount_base_fp > 0, AmmError::InvalidAmount);
 
+        // RFQ markets: only the requester and whitelisted responders may quote.
+        if market.mode == MarketMode::Rfq {
+            let whitelist = ctx
+                .accounts
+                .rfq_whitelist
+                .as_ref()
+                .ok_or(AmmError::RfqWhitelistRequired)?;
+            require_keys_eq!(whitelist.market, market.key(), AmmError::RfqWhitelistRequired);
+            require!(
+                whitelist.is_allowed(&ctx.accounts.user.key()),
+                AmmError::NotRfqParticipant
+            );
+        }
+
+        // Launch markets: users only bid against the authority's seeded supply.
+        if market.mode == MarketMode::Launch {
+            require!(market.launch_batches_remaining > 0, AmmError::LaunchNotActive);
+            require!(side == OrderSide::Bid, AmmError::LaunchBidsOnly);
+        }
+
+        if let Some(t) = trigger.as_ref() {
+            require!(t.condition != TriggerCondition::None, AmmError::InvalidTrigger);
+            require!(t.price_fp > 0, AmmError::InvalidTrigger);
+            require_keys_neq!(t.market, market.key(), AmmError::InvalidTrigger);
+        }
+
         // Approx order notional in quote (fp)
-        let order_notional_quote_fp: u128 = (amount_base_fp as u128)
-            .checked_mul(limit_price_fp as u128)
-            .ok_or(AmmError::MathOverflow)?
-            / (PRICE_SCALE as u128);
+        let order_notional_quote_fp: u128 =
+            market.quote_for_base(amount_base_fp as u128, limit_price_fp)?;
 
         // Dust guards
         match side {
@@ -126,16 +311,30 @@ pub mod micro_batch_amm {
             }
         }
 
+        // Size bands: cap both base size and quote notional
+        require!(
+            amount_base_fp <= market.max_base_order_fp,
+            AmmError::OrderTooLarge
+        );
+        require!(
+            order_notional_quote_fp <= market.max_quote_order_fp as u128,
+            AmmError::OrderTooLarge
+        );
+
+        // Orders placed through a sub-account are owned (and limited) by the sub-account.
+        let trader = trader_key(&ctx.accounts.user.key(), &ctx.accounts.sub_account);
+
         // Per-user-per-batch order count & notional caps
         let user_batch = &mut ctx.accounts.user_batch_stats;
         if user_batch.order_count == 0 {
-            user_batch.user = ctx.accounts.user.key();
+            user_batch.user = trader;
             user_batch.market = market.key();
             user_batch.batch_id = market.current_batch_id;
             user_batch.notional_quote_fp = 0;
             user_batch.bump = ctx.bumps.user_batch_stats;
+            user_batch.cancel_count = 0;
         } else {
-            require_keys_eq!(user_batch.user, ctx.accounts.user.key(), AmmError::InvalidUserBatch);
+            require_keys_eq!(user_batch.user, trader, AmmError::InvalidUserBatch);
             require_keys_eq!(user_batch.market, market.key(), AmmError::InvalidUserBatch);
             require_eq!(user_batch.batch_id, market.current_batch_id, AmmError::InvalidUserBatch);
         }
@@ -161,6 +360,30 @@ pub mod micro_batch_amm {
             .checked_add(1)
             .ok_or(AmmError::MathOverflow)?;
 
+        // Concurrent batch exposure: a batch counts as active for the user while
+        // any of their orders in it is still open (not settled or cancelled).
+        let user_stats = &mut ctx.accounts.user_stats;
+        if user_stats.user == Pubkey::default() {
+            user_stats.user = trader;
+            user_stats.market = market.key();
+            user_stats.active_batch_count = 0;
+            user_stats.bump = ctx.bumps.user_stats;
+        }
+        if user_batch.open_order_count == 0 {
+            require!(
+                user_stats.active_batch_count < market.max_active_batches_per_user,
+                AmmError::TooManyActiveBatches
+            );
+            user_stats.active_batch_count = user_stats
+                .active_batch_count
+                .checked_add(1)
+                .ok_or(AmmError::MathOverflow)?;
+        }
+        user_batch.open_order_count = user_batch
+            .open_order_count
+            .checked_add(1)
+            .ok_or(AmmError::MathOverflow)?;
+
         // Global batch notional + global order count
         let new_batch_notional = market
             .batch_notional_quote_fp
@@ -176,6 +399,8 @@ pub mod micro_batch_amm {
             market.global_orders_in_batch < market.max_orders_global_per_batch,
             AmmError::MaxOrdersGlobalExceeded
         );
+        // Per-batch arrival sequence (queue position within a price level).
+        let batch_seq = market.global_orders_in_batch;
         market.global_orders_in_batch = market
             .global_orders_in_batch
             .checked_add(1)
@@ -190,61 +415,163 @@ pub mod micro_batch_amm {
 
         let mut quote_deposit_fp: u64 = 0;
 
+        // Deposits come from the sub-account's token accounts, signed by its PDA.
+        let depositor = match ctx.accounts.sub_account.as_ref() {
+            Some(sub) => sub.to_account_info(),
+            None => ctx.accounts.user.to_account_info(),
+        };
+        let sub_seeds = ctx.accounts.sub_account.as_ref().map(|s| s.seed_parts());
+        let sub_seed_refs = sub_seeds.as_ref().map(|(market_key, master_key, id, bump)| {
+            [
+                SUB_ACCOUNT_SEED,
+                market_key.as_ref(),
+                master_key.as_ref(),
+                id.as_ref(),
+                bump.as_ref(),
+            ]
+        });
+        let sub_seed_slice: Option<&[&[u8]]> = sub_seed_refs.as_ref().map(|s| &s[..]);
+        let signer_seeds = sub_seed_slice.as_slice();
+
         match side {
             OrderSide::Bid => {
                 // User wants to buy `amount_base_fp` of base at limit_price_fp.
                 // We deposit max quote upfront.
-                let quote_needed = ((amount_base_fp as u128)
-                    .checked_mul(limit_price_fp as u128)
-                    .ok_or(AmmError::MathOverflow)?
-                    / PRICE_SCALE as u128) as u64;
+                let quote_needed =
+                    market.quote_for_base(amount_base_fp as u128, limit_price_fp)? as u64;
                 require!(quote_needed > 0, AmmError::InvalidAmount);
                 quote_deposit_fp = quote_needed;
 
                 // Transfer quote from user to vault_quote.
-                let cpi_accounts = Transfer {
+                let cpi_accounts = TransferChecked {
                     from: ctx.accounts.user_quote_ata.to_account_info(),
+                    mint: ctx.accounts.quote_mint.to_account_info(),
                     to: ctx.accounts.vault_quote.to_account_info(),
-                    authority: ctx.accounts.user.to_account_info(),
+                    authority: depositor.clone(),
                 };
-                let cpi_ctx =
-                    CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
-                token::transfer(cpi_ctx, quote_needed)?;
+                let cpi_ctx = CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    cpi_accounts,
+                    signer_seeds,
+                )
+                .with_remaining_accounts(ctx.remaining_accounts.to_vec());
+                transfer_checked_with_hook(
+                    cpi_ctx,
+                    &market.transfer_hook_programs,
+                    quote_needed,
+                    ctx.accounts.quote_mint.decimals,
+                )?;
             }
             OrderSide::Ask => {
                 // User wants to sell `amount_base_fp` of base.
                 // Transfer base from user to vault_base.
-                let cpi_accounts = Transfer {
+                let cpi_accounts = TransferChecked {
                     from: ctx.accounts.user_base_ata.to_account_info(),
+                    mint: ctx.accounts.base_mint.to_account_info(),
                     to: ctx.accounts.vault_base.to_account_info(),
-                    authority: ctx.accounts.user.to_account_info(),
+                    authority: depositor.clone(),
                 };
-                let cpi_ctx =
-                    CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
-                token::transfer(cpi_ctx, amount_base_fp)?;
+                let cpi_ctx = CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    cpi_accounts,
+                    signer_seeds,
+                )
+                .with_remaining_accounts(ctx.remaining_accounts.to_vec());
+                transfer_checked_with_hook(
+                    cpi_ctx,
+                    &market.transfer_hook_programs,
+                    amount_base_fp,
+                    ctx.accounts.base_mint.decimals,
+                )?;
             }
         }
 
+        if let Some(sub) = ctx.accounts.sub_account.as_mut() {
+            sub.orders_placed = sub.orders_placed.checked_add(1).ok_or(AmmError::MathOverflow)?;
+        }
+
         let order = &mut ctx.accounts.order;
-        order.user = ctx.accounts.user.key();
+        order.user = trader;
         order.market = market.key();
         order.side = side;
         order.limit_price_fp = limit_price_fp;
         order.amount_base_fp = amount_base_fp;
         order.batch_id = market.current_batch_id;
-        order.filled = false;
-        order.cancelled = false;
+        order.flags = 0;
         order.quote_deposit_fp = quote_deposit_fp;
         order.id = order_id;
+        order.user_nonce = ctx.accounts.user_stats.order_nonce;
+        order.batch_seq = batch_seq;
+        order.matched_base_fp = 0;
+        order.matched_quote_fp = 0;
+        order.claim_base_fp = 0;
+        order.claim_quote_fp = 0;
+        ctx.accounts.user_stats.order_nonce = ctx
+            .accounts
+            .user_stats
+            .order_nonce
+            .checked_add(1)
+            .ok_or(AmmError::MathOverflow)?;
+
+        match trigger {
+            Some(t) => {
+                order.trigger_market = t.market;
+                order.trigger_condition = t.condition;
+                order.trigger_price_fp = t.price_fp;
+            }
+            None => {
+                order.trigger_market = Pubkey::default();
+                order.trigger_condition = TriggerCondition::None;
+                order.trigger_price_fp = 0;
+            }
+        }
+        order.oco_link = Pubkey::default();
+        order.frozen_until_slot = 0;
+        order.memo = memo.unwrap_or_default();
+        order.settle_nonce = 0;
+        order.keeper_tip_fp = 0;
+        order.cancel_authority = Pubkey::default();
+        order.set_flag(OrderFlags::ALLOW_EXTERNAL_FILL, allow_external_fill);
+        // Cancel-and-replace cannot jump the queue: after a cancel in this batch, the
+        // user's new orders queue behind every order that was not re-placed.
+        order.set_flag(
+            OrderFlags::REQUEUED,
+            ctx.accounts.user_batch_stats.cancel_count > 0,
+        );
+        // Institutional accounts: payouts above the threshold need the co-signer.
+        order.set_flag(
+            OrderFlags::REQUIRES_COSIGN,
+            ctx.accounts.user_stats.settlement_cosigner != Pubkey::default(),
+        );
+
+        // Append to the batch's order index (the first order of a page initializes it).
+        let order_index = &mut ctx.accounts.order_index;
+        if order_index.orders.is_empty() {
+            order_index.market = market.key();
+            order_index.batch_id = order.batch_id;
+            order_index.page = batch_seq / ORDER_INDEX_PAGE_SIZE as u32;
+            order_index.bump = ctx.bumps.order_index;
+        }
+        require!(
+            order_index.orders.len() < ORDER_INDEX_PAGE_SIZE,
+            AmmError::OrderIndexFull
+        );
+        order_index.orders.push(order.key());
 
-        emit!(OrderPlaced {
+        // Size-bucketed markets only reveal the order of magnitude until the clear.
+        let bucket_sizes = market.has_flag(MarketFlags::BUCKET_ORDER_SIZES);
+        emit_logged!(ctx.accounts.event_log, OrderPlaced {
             market: market.key(),
             order: order.key(),
             user: order.user,
             side,
             limit_price_fp,
-            amount_base_fp,
+            amount_base_fp: if bucket_sizes { 0 } else { amount_base_fp },
+            size_bucket: size_bucket(amount_base_fp),
             batch_id: order.batch_id,
+            batch_seq,
+            allow_external_fill,
+            memo: order.memo,
         });
 
         Ok(())
@@ -252,13 +579,27 @@ pub mod micro_batch_amm {
 
     /// Clear the current batch using a uniform clearing price.
     ///
-    /// This ix computes the clearing price and volumes and rolls the batch.
-    /// Token settlement happens later via `settle_order`.
+    /// This ix computes the clearing price and volumes, records each passed order's
+    /// claim (matched fill + unused deposit) on the order, and rolls the batch.
+    /// Token settlement happens later via `settle_order`, which only debits those claims.
+    ///
+    /// Conditional orders are activated here: if `trigger_market` is passed and its last
+    /// clearing price satisfies an order's condition, the order is marked `triggered`
+    /// (written back to the Order account) and joins the batch. Otherwise it is skipped.
     ///
-    /// remaining_accounts = triplets: [Order, user_base_ata, user_quote_ata] * N
-    pub fn clear_batch(ctx: Context<ClearBatch>) -> Result<()> {
-        let clock = Clock::get()?;
-        let remaining = &ctx.remaining_accounts;
+    /// If a liquidity adapter is registered and passed, the residual imbalance left after the
+    /// uniform-price match is quoted against it (via CPI) and recorded on BatchState.
+    ///
+    /// remaining_accounts = triplets: [Order, user_base_ata, user_quote_ata] * N,
+    /// followed by the adapter's registered accounts (in order) when an adapter is used,
+    /// then optionally [Order, Claimables] pairs: each order that receives a claim is
+    /// listed in its owner's `Claimables` index.
+    ///
+    /// Passing a `clearing_state` finalizes a batch staged across transactions instead
+    /// (see `finalize_clear`).
+    pub fn clear_batch<'info>(ctx: Context<'_, '_, '_, 'info, ClearBatch<'info>>) -> Result<()> {
+        let clock = ctx.accounts.market.clock()?;
+        let (remaining, claimables_pairs) = split_claimables_pairs(ctx.remaining_accounts)?;
 
         let market = &mut ctx.accounts.market;
         let batch_state = &mut ctx.accounts.batch_state;
@@ -281,7 +622,7 @@ pub mod micro_batch_amm {
                 mv.key(),
                 mv.current_batch_id,
                 mv.fee_bps as u128,
-                mv.paused,
+                mv.is_paused(PauseModes::CLEARING),
                 mv.last_batch_slot,
                 mv.batch_duration_slots,
                 mv.max_price_move_bps,
@@ -292,8 +633,40 @@ pub mod micro_batch_amm {
 
         require!(!paused, AmmError::MarketPaused);
 
+        // A batch staged with `begin_clear` only clears from its stage, finalized by the
+        // keeper that began it; the staged orders replace the remaining_accounts triplets.
+        let staged_clearing_state = match ctx.accounts.clearing_state.as_ref() {
+            Some(state) => {
+                require_keys_eq!(
+                    state.key(),
+                    market.clearing_state,
+                    AmmError::ClearingStateRequired
+                );
+                require_keys_eq!(state.keeper, authority.key(), AmmError::Unauthorized);
+                state.key()
+            }
+            None => {
+                require!(market.clearing_state == Pubkey::default(), AmmError::ClearInProgress);
+                Pubkey::default()
+            }
+        };
+        market.clearing_state = Pubkey::default();
+
+        // Rewards go to the keeper's registered recipient (e.g. a DAO treasury), else to
+        // the clearing signer itself.
+        let keeper_reward_recipient = ctx
+            .accounts
+            .keeper_record
+            .as_ref()
+            .map_or(authority.key(), |record| record.reward_recipient);
+        // The open batch's order tips go to whoever clears it, crossed or not.
+        let keeper_tips_quote_fp = std::mem::take(&mut market.batch_keeper_tips_fp);
+
+        // Most recent entry of the SlotHashes sysvar seeds the next batch's order salt.
+        let slot_hash = most_recent_slot_hash(&ctx.accounts.slot_hashes)?;
+
         // Keeper gating
-        if market.keeper_restricted {
+        if market.has_flag(MarketFlags::KEEPER_RESTRICTED) {
             require_keys_eq!(
                 market.only_keeper,
                 authority.key(),
@@ -310,32 +683,213 @@ pub mod micro_batch_amm {
             clock.slot >= last_batch_slot + market.min_slots_between_clears,
             AmmError::BatchNotReady
         );
+        require!(market.may_clear(), AmmError::BatchBelowParticipation);
+        // Per-epoch clear budget, a backstop against a misconfigured low clear spacing.
+        if market.clear_epoch != clock.epoch {
+            market.clear_epoch = clock.epoch;
+            market.clears_in_epoch = 0;
+        }
+        require!(
+            market.max_clears_per_epoch == 0
+                || market.clears_in_epoch < market.max_clears_per_epoch,
+            AmmError::ClearBudgetExhausted
+        );
+        market.clears_in_epoch = market
+            .clears_in_epoch
+            .checked_add(1)
+            .ok_or(AmmError::MathOverflow)?;
+        // Clustered markets clear together, just after a shared boundary.
+        if market.cluster != Pubkey::default() {
+            require!(
+                market.awaiting_cluster_clear(clock.slot)
+                    && market.in_cluster_clear_window(clock.slot),
+                AmmError::ClusterClearWindowClosed
+            );
+        }
+        require!(
+            market.work_queue == Pubkey::default() || ctx.accounts.work_queue.is_some(),
+            AmmError::WorkQueueRequired
+        );
+
+        // MEV protection: the clear must ride in a bundle that tips the configured account.
+        if market.has_flag(MarketFlags::REQUIRE_CLEAR_TIP) {
+            let ix_sysvar = ctx
+                .accounts
+                .instructions_sysvar
+                .as_ref()
+                .ok_or(AmmError::ClearTipMissing)?;
+            require!(
+                tx_has_tip(
+                    &ix_sysvar.to_account_info(),
+                    &market.clear_tip_account,
+                    market.min_clear_tip_lamports,
+                )?,
+                AmmError::ClearTipMissing
+            );
+        }
+
+        // Clear intent: the registered keeper has exclusive rights for `intent_window_slots`
+        // after eligibility and earns a bonus. The bond goes to whoever clears: back to the
+        // committed keeper, or forfeited to the fallback clearer once the window lapses.
+        let mut intent_bonus_bps: u16 = 0;
+        let mut keeper_rebate_bps: u16 = 0;
+        if market.intent_keeper != Pubkey::default() && market.intent_batch_id == current_batch_id
+        {
+            let window_end = market
+                .next_clear_slot()
+                .saturating_add(market.intent_window_slots);
+            let committed = market.intent_keeper == authority.key();
+            let honored = committed && clock.slot < window_end;
+            if honored {
+                intent_bonus_bps = market.intent_bonus_bps;
+            }
+            if !committed {
+                require!(clock.slot >= window_end, AmmError::ClearIntentWindowActive);
+            }
+            // The winning bid's rebate binds the batch whoever clears it.
+            keeper_rebate_bps = market
+                .intent_rebate_bps
+                .min(keeper_fee_bps.saturating_add(intent_bonus_bps));
+
+            let intent = ctx
+                .accounts
+                .clear_intent
+                .as_ref()
+                .ok_or(AmmError::ClearIntentRequired)?;
+            require_keys_eq!(intent.market, market_pk, AmmError::ClearIntentRequired);
+            require_eq!(intent.batch_id, current_batch_id, AmmError::ClearIntentRequired);
+            intent.close(authority.to_account_info())?;
+
+            emit!(ClearIntentResolved {
+                market: market_pk,
+                batch_id: current_batch_id,
+                committed_keeper: market.intent_keeper,
+                clearer: authority.key(),
+                honored,
+            });
+            market.intent_keeper = Pubkey::default();
+        }
+
+        // Reimburse the clearer's transaction fees out of the lamport vault, as far as its
+        // balance above rent allows. Any later failure reverts the payment with the clear.
+        if let Some(vault) = ctx.accounts.reimbursement_vault.as_ref() {
+            let vault_ai = vault.to_account_info();
+            let rent_floor = Rent::get()?.minimum_balance(vault_ai.data_len());
+            let lamports = market
+                .clear_reimbursement_lamports
+                .min(vault_ai.lamports().saturating_sub(rent_floor));
+            if lamports > 0 {
+                vault_ai.sub_lamports(lamports)?;
+                authority.to_account_info().add_lamports(lamports)?;
+                emit!(ClearReimbursed {
+                    market: market_pk,
+                    batch_id: current_batch_id,
+                    clearer: authority.key(),
+                    lamports,
+                });
+            }
+        }
 
+        // Correctness bond: held on the batch state through the dispute window, slashed to
+        // a successful `audit_batch` challenger or released back by `release_clear_bond`.
+        let clear_bond_lamports = market.clear_bond_lamports;
+        if clear_bond_lamports > 0 {
+            let cpi_ctx = CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: authority.to_account_info(),
+                    to: batch_state.to_account_info(),
+                },
+            );
+            system_program::transfer(cpi_ctx, clear_bond_lamports)?;
+        }
+        let dispute_deadline_slot = clock.slot.saturating_add(market.dispute_window_slots);
+        let settlement_open_slot = clock.slot.saturating_add(market.settlement_delay_slots);
+        // Every order in this batch was allocated an id below this (kept for `audit_batch`).
+        let closing_order_id = market.next_order_id;
+
+        // Trailing adapter accounts (if any) are not part of the order triplets.
+        let adapter_accounts_len = match ctx.accounts.liquidity_adapter.as_ref() {
+            Some(adapter) => {
+                require_keys_eq!(adapter.market, market_pk, AmmError::InvalidLiquidityAdapter);
+                adapter.accounts.len()
+            }
+            None => 0,
+        };
         require!(
-            remaining.len() % 3 == 0,
+            remaining.len() >= adapter_accounts_len,
+            AmmError::InvalidRemainingAccountsLayout
+        );
+        let order_accounts_len = remaining.len() - adapter_accounts_len;
+        require!(
+            order_accounts_len.is_multiple_of(3),
             AmmError::InvalidRemainingAccountsLayout
         );
 
         // 1) Collect active orders for this batch.
         let mut temp_orders: Vec<TempOrder> = Vec::new();
         let mut candidate_prices: Vec<u64> = Vec::new();
+        let mut seen_orders: Vec<Pubkey> = Vec::new();
+        // (temp_orders index, linked leg) for activated OCO legs.
+        let mut oco_legs: Vec<(usize, Pubkey)> = Vec::new();
+
+        if let Some(state) = ctx.accounts.clearing_state.as_ref() {
+            require!(order_accounts_len == 0, AmmError::InvalidRemainingAccountsLayout);
+            for (i, staged) in state.entries.iter().enumerate().filter(|(_, s)| s.active) {
+                temp_orders.push(TempOrder {
+                    account_index: i,
+                    side: staged.side,
+                    limit_price_fp: staged.limit_price_fp,
+                    original_base_fp: staged.amount_base_fp as u128,
+                    remaining_base_fp: staged.amount_base_fp as u128,
+                    matched_quote_fp: 0,
+                    quote_deposit_fp: staged.quote_deposit_fp as u128,
+                    queue_seq: staged.queue_seq,
+                    allow_external_fill: staged.allow_external_fill,
+                });
+            }
+        }
 
         let mut idx = 0usize;
-        while idx < remaining.len() {
+        while idx < order_accounts_len {
             let order_ai = &remaining[idx];
 
             // Deserialize Order directly from account data.
-            let mut data_slice: &[u8] = &order_ai.data.borrow();
-            let order_acc: Order = Order::try_deserialize(&mut data_slice)?;
+            let mut order_acc: Order = load_account(order_ai)?;
 
             if order_acc.market != market_pk
                 || order_acc.batch_id != current_batch_id
                 || order_acc.amount_base_fp == 0
-                || order_acc.cancelled
+                || order_acc.has_flag(OrderFlags::CANCELLED)
+                || seen_orders.contains(order_ai.key)
             {
                 idx += 3;
                 continue;
             }
+            seen_orders.push(*order_ai.key);
+            // Claims are written back to every order that joins the batch.
+            require!(order_ai.is_writable, AmmError::InvalidRemainingAccountsLayout);
+
+            // Conditional orders: activation pass against the trigger market.
+            if order_acc.trigger_condition != TriggerCondition::None
+                && !order_acc.has_flag(OrderFlags::TRIGGERED)
+            {
+                let fired = match ctx.accounts.trigger_market.as_ref() {
+                    Some(tm) if tm.key() == order_acc.trigger_market => {
+                        order_acc.trigger_fires(tm.last_clearing_price_fp)
+                    }
+                    _ => false,
+                };
+                if !fired {
+                    idx += 3;
+                    continue;
+                }
+
+                order_acc.set_flag(OrderFlags::TRIGGERED, true);
+                let mut data = order_ai.try_borrow_mut_data()?;
+                let mut writer: &mut [u8] = &mut data;
+                order_acc.try_serialize(&mut writer)?;
+            }
 
             temp_orders.push(TempOrder {
                 account_index: idx,
@@ -343,26 +897,126 @@ pub mod micro_batch_amm {
                 limit_price_fp: order_acc.limit_price_fp,
                 original_base_fp: order_acc.amount_base_fp as u128,
                 remaining_base_fp: order_acc.amount_base_fp as u128,
+                matched_quote_fp: 0,
                 quote_deposit_fp: order_acc.quote_deposit_fp as u128,
+                queue_seq: order_acc.queue_seq(),
+                allow_external_fill: order_acc.has_flag(OrderFlags::ALLOW_EXTERNAL_FILL),
             });
-
-            if !candidate_prices.contains(&order_acc.limit_price_fp) {
-                candidate_prices.push(order_acc.limit_price_fp);
+            if order_acc.oco_link != Pubkey::default() {
+                oco_legs.push((temp_orders.len() - 1, order_acc.oco_link));
             }
 
             idx += 3;
         }
 
+        // Every order placed in the batch and not cancelled must be passed (or staged), so a
+        // keeper can't move the price by leaving orders out. Order accounts are distinct PDAs
+        // and duplicates are skipped above, so matching the count proves the set complete.
+        let passed_orders = match ctx.accounts.clearing_state.as_ref() {
+            Some(state) => state.entries.len(),
+            None => seen_orders.len(),
+        };
+        require!(
+            passed_orders as u32 == market.live_orders_in_batch(),
+            AmmError::IncompleteOrderSet
+        );
+
+        // One-cancels-the-other: when both legs of a linked pair activated, the leg queued
+        // later sits the batch out. It is un-triggered, so it settles as a full refund.
+        let mut oco_dropped: Vec<usize> = Vec::new();
+        for &(i, link) in oco_legs.iter() {
+            let partner_first = oco_legs.iter().any(|&(j, _)| {
+                *remaining[temp_orders[j].account_index].key == link
+                    && temp_orders[j].queue_seq < temp_orders[i].queue_seq
+            });
+            if partner_first {
+                oco_dropped.push(temp_orders[i].account_index);
+            }
+        }
+        for &account_index in oco_dropped.iter() {
+            let order_ai = &remaining[account_index];
+            let mut order_acc: Order = load_account(order_ai)?;
+            order_acc.set_flag(OrderFlags::TRIGGERED, false);
+            write_account(order_ai, &order_acc)?;
+            emit!(OcoLegCancelled {
+                market: market_pk,
+                batch_id: current_batch_id,
+                order: *order_ai.key,
+                linked_order: order_acc.oco_link,
+            });
+        }
+        temp_orders.retain(|o| !oco_dropped.contains(&o.account_index));
+
+        for o in temp_orders.iter() {
+            if !candidate_prices.contains(&o.limit_price_fp) {
+                candidate_prices.push(o.limit_price_fp);
+            }
+        }
+
+        // Launch auctions: this batch's slice of the seeded supply is a synthetic ask
+        // at the reserve price (no Order account behind it).
+        let launch_offer_base_fp = market.launch_offer_for_batch();
+        if launch_offer_base_fp > 0 {
+            temp_orders.push(TempOrder {
+                account_index: usize::MAX,
+                side: OrderSide::Ask,
+                limit_price_fp: market.launch_reserve_price_fp,
+                original_base_fp: launch_offer_base_fp as u128,
+                remaining_base_fp: launch_offer_base_fp as u128,
+                matched_quote_fp: 0,
+                quote_deposit_fp: 0,
+                queue_seq: 0,
+                allow_external_fill: false,
+            });
+            if !candidate_prices.contains(&market.launch_reserve_price_fp) {
+                candidate_prices.push(market.launch_reserve_price_fp);
+            }
+        }
+
+        // Too many distinct prices to search within compute: degrade to the tick grid
+        // (each candidate rounded down to a tick) instead of failing the clear.
+        let unique_price_count = candidate_prices.len() as u32;
+        let max_candidates = market.max_candidate_prices as usize;
+        let prices_bucketed = max_candidates > 0 && candidate_prices.len() > max_candidates;
+        if prices_bucketed {
+            let tick = market.tick_size_fp;
+            require!(tick > 0, AmmError::TooManyCandidatePrices);
+            bucket_to_tick(&mut candidate_prices, tick);
+            require!(
+                candidate_prices.len() <= max_candidates,
+                AmmError::TooManyCandidatePrices
+            );
+        }
+
+        // What `audit_batch` needs to replay this discovery once the batch has rolled.
+        let price_tick_fp = if prices_bucketed { market.tick_size_fp } else { 0 };
+        let batch_order_count = market.global_orders_in_batch;
+
+        // Compute-relevant sizes, emitted on every exit so operators can spot batches
+        // approaching the compute limit.
+        let orders_passed = match ctx.accounts.clearing_state.as_ref() {
+            Some(state) => state.entries.len() as u32,
+            None => (order_accounts_len / 3) as u32,
+        };
+        let mut metrics = ClearMetrics {
+            market: market_pk,
+            batch_id: current_batch_id,
+            orders_passed,
+            order_count: temp_orders.len() as u32,
+            unique_price_count,
+            evaluated_price_count: candidate_prices.len() as u32,
+            prices_bucketed,
+            match_iterations: 0,
+            index_pages: orders_passed.div_ceil(ORDER_INDEX_PAGE_SIZE as u32),
+        };
+
         if temp_orders.is_empty() {
             // No orders in this batch; just roll batch.
-            let cleared_batch_id = market.current_batch_id;
-            market.last_batch_slot = clock.slot;
-            market.current_batch_id = market
-                .current_batch_id
-                .checked_add(1)
-                .ok_or(AmmError::MathOverflow)?;
-            market.batch_notional_quote_fp = 0;
-            market.global_orders_in_batch = 0;
+            market.record_launch_clear(0, 0)?;
+            let cleared_batch_id = market.roll_batch(market_pk, clock.slot, &slot_hash)?;
+            if let Some(queue) = ctx.accounts.work_queue.as_mut() {
+                queue.schedule_clear(market_pk, market.current_batch_id, market.next_clear_slot());
+            }
 
             // Reset batch state
             batch_state.market = market_pk;
@@ -374,64 +1028,67 @@ pub mod micro_batch_amm {
             batch_state.cleared_slot = clock.slot;
             batch_state.settled = true; // trivially settled (no fills)
             batch_state.keeper = authority.key();
+            batch_state.keeper_reward_recipient = keeper_reward_recipient;
             batch_state.keeper_reward_quote_fp = 0;
+            batch_state.keeper_reward_shortfall_fp = 0;
             batch_state.remaining_base_to_settle_fp = 0;
             batch_state.remaining_quote_to_settle_fp = 0;
+            batch_state.bid_volume_at_clear_fp = 0;
+            batch_state.ask_volume_at_clear_fp = 0;
+            batch_state.residual_side = OrderSide::Bid;
+            batch_state.residual_base_fp = 0;
+            batch_state.routable_base_fp = 0;
+            batch_state.external_price_fp = 0;
+            batch_state.external_base_fp = 0;
+            batch_state.residual_routed = false;
+            batch_state.routed_base_fp = 0;
+            batch_state.routed_quote_fp = 0;
+            batch_state.routed_base_remaining_fp = 0;
+            batch_state.routed_quote_remaining_fp = 0;
+            batch_state.settled_bid_base_fp = 0;
+            batch_state.settled_ask_quote_fp = 0;
+            batch_state.vesting_tranches = 0;
+            batch_state.awaiting_confirmation = false;
+            batch_state.order_count = batch_order_count;
+            batch_state.settled_order_count = 0;
+            batch_state.keeper_tips_quote_fp = keeper_tips_quote_fp;
+            batch_state.clearing_state = Pubkey::default();
+            batch_state.closing_order_id = closing_order_id;
+            batch_state.launch_offer_base_fp = launch_offer_base_fp;
+            batch_state.keeper_rebate_bps = keeper_rebate_bps;
+            batch_state.price_tick_fp = price_tick_fp;
+            batch_state.keeper_bond_lamports = clear_bond_lamports;
+            batch_state.dispute_deadline_slot = dispute_deadline_slot;
+            batch_state.disputed = false;
+            batch_state.settlement_open_slot = settlement_open_slot;
+
+            // Nothing crossed, so every staged order is a plain refund: drop the stage.
+            if let Some(state) = ctx.accounts.clearing_state.as_ref() {
+                state.close(authority.to_account_info())?;
+            }
 
-            emit!(BatchCleared {
+            emit_logged!(ctx.accounts.event_log, BatchCleared {
                 market: market_pk,
                 batch_id: cleared_batch_id,
                 clearing_price_fp: 0,
                 total_base_traded_fp: 0,
                 total_quote_traded_fp: 0,
             });
+            emit!(metrics);
             return Ok(());
         }
 
         // 2) Find clearing price: maximize min(bid_volume, ask_volume).
-        let mut best_price: u64 = 0;
-        let mut best_traded: u128 = 0;
-
-        for &p in candidate_prices.iter() {
-            let mut bid_vol: u128 = 0;
-            let mut ask_vol: u128 = 0;
-
-            for o in temp_orders.iter() {
-                match o.side {
-                    OrderSide::Bid => {
-                        if o.limit_price_fp >= p {
-                            bid_vol = bid_vol
-                                .checked_add(o.original_base_fp)
-                                .ok_or(AmmError::MathOverflow)?;
-                        }
-                    }
-                    OrderSide::Ask => {
-                        if o.limit_price_fp <= p {
-                            ask_vol = ask_vol
-                                .checked_add(o.original_base_fp)
-                                .ok_or(AmmError::MathOverflow)?;
-                        }
-                    }
-                }
-            }
-
-            let traded = bid_vol.min(ask_vol);
-            if traded > best_traded {
-                best_traded = traded;
-                best_price = p;
-            }
-        }
+        let (best_price, best_traded, best_bid_vol, best_ask_vol) =
+            discover_clearing_price(&temp_orders, &candidate_prices)?;
 
         if best_traded == 0 || best_price == 0 {
             // No price where bids and asks cross.
-            let cleared_batch_id = market.current_batch_id;
-            market.last_batch_slot = clock.slot;
-            market.current_batch_id = market
-                .current_batch_id
-                .checked_add(1)
-                .ok_or(AmmError::MathOverflow)?;
-            market.batch_notional_quote_fp = 0;
-            market.global_orders_in_batch = 0;
+            market.record_launch_clear(0, 0)?;
+            let cleared_batch_id = market.roll_batch(market_pk, clock.slot, &slot_hash)?;
+            if let Some(queue) = ctx.accounts.work_queue.as_mut() {
+                queue.schedule_clear(market_pk, market.current_batch_id, market.next_clear_slot());
+            }
 
             batch_state.market = market_pk;
             batch_state.batch_id = cleared_batch_id;
@@ -442,21 +1099,58 @@ pub mod micro_batch_amm {
             batch_state.cleared_slot = clock.slot;
             batch_state.settled = true;
             batch_state.keeper = authority.key();
+            batch_state.keeper_reward_recipient = keeper_reward_recipient;
             batch_state.keeper_reward_quote_fp = 0;
+            batch_state.keeper_reward_shortfall_fp = 0;
             batch_state.remaining_base_to_settle_fp = 0;
             batch_state.remaining_quote_to_settle_fp = 0;
+            batch_state.bid_volume_at_clear_fp = 0;
+            batch_state.ask_volume_at_clear_fp = 0;
+            batch_state.residual_side = OrderSide::Bid;
+            batch_state.residual_base_fp = 0;
+            batch_state.routable_base_fp = 0;
+            batch_state.external_price_fp = 0;
+            batch_state.external_base_fp = 0;
+            batch_state.residual_routed = false;
+            batch_state.routed_base_fp = 0;
+            batch_state.routed_quote_fp = 0;
+            batch_state.routed_base_remaining_fp = 0;
+            batch_state.routed_quote_remaining_fp = 0;
+            batch_state.settled_bid_base_fp = 0;
+            batch_state.settled_ask_quote_fp = 0;
+            batch_state.vesting_tranches = 0;
+            batch_state.awaiting_confirmation = false;
+            batch_state.order_count = batch_order_count;
+            batch_state.settled_order_count = 0;
+            batch_state.keeper_tips_quote_fp = keeper_tips_quote_fp;
+            batch_state.clearing_state = Pubkey::default();
+            batch_state.closing_order_id = closing_order_id;
+            batch_state.launch_offer_base_fp = launch_offer_base_fp;
+            batch_state.keeper_rebate_bps = keeper_rebate_bps;
+            batch_state.price_tick_fp = price_tick_fp;
+            batch_state.keeper_bond_lamports = clear_bond_lamports;
+            batch_state.dispute_deadline_slot = dispute_deadline_slot;
+            batch_state.disputed = false;
+            batch_state.settlement_open_slot = settlement_open_slot;
+
+            // Nothing crossed, so every staged order is a plain refund: drop the stage.
+            if let Some(state) = ctx.accounts.clearing_state.as_ref() {
+                state.close(authority.to_account_info())?;
+            }
 
-            emit!(BatchCleared {
+            emit_logged!(ctx.accounts.event_log, BatchCleared {
                 market: market_pk,
                 batch_id: cleared_batch_id,
                 clearing_price_fp: 0,
                 total_base_traded_fp: 0,
                 total_quote_traded_fp: 0,
             });
+            emit!(metrics);
             return Ok(());
         }
 
         let clearing_price_fp = best_price;
+        let quote_scale_fp = market.quote_scale_fp;
 
         // Price-band circuit breaker
         if last_clearing_price_fp > 0 && max_price_move_bps > 0 {
@@ -476,13 +1170,17 @@ pub mod micro_batch_amm {
             );
         }
 
-        // 3) Build sorted indices: bids (desc price), asks (asc price).
+        // 3) Build sorted indices: bids (desc price), asks (asc price), crossed at the
+        //    clearing price. Ties within a price level are ordered FIFO by queue_seq
+        //    (batch_seq, with re-placed orders queued last), which only decides who gets
+        //    the rounding dust of a pro-rata level.
         let mut bid_indices: Vec<usize> = Vec::new();
         let mut ask_indices: Vec<usize> = Vec::new();
         for (i, o) in temp_orders.iter().enumerate() {
             match o.side {
-                OrderSide::Bid => bid_indices.push(i),
-                OrderSide::Ask => ask_indices.push(i),
+                OrderSide::Bid if o.limit_price_fp >= clearing_price_fp => bid_indices.push(i),
+                OrderSide::Ask if o.limit_price_fp <= clearing_price_fp => ask_indices.push(i),
+                _ => {}
             }
         }
 
@@ -490,113 +1188,253 @@ pub mod micro_batch_amm {
             temp_orders[j]
                 .limit_price_fp
                 .cmp(&temp_orders[i].limit_price_fp)
+                .then(temp_orders[i].queue_seq.cmp(&temp_orders[j].queue_seq))
         });
         ask_indices.sort_by(|&i, &j| {
             temp_orders[i]
                 .limit_price_fp
                 .cmp(&temp_orders[j].limit_price_fp)
+                .then(temp_orders[i].queue_seq.cmp(&temp_orders[j].queue_seq))
         });
 
-        let mut total_base_traded: u128 = 0;
-        let mut total_quote_traded: u128 = 0;
-
-        let mut bi = 0usize;
-        let mut ai = 0usize;
-
-        while bi < bid_indices.len() && ai < ask_indices.len() {
-            let b_idx = bid_indices[bi];
-            let a_idx = ask_indices[ai];
-
-            // Only match orders that are crossed at clearing_price.
-            let (bid_price, ask_price) = (
-                temp_orders[b_idx].limit_price_fp,
-                temp_orders[a_idx].limit_price_fp,
-            );
-            if bid_price < clearing_price_fp || ask_price > clearing_price_fp {
-                break;
-            }
-
-            if temp_orders[b_idx].remaining_base_fp == 0 {
-                bi += 1;
-                continue;
-            }
-            if temp_orders[a_idx].remaining_base_fp == 0 {
-                ai += 1;
-                continue;
-            }
-
-            // Compute maximum base trade size for this pair.
-            let mut trade_base_fp = temp_orders[b_idx]
-                .remaining_base_fp
-                .min(temp_orders[a_idx].remaining_base_fp);
-
-            if trade_base_fp == 0 {
-                break;
-            }
-
-            // For the bid, ensure we don't exceed quote deposit at clearing price.
-            let bid_quote_deposit = temp_orders[b_idx].quote_deposit_fp;
-            let max_base_affordable = (bid_quote_deposit * (PRICE_SCALE as u128))
-                / (clearing_price_fp as u128).max(1);
-            trade_base_fp = trade_base_fp.min(max_base_affordable);
-            if trade_base_fp == 0 {
-                bi += 1;
-                continue;
-            }
-
-            let quote_gross = (trade_base_fp
-                .checked_mul(clearing_price_fp as u128)
-                .ok_or(AmmError::MathOverflow)?)
-                / PRICE_SCALE as u128;
-
-            if quote_gross == 0 {
-                break;
-            }
-
-            temp_orders[b_idx].remaining_base_fp = temp_orders[b_idx]
-                .remaining_base_fp
-                .checked_sub(trade_base_fp)
-                .ok_or(AmmError::MathOverflow)?;
-            temp_orders[a_idx].remaining_base_fp = temp_orders[a_idx]
-                .remaining_base_fp
-                .checked_sub(trade_base_fp)
-                .ok_or(AmmError::MathOverflow)?;
+        // 4) Allocate the executable volume on each side: better-priced levels fill in
+        //    full and the marginal level shares what is left pro-rata, so every order's
+        //    fill is fixed here and never depends on settlement order.
+        let fillable_volume = |indices: &[usize]| -> u128 {
+            indices
+                .iter()
+                .map(|&i| temp_orders[i].fillable_base_fp(clearing_price_fp, quote_scale_fp))
+                .sum()
+        };
+        let total_base_traded = fillable_volume(&bid_indices)
+            .min(fillable_volume(&ask_indices))
+            .min(u64::MAX as u128);
+        let bid_fills = allocate_side(
+            &temp_orders,
+            &bid_indices,
+            total_base_traded,
+            clearing_price_fp,
+            quote_scale_fp,
+        );
+        let ask_fills = allocate_side(
+            &temp_orders,
+            &ask_indices,
+            total_base_traded,
+            clearing_price_fp,
+            quote_scale_fp,
+        );
 
-            total_base_traded = total_base_traded
-                .checked_add(trade_base_fp)
-                .ok_or(AmmError::MathOverflow)?;
+        // Bids pay for their fills at the clearing price; asks split exactly that quote
+        // pro-rata to their fills, so rounding can never pay out more than came in.
+        let mut total_quote_traded: u128 = 0;
+        for &(i, fill_fp) in bid_fills.iter() {
+            metrics.match_iterations += 1;
+            let quote_fp = market.quote_for_base(fill_fp, clearing_price_fp)?;
+            let o = &mut temp_orders[i];
+            o.remaining_base_fp -= fill_fp;
+            // Fills are capped by what the deposit affords, so this cannot underflow.
+            o.quote_deposit_fp -= quote_fp;
+            o.matched_quote_fp = quote_fp;
             total_quote_traded = total_quote_traded
-                .checked_add(quote_gross)
+                .checked_add(quote_fp)
                 .ok_or(AmmError::MathOverflow)?;
+        }
+        let total_quote_traded = total_quote_traded.min(u64::MAX as u128);
+        for &(i, fill_fp) in ask_fills.iter() {
+            metrics.match_iterations += 1;
+            let o = &mut temp_orders[i];
+            o.remaining_base_fp -= fill_fp;
+            o.matched_quote_fp =
+                pro_rata(total_quote_traded as u64, fill_fp, total_base_traded) as u128;
+        }
 
-            if temp_orders[b_idx].remaining_base_fp == 0 {
-                bi += 1;
+        // Residual imbalance: crossed size left unmatched at the clearing price (bids only
+        // count what their deposit can still pay for).
+        let mut residual_bid_fp: u128 = 0;
+        let mut residual_ask_fp: u128 = 0;
+        for o in temp_orders.iter() {
+            match o.side {
+                OrderSide::Bid if o.limit_price_fp >= clearing_price_fp => {
+                    residual_bid_fp = residual_bid_fp
+                        .saturating_add(o.fillable_base_fp(clearing_price_fp, quote_scale_fp))
+                }
+                OrderSide::Ask if o.limit_price_fp <= clearing_price_fp => {
+                    residual_ask_fp = residual_ask_fp
+                        .saturating_add(o.fillable_base_fp(clearing_price_fp, quote_scale_fp))
+                }
+                _ => {}
             }
-            if temp_orders[a_idx].remaining_base_fp == 0 {
-                ai += 1;
+        }
+        let (residual_side, residual_base_fp) = if residual_bid_fp >= residual_ask_fp {
+            (OrderSide::Bid, residual_bid_fp - residual_ask_fp)
+        } else {
+            (OrderSide::Ask, residual_ask_fp - residual_bid_fp)
+        };
+        let residual_base_fp = residual_base_fp.min(u64::MAX as u128) as u64;
+
+        // Only orders that opted in may be filled externally.
+        let opted_in_fp: u128 = temp_orders
+            .iter()
+            .filter(|o| {
+                o.allow_external_fill
+                    && o.side == residual_side
+                    && match o.side {
+                        OrderSide::Bid => o.limit_price_fp >= clearing_price_fp,
+                        OrderSide::Ask => o.limit_price_fp <= clearing_price_fp,
+                    }
+            })
+            .fold(0u128, |acc, o| {
+                acc.saturating_add(o.fillable_base_fp(clearing_price_fp, quote_scale_fp))
+            });
+        let routable_base_fp = (residual_base_fp as u128).min(opted_in_fp) as u64;
+
+        // Source a firm external quote for the routable residual, if an adapter is wired in.
+        let mut external_quote = FirmQuote::default();
+        if routable_base_fp > 0 {
+            if let (Some(adapter), Some(program)) = (
+                ctx.accounts.liquidity_adapter.as_ref(),
+                ctx.accounts.adapter_program.as_ref(),
+            ) {
+                require_keys_eq!(program.key(), adapter.program, AmmError::InvalidLiquidityAdapter);
+                let adapter_accounts = &remaining[order_accounts_len..];
+                for (ai, expected) in adapter_accounts.iter().zip(adapter.accounts.iter()) {
+                    require_keys_eq!(*ai.key, *expected, AmmError::InvalidLiquidityAdapter);
+                }
+                let source = CpiLiquiditySource {
+                    program: &program.to_account_info(),
+                    accounts: adapter_accounts,
+                };
+                let request = QuoteRequest {
+                    market: market_pk,
+                    residual_side,
+                    amount_base_fp: routable_base_fp,
+                    limit_price_fp: clearing_price_fp,
+                };
+                if let Some(q) = source.firm_quote(&request)? {
+                    external_quote = q;
+                }
             }
         }
 
-        // Keeper reward (accounting only)
-        let keeper_reward_quote_fp: u128 = if keeper_fee_bps > 0 {
+        // Keeper reward, booked against the protocol fee ledger of earlier settlements; any
+        // part that ledger can't cover is booked as a shortfall owed by the insurance fund.
+        // Accounting only: settlement doesn't withhold fees from payouts and the reward isn't
+        // paid from the vault, so no tokens move either way. Until settlements have accrued
+        // fees (e.g. a market's first clear), the whole reward is shortfall.
+        let keeper_reward_bps =
+            keeper_fee_bps as u128 + intent_bonus_bps as u128 - keeper_rebate_bps as u128;
+        let keeper_reward_due_fp: u128 = if keeper_reward_bps > 0 {
             total_quote_traded
-                .checked_mul(keeper_fee_bps as u128)
+                .checked_mul(keeper_reward_bps)
                 .ok_or(AmmError::MathOverflow)?
                 / (BPS_DENOM as u128)
         } else {
             0
         };
+        let keeper_reward_quote_fp = keeper_reward_due_fp.min(market.protocol_fees_accrued_fp);
+        let keeper_reward_shortfall_fp = keeper_reward_due_fp - keeper_reward_quote_fp;
+        market.protocol_fees_accrued_fp -= keeper_reward_quote_fp;
+        market.keeper_reward_shortfall_fp = market
+            .keeper_reward_shortfall_fp
+            .checked_add(keeper_reward_shortfall_fp)
+            .ok_or(AmmError::MathOverflow)?;
+        if keeper_reward_due_fp > 0 {
+            emit!(KeeperRewardBooked {
+                market: market_pk,
+                batch_id: current_batch_id,
+                reward_due_quote_fp: keeper_reward_due_fp,
+                booked_quote_fp: keeper_reward_quote_fp,
+                shortfall_quote_fp: keeper_reward_shortfall_fp,
+                total_shortfall_quote_fp: market.keeper_reward_shortfall_fp,
+            });
+        }
+
+        // Claims table: record each order's net vault-to-user obligation on the order
+        // itself, so settlement only debits what the match produced.
+        //
+        // Each claim is checked against what `settle_order` will enforce first, so a keeper
+        // simulating the clear sees a `FillNotSettleable` event naming the order instead of
+        // a batch that clears and then cannot settle. Staged batches keep the claims on
+        // their `ClearingState` until settlement copies them to the orders.
+        let mut fills = FillTotals::new(
+            total_base_traded,
+            total_quote_traded,
+            ctx.accounts.vault_base.amount,
+            ctx.accounts.vault_quote.amount,
+        );
+        let mut check_fill = |order: Pubkey,
+                              side: OrderSide,
+                              deposit_fp: u64,
+                              claim: &OrderClaim| {
+            fills.record(side, deposit_fp, claim).or_else(|check| {
+                emit!(FillNotSettleable {
+                    market: market_pk,
+                    batch_id: current_batch_id,
+                    order,
+                    check,
+                });
+                err!(AmmError::FillNotSettleable)
+            })
+        };
+        for o in temp_orders.iter().filter(|o| o.account_index != usize::MAX) {
+            let matched_base_fp = o.original_base_fp - o.remaining_base_fp;
+            let (claim_base_fp, claim_quote_fp) = match o.side {
+                OrderSide::Bid => (matched_base_fp as u64, o.quote_deposit_fp as u64),
+                OrderSide::Ask => (o.remaining_base_fp as u64, o.matched_quote_fp as u64),
+            };
+            let claim = OrderClaim {
+                matched_base_fp: matched_base_fp as u64,
+                matched_quote_fp: o.matched_quote_fp as u64,
+                claim_base_fp,
+                claim_quote_fp,
+                fillable_residual_base_fp: o.fillable_base_fp(clearing_price_fp, quote_scale_fp)
+                    as u64,
+            };
+            if let Some(state) = ctx.accounts.clearing_state.as_mut() {
+                let staged = &mut state.entries[o.account_index];
+                check_fill(staged.order, staged.side, staged.deposit_fp(), &claim)?;
+                staged.claim = claim;
+            } else {
+                let order_ai = &remaining[o.account_index];
+                let mut order_acc: Order = load_account(order_ai)?;
+                check_fill(order_ai.key(), order_acc.side, order_acc.deposit_fp(), &claim)?;
+                order_acc.record_claim(&claim);
+                write_account(order_ai, &order_acc)?;
+            }
+        }
+        if let Some(state) = ctx.accounts.clearing_state.as_mut() {
+            state.finalized = true;
+        }
+        list_claims(
+            claimables_pairs,
+            market_pk,
+            current_batch_id,
+            ctx.accounts.clearing_state.as_deref().map(|state| &**state),
+        )?;
+
+        // Launch fills vest on the schedule in force when the offer was made.
+        let vesting_tranches = if launch_offer_base_fp > 0 {
+            market.launch_vesting_batches
+        } else {
+            0
+        };
+
+        // Volume circuit breaker: a spike holds settlement until `confirm_batch`.
+        let volume_spike = market.record_volume(total_quote_traded);
 
         // Final state update + event.
-        let cleared_batch_id = market.current_batch_id;
-        market.last_batch_slot = clock.slot;
-        market.current_batch_id = market
-            .current_batch_id
-            .checked_add(1)
-            .ok_or(AmmError::MathOverflow)?;
-        market.batch_notional_quote_fp = 0;
-        market.global_orders_in_batch = 0;
+        let cleared_batch_id = market.roll_batch(market_pk, clock.slot, &slot_hash)?;
+        if let Some(queue) = ctx.accounts.work_queue.as_mut() {
+            queue.schedule_clear(market_pk, market.current_batch_id, market.next_clear_slot());
+            if total_base_traded > 0 {
+                queue.schedule_settle(market_pk, cleared_batch_id, settlement_open_slot);
+            }
+        }
         market.last_clearing_price_fp = clearing_price_fp;
+        market.record_launch_clear(total_base_traded, total_quote_traded)?;
+        ctx.accounts
+            .price_history
+            .record(cleared_batch_id, clearing_price_fp, clock.slot);
 
         // Update batch_state for settlement phase
         batch_state.market = market_pk;
@@ -608,34 +1446,660 @@ pub mod micro_batch_amm {
         batch_state.cleared_slot = clock.slot;
         batch_state.settled = total_base_traded == 0;
         batch_state.keeper = authority.key();
+        batch_state.keeper_reward_recipient = keeper_reward_recipient;
         batch_state.keeper_reward_quote_fp = keeper_reward_quote_fp;
+        batch_state.keeper_reward_shortfall_fp = keeper_reward_shortfall_fp;
         batch_state.remaining_base_to_settle_fp = total_base_traded;
         batch_state.remaining_quote_to_settle_fp = total_quote_traded;
+        batch_state.bid_volume_at_clear_fp = best_bid_vol;
+        batch_state.ask_volume_at_clear_fp = best_ask_vol;
+        batch_state.residual_side = residual_side;
+        batch_state.residual_base_fp = residual_base_fp;
+        batch_state.routable_base_fp = routable_base_fp;
+        batch_state.external_price_fp = external_quote.price_fp;
+        batch_state.external_base_fp = external_quote.max_base_fp;
+        batch_state.residual_routed = false;
+        batch_state.routed_base_fp = 0;
+        batch_state.routed_quote_fp = 0;
+        batch_state.routed_base_remaining_fp = 0;
+        batch_state.routed_quote_remaining_fp = 0;
+        batch_state.settled_bid_base_fp = 0;
+        batch_state.settled_ask_quote_fp = 0;
+        batch_state.vesting_tranches = vesting_tranches;
+        batch_state.awaiting_confirmation = volume_spike;
+        batch_state.order_count = batch_order_count;
+        batch_state.settled_order_count = 0;
+        batch_state.keeper_tips_quote_fp = keeper_tips_quote_fp;
+        batch_state.clearing_state = staged_clearing_state;
+        batch_state.closing_order_id = closing_order_id;
+        batch_state.launch_offer_base_fp = launch_offer_base_fp;
+        batch_state.keeper_rebate_bps = keeper_rebate_bps;
+        batch_state.price_tick_fp = price_tick_fp;
+        batch_state.keeper_bond_lamports = clear_bond_lamports;
+        batch_state.dispute_deadline_slot = dispute_deadline_slot;
+        batch_state.disputed = false;
+        batch_state.settlement_open_slot = settlement_open_slot;
+
+        // Clustered markets: book the matched volume to the keeper's pool share, paying
+        // out what it earned in the last closed epoch first.
+        if let (Some(cluster), Some(share)) = (
+            ctx.accounts.cluster.as_mut(),
+            ctx.accounts.cluster_keeper_share.as_mut(),
+        ) {
+            let epoch = share.epoch;
+            let lamports = cluster.roll_share(share);
+            if lamports > 0 {
+                cluster.to_account_info().sub_lamports(lamports)?;
+                authority.to_account_info().add_lamports(lamports)?;
+                emit!(ClusterRewardsClaimed {
+                    cluster: cluster.key(),
+                    keeper: share.keeper,
+                    epoch,
+                    lamports,
+                });
+            }
+            share.volume_quote_fp = share
+                .volume_quote_fp
+                .checked_add(total_quote_traded)
+                .ok_or(AmmError::MathOverflow)?;
+            cluster.epoch_volume_quote_fp = cluster
+                .epoch_volume_quote_fp
+                .checked_add(total_quote_traded)
+                .ok_or(AmmError::MathOverflow)?;
+        }
 
-        emit!(BatchCleared {
+        // Per-side fills and the fee each order's settlement will accrue, for accounting.
+        let base_fee_bps = market.effective_protocol_fee_bps(cleared_batch_id);
+        let mut report = BatchReport {
             market: market_pk,
             batch_id: cleared_batch_id,
             clearing_price_fp,
-            total_base_traded_fp: total_base_traded as u64,
-            total_quote_traded_fp: total_quote_traded as u64,
-        });
-
+            bid_filled_base_fp: 0,
+            bid_filled_quote_fp: 0,
+            ask_filled_base_fp: 0,
+            ask_filled_quote_fp: 0,
+            protocol_fee_quote_fp: 0,
+            keeper_reward_quote_fp,
+            residual_side,
+            residual_base_fp,
+        };
+        for o in temp_orders.iter() {
+            let matched_base_fp = o.original_base_fp - o.remaining_base_fp;
+            if matched_base_fp == 0 {
+                continue;
+            }
+            match o.side {
+                OrderSide::Bid => {
+                    report.bid_filled_base_fp += matched_base_fp;
+                    report.bid_filled_quote_fp += o.matched_quote_fp;
+                }
+                OrderSide::Ask => {
+                    report.ask_filled_base_fp += matched_base_fp;
+                    report.ask_filled_quote_fp += o.matched_quote_fp;
+                }
+            }
+            // The synthetic launch ask never settles, so it accrues no fee.
+            if o.account_index != usize::MAX {
+                let fee_bps = batch_state
+                    .imbalance_adjusted_fee_bps(base_fee_bps, o.side, market.imbalance_fee_bps)
+                    .saturating_sub(batch_state.keeper_rebate_bps);
+                report.protocol_fee_quote_fp +=
+                    o.matched_quote_fp * fee_bps as u128 / BPS_DENOM as u128;
+            }
+        }
+
+        emit_logged!(ctx.accounts.event_log, BatchCleared {
+            market: market_pk,
+            batch_id: cleared_batch_id,
+            clearing_price_fp,
+            total_base_traded_fp: total_base_traded as u64,
+            total_quote_traded_fp: total_quote_traded as u64,
+        });
+        emit!(report);
+        emit!(metrics);
+        if volume_spike {
+            emit!(VolumeSpikeDetected {
+                market: market_pk,
+                batch_id: cleared_batch_id,
+                total_quote_traded_fp: total_quote_traded as u64,
+                trailing_average_quote_fp: market.trailing_volume_quote_fp,
+                max_volume_multiple: market.max_volume_multiple,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Start clearing the current batch across several transactions, for batches with
+    /// more orders than fit in one `clear_batch`. Creates the batch's `ClearingState` and
+    /// freezes the batch (no placement or extensions) until it is finalized or closed.
+    ///
+    /// Same pause, keeper and timing gates as `clear_batch`; the signer alone may then
+    /// add chunks and finalize.
+    pub fn begin_clear(ctx: Context<BeginClear>) -> Result<()> {
+        let clock = ctx.accounts.market.clock()?;
+        let market = &mut ctx.accounts.market;
+        let authority = ctx.accounts.authority.key();
+
+        require!(!market.is_paused(PauseModes::CLEARING), AmmError::MarketPaused);
+        require!(market.clearing_state == Pubkey::default(), AmmError::ClearInProgress);
+        if market.has_flag(MarketFlags::KEEPER_RESTRICTED) {
+            require_keys_eq!(market.only_keeper, authority, AmmError::KeeperNotAllowed);
+        }
+        require!(clock.slot >= market.next_clear_slot(), AmmError::BatchNotReady);
+        require!(market.may_clear(), AmmError::BatchBelowParticipation);
+
+        let state = &mut ctx.accounts.clearing_state;
+        state.market = market.key();
+        state.batch_id = market.current_batch_id;
+        state.keeper = authority;
+        state.begun_slot = clock.slot;
+        state.finalized = false;
+        state.claims_taken = 0;
+        state.bump = ctx.bumps.clearing_state;
+        state.entries = Vec::new();
+        market.clearing_state = state.key();
+
+        emit!(ClearBegun {
+            market: state.market,
+            batch_id: state.batch_id,
+            keeper: authority,
+            clearing_state: state.key(),
+        });
+
+        Ok(())
+    }
+
+    /// Stage a chunk of the batch's orders on its `ClearingState` (beginning keeper only).
+    ///
+    /// remaining_accounts = Order accounts. Orders from another market or batch, cancelled
+    /// or already staged are skipped. Conditional orders only activate in `clear_batch`,
+    /// so they are staged inactive: they sit out a chunked clear and are refunded. That
+    /// includes both legs of every OCO pair, so `finalize_clear` has no pair to resolve.
+    pub fn clear_chunk<'info>(ctx: Context<'_, '_, '_, 'info, ClearChunk<'info>>) -> Result<()> {
+        let market_pk = ctx.accounts.market.key();
+        let state = &mut ctx.accounts.clearing_state;
+        require!(!state.finalized, AmmError::ClearingStateFinalized);
+
+        let mut staged_count: u32 = 0;
+        for order_ai in ctx.remaining_accounts.iter() {
+            let order: Order = load_account(order_ai)?;
+            if order.market != market_pk
+                || order.batch_id != state.batch_id
+                || order.amount_base_fp == 0
+                || order.has_flag(OrderFlags::CANCELLED)
+                || state.entries.iter().any(|staged| staged.order == *order_ai.key)
+            {
+                continue;
+            }
+            require!(
+                state.entries.len() < MAX_STAGED_ORDERS,
+                AmmError::ClearingStateFull
+            );
+            state.entries.push(StagedOrder {
+                order: *order_ai.key,
+                side: order.side,
+                limit_price_fp: order.limit_price_fp,
+                amount_base_fp: order.amount_base_fp,
+                quote_deposit_fp: order.quote_deposit_fp,
+                queue_seq: order.queue_seq(),
+                allow_external_fill: order.has_flag(OrderFlags::ALLOW_EXTERNAL_FILL),
+                // OCO legs must never both trade; without the pair resolution `clear_batch`
+                // runs, neither may.
+                active: order.trigger_condition == TriggerCondition::None
+                    && order.oco_link == Pubkey::default(),
+                claim: OrderClaim::default(),
+            });
+            staged_count += 1;
+        }
+
+        emit!(ClearChunkStaged {
+            market: market_pk,
+            batch_id: state.batch_id,
+            staged_count,
+            total_staged: state.entries.len() as u32,
+        });
+
+        Ok(())
+    }
+
+    /// Clear a batch staged with `begin_clear` / `clear_chunk`: `clear_batch` over the
+    /// staged orders, with the same guards, pricing and keeper economics. Claims are
+    /// written to the `ClearingState` instead of the orders; `settle_order` copies each
+    /// order's claim across when passed the stage.
+    ///
+    /// Accounts as `clear_batch` with `clearing_state` required; remaining_accounts only
+    /// carry the liquidity adapter's accounts, if one is used, and the [Order, Claimables]
+    /// pairs.
+    pub fn finalize_clear<'info>(
+        ctx: Context<'_, '_, '_, 'info, ClearBatch<'info>>,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.clearing_state.is_some(),
+            AmmError::ClearingStateRequired
+        );
+        clear_batch(ctx)
+    }
+
+    /// Close a `ClearingState`, returning its rent to the keeper that began it.
+    ///
+    /// An unfinalized stage is abandoned, unfreezing the batch: by its keeper at any time,
+    /// or by anyone once it has been open a full batch duration. A finalized stage closes
+    /// once settlement has taken every staged claim.
+    pub fn close_clearing_state(ctx: Context<CloseClearingState>) -> Result<()> {
+        let clock = ctx.accounts.market.clock()?;
+        let market = &mut ctx.accounts.market;
+        let state = &ctx.accounts.clearing_state;
+
+        let aborted = !state.finalized;
+        if aborted {
+            let stale = clock.slot
+                >= state.begun_slot.saturating_add(market.batch_duration_slots);
+            require!(
+                ctx.accounts.closer.key() == state.keeper || stale,
+                AmmError::ClearingStateNotClosable
+            );
+            if market.clearing_state == state.key() {
+                market.clearing_state = Pubkey::default();
+            }
+        } else {
+            require!(
+                state.claims_taken as usize == state.entries.len(),
+                AmmError::ClearingStateNotClosable
+            );
+        }
+
+        emit!(ClearingStateClosed {
+            market: market.key(),
+            batch_id: state.batch_id,
+            clearing_state: state.key(),
+            aborted,
+        });
+
+        Ok(())
+    }
+
+    /// Re-run price discovery for a cleared batch over its full order set, as recorded in
+    /// the batch's `OrderIndex`, and compare it with the recorded clearing price.
+    /// Permissionless; the verdict is emitted as `AuditResult`.
+    ///
+    /// A mismatch found before `dispute_deadline_slot` is a successful challenge: the
+    /// keeper's clear bond goes to the auditor and the batch is marked `disputed`, which
+    /// stops further settlement.
+    ///
+    /// `remaining_accounts`: the index pages (0..), then every indexed order in index order.
+    pub fn audit_batch<'info>(ctx: Context<'_, '_, '_, 'info, AuditBatch<'info>>) -> Result<()> {
+        let market = &ctx.accounts.market;
+        let batch_state = &mut ctx.accounts.batch_state;
+        let market_pk = market.key();
+        let batch_id = batch_state.batch_id;
+        let remaining = ctx.remaining_accounts;
+
+        let order_count = batch_state.order_count as usize;
+        let page_count = order_count.div_ceil(ORDER_INDEX_PAGE_SIZE);
+        require!(
+            remaining.len() == page_count + order_count,
+            AmmError::InvalidRemainingAccountsLayout
+        );
+        let (page_ais, order_ais) = remaining.split_at(page_count);
+
+        // Same order set `clear_batch` was obliged to consider, so an order the keeper
+        // left out still counts here.
+        let mut temp_orders: Vec<TempOrder> = Vec::new();
+        let mut candidate_prices: Vec<u64> = Vec::new();
+        let mut indexed = 0usize;
+        for (page_no, page_ai) in page_ais.iter().enumerate() {
+            let page: OrderIndex = load_account(page_ai)?;
+            require!(
+                page.market == market_pk
+                    && page.batch_id == batch_id
+                    && page.page as usize == page_no,
+                AmmError::InvalidOrderIndex
+            );
+            for order_key in page.orders.iter() {
+                let order_ai = order_ais
+                    .get(indexed)
+                    .ok_or(AmmError::InvalidOrderIndex)?;
+                require_keys_eq!(*order_ai.key, *order_key, AmmError::InvalidOrderIndex);
+                indexed += 1;
+
+                let order: Order = load_account(order_ai)?;
+                require!(
+                    order.id < batch_state.closing_order_id,
+                    AmmError::InvalidOrderIndex
+                );
+                if order.market != market_pk
+                    || order.batch_id != batch_id
+                    || order.amount_base_fp == 0
+                    || order.has_flag(OrderFlags::CANCELLED)
+                    || (order.trigger_condition != TriggerCondition::None
+                        && !order.has_flag(OrderFlags::TRIGGERED))
+                {
+                    continue;
+                }
+                temp_orders.push(TempOrder {
+                    account_index: usize::MAX,
+                    side: order.side,
+                    limit_price_fp: order.limit_price_fp,
+                    original_base_fp: order.amount_base_fp as u128,
+                    remaining_base_fp: order.amount_base_fp as u128,
+                    matched_quote_fp: 0,
+                    quote_deposit_fp: order.quote_deposit_fp as u128,
+                    queue_seq: order.queue_seq(),
+                    allow_external_fill: false,
+                });
+                if !candidate_prices.contains(&order.limit_price_fp) {
+                    candidate_prices.push(order.limit_price_fp);
+                }
+            }
+        }
+        require!(indexed == order_count, AmmError::InvalidOrderIndex);
+
+        if batch_state.launch_offer_base_fp > 0 {
+            temp_orders.push(TempOrder {
+                account_index: usize::MAX,
+                side: OrderSide::Ask,
+                limit_price_fp: market.launch_reserve_price_fp,
+                original_base_fp: batch_state.launch_offer_base_fp as u128,
+                remaining_base_fp: batch_state.launch_offer_base_fp as u128,
+                matched_quote_fp: 0,
+                quote_deposit_fp: 0,
+                queue_seq: 0,
+                allow_external_fill: false,
+            });
+            if !candidate_prices.contains(&market.launch_reserve_price_fp) {
+                candidate_prices.push(market.launch_reserve_price_fp);
+            }
+        }
+        if batch_state.price_tick_fp > 0 {
+            bucket_to_tick(&mut candidate_prices, batch_state.price_tick_fp);
+        }
+
+        let (recomputed_price_fp, recomputed_base_fp, _, _) =
+            discover_clearing_price(&temp_orders, &candidate_prices)?;
+        let consistent = recomputed_price_fp == batch_state.clearing_price_fp;
+
+        emit!(AuditResult {
+            market: market_pk,
+            batch_id,
+            auditor: ctx.accounts.auditor.key(),
+            recorded_price_fp: batch_state.clearing_price_fp,
+            recomputed_price_fp,
+            recomputed_base_fp: recomputed_base_fp as u64,
+            order_count: batch_state.order_count,
+            consistent,
+        });
+
+        // A dispute must land before settlement opens: once an order may have been paid,
+        // the batch could no longer be voided and its other deposits would be stranded.
+        let slot = market.clock()?.slot;
+        if !consistent
+            && !batch_state.disputed
+            && slot <= batch_state.dispute_deadline_slot
+            && slot < batch_state.settlement_open_slot
+        {
+            let bond_lamports = batch_state.keeper_bond_lamports;
+            if bond_lamports > 0 {
+                batch_state.to_account_info().sub_lamports(bond_lamports)?;
+                ctx.accounts.auditor.to_account_info().add_lamports(bond_lamports)?;
+            }
+            batch_state.keeper_bond_lamports = 0;
+            batch_state.disputed = true;
+
+            emit!(KeeperSlashed {
+                market: market_pk,
+                batch_id,
+                keeper: batch_state.keeper,
+                challenger: ctx.accounts.auditor.key(),
+                bond_lamports,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Keep a due batch that hasn't met the market's participation threshold open for
+    /// another batch duration instead of clearing it thin. Permissionless. Once the batch
+    /// has been extended `max_batch_extensions` times it must clear instead.
+    pub fn extend_batch(ctx: Context<ExtendBatch>) -> Result<()> {
+        let clock = ctx.accounts.market.clock()?;
+        let market = &mut ctx.accounts.market;
+        require!(!market.is_paused(PauseModes::CLEARING), AmmError::MarketPaused);
+        require!(market.clearing_state == Pubkey::default(), AmmError::ClearInProgress);
+        require!(clock.slot >= market.next_clear_slot(), AmmError::BatchNotReady);
+        require!(!market.may_clear(), AmmError::BatchMeetsParticipation);
+        require!(
+            market.work_queue == Pubkey::default() || ctx.accounts.work_queue.is_some(),
+            AmmError::WorkQueueRequired
+        );
+
+        market.last_batch_slot = clock.slot;
+        market.batch_extensions = market
+            .batch_extensions
+            .checked_add(1)
+            .ok_or(AmmError::MathOverflow)?;
+        if let Some(queue) = ctx.accounts.work_queue.as_mut() {
+            queue.schedule_clear(market.key(), market.current_batch_id, market.next_clear_slot());
+        }
+
+        emit!(BatchExtended {
+            market: market.key(),
+            batch_id: market.current_batch_id,
+            extensions: market.batch_extensions,
+            orders: market.global_orders_in_batch,
+            notional_quote_fp: market.batch_notional_quote_fp,
+            scheduled_close_slot: market.next_clear_slot(),
+        });
+
+        Ok(())
+    }
+
+    /// Return the clearing keeper's bond once the dispute window has passed without a
+    /// successful challenge. Permissionless.
+    pub fn release_clear_bond(ctx: Context<ReleaseClearBond>) -> Result<()> {
+        let batch_state = &mut ctx.accounts.batch_state;
+        require!(
+            ctx.accounts.market.clock()?.slot > batch_state.dispute_deadline_slot,
+            AmmError::DisputeWindowActive
+        );
+
+        let bond_lamports = batch_state.keeper_bond_lamports;
+        require!(bond_lamports > 0, AmmError::InvalidAmount);
+        batch_state.to_account_info().sub_lamports(bond_lamports)?;
+        ctx.accounts.keeper.to_account_info().add_lamports(bond_lamports)?;
+        batch_state.keeper_bond_lamports = 0;
+
+        emit!(ClearBondReleased {
+            market: batch_state.market,
+            batch_id: batch_state.batch_id,
+            keeper: batch_state.keeper,
+            bond_lamports,
+        });
+
+        Ok(())
+    }
+
+    /// Tip `amount_quote_fp` of quote to the keeper that clears `order`'s batch, paid from
+    /// the signer's wallet. Anyone may tip an open order, so urgent flow can crowd-fund a
+    /// prompt clear; the tip is non-refundable, even if the order is later cancelled.
+    pub fn tip_order<'info>(
+        ctx: Context<'_, '_, '_, 'info, TipOrder<'info>>,
+        amount_quote_fp: u64,
+    ) -> Result<()> {
+        require!(amount_quote_fp > 0, AmmError::InvalidAmount);
+        let market = &mut ctx.accounts.market;
+        let order = &mut ctx.accounts.order;
+        require!(
+            order.batch_id == market.current_batch_id,
+            AmmError::BatchAlreadyClosed
+        );
+        require!(!order.has_flag(OrderFlags::CANCELLED), AmmError::OrderCancelled);
+
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.tipper_quote_ata.to_account_info(),
+            mint: ctx.accounts.quote_mint.to_account_info(),
+            to: ctx.accounts.vault_quote.to_account_info(),
+            authority: ctx.accounts.tipper.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts)
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec());
+        transfer_checked_with_hook(
+            cpi_ctx,
+            &market.transfer_hook_programs,
+            amount_quote_fp,
+            ctx.accounts.quote_mint.decimals,
+        )?;
+
+        order.keeper_tip_fp = order
+            .keeper_tip_fp
+            .checked_add(amount_quote_fp)
+            .ok_or(AmmError::MathOverflow)?;
+        market.batch_keeper_tips_fp = market
+            .batch_keeper_tips_fp
+            .checked_add(amount_quote_fp)
+            .ok_or(AmmError::MathOverflow)?;
+
+        emit!(OrderTipped {
+            market: market.key(),
+            order: order.key(),
+            batch_id: order.batch_id,
+            tipper: ctx.accounts.tipper.key(),
+            amount_quote_fp,
+            order_tip_fp: order.keeper_tip_fp,
+            batch_tips_fp: market.batch_keeper_tips_fp,
+        });
+
+        Ok(())
+    }
+
+    /// Pay a cleared batch's order tips to its keeper's reward recipient once the dispute
+    /// window has passed. Permissionless. Tips of a disputed batch go to the protocol
+    /// fees instead.
+    pub fn claim_keeper_tips<'info>(
+        ctx: Context<'_, '_, '_, 'info, ClaimKeeperTips<'info>>,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        let batch_state = &mut ctx.accounts.batch_state;
+        require!(
+            market.clock()?.slot > batch_state.dispute_deadline_slot,
+            AmmError::DisputeWindowActive
+        );
+        let tips_quote_fp = batch_state.keeper_tips_quote_fp;
+        require!(tips_quote_fp > 0, AmmError::InvalidAmount);
+        batch_state.keeper_tips_quote_fp = 0;
+
+        if batch_state.disputed {
+            market.protocol_fees_accrued_fp = market
+                .protocol_fees_accrued_fp
+                .checked_add(tips_quote_fp as u128)
+                .ok_or(AmmError::MathOverflow)?;
+        } else {
+            let authority_key = market.authority;
+            let base_mint_key = market.base_mint;
+            let quote_mint_key = market.quote_mint;
+            let bump = market.bump;
+            let market_seeds: &[&[u8]] = &[
+                MARKET_SEED,
+                authority_key.as_ref(),
+                base_mint_key.as_ref(),
+                quote_mint_key.as_ref(),
+                &[bump],
+            ];
+            let signer_seeds: &[&[&[u8]]] = &[market_seeds];
+
+            let cpi_accounts = TransferChecked {
+                from: ctx.accounts.vault_quote.to_account_info(),
+                mint: ctx.accounts.quote_mint.to_account_info(),
+                to: ctx.accounts.recipient_quote_ata.to_account_info(),
+                authority: market.to_account_info(),
+            };
+            let cpi_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                cpi_accounts,
+                signer_seeds,
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec());
+            transfer_checked_with_hook(
+                cpi_ctx,
+                &market.transfer_hook_programs,
+                tips_quote_fp,
+                ctx.accounts.quote_mint.decimals,
+            )?;
+        }
+
+        emit!(KeeperTipsClaimed {
+            market: market.key(),
+            batch_id: batch_state.batch_id,
+            recipient: batch_state.keeper_reward_recipient,
+            tips_quote_fp,
+            forfeited: batch_state.disputed,
+        });
+
+        Ok(())
+    }
+
+    /// Confirm a batch the volume circuit breaker held, opening it for settlement.
+    /// Only the market authority or the keeper that cleared the batch may confirm.
+    pub fn confirm_batch(ctx: Context<ConfirmBatch>) -> Result<()> {
+        let batch_state = &mut ctx.accounts.batch_state;
+        let signer = ctx.accounts.authority.key();
+        require!(
+            signer == ctx.accounts.market.authority || signer == batch_state.keeper,
+            AmmError::Unauthorized
+        );
+        require!(batch_state.awaiting_confirmation, AmmError::BatchNotAwaitingConfirmation);
+
+        batch_state.awaiting_confirmation = false;
+
+        emit!(BatchConfirmed {
+            market: batch_state.market,
+            batch_id: batch_state.batch_id,
+            confirmed_by: signer,
+        });
+
         Ok(())
     }
 
     /// Settle a single order after a batch has been cleared.
     ///
-    /// This handles:
-    /// - base/quote payouts
-    /// - unused quote/base refunds
-    /// - per-order fill record
-    pub fn settle_order(ctx: Context<SettleOrder>) -> Result<()> {
+    /// Pays out the claim `clear_batch` recorded on the order (fill plus unused deposit)
+    /// in at most one transfer per token, adds any externally routed residual share, and
+    /// writes the per-order fill record.
+    ///
+    /// `settle_nonce` is the order's `settle_nonce` as read by the caller: a retried or
+    /// raced transaction built against an order that has since been settled fails with
+    /// `StaleSettleNonce` before anything else runs.
+    pub fn settle_order<'info>(
+        ctx: Context<'_, '_, '_, 'info, SettleOrder<'info>>,
+        settle_nonce: u32,
+    ) -> Result<()> {
         let market = &mut ctx.accounts.market;
         let batch_state = &mut ctx.accounts.batch_state;
         let order = &mut ctx.accounts.order;
         let order_fill = &mut ctx.accounts.order_fill;
 
-        require!(!market.paused, AmmError::MarketPaused);
+        require_eq!(settle_nonce, order.settle_nonce, AmmError::StaleSettleNonce);
+
+        require!(
+            !(market.is_paused(PauseModes::SETTLEMENT)
+                && market.pause_reason == PAUSE_REASON_CONSERVATION),
+            AmmError::ConservationViolation
+        );
+        require!(!market.is_paused(PauseModes::SETTLEMENT), AmmError::MarketPaused);
+        require!(
+            !batch_state.awaiting_confirmation,
+            AmmError::BatchAwaitingConfirmation
+        );
+        require!(!batch_state.disputed, AmmError::BatchDisputed);
+        require!(
+            market.clock()?.slot >= batch_state.settlement_open_slot,
+            AmmError::SettlementNotOpen
+        );
+        // Swept quote must be back in the vault before any payouts.
+        require!(
+            market.yield_deployed_quote_fp == 0,
+            AmmError::YieldNotRecalled
+        );
         require!(
             batch_state.market == market.key(),
             AmmError::BatchMarketMismatch
@@ -648,19 +2112,13 @@ pub mod micro_batch_amm {
             batch_state.clearing_price_fp > 0,
             AmmError::BatchNotCleared
         );
-        require!(!order.cancelled, AmmError::OrderCancelled);
+        require!(!order.has_flag(OrderFlags::CANCELLED), AmmError::OrderCancelled);
         require!(!order_fill.claimed, AmmError::OrderAlreadySettled);
+        require!(!order.is_frozen(market.clock()?.slot), AmmError::OrderFrozen);
 
-        let price_fp = batch_state.clearing_price_fp as u128;
         let amount_base_fp_u128 = order.amount_base_fp as u128;
         let quote_deposit_fp_u128 = order.quote_deposit_fp as u128;
 
-        // Check if order is crossed at clearing price
-        let crossed = match order.side {
-            OrderSide::Bid => order.limit_price_fp as u128 >= price_fp,
-            OrderSide::Ask => order.limit_price_fp as u128 <= price_fp,
-        };
-
         // Take local copies for seeds to avoid borrowing market immutably for the whole scope.
         let authority_key = market.authority;
         let base_mint_key = market.base_mint;
@@ -669,7 +2127,7 @@ pub mod micro_batch_amm {
 
         // Helper seeds so vault PDAs can sign transfers
         let market_seeds: &[&[u8]] = &[
-            b"market",
+            MARKET_SEED,
             authority_key.as_ref(),
             base_mint_key.as_ref(),
             quote_mint_key.as_ref(),
@@ -677,62 +2135,152 @@ pub mod micro_batch_amm {
         ];
         let signer_seeds: &[&[&[u8]]] = &[market_seeds];
 
-        // Compute fill & refunds
+        // Settlement debits the claim `clear_batch` recorded on the order. An order the
+        // keeper did not pass to the clear took no part in the match and is refunded in full.
+        take_staged_claim(order, batch_state, ctx.accounts.clearing_state.as_mut())?;
         let mut filled_base_fp: u128 = 0;
         let mut filled_quote_fp: u128 = 0;
         let mut refund_base_fp: u128 = 0;
         let mut refund_quote_fp: u128 = 0;
+        let mut routed_share: (u128, u128) = (0, 0);
 
-        if crossed {
-            // All-or-nothing settlement, constrained by remaining batch volume.
-            require!(
-                amount_base_fp_u128 <= batch_state.remaining_base_to_settle_fp,
-                AmmError::BatchFullySettled
-            );
-
-            let gross_quote = amount_base_fp_u128
-                .checked_mul(price_fp)
-                .ok_or(AmmError::MathOverflow)?
-                / (PRICE_SCALE as u128);
+        if order.has_flag(OrderFlags::CLAIM_RECORDED) {
+            filled_base_fp = order.matched_base_fp as u128;
+            filled_quote_fp = order.matched_quote_fp as u128;
+            match order.side {
+                OrderSide::Bid => refund_quote_fp = order.claim_quote_fp as u128,
+                OrderSide::Ask => refund_base_fp = order.claim_base_fp as u128,
+            }
 
-            require!(
-                gross_quote <= quote_deposit_fp_u128 || matches!(order.side, OrderSide::Ask),
-                AmmError::MathOverflow
-            );
+            // Opted-in orders on the residual side take their pro-rata share of whatever
+            // `route_residual` filled externally, out of their unmatched size.
+            let routable_base_fp = batch_state.routable_base_fp as u128;
+            if order.has_flag(OrderFlags::ALLOW_EXTERNAL_FILL)
+                && order.side == batch_state.residual_side
+                && batch_state.residual_routed
+                && routable_base_fp > 0
+            {
+                let unmatched_base_fp = order.fillable_residual_base_fp as u128;
+                let share_base = (unmatched_base_fp
+                    .checked_mul(batch_state.routed_base_fp as u128)
+                    .ok_or(AmmError::MathOverflow)?
+                    / routable_base_fp)
+                    .min(unmatched_base_fp)
+                    .min(batch_state.routed_base_remaining_fp as u128);
+                let share_quote = (unmatched_base_fp
+                    .checked_mul(batch_state.routed_quote_fp as u128)
+                    .ok_or(AmmError::MathOverflow)?
+                    / routable_base_fp)
+                    .min(batch_state.routed_quote_remaining_fp as u128);
 
-            match order.side {
-                OrderSide::Bid => {
-                    filled_base_fp = amount_base_fp_u128;
-                    filled_quote_fp = gross_quote;
-                    refund_base_fp = 0;
-                    refund_quote_fp = quote_deposit_fp_u128
-                        .checked_sub(gross_quote)
-                        .ok_or(AmmError::MathOverflow)?;
-                }
-                OrderSide::Ask => {
-                    filled_base_fp = amount_base_fp_u128;
-                    filled_quote_fp = gross_quote;
-                    refund_base_fp = 0; // full fill
-                    refund_quote_fp = 0;
+                match order.side {
+                    OrderSide::Bid => {
+                        refund_quote_fp = refund_quote_fp
+                            .checked_sub(share_quote)
+                            .ok_or(AmmError::MathOverflow)?;
+                    }
+                    OrderSide::Ask => {
+                        refund_base_fp = refund_base_fp
+                            .checked_sub(share_base)
+                            .ok_or(AmmError::MathOverflow)?;
+                    }
                 }
+                filled_base_fp += share_base;
+                filled_quote_fp += share_quote;
+                routed_share = (share_base, share_quote);
+            }
+        } else {
+            match order.side {
+                OrderSide::Bid => refund_quote_fp = quote_deposit_fp_u128,
+                OrderSide::Ask => refund_base_fp = amount_base_fp_u128,
             }
+        }
 
-            // Update batch remaining volumes
-            batch_state.remaining_base_to_settle_fp = batch_state
-                .remaining_base_to_settle_fp
-                .checked_sub(filled_base_fp)
-                .ok_or(AmmError::MathOverflow)?;
-            batch_state.remaining_quote_to_settle_fp = batch_state
-                .remaining_quote_to_settle_fp
-                .checked_sub(filled_quote_fp)
-                .ok_or(AmmError::MathOverflow)?;
+        // Conservation safety net, checked before any batch accounting moves. A violation
+        // means the books have drifted: pause the market for the admin instead of paying
+        // out. This returns Ok, since an error would roll the pause back; further
+        // settlements fail with `ConservationViolation` until the market is unpaused.
+        let refund_fp = match order.side {
+            OrderSide::Bid => refund_quote_fp,
+            OrderSide::Ask => refund_base_fp,
+        };
+        let deposit_fp = match order.side {
+            OrderSide::Bid => quote_deposit_fp_u128,
+            OrderSide::Ask => amount_base_fp_u128,
+        };
+        if refund_fp > deposit_fp
+            || !batch_state.settlement_conserves(
+                order.side,
+                order.matched_base_fp as u128,
+                order.matched_quote_fp as u128,
+                filled_base_fp,
+                filled_quote_fp,
+            )
+        {
+            market.pause_modes = PauseModes::ALL;
+            market.pause_reason = PAUSE_REASON_CONSERVATION;
+            emit!(ConservationViolated {
+                market: market.key(),
+                batch_id: batch_state.batch_id,
+                order: order.key(),
+                filled_base_fp: filled_base_fp as u64,
+                filled_quote_fp: filled_quote_fp as u64,
+                refund_fp: refund_fp as u64,
+            });
+            return Ok(());
+        }
+
+        batch_state.routed_base_remaining_fp -= routed_share.0 as u64;
+        batch_state.routed_quote_remaining_fp -= routed_share.1 as u64;
+        // Matched volume leaves the batch totals: base owed to bids, quote owed to asks
+        // (the check above keeps these non-negative).
+        match order.side {
+            OrderSide::Bid => {
+                batch_state.remaining_base_to_settle_fp -= order.matched_base_fp as u128;
+                batch_state.settled_bid_base_fp += filled_base_fp as u64;
+            }
+            OrderSide::Ask => {
+                batch_state.remaining_quote_to_settle_fp -= order.matched_quote_fp as u128;
+                batch_state.settled_ask_quote_fp += filled_quote_fp as u64;
+            }
+        }
+        if batch_state.remaining_base_to_settle_fp == 0
+            && batch_state.remaining_quote_to_settle_fp == 0
+        {
+            batch_state.settled = true;
+        }
+
+        if order.has_flag(OrderFlags::REQUIRES_COSIGN)
+            && !order.has_flag(OrderFlags::COSIGN_APPROVED)
+        {
+            require!(
+                filled_quote_fp <= ctx.accounts.user_stats.cosign_threshold_quote_fp as u128,
+                AmmError::SettlementApprovalRequired
+            );
+        }
 
-            if batch_state.remaining_base_to_settle_fp == 0 {
-                batch_state.settled = true;
+        // Large fills must reference the owner's travel-rule payload.
+        let travel_rule_hash = match ctx.accounts.travel_rule.as_ref() {
+            Some(record) => record.payload_hash,
+            None => {
+                require!(
+                    market.travel_rule_threshold_quote_fp == 0
+                        || filled_quote_fp <= market.travel_rule_threshold_quote_fp as u128,
+                    AmmError::TravelRuleRecordRequired
+                );
+                [0u8; 32]
             }
+        };
 
-            // Fee accounting (protocol only, referral bucket rolled into same for now)
-            let protocol_fee_bps = market.protocol_fee_bps as u128;
+        // Fee accounting (protocol only, referral bucket rolled into same for now)
+        if filled_quote_fp > 0 {
+            let protocol_fee_bps = batch_state
+                .imbalance_adjusted_fee_bps(
+                    market.effective_protocol_fee_bps(order.batch_id),
+                    order.side,
+                    market.imbalance_fee_bps,
+                )
+                .saturating_sub(batch_state.keeper_rebate_bps) as u128;
             if protocol_fee_bps > 0 {
                 let protocol_fee = filled_quote_fp
                     .checked_mul(protocol_fee_bps)
@@ -743,123 +2291,109 @@ pub mod micro_batch_amm {
                     .checked_add(protocol_fee)
                     .ok_or(AmmError::MathOverflow)?;
             }
+        }
 
-            // Transfers
-            let token_program_ai = ctx.accounts.token_program.to_account_info();
+        // Net vault -> user transfers: one per token at most.
+        let (mut payout_base_fp, payout_quote_fp) = match order.side {
+            OrderSide::Bid => (filled_base_fp, refund_quote_fp),
+            OrderSide::Ask => (refund_base_fp, filled_quote_fp),
+        };
 
-            match order.side {
-                OrderSide::Bid => {
-                    // BASE: vault_base -> user_base_ata
-                    let cpi_accounts_base = Transfer {
-                        from: ctx.accounts.vault_base.to_account_info(),
-                        to: ctx.accounts.user_base_ata.to_account_info(),
-                        authority: market.to_account_info(),
-                    };
-                    let cpi_ctx_base = CpiContext::new_with_signer(
-                        token_program_ai.clone(),
-                        cpi_accounts_base,
-                        signer_seeds,
-                    );
-                    token::transfer(cpi_ctx_base, filled_base_fp as u64)?;
-
-                    // QUOTE refund: vault_quote -> user_quote_ata
-                    if refund_quote_fp > 0 {
-                        let cpi_accounts_quote = Transfer {
-                            from: ctx.accounts.vault_quote.to_account_info(),
-                            to: ctx.accounts.user_quote_ata.to_account_info(),
-                            authority: market.to_account_info(),
-                        };
-                        let cpi_ctx_quote = CpiContext::new_with_signer(
-                            token_program_ai.clone(),
-                            cpi_accounts_quote,
-                            signer_seeds,
-                        );
-                        token::transfer(cpi_ctx_quote, refund_quote_fp as u64)?;
-                    }
-                }
-                OrderSide::Ask => {
-                    // QUOTE: vault_quote -> user_quote_ata
-                    let cpi_accounts_quote = Transfer {
-                        from: ctx.accounts.vault_quote.to_account_info(),
-                        to: ctx.accounts.user_quote_ata.to_account_info(),
-                        authority: market.to_account_info(),
-                    };
-                    let cpi_ctx_quote = CpiContext::new_with_signer(
-                        token_program_ai.clone(),
-                        cpi_accounts_quote,
-                        signer_seeds,
-                    );
-                    token::transfer(cpi_ctx_quote, filled_quote_fp as u64)?;
-
-                    // BASE refund (if any): vault_base -> user_base_ata
-                    if refund_base_fp > 0 {
-                        let cpi_accounts_base = Transfer {
-                            from: ctx.accounts.vault_base.to_account_info(),
-                            to: ctx.accounts.user_base_ata.to_account_info(),
-                            authority: market.to_account_info(),
-                        };
-                        let cpi_ctx_base = CpiContext::new_with_signer(
-                            token_program_ai,
-                            cpi_accounts_base,
-                            signer_seeds,
-                        );
-                        token::transfer(cpi_ctx_base, refund_base_fp as u64)?;
-                    }
-                }
-            }
-        } else {
-            // Not crossed: pure refund.
-            match order.side {
-                OrderSide::Bid => {
-                    refund_quote_fp = quote_deposit_fp_u128;
-                    refund_base_fp = 0;
-                }
-                OrderSide::Ask => {
-                    refund_base_fp = amount_base_fp_u128;
-                    refund_quote_fp = 0;
-                }
-            }
-
-            let token_program_ai = ctx.accounts.token_program.to_account_info();
+        // Vested launch fills stay in the vault; `claim_tranche` releases them per batch.
+        if order.side == OrderSide::Bid
+            && batch_state.vesting_tranches > 0
+            && payout_base_fp > 0
+        {
+            let vesting = ctx
+                .accounts
+                .vesting
+                .as_mut()
+                .ok_or(AmmError::VestingAccountRequired)?;
+            vesting.market = market.key();
+            vesting.order = order.key();
+            vesting.user = order.user;
+            vesting.batch_id = batch_state.batch_id;
+            vesting.tranches = batch_state.vesting_tranches;
+            vesting.total_base_fp = payout_base_fp as u64;
+            vesting.claimed_base_fp = 0;
+            vesting.bump = ctx.bumps.vesting.ok_or(AmmError::VestingAccountRequired)?;
+
+            emit!(VestingScheduled {
+                market: vesting.market,
+                order: vesting.order,
+                user: vesting.user,
+                batch_id: vesting.batch_id,
+                total_base_fp: vesting.total_base_fp,
+                tranches: vesting.tranches,
+            });
+            payout_base_fp = 0;
+        }
+        let token_program_ai = ctx.accounts.token_program.to_account_info();
 
-            match order.side {
-                OrderSide::Bid => {
-                    // Quote refund only
-                    if refund_quote_fp > 0 {
-                        let cpi_accounts_quote = Transfer {
-                            from: ctx.accounts.vault_quote.to_account_info(),
-                            to: ctx.accounts.user_quote_ata.to_account_info(),
-                            authority: market.to_account_info(),
-                        };
-                        let cpi_ctx_quote = CpiContext::new_with_signer(
-                            token_program_ai,
-                            cpi_accounts_quote,
-                            signer_seeds,
-                        );
-                        token::transfer(cpi_ctx_quote, refund_quote_fp as u64)?;
-                    }
-                }
-                OrderSide::Ask => {
-                    // Base refund only
-                    if refund_base_fp > 0 {
-                        let cpi_accounts_base = Transfer {
-                            from: ctx.accounts.vault_base.to_account_info(),
-                            to: ctx.accounts.user_base_ata.to_account_info(),
-                            authority: market.to_account_info(),
-                        };
-                        let cpi_ctx_base = CpiContext::new_with_signer(
-                            token_program_ai,
-                            cpi_accounts_base,
-                            signer_seeds,
-                        );
-                        token::transfer(cpi_ctx_base, refund_base_fp as u64)?;
-                    }
-                }
-            }
+        if payout_base_fp > 0 {
+            let cpi_accounts_base = TransferChecked {
+                from: ctx.accounts.vault_base.to_account_info(),
+                mint: ctx.accounts.base_mint.to_account_info(),
+                to: ctx.accounts.user_base_ata.to_account_info(),
+                authority: market.to_account_info(),
+            };
+            let cpi_ctx_base = CpiContext::new_with_signer(
+                token_program_ai.clone(),
+                cpi_accounts_base,
+                signer_seeds,
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec());
+            transfer_checked_with_hook(
+                cpi_ctx_base,
+                &market.transfer_hook_programs,
+                payout_base_fp as u64,
+                ctx.accounts.base_mint.decimals,
+            )?;
+        }
+        if payout_quote_fp > 0 {
+            let cpi_accounts_quote = TransferChecked {
+                from: ctx.accounts.vault_quote.to_account_info(),
+                mint: ctx.accounts.quote_mint.to_account_info(),
+                to: ctx.accounts.user_quote_ata.to_account_info(),
+                authority: market.to_account_info(),
+            };
+            let cpi_ctx_quote = CpiContext::new_with_signer(
+                token_program_ai,
+                cpi_accounts_quote,
+                signer_seeds,
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec());
+            transfer_checked_with_hook(
+                cpi_ctx_quote,
+                &market.transfer_hook_programs,
+                payout_quote_fp as u64,
+                ctx.accounts.quote_mint.decimals,
+            )?;
         }
+        order.claim_base_fp = 0;
+        order.claim_quote_fp = 0;
 
         // Mark order + fill
-        order.filled = true;
+        order.set_flag(OrderFlags::FILLED, true);
+        order.settle_nonce = order
+            .settle_nonce
+            .checked_add(1)
+            .ok_or(AmmError::MathOverflow)?;
+        batch_state.settled_order_count = batch_state
+            .settled_order_count
+            .checked_add(1)
+            .ok_or(AmmError::MathOverflow)?;
+        if let Some(claimables) = ctx.accounts.claimables.as_mut() {
+            claimables.remove(&order.key());
+        }
+        ctx.accounts
+            .user_stats
+            .release_open_order(&mut ctx.accounts.user_batch_stats);
+        if filled_base_fp > 0 {
+            ctx.accounts
+                .user_stats
+                .record_fill(market.clock()?.epoch, filled_quote_fp);
+        }
 
         order_fill.order = order.key();
         order_fill.batch_id = batch_state.batch_id;
@@ -868,8 +2402,20 @@ pub mod micro_batch_amm {
         order_fill.refund_quote_fp = refund_quote_fp as u64;
         order_fill.refund_base_fp = refund_base_fp as u64;
         order_fill.claimed = true;
+        order_fill.travel_rule_hash = travel_rule_hash;
+
+        if let Some(sub) = ctx.accounts.sub_account.as_mut() {
+            sub.filled_base_fp = sub
+                .filled_base_fp
+                .checked_add(order_fill.filled_base_fp)
+                .ok_or(AmmError::MathOverflow)?;
+            sub.filled_quote_fp = sub
+                .filled_quote_fp
+                .checked_add(order_fill.filled_quote_fp)
+                .ok_or(AmmError::MathOverflow)?;
+        }
 
-        emit!(OrderSettled {
+        emit_logged!(ctx.accounts.event_log, OrderSettled {
             market: market.key(),
             order: order.key(),
             user: order.user,
@@ -880,6 +2426,7 @@ pub mod micro_batch_amm {
             filled_quote_fp: order_fill.filled_quote_fp,
             refund_base_fp: order_fill.refund_base_fp,
             refund_quote_fp: order_fill.refund_quote_fp,
+            memo: order.memo,
         });
 
         Ok(())
@@ -887,23 +2434,37 @@ pub mod micro_batch_amm {
 
     /// Cancel an open order before the batch is cleared.
     ///
-    /// - Refunds full deposit (base or quote)
+    /// - Refunds the deposit (base or quote), less the late-cancel fee inside the final window
     /// - Marks order as cancelled so clear_batch / settle_order ignore it.
-    pub fn cancel_order(ctx: Context<CancelOrder>) -> Result<()> {
-        let clock = Clock::get()?;
+    pub fn cancel_order<'info>(ctx: Context<'_, '_, '_, 'info, CancelOrder<'info>>) -> Result<()> {
+        let clock = ctx.accounts.market.clock()?;
         let market = &mut ctx.accounts.market;
         let order = &mut ctx.accounts.order;
 
-        require!(!market.paused, AmmError::MarketPaused);
-        require!(!order.cancelled, AmmError::OrderCancelled);
-        require!(!order.filled, AmmError::OrderAlreadySettled);
+        require!(!market.is_paused(PauseModes::SETTLEMENT), AmmError::MarketPaused);
+        require!(!order.has_flag(OrderFlags::CANCELLED), AmmError::OrderCancelled);
+        require!(!order.has_flag(OrderFlags::FILLED), AmmError::OrderAlreadySettled);
+        require!(!order.is_frozen(clock.slot), AmmError::OrderFrozen);
 
-        // Batch must still be open
+        // Batch must still be open, and be the order's own: an order cleared in an earlier
+        // batch owes its deposit to that batch's settlement.
+        let close_slot = market.last_batch_slot + market.batch_duration_slots;
+        require!(clock.slot < close_slot, AmmError::BatchAlreadyClosed);
         require!(
-            clock.slot < market.last_batch_slot + market.batch_duration_slots,
+            order.batch_id == market.current_batch_id
+                && !order.has_flag(OrderFlags::CLAIM_RECORDED),
             AmmError::BatchAlreadyClosed
         );
 
+        // Pulling an order in the final window before the clear costs a fee, kept in the
+        // vault for the protocol (discourages spoofing right before the batch closes).
+        let deposit_fp = match order.side {
+            OrderSide::Bid => order.quote_deposit_fp,
+            OrderSide::Ask => order.amount_base_fp,
+        };
+        let late_cancel_fee_fp = market.charge_late_cancel_fee(order.side, deposit_fp, clock.slot)?;
+        let refund_fp = deposit_fp - late_cancel_fee_fp;
+
         // Take local copies for seeds
         let authority_key = market.authority;
         let base_mint_key = market.base_mint;
@@ -912,7 +2473,7 @@ pub mod micro_batch_amm {
 
         let token_program_ai = ctx.accounts.token_program.to_account_info();
         let market_seeds: &[&[u8]] = &[
-            b"market",
+            MARKET_SEED,
             authority_key.as_ref(),
             base_mint_key.as_ref(),
             quote_mint_key.as_ref(),
@@ -920,57 +2481,525 @@ pub mod micro_batch_amm {
         ];
         let signer_seeds: &[&[&[u8]]] = &[market_seeds];
 
-        // Simple full refund
+        // Refund the deposit, less any late-cancel fee
         match order.side {
             OrderSide::Bid => {
-                if order.quote_deposit_fp > 0 {
-                    let cpi_accounts = Transfer {
+                if refund_fp > 0 {
+                    let cpi_accounts = TransferChecked {
                         from: ctx.accounts.vault_quote.to_account_info(),
+                        mint: ctx.accounts.quote_mint.to_account_info(),
                         to: ctx.accounts.user_quote_ata.to_account_info(),
                         authority: market.to_account_info(),
                     };
                     let cpi_ctx =
-                        CpiContext::new_with_signer(token_program_ai, cpi_accounts, signer_seeds);
-                    token::transfer(cpi_ctx, order.quote_deposit_fp)?;
+                        CpiContext::new_with_signer(token_program_ai, cpi_accounts, signer_seeds)
+                            .with_remaining_accounts(ctx.remaining_accounts.to_vec());
+                    transfer_checked_with_hook(
+                        cpi_ctx,
+                        &market.transfer_hook_programs,
+                        refund_fp,
+                        ctx.accounts.quote_mint.decimals,
+                    )?;
                 }
             }
             OrderSide::Ask => {
-                if order.amount_base_fp > 0 {
-                    let cpi_accounts = Transfer {
+                if refund_fp > 0 {
+                    let cpi_accounts = TransferChecked {
                         from: ctx.accounts.vault_base.to_account_info(),
+                        mint: ctx.accounts.base_mint.to_account_info(),
                         to: ctx.accounts.user_base_ata.to_account_info(),
                         authority: market.to_account_info(),
                     };
                     let cpi_ctx =
-                        CpiContext::new_with_signer(token_program_ai, cpi_accounts, signer_seeds);
-                    token::transfer(cpi_ctx, order.amount_base_fp)?;
+                        CpiContext::new_with_signer(token_program_ai, cpi_accounts, signer_seeds)
+                            .with_remaining_accounts(ctx.remaining_accounts.to_vec());
+                    transfer_checked_with_hook(
+                        cpi_ctx,
+                        &market.transfer_hook_programs,
+                        refund_fp,
+                        ctx.accounts.base_mint.decimals,
+                    )?;
                 }
             }
         }
 
-        order.cancelled = true;
+        order.set_flag(OrderFlags::CANCELLED, true);
+        order.settle_nonce = order
+            .settle_nonce
+            .checked_add(1)
+            .ok_or(AmmError::MathOverflow)?;
+        market.cancelled_orders_in_batch = market
+            .cancelled_orders_in_batch
+            .checked_add(1)
+            .ok_or(AmmError::MathOverflow)?;
+        if let Some(claimables) = ctx.accounts.claimables.as_mut() {
+            claimables.remove(&order.key());
+        }
+        ctx.accounts.user_batch_stats.cancel_count = ctx
+            .accounts
+            .user_batch_stats
+            .cancel_count
+            .checked_add(1)
+            .ok_or(AmmError::MathOverflow)?;
+        ctx.accounts
+            .user_stats
+            .release_open_order(&mut ctx.accounts.user_batch_stats);
+
+        emit_logged!(ctx.accounts.event_log, OrderCancelled {
+            market: market.key(),
+            order: order.key(),
+            user: order.user,
+            batch_id: order.batch_id,
+            side: order.side,
+            late_cancel_fee_fp,
+            refund_base_fp: if order.side == OrderSide::Ask { refund_fp } else { 0 },
+            refund_quote_fp: if order.side == OrderSide::Bid { refund_fp } else { 0 },
+            slot: clock.slot,
+            slots_before_close: close_slot - clock.slot,
+        });
+
+        Ok(())
+    }
+
+    /// Shrink an open order by `amount_base_fp_delta` and refund the freed deposit,
+    /// keeping the order (and its queue position) in the batch.
+    ///
+    /// The reduced order must still clear the dust minimums; within the late-cancel window
+    /// the freed deposit pays the late-cancel fee, as a cancellation would. Only the owner
+    /// may reduce; the order's `cancel_authority` can only cancel it outright.
+    pub fn reduce_order<'info>(
+        ctx: Context<'_, '_, '_, 'info, CancelOrder<'info>>,
+        amount_base_fp_delta: u64,
+    ) -> Result<()> {
+        let clock = ctx.accounts.market.clock()?;
+        let trader = trader_key(&ctx.accounts.user.key(), &ctx.accounts.sub_account);
+        let market = &mut ctx.accounts.market;
+        let order = &mut ctx.accounts.order;
+
+        require_keys_eq!(order.user, trader, AmmError::Unauthorized);
+
+        require!(!market.is_paused(PauseModes::SETTLEMENT), AmmError::MarketPaused);
+        require!(!order.has_flag(OrderFlags::CANCELLED), AmmError::OrderCancelled);
+        require!(!order.has_flag(OrderFlags::FILLED), AmmError::OrderAlreadySettled);
+        require!(!order.is_frozen(clock.slot), AmmError::OrderFrozen);
+        require!(
+            clock.slot < market.last_batch_slot + market.batch_duration_slots,
+            AmmError::BatchAlreadyClosed
+        );
+        // As for cancels: a cleared order's deposit is owed to its batch's settlement.
+        require!(
+            order.batch_id == market.current_batch_id
+                && !order.has_flag(OrderFlags::CLAIM_RECORDED),
+            AmmError::BatchAlreadyClosed
+        );
+        require!(
+            amount_base_fp_delta > 0 && amount_base_fp_delta < order.amount_base_fp,
+            AmmError::InvalidAmount
+        );
+
+        let new_amount_base_fp = order.amount_base_fp - amount_base_fp_delta;
+        let new_notional_quote_fp =
+            market.quote_for_base(new_amount_base_fp as u128, order.limit_price_fp)? as u64;
+        let freed_fp = match order.side {
+            OrderSide::Bid => {
+                require!(
+                    new_notional_quote_fp > 0
+                        && new_notional_quote_fp >= market.min_quote_order_fp,
+                    AmmError::DustOrderTooSmall
+                );
+                order.quote_deposit_fp - new_notional_quote_fp
+            }
+            OrderSide::Ask => {
+                require!(
+                    new_amount_base_fp >= market.min_base_order_fp,
+                    AmmError::DustOrderTooSmall
+                );
+                amount_base_fp_delta
+            }
+        };
+        let late_cancel_fee_fp = market.charge_late_cancel_fee(order.side, freed_fp, clock.slot)?;
+        let refund_fp = freed_fp - late_cancel_fee_fp;
+
+        let market_seeds: &[&[u8]] = &[
+            MARKET_SEED,
+            market.authority.as_ref(),
+            market.base_mint.as_ref(),
+            market.quote_mint.as_ref(),
+            &[market.bump],
+        ];
+        let signer_seeds: &[&[&[u8]]] = &[market_seeds];
+
+        if refund_fp > 0 {
+            let (from, to, mint) = match order.side {
+                OrderSide::Bid => (
+                    ctx.accounts.vault_quote.to_account_info(),
+                    ctx.accounts.user_quote_ata.to_account_info(),
+                    &ctx.accounts.quote_mint,
+                ),
+                OrderSide::Ask => (
+                    ctx.accounts.vault_base.to_account_info(),
+                    ctx.accounts.user_base_ata.to_account_info(),
+                    &ctx.accounts.base_mint,
+                ),
+            };
+            let cpi_accounts = TransferChecked {
+                from,
+                mint: mint.to_account_info(),
+                to,
+                authority: market.to_account_info(),
+            };
+            let cpi_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                cpi_accounts,
+                signer_seeds,
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec());
+            transfer_checked_with_hook(
+                cpi_ctx,
+                &market.transfer_hook_programs,
+                refund_fp,
+                mint.decimals,
+            )?;
+        }
+
+        order.amount_base_fp = new_amount_base_fp;
+        if order.side == OrderSide::Bid {
+            order.quote_deposit_fp = new_notional_quote_fp;
+        }
+
+        emit_logged!(ctx.accounts.event_log, OrderReduced {
+            market: market.key(),
+            order: order.key(),
+            user: order.user,
+            batch_id: order.batch_id,
+            side: order.side,
+            amount_base_fp: new_amount_base_fp,
+            refund_fp,
+            late_cancel_fee_fp,
+        });
+
+        Ok(())
+    }
+
+    /// Link two of the caller's conditional orders in the open batch as a one-cancels-the-
+    /// other pair (e.g. a stop-loss and a take-profit). If both activate at the clear, the
+    /// one queued first trades and the other is dropped and refunded at settlement.
+    pub fn link_oco_orders(ctx: Context<LinkOcoOrders>) -> Result<()> {
+        let market = &ctx.accounts.market;
+        let order_a = &mut ctx.accounts.order_a;
+        let order_b = &mut ctx.accounts.order_b;
+
+        require_keys_neq!(order_a.key(), order_b.key(), AmmError::InvalidOcoLink);
+        for order in [&**order_a, &**order_b] {
+            require!(
+                order.batch_id == market.current_batch_id
+                    && order.trigger_condition != TriggerCondition::None
+                    && order.oco_link == Pubkey::default()
+                    && !order.has_flag(OrderFlags::CANCELLED),
+                AmmError::InvalidOcoLink
+            );
+        }
+
+        order_a.oco_link = order_b.key();
+        order_b.oco_link = order_a.key();
+
+        emit!(OcoLinked {
+            market: market.key(),
+            batch_id: market.current_batch_id,
+            order_a: order_a.key(),
+            order_b: order_b.key(),
+        });
+
+        Ok(())
+    }
+
+    /// Let `cancel_authority` cancel one of the caller's orders, so an independent process
+    /// (e.g. a risk bot) can pull it without holding the trading key. Refunds still go to
+    /// the order's owner. `Pubkey::default()` revokes the delegate.
+    pub fn set_cancel_authority(
+        ctx: Context<SetCancelAuthority>,
+        cancel_authority: Pubkey,
+    ) -> Result<()> {
+        let order = &mut ctx.accounts.order;
+        require!(!order.has_flag(OrderFlags::CANCELLED), AmmError::OrderCancelled);
+        require!(!order.has_flag(OrderFlags::FILLED), AmmError::OrderAlreadySettled);
+
+        order.cancel_authority = cancel_authority;
+
+        emit!(CancelAuthoritySet {
+            market: order.market,
+            order: order.key(),
+            cancel_authority,
+        });
+
+        Ok(())
+    }
+
+    /// Mass-refund deposits for a batch that cleared at price 0, or that a successful
+    /// `audit_batch` challenge disputed before anything from it was paid out.
+    ///
+    /// Permissionless crank. remaining_accounts are pages of
+    /// [Order, user_base_ata, user_quote_ata, user_batch_stats, user_stats, claimables]
+    /// (`claimables` is the program id for an owner without an index), then the last
+    /// `hook_accounts` accounts for the mints' transfer hooks. Orders that are already
+    /// settled, cancelled or from another batch are skipped, so pages may overlap.
+    pub fn refund_uncrossed_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, RefundUncrossedBatch<'info>>,
+        hook_accounts: u8,
+    ) -> Result<()> {
+        let market = &ctx.accounts.market;
+        let batch_state = &ctx.accounts.batch_state;
+        let market_pk = market.key();
+        // Order pages first, then `hook_accounts` transfer-hook accounts for the refunds.
+        let pages_len = ctx
+            .remaining_accounts
+            .len()
+            .checked_sub(hook_accounts as usize)
+            .ok_or(AmmError::InvalidRemainingAccountsLayout)?;
+        let (remaining, hook_remaining) = ctx.remaining_accounts.split_at(pages_len);
+
+        require!(!market.is_paused(PauseModes::SETTLEMENT), AmmError::MarketPaused);
+        require!(
+            batch_state.market == market_pk,
+            AmmError::BatchMarketMismatch
+        );
+        require!(batch_state.refunds_deposits(), AmmError::BatchCrossed);
+        require!(
+            remaining.len().is_multiple_of(6),
+            AmmError::InvalidRemainingAccountsLayout
+        );
+        let slot = market.clock()?.slot;
+
+        let market_seeds: &[&[u8]] = &[
+            MARKET_SEED,
+            market.authority.as_ref(),
+            market.base_mint.as_ref(),
+            market.quote_mint.as_ref(),
+            &[market.bump],
+        ];
+        let signer_seeds: &[&[&[u8]]] = &[market_seeds];
+        let token_program_ai = ctx.accounts.token_program.to_account_info();
+
+        let mut orders_refunded: u32 = 0;
+        for page in remaining.chunks(6) {
+            let (order_ai, base_ai, quote_ai, batch_stats_ai, stats_ai, claimables_ai) =
+                (&page[0], &page[1], &page[2], &page[3], &page[4], &page[5]);
+            let mut order: Order = load_account(order_ai)?;
+            if order.market != market_pk
+                || order.batch_id != batch_state.batch_id
+                || order.has_flag(OrderFlags::CANCELLED)
+                || order.has_flag(OrderFlags::FILLED)
+                || order.is_frozen(slot)
+            {
+                continue;
+            }
+
+            let mut user_batch: UserBatchStats = load_account(batch_stats_ai)?;
+            let mut user_stats: UserStats = load_account(stats_ai)?;
+            require!(
+                user_batch.user == order.user
+                    && user_batch.market == market_pk
+                    && user_batch.batch_id == order.batch_id
+                    && user_stats.user == order.user
+                    && user_stats.market == market_pk,
+                AmmError::InvalidRemainingAccountsLayout
+            );
+
+            let (refund_ai, mint, refund_amount, vault_ai) = match order.side {
+                OrderSide::Bid => (
+                    quote_ai,
+                    &ctx.accounts.quote_mint,
+                    order.quote_deposit_fp,
+                    ctx.accounts.vault_quote.to_account_info(),
+                ),
+                OrderSide::Ask => (
+                    base_ai,
+                    &ctx.accounts.base_mint,
+                    order.amount_base_fp,
+                    ctx.accounts.vault_base.to_account_info(),
+                ),
+            };
+            let refund_ata = InterfaceAccount::<TokenAccount>::try_from(refund_ai)?;
+            require!(
+                refund_ata.owner == order.user && refund_ata.mint == mint.key(),
+                AmmError::InvalidRemainingAccountsLayout
+            );
+
+            if refund_amount > 0 {
+                let cpi_accounts = TransferChecked {
+                    from: vault_ai,
+                    mint: mint.to_account_info(),
+                    to: refund_ai.clone(),
+                    authority: market.to_account_info(),
+                };
+                let cpi_ctx = CpiContext::new_with_signer(
+                    token_program_ai.clone(),
+                    cpi_accounts,
+                    signer_seeds,
+                )
+                .with_remaining_accounts(hook_remaining.to_vec());
+                transfer_checked_with_hook(
+                    cpi_ctx,
+                    &market.transfer_hook_programs,
+                    refund_amount,
+                    mint.decimals,
+                )?;
+            }
+
+            order.set_flag(OrderFlags::FILLED, true);
+            order.settle_nonce = order
+                .settle_nonce
+                .checked_add(1)
+                .ok_or(AmmError::MathOverflow)?;
+            user_stats.release_open_order(&mut user_batch);
+            write_account(order_ai, &order)?;
+            write_account(batch_stats_ai, &user_batch)?;
+            write_account(stats_ai, &user_stats)?;
+            // The program id stands in for an owner without a `Claimables` index.
+            if *claimables_ai.key != crate::ID {
+                let mut claimables: Claimables = load_account(claimables_ai)?;
+                require!(
+                    claimables.market == market_pk && claimables.user == order.user,
+                    AmmError::InvalidRemainingAccountsLayout
+                );
+                claimables.remove(order_ai.key);
+                write_account(claimables_ai, &claimables)?;
+            }
+
+            emit_logged!(ctx.accounts.event_log, OrderSettled {
+                market: market_pk,
+                order: order_ai.key(),
+                user: order.user,
+                batch_id: order.batch_id,
+                side: order.side,
+                clearing_price_fp: 0,
+                filled_base_fp: 0,
+                filled_quote_fp: 0,
+                refund_base_fp: if order.side == OrderSide::Ask { refund_amount } else { 0 },
+                refund_quote_fp: if order.side == OrderSide::Bid { refund_amount } else { 0 },
+                memo: order.memo,
+            });
+            orders_refunded += 1;
+        }
+
+        emit!(UncrossedBatchRefunded {
+            market: market_pk,
+            batch_id: batch_state.batch_id,
+            orders_refunded,
+        });
+        let batch_state = &mut ctx.accounts.batch_state;
+        batch_state.settled_order_count = batch_state
+            .settled_order_count
+            .checked_add(orders_refunded)
+            .ok_or(AmmError::MathOverflow)?;
+
+        Ok(())
+    }
+
+    /// Refund one order of an uncrossed (or voided) batch, recreating the owner's token
+    /// account if it was closed, so a wallet without ATAs recovers its deposit in one
+    /// transaction. Permissionless; `payer` funds any account it creates.
+    pub fn refund_order<'info>(
+        ctx: Context<'_, '_, '_, 'info, RefundOrder<'info>>,
+    ) -> Result<()> {
+        let market = &ctx.accounts.market;
+        let batch_state = &mut ctx.accounts.batch_state;
+        let order = &mut ctx.accounts.order;
+
+        require!(!market.is_paused(PauseModes::SETTLEMENT), AmmError::MarketPaused);
+        require!(batch_state.refunds_deposits(), AmmError::BatchCrossed);
+        require!(!order.has_flag(OrderFlags::CANCELLED), AmmError::OrderCancelled);
+        require!(!order.has_flag(OrderFlags::FILLED), AmmError::OrderAlreadySettled);
+        require!(!order.is_frozen(market.clock()?.slot), AmmError::OrderFrozen);
+
+        let (refund_ata, mint, refund_amount, vault) = match order.side {
+            OrderSide::Bid => (
+                ctx.accounts.user_quote_ata.to_account_info(),
+                &ctx.accounts.quote_mint,
+                order.quote_deposit_fp,
+                ctx.accounts.vault_quote.to_account_info(),
+            ),
+            OrderSide::Ask => (
+                ctx.accounts.user_base_ata.to_account_info(),
+                &ctx.accounts.base_mint,
+                order.amount_base_fp,
+                ctx.accounts.vault_base.to_account_info(),
+            ),
+        };
+        if refund_amount > 0 {
+            let market_seeds: &[&[u8]] = &[
+                MARKET_SEED,
+                market.authority.as_ref(),
+                market.base_mint.as_ref(),
+                market.quote_mint.as_ref(),
+                &[market.bump],
+            ];
+            let signer_seeds: &[&[&[u8]]] = &[market_seeds];
+            let cpi_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: vault,
+                    mint: mint.to_account_info(),
+                    to: refund_ata,
+                    authority: market.to_account_info(),
+                },
+                signer_seeds,
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec());
+            transfer_checked_with_hook(
+                cpi_ctx,
+                &market.transfer_hook_programs,
+                refund_amount,
+                mint.decimals,
+            )?;
+        }
+
+        order.set_flag(OrderFlags::FILLED, true);
+        order.settle_nonce = order
+            .settle_nonce
+            .checked_add(1)
+            .ok_or(AmmError::MathOverflow)?;
+        batch_state.settled_order_count = batch_state
+            .settled_order_count
+            .checked_add(1)
+            .ok_or(AmmError::MathOverflow)?;
+        if let Some(claimables) = ctx.accounts.claimables.as_mut() {
+            claimables.remove(&order.key());
+        }
+        ctx.accounts
+            .user_stats
+            .release_open_order(&mut ctx.accounts.user_batch_stats);
 
-        emit!(OrderCancelled {
+        emit_logged!(ctx.accounts.event_log, OrderSettled {
             market: market.key(),
             order: order.key(),
             user: order.user,
             batch_id: order.batch_id,
             side: order.side,
+            clearing_price_fp: 0,
+            filled_base_fp: 0,
+            filled_quote_fp: 0,
+            refund_base_fp: if order.side == OrderSide::Ask { refund_amount } else { 0 },
+            refund_quote_fp: if order.side == OrderSide::Bid { refund_amount } else { 0 },
+            memo: order.memo,
         });
 
         Ok(())
     }
 
-    /// Pause/unpause a market and optionally set a pause reason code.
-    pub fn set_paused(ctx: Context<SetPaused>, paused: bool, pause_reason: u8) -> Result<()> {
+    /// Set which parts of the market are paused (`PauseModes` bits; 0 unpauses) and
+    /// optionally a pause reason code. E.g. `PLACEMENT` alone halts new orders while
+    /// pending batches still clear and settle, as during a wind-down.
+    pub fn set_paused(ctx: Context<SetPaused>, pause_modes: u8, pause_reason: u8) -> Result<()> {
         let market = &mut ctx.accounts.market;
         require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
-        market.paused = paused;
+        require!(pause_modes & !PauseModes::ALL == 0, AmmError::InvalidPauseModes);
+        market.pause_modes = pause_modes;
         market.pause_reason = pause_reason;
 
         emit!(PausedSet {
             market: market.key(),
-            paused,
+            pause_modes,
             reason: pause_reason,
         });
 
@@ -978,25 +3007,95 @@ pub mod micro_batch_amm {
     }
 
     /// Admin function to tweak core risk and fee parameters.
-    pub fn set_params(
-        ctx: Context<SetParams>,
-        new_fee_bps: u16,
-        max_notional_per_batch_quote_fp: u128,
-        max_notional_per_user_per_batch_quote_fp: u128,
-        max_orders_global_per_batch: u32,
-        max_price_move_bps: u16,
-        keeper_fee_bps: u16,
-        min_base_order_fp: u64,
-        min_quote_order_fp: u64,
-        protocol_fee_bps: u16,
-        referral_fee_bps: u16,
-    ) -> Result<()> {
+    pub fn set_params(ctx: Context<SetParams>, params: RiskFeeParams) -> Result<()> {
+        let RiskFeeParams {
+            fee_bps: new_fee_bps,
+            max_notional_per_batch_quote_fp,
+            max_notional_per_user_per_batch_quote_fp,
+            max_orders_global_per_batch,
+            max_price_move_bps,
+            keeper_fee_bps,
+            min_base_order_fp,
+            min_quote_order_fp,
+            protocol_fee_bps,
+            referral_fee_bps,
+            max_base_order_fp,
+            max_quote_order_fp,
+        } = params;
         let market = &mut ctx.accounts.market;
         require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
 
         require!(new_fee_bps as u64 <= BPS_DENOM, AmmError::InvalidFeeBps);
         require!(protocol_fee_bps as u64 <= new_fee_bps as u64, AmmError::InvalidFeeBps);
         require!(referral_fee_bps as u64 <= new_fee_bps as u64, AmmError::InvalidFeeBps);
+        require!(max_base_order_fp >= min_base_order_fp, AmmError::InvalidOrderSizeBand);
+        require!(max_quote_order_fp >= min_quote_order_fp, AmmError::InvalidOrderSizeBand);
+        ctx.accounts
+            .program_config
+            .check_fees(new_fee_bps, keeper_fee_bps)?;
+
+        let mut changes = Vec::new();
+        let mut diff = |param: MarketParam, old: u128, new: u128| {
+            if old != new {
+                changes.push(ParamChange { param, old, new });
+            }
+        };
+        diff(MarketParam::FeeBps, market.fee_bps as u128, new_fee_bps as u128);
+        diff(
+            MarketParam::MaxNotionalPerBatch,
+            market.max_notional_per_batch_quote_fp,
+            max_notional_per_batch_quote_fp,
+        );
+        diff(
+            MarketParam::MaxNotionalPerUserPerBatch,
+            market.max_notional_per_user_per_batch_quote_fp,
+            max_notional_per_user_per_batch_quote_fp,
+        );
+        diff(
+            MarketParam::MaxOrdersGlobalPerBatch,
+            market.max_orders_global_per_batch as u128,
+            max_orders_global_per_batch as u128,
+        );
+        diff(
+            MarketParam::MaxPriceMoveBps,
+            market.max_price_move_bps as u128,
+            max_price_move_bps as u128,
+        );
+        diff(
+            MarketParam::KeeperFeeBps,
+            market.keeper_fee_bps as u128,
+            keeper_fee_bps as u128,
+        );
+        diff(
+            MarketParam::MinBaseOrder,
+            market.min_base_order_fp as u128,
+            min_base_order_fp as u128,
+        );
+        diff(
+            MarketParam::MinQuoteOrder,
+            market.min_quote_order_fp as u128,
+            min_quote_order_fp as u128,
+        );
+        diff(
+            MarketParam::ProtocolFeeBps,
+            market.protocol_fee_bps as u128,
+            protocol_fee_bps as u128,
+        );
+        diff(
+            MarketParam::ReferralFeeBps,
+            market.referral_fee_bps as u128,
+            referral_fee_bps as u128,
+        );
+        diff(
+            MarketParam::MaxBaseOrder,
+            market.max_base_order_fp as u128,
+            max_base_order_fp as u128,
+        );
+        diff(
+            MarketParam::MaxQuoteOrder,
+            market.max_quote_order_fp as u128,
+            max_quote_order_fp as u128,
+        );
 
         market.fee_bps = new_fee_bps;
         market.max_notional_per_batch_quote_fp = max_notional_per_batch_quote_fp;
@@ -1008,6 +3107,10 @@ pub mod micro_batch_amm {
         market.min_quote_order_fp = min_quote_order_fp;
         market.protocol_fee_bps = protocol_fee_bps;
         market.referral_fee_bps = referral_fee_bps;
+        market.max_base_order_fp = max_base_order_fp;
+        market.max_quote_order_fp = max_quote_order_fp;
+        // Explicit values win over a ramp in progress.
+        market.ramp_param = RampParam::None;
 
         emit!(ParamsUpdated {
             market: market.key(),
@@ -1021,560 +3124,8283 @@ pub mod micro_batch_amm {
             min_quote_order_fp,
             protocol_fee_bps,
             referral_fee_bps,
+            max_base_order_fp,
+            max_quote_order_fp,
         });
+        if !changes.is_empty() {
+            emit!(ParamsChanged {
+                market: market.key(),
+                changes,
+            });
+        }
 
         Ok(())
     }
 
-    /// Simple read helper: emit key market params for off-chain UIs.
-    pub fn view_market(ctx: Context<ViewMarket>) -> Result<()> {
-        let market = &ctx.accounts.market;
+    /// Admin function to move one parameter to `target` gradually: the value steps linearly
+    /// with each new batch and reaches `target` after `ramp_batches` batches, so traders
+    /// see no abrupt economic change mid-strategy. Replaces any ramp in progress;
+    /// `set_params` cancels it.
+    pub fn start_param_ramp(
+        ctx: Context<SetParams>,
+        param: RampParam,
+        target: u128,
+        ramp_batches: u32,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+        require!(
+            param != RampParam::None && ramp_batches > 0,
+            AmmError::InvalidParamRamp
+        );
 
-        emit!(MarketView {
+        let config = &ctx.accounts.program_config;
+        match param {
+            RampParam::FeeBps => {
+                require!(target <= BPS_DENOM as u128, AmmError::InvalidFeeBps);
+                require!(
+                    target >= market.protocol_fee_bps as u128
+                        && target >= market.referral_fee_bps as u128,
+                    AmmError::InvalidFeeBps
+                );
+                config.check_fees(target as u16, market.keeper_fee_bps)?;
+            }
+            RampParam::ProtocolFeeBps => {
+                require!(target <= market.fee_bps as u128, AmmError::InvalidFeeBps);
+            }
+            RampParam::KeeperFeeBps => {
+                require!(target <= BPS_DENOM as u128, AmmError::InvalidFeeBps);
+                config.check_fees(market.fee_bps, target as u16)?;
+            }
+            RampParam::MaxNotionalPerBatch
+            | RampParam::MaxNotionalPerUserPerBatch
+            | RampParam::None => {}
+        }
+
+        let start = market.ramp_param_value(param);
+        market.ramp_param = param;
+        market.ramp_start = start;
+        market.ramp_target = target;
+        market.ramp_start_batch_id = market.current_batch_id;
+        market.ramp_batches = ramp_batches;
+
+        emit!(ParamRampStarted {
             market: market.key(),
-            authority: market.authority,
-            base_mint: market.base_mint,
-            quote_mint: market.quote_mint,
-            batch_duration_slots: market.batch_duration_slots,
-            last_batch_slot: market.last_batch_slot,
-            current_batch_id: market.current_batch_id,
-            next_order_id: market.next_order_id,
-            fee_bps: market.fee_bps,
-            max_orders_per_user_per_batch: market.max_orders_per_user_per_batch,
-            paused: market.paused,
-            max_notional_per_batch_quote_fp: market.max_notional_per_batch_quote_fp,
-            max_notional_per_user_per_batch_quote_fp: market.max_notional_per_user_per_batch_quote_fp,
-            batch_notional_quote_fp: market.batch_notional_quote_fp,
-            max_orders_global_per_batch: market.max_orders_global_per_batch,
-            global_orders_in_batch: market.global_orders_in_batch,
-            max_price_move_bps: market.max_price_move_bps,
-            last_clearing_price_fp: market.last_clearing_price_fp,
-            keeper_fee_bps: market.keeper_fee_bps,
-            min_base_order_fp: market.min_base_order_fp,
-            min_quote_order_fp: market.min_quote_order_fp,
-            protocol_fee_bps: market.protocol_fee_bps,
-            referral_fee_bps: market.referral_fee_bps,
-            protocol_fees_accrued_fp: market.protocol_fees_accrued_fp,
-            pause_reason: market.pause_reason,
+            param,
+            start,
+            target,
+            start_batch_id: market.current_batch_id,
+            ramp_batches,
         });
 
         Ok(())
     }
-}
 
-// -------------------------------
-// Accounts
-// -------------------------------
+    /// Create the program-wide parameter bounds (program upgrade authority only).
+    ///
+    /// `initialize_market` and `set_params` must stay within them, so traders on
+    /// permissionless markets are protected from predatory fees or hyper-fast batches.
+    pub fn init_program_config(
+        ctx: Context<InitProgramConfig>,
+        max_fee_bps: u16,
+        max_keeper_fee_bps: u16,
+        min_batch_duration_slots: u64,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.program_config;
+        config.admin = ctx.accounts.authority.key();
+        config.bump = ctx.bumps.program_config;
+        config.program_version = PROGRAM_VERSION;
+        config.apply(max_fee_bps, max_keeper_fee_bps, min_batch_duration_slots)?;
+        Ok(())
+    }
 
-#[derive(Accounts)]
-#[instruction(batch_duration_slots: u64, fee_bps: u16, max_orders_per_user_per_batch: u32)]
-pub struct InitializeMarket<'info> {
-    #[account(mut)]
-    pub authority: Signer<'info>,
+    /// Update the program-wide parameter bounds (config admin only). Existing markets keep
+    /// their parameters; the new bounds apply to their next `set_params`.
+    pub fn set_program_config(
+        ctx: Context<SetProgramConfig>,
+        max_fee_bps: u16,
+        max_keeper_fee_bps: u16,
+        min_batch_duration_slots: u64,
+    ) -> Result<()> {
+        ctx.accounts
+            .program_config
+            .apply(max_fee_bps, max_keeper_fee_bps, min_batch_duration_slots)
+    }
 
-    pub base_mint: Account<'info, Mint>,
-    pub quote_mint: Account<'info, Mint>,
+    /// Bring program state up to this build's `PROGRAM_VERSION` after an upgrade (config
+    /// admin only). Until it has run, every other handler fails with `MigrationPending`,
+    /// so a freshly upgraded program never works on state laid out for the previous
+    /// version. A config from before versioning is grown to the current size first, the
+    /// admin paying any extra rent.
+    pub fn migrate_program_state(ctx: Context<MigrateProgramState>) -> Result<()> {
+        let config_ai = ctx.accounts.program_config.to_account_info();
+        let space = 8 + ProgramConfig::INIT_SPACE;
+        if config_ai.data_len() < space {
+            let shortfall = Rent::get()?
+                .minimum_balance(space)
+                .saturating_sub(config_ai.lamports());
+            if shortfall > 0 {
+                let cpi_ctx = CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: ctx.accounts.admin.to_account_info(),
+                        to: config_ai.clone(),
+                    },
+                );
+                system_program::transfer(cpi_ctx, shortfall)?;
+            }
+            config_ai.realloc(space, true)?;
+        }
 
-    #[account(
-        init,
-        payer = authority,
-        seeds = [
-            b"market",
-            authority.key().as_ref(),
-            base_mint.key().as_ref(),
-            quote_mint.key().as_ref()
-        ],
-        bump,
-        space = 8 + Market::LEN
-    )]
-    pub market: Account<'info, Market>,
+        let mut config: ProgramConfig = load_account(&config_ai)?;
+        require_keys_eq!(config.admin, ctx.accounts.admin.key(), AmmError::Unauthorized);
+        let from_version = config.program_version;
+        require!(from_version < PROGRAM_VERSION, AmmError::AlreadyMigrated);
 
-    #[account(
-        init,
-        payer = authority,
-        seeds = [b"vault_base", market.key().as_ref()],
-        bump,
-        token::mint = base_mint,
-        token::authority = market
-    )]
-    pub vault_base: Account<'info, TokenAccount>,
+        // Per-version migration steps go here, applied in order from `from_version`.
+        config.program_version = PROGRAM_VERSION;
+        write_account(&config_ai, &config)?;
 
-    #[account(
-        init,
-        payer = authority,
-        seeds = [b"vault_quote", market.key().as_ref()],
-        bump,
-        token::mint = quote_mint,
-        token::authority = market
-    )]
-    pub vault_quote: Account<'info, TokenAccount>,
+        emit!(ProgramStateMigrated {
+            admin: config.admin,
+            from_version,
+            to_version: PROGRAM_VERSION,
+        });
 
-    pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
-    pub rent: Sysvar<'info, Rent>,
-}
+        Ok(())
+    }
 
-#[derive(Accounts)]
-pub struct PlaceOrder<'info> {
-    #[account(mut)]
-    pub user: Signer<'info>,
+    /// Register (or update) the caller's keeper record for a market, naming where its
+    /// clear rewards go. Lets e.g. a DAO-run keeper clear with a hot key while rewards
+    /// accrue to its treasury.
+    pub fn register_keeper(ctx: Context<RegisterKeeper>, reward_recipient: Pubkey) -> Result<()> {
+        let record = &mut ctx.accounts.keeper_record;
+        record.market = ctx.accounts.market.key();
+        record.keeper = ctx.accounts.keeper.key();
+        record.reward_recipient = reward_recipient;
+        record.bump = ctx.bumps.keeper_record;
+
+        emit!(KeeperRegistered {
+            market: record.market,
+            keeper: record.keeper,
+            reward_recipient,
+        });
 
-    #[account(
-        mut,
-        has_one = base_mint,
-        has_one = quote_mint,
-        constraint = !market.paused
-    )]
-    pub market: Account<'info, Market>,
+        Ok(())
+    }
 
-    pub base_mint: Account<'info, Mint>,
-    pub quote_mint: Account<'info, Mint>,
+    /// Create a cluster of markets whose batches all close on multiples of `period_slots`
+    /// and clear within `clear_window_slots` after each boundary, so cross-market
+    /// arbitrage happens across matched auction windows.
+    pub fn create_market_cluster(
+        ctx: Context<CreateMarketCluster>,
+        cluster_id: u16,
+        period_slots: u64,
+        clear_window_slots: u64,
+    ) -> Result<()> {
+        require!(
+            clear_window_slots > 0 && clear_window_slots < period_slots,
+            AmmError::InvalidClusterParams
+        );
 
-    #[account(
-        mut,
-        constraint = vault_base.key() == market.vault_base
-    )]
-    pub vault_base: Account<'info, TokenAccount>,
+        let cluster = &mut ctx.accounts.cluster;
+        cluster.authority = ctx.accounts.authority.key();
+        cluster.cluster_id = cluster_id;
+        cluster.period_slots = period_slots;
+        cluster.clear_window_slots = clear_window_slots;
+        cluster.quote_mint = Pubkey::default();
+        cluster.reward_epoch = 0;
+        cluster.epoch_volume_quote_fp = 0;
+        cluster.closed_epoch_reward_lamports = 0;
+        cluster.closed_epoch_volume_quote_fp = 0;
+        cluster.members = Vec::new();
+        cluster.bump = ctx.bumps.cluster;
+
+        emit!(MarketClusterCreated {
+            cluster: cluster.key(),
+            authority: cluster.authority,
+            period_slots,
+            clear_window_slots,
+        });
 
-    #[account(
-        mut,
-        constraint = vault_quote.key() == market.vault_quote
-    )]
-    pub vault_quote: Account<'info, TokenAccount>,
+        Ok(())
+    }
 
-    #[account(
-        mut,
-        constraint = user_base_ata.owner == user.key(),
-        constraint = user_base_ata.mint == base_mint.key()
-    )]
-    pub user_base_ata: Account<'info, TokenAccount>,
+    /// Add a market to a cluster (signed by the authority of both). The open batch must
+    /// be empty; it then closes at the next boundary like every other member's.
+    pub fn join_market_cluster(ctx: Context<JoinMarketCluster>) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        let cluster = &mut ctx.accounts.cluster;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+        require!(market.cluster == Pubkey::default(), AmmError::AlreadyInCluster);
+        require!(
+            cluster.members.len() < MAX_CLUSTER_MARKETS,
+            AmmError::ClusterFull
+        );
+        require!(
+            market.global_orders_in_batch == 0,
+            AmmError::OpenBatchNotEmpty
+        );
+        if cluster.members.is_empty() {
+            cluster.quote_mint = market.quote_mint;
+        }
+        require_keys_eq!(
+            cluster.quote_mint,
+            market.quote_mint,
+            AmmError::ClusterQuoteMismatch
+        );
 
-    #[account(
-        mut,
-        constraint = user_quote_ata.owner == user.key(),
-        constraint = user_quote_ata.mint == quote_mint.key()
-    )]
-    pub user_quote_ata: Account<'info, TokenAccount>,
+        cluster.members.push(market.key());
+        market.cluster = cluster.key();
+        market.cluster_period_slots = cluster.period_slots;
+        market.cluster_clear_window_slots = cluster.clear_window_slots;
 
-    #[account(
-        init,
-        payer = user,
-        seeds = [
-            b"order",
-            market.key().as_ref(),
-            &market.next_order_id.to_le_bytes()
-        ],
-        bump,
-        space = 8 + Order::LEN
-    )]
-    pub order: Account<'info, Order>,
+        emit!(MarketJoinedCluster {
+            cluster: cluster.key(),
+            market: market.key(),
+            member_count: cluster.members.len() as u8,
+        });
 
-    #[account(
-        init_if_needed,
-        payer = user,
-        seeds = [
-            b"user_batch",
-            market.key().as_ref(),
-            user.key().as_ref(),
-            &market.current_batch_id.to_le_bytes()
-        ],
-        bump,
-        space = 8 + UserBatchStats::LEN
-    )]
-    pub user_batch_stats: Account<'info, UserBatchStats>,
+        Ok(())
+    }
 
-    pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
-    pub rent: Sysvar<'info, Rent>,
-}
+    /// Add lamports to a cluster's shared keeper reward pool. Permissionless.
+    pub fn fund_cluster_rewards(ctx: Context<FundClusterRewards>, lamports: u64) -> Result<()> {
+        let cpi_ctx = CpiContext::new(
+            ctx.accounts.system_program.to_account_info(),
+            system_program::Transfer {
+                from: ctx.accounts.funder.to_account_info(),
+                to: ctx.accounts.cluster.to_account_info(),
+            },
+        );
+        system_program::transfer(cpi_ctx, lamports)?;
 
-#[derive(Accounts)]
-pub struct ClearBatch<'info> {
-    #[account(mut)]
-    pub authority: Signer<'info>,
+        emit!(ClusterRewardsFunded {
+            cluster: ctx.accounts.cluster.key(),
+            funder: ctx.accounts.funder.key(),
+            lamports,
+        });
 
-    #[account(
-        mut,
-        has_one = base_mint,
-        has_one = quote_mint
-    )]
-    pub market: Account<'info, Market>,
+        Ok(())
+    }
 
-    pub base_mint: Account<'info, Mint>,
-    pub quote_mint: Account<'info, Mint>,
+    /// Open the caller's share record for a cluster's reward pool. Clears of any member
+    /// market that pass it book their matched quote volume to the keeper.
+    pub fn register_cluster_keeper(ctx: Context<RegisterClusterKeeper>) -> Result<()> {
+        let share = &mut ctx.accounts.cluster_keeper_share;
+        share.cluster = ctx.accounts.cluster.key();
+        share.keeper = ctx.accounts.keeper.key();
+        share.epoch = ctx.accounts.cluster.reward_epoch;
+        share.volume_quote_fp = 0;
+        share.bump = ctx.bumps.cluster_keeper_share;
+
+        emit!(ClusterKeeperRegistered {
+            cluster: share.cluster,
+            keeper: share.keeper,
+        });
 
-    #[account(
-        mut,
-        constraint = vault_base.key() == market.vault_base
-    )]
-    pub vault_base: Account<'info, TokenAccount>,
+        Ok(())
+    }
 
-    #[account(
-        mut,
-        constraint = vault_quote.key() == market.vault_quote
-    )]
-    pub vault_quote: Account<'info, TokenAccount>,
+    /// Close the cluster's reward epoch (cluster authority only): the pool's whole balance
+    /// above rent, including anything unclaimed from the previous epoch, is split
+    /// pro-rata over the volume keepers cleared during it.
+    pub fn close_cluster_reward_epoch(ctx: Context<CloseClusterRewardEpoch>) -> Result<()> {
+        let cluster = &mut ctx.accounts.cluster;
+        let cluster_ai = cluster.to_account_info();
+        let rent_floor = Rent::get()?.minimum_balance(cluster_ai.data_len());
+
+        cluster.closed_epoch_reward_lamports = cluster_ai.lamports().saturating_sub(rent_floor);
+        cluster.closed_epoch_volume_quote_fp = cluster.epoch_volume_quote_fp;
+        cluster.epoch_volume_quote_fp = 0;
+        cluster.reward_epoch = cluster
+            .reward_epoch
+            .checked_add(1)
+            .ok_or(AmmError::MathOverflow)?;
 
-    #[account(
-        init_if_needed,
-        payer = authority,
-        seeds = [b"batch_state", market.key().as_ref(), &market.current_batch_id.to_le_bytes()],
-        bump,
-        space = 8 + BatchState::LEN
-    )]
-    pub batch_state: Account<'info, BatchState>,
+        emit!(ClusterRewardEpochClosed {
+            cluster: cluster.key(),
+            epoch: cluster.reward_epoch - 1,
+            reward_lamports: cluster.closed_epoch_reward_lamports,
+            volume_quote_fp: cluster.closed_epoch_volume_quote_fp,
+        });
 
-    pub token_program: Program<'info, Token>,
-    // no #[account] attribute: avoids AccountDeserialize requirement
-    pub system_program: Program<'info, System>,
-}
+        Ok(())
+    }
 
-#[derive(Accounts)]
-pub struct SettleOrder<'info> {
-    #[account(mut)]
-    pub user: Signer<'info>,
+    /// Pay the keeper its pro-rata part of the last closed epoch's pool. Claims lapse when
+    /// the following epoch closes.
+    pub fn claim_cluster_rewards(ctx: Context<ClaimClusterRewards>) -> Result<()> {
+        let cluster = &mut ctx.accounts.cluster;
+        let share = &mut ctx.accounts.cluster_keeper_share;
+        let epoch = share.epoch;
+        let lamports = cluster.roll_share(share);
+        require!(lamports > 0, AmmError::NoClusterRewards);
+
+        cluster.to_account_info().sub_lamports(lamports)?;
+        ctx.accounts.keeper.to_account_info().add_lamports(lamports)?;
+
+        emit!(ClusterRewardsClaimed {
+            cluster: cluster.key(),
+            keeper: share.keeper,
+            epoch,
+            lamports,
+        });
 
-    #[account(mut)]
-    pub market: Account<'info, Market>,
+        Ok(())
+    }
 
-    #[account(
-        mut,
-        has_one = market
-    )]
-    pub batch_state: Account<'info, BatchState>,
+    /// Open the trader's `Claimables` index (wallet, or `sub_account` of `user`). From then on
+    /// a clear passed the index lists each of the trader's orders that receives a claim, and
+    /// settlement, refunds and `cancel_order` drop it again, so wallets can show pending
+    /// settlements without scanning.
+    pub fn open_claimables(ctx: Context<OpenClaimables>) -> Result<()> {
+        let claimables = &mut ctx.accounts.claimables;
+        claimables.market = ctx.accounts.market.key();
+        claimables.user = trader_key(&ctx.accounts.user.key(), &ctx.accounts.sub_account);
+        claimables.entries = Vec::new();
+        claimables.bump = ctx.bumps.claimables;
+        Ok(())
+    }
 
-    #[account(
-        mut,
-        constraint = order.user == user.key(),
-        constraint = order.market == market.key()
-    )]
-    pub order: Account<'info, Order>,
+    /// Close a `UserBatchStats` once its batch has closed and none of its orders await
+    /// settlement, returning the rent to the trader (a sub-account's master, when passed
+    /// `sub_account`). Permissionless, so keepers can crank it.
+    pub fn close_user_batch_stats(ctx: Context<CloseUserBatchStats>) -> Result<()> {
+        let stats = &ctx.accounts.user_batch_stats;
+        require!(
+            stats.is_closable(ctx.accounts.market.current_batch_id),
+            AmmError::UserBatchStatsInUse
+        );
 
-    #[account(
-        init_if_needed,
-        payer = user,
-        seeds = [b"order_fill", order.key().as_ref()],
-        bump,
-        space = 8 + OrderFill::LEN
-    )]
-    pub order_fill: Account<'info, OrderFill>,
+        emit!(UserBatchStatsClosed {
+            market: stats.market,
+            user: stats.user,
+            batch_id: stats.batch_id,
+        });
 
-    #[account(
-        mut,
-        constraint = vault_base.key() == market.vault_base
-    )]
-    pub vault_base: Account<'info, TokenAccount>,
+        Ok(())
+    }
 
-    #[account(
-        mut,
-        constraint = vault_quote.key() == market.vault_quote
-    )]
-    pub vault_quote: Account<'info, TokenAccount>,
+    /// Batch version of `close_user_batch_stats`: remaining_accounts are
+    /// `[UserBatchStats, user]` pairs, each stats account closed to its wallet. Pairs that
+    /// aren't closable yet, or whose user is a sub-account, are skipped.
+    pub fn sweep_user_batch_stats(ctx: Context<SweepUserBatchStats>) -> Result<()> {
+        let market_pk = ctx.accounts.market.key();
+        let current_batch_id = ctx.accounts.market.current_batch_id;
+        let pairs = ctx.remaining_accounts.chunks_exact(2);
+        require!(
+            pairs.remainder().is_empty(),
+            AmmError::InvalidRemainingAccountsLayout
+        );
 
-    #[account(
-        mut,
-        constraint = user_base_ata.owner == user.key(),
-        constraint = user_base_ata.mint == market.base_mint
-    )]
-    pub user_base_ata: Account<'info, TokenAccount>,
+        for pair in pairs {
+            let (stats_ai, user_ai) = (&pair[0], &pair[1]);
+            if stats_ai.data_is_empty() {
+                continue;
+            }
+            let stats: UserBatchStats = load_account(stats_ai)?;
+            require_keys_eq!(stats.market, market_pk, AmmError::BatchMarketMismatch);
+            require_keys_eq!(stats.user, *user_ai.key, AmmError::Unauthorized);
+            if *user_ai.owner == crate::ID || !stats.is_closable(current_batch_id) {
+                continue;
+            }
 
-    #[account(
-        mut,
-        constraint = user_quote_ata.owner == user.key(),
-        constraint = user_quote_ata.mint == market.quote_mint
-    )]
-    pub user_quote_ata: Account<'info, TokenAccount>,
+            close_program_account(stats_ai, user_ai)?;
+            emit!(UserBatchStatsClosed {
+                market: stats.market,
+                user: stats.user,
+                batch_id: stats.batch_id,
+            });
+        }
 
-    pub token_program: Program<'info, Token>,
-    // no #[account] attribute
-    pub system_program: Program<'info, System>,
-}
+        Ok(())
+    }
 
-#[derive(Accounts)]
-pub struct CancelOrder<'info> {
-    #[account(mut)]
-    pub user: Signer<'info>,
+    /// Drop entries whose orders are settled, cancelled or closed, e.g. after a keeper
+    /// settled without passing the index. Permissionless; remaining_accounts are the orders.
+    pub fn prune_claimables(ctx: Context<PruneClaimables>) -> Result<()> {
+        let claimables = &mut ctx.accounts.claimables;
+        for ai in ctx.remaining_accounts.iter() {
+            let done = if *ai.owner != crate::ID || ai.data_is_empty() {
+                true
+            } else {
+                let order: Order = load_account(ai)?;
+                order.has_flag(OrderFlags::FILLED) || order.has_flag(OrderFlags::CANCELLED)
+            };
+            if done {
+                claimables.remove(ai.key);
+            }
+        }
+        Ok(())
+    }
 
-    #[account(mut)]
-    pub market: Account<'info, Market>,
+    /// Create a "due work" account that markets publish their pending clears and
+    /// settlements to, so generic crank networks can find work without indexing.
+    pub fn create_work_queue(ctx: Context<CreateWorkQueue>, queue_id: u16) -> Result<()> {
+        let queue = &mut ctx.accounts.work_queue;
+        queue.authority = ctx.accounts.authority.key();
+        queue.queue_id = queue_id;
+        queue.items = Vec::new();
+        queue.bump = ctx.bumps.work_queue;
+        Ok(())
+    }
 
-    #[account(
-        mut,
-        constraint = order.user == user.key(),
-        constraint = order.market == market.key()
-    )]
-    pub order: Account<'info, Order>,
+    /// Publish a market's work to a queue (signed by the authority of both). Its open
+    /// batch is listed right away; `clear_batch` keeps the entries current from then on.
+    pub fn attach_work_queue(ctx: Context<AttachWorkQueue>) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        let queue = &mut ctx.accounts.work_queue;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+        require!(
+            market.work_queue == Pubkey::default(),
+            AmmError::WorkQueueAlreadySet
+        );
+        let markets = queue
+            .items
+            .iter()
+            .filter(|item| item.kind == WorkKind::Clear)
+            .count();
+        require!(markets < MAX_WORK_QUEUE_MARKETS, AmmError::WorkQueueFull);
+
+        market.work_queue = queue.key();
+        queue.schedule_clear(market.key(), market.current_batch_id, market.next_clear_slot());
+
+        emit!(WorkQueueAttached {
+            work_queue: queue.key(),
+            market: market.key(),
+        });
 
-    #[account(
-        mut,
-        constraint = vault_base.key() == market.vault_base
-    )]
-    pub vault_base: Account<'info, TokenAccount>,
+        Ok(())
+    }
+
+    /// Drop settle items whose batches have fully settled. Permissionless;
+    /// remaining_accounts are the `BatchState`s to check.
+    pub fn prune_work_queue(ctx: Context<PruneWorkQueue>) -> Result<()> {
+        let queue = &mut ctx.accounts.work_queue;
+        for ai in ctx.remaining_accounts.iter() {
+            let batch_state: BatchState = load_account(ai)?;
+            if batch_state.settled {
+                queue.items.retain(|item| {
+                    !(item.kind == WorkKind::Settle
+                        && item.market == batch_state.market
+                        && item.batch_id == batch_state.batch_id)
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Switch a market into (or out of) RFQ mode.
+    ///
+    /// In RFQ mode the authority is the requester and only it plus the whitelisted
+    /// responders can place orders. Batches are usually short (a few slots) so the
+    /// block trade clears quickly through the same uniform-price engine.
+    pub fn configure_rfq(
+        ctx: Context<ConfigureRfq>,
+        enabled: bool,
+        responders: Vec<Pubkey>,
+        batch_duration_slots: u64,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+        require!(market.mode != MarketMode::Launch, AmmError::InvalidMarketMode);
+        require!(
+            responders.len() <= MAX_RFQ_RESPONDERS,
+            AmmError::TooManyRfqResponders
+        );
+
+        let whitelist = &mut ctx.accounts.rfq_whitelist;
+        whitelist.market = market.key();
+        whitelist.requester = market.authority;
+        whitelist.responders = responders;
+        whitelist.bump = ctx.bumps.rfq_whitelist;
+
+        market.mode = if enabled { MarketMode::Rfq } else { MarketMode::Standard };
+        market.batch_duration_slots = batch_duration_slots;
+
+        emit!(RfqConfigured {
+            market: market.key(),
+            requester: whitelist.requester,
+            enabled,
+            responder_count: whitelist.responders.len() as u8,
+            batch_duration_slots,
+        });
+
+        Ok(())
+    }
+
+    /// Turn a fresh market into a single-sided launch auction.
+    ///
+    /// The authority deposits `total_supply_base_fp` of base into the vault. Over the next
+    /// `num_batches` clears, each batch offers `unsold / batches_remaining` as a synthetic
+    /// ask at `reserve_price_fp`; users may only place bids. Raised quote accrues to
+    /// `beneficiary` and is paid out with `claim_launch_proceeds`.
+    pub fn configure_launch<'info>(
+        ctx: Context<'_, '_, '_, 'info, ConfigureLaunch<'info>>,
+        total_supply_base_fp: u64,
+        num_batches: u32,
+        reserve_price_fp: u64,
+        beneficiary: Pubkey,
+        vesting_batches: u32,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+        require!(market.mode == MarketMode::Standard, AmmError::InvalidMarketMode);
+        require!(total_supply_base_fp > 0, AmmError::InvalidAmount);
+        require!(num_batches > 0, AmmError::InvalidAmount);
+        require!(reserve_price_fp > 0, AmmError::InvalidPrice);
+
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.authority_base_ata.to_account_info(),
+            mint: ctx.accounts.base_mint.to_account_info(),
+            to: ctx.accounts.vault_base.to_account_info(),
+            authority: ctx.accounts.authority.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts)
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec());
+        transfer_checked_with_hook(
+            cpi_ctx,
+            &market.transfer_hook_programs,
+            total_supply_base_fp,
+            ctx.accounts.base_mint.decimals,
+        )?;
+
+        market.mode = MarketMode::Launch;
+        market.launch_beneficiary = beneficiary;
+        market.launch_reserve_price_fp = reserve_price_fp;
+        market.launch_unsold_base_fp = total_supply_base_fp;
+        market.launch_batches_remaining = num_batches;
+        market.launch_proceeds_quote_fp = 0;
+        market.launch_vesting_batches = vesting_batches;
+
+        emit!(LaunchConfigured {
+            market: market.key(),
+            beneficiary,
+            total_supply_base_fp,
+            num_batches,
+            reserve_price_fp,
+            vesting_batches,
+        });
+
+        Ok(())
+    }
+
+    /// Pay accrued launch proceeds (quote) to the beneficiary. Once every launch batch
+    /// has cleared, any unsold base is returned to the beneficiary as well.
+    ///
+    /// Permissionless: destinations are pinned to the beneficiary's token accounts.
+    pub fn claim_launch_proceeds<'info>(
+        ctx: Context<'_, '_, '_, 'info, ClaimLaunchProceeds<'info>>,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require!(market.mode == MarketMode::Launch, AmmError::InvalidMarketMode);
+
+        let proceeds_quote_fp = market.launch_proceeds_quote_fp as u64;
+        let unsold_base_fp = if market.launch_batches_remaining == 0 {
+            market.launch_unsold_base_fp
+        } else {
+            0
+        };
+
+        let authority_key = market.authority;
+        let base_mint_key = market.base_mint;
+        let quote_mint_key = market.quote_mint;
+        let bump = market.bump;
+
+        let token_program_ai = ctx.accounts.token_program.to_account_info();
+        let market_seeds: &[&[u8]] = &[
+            MARKET_SEED,
+            authority_key.as_ref(),
+            base_mint_key.as_ref(),
+            quote_mint_key.as_ref(),
+            &[bump],
+        ];
+        let signer_seeds: &[&[&[u8]]] = &[market_seeds];
+
+        if proceeds_quote_fp > 0 {
+            let cpi_accounts = TransferChecked {
+                from: ctx.accounts.vault_quote.to_account_info(),
+                mint: ctx.accounts.quote_mint.to_account_info(),
+                to: ctx.accounts.beneficiary_quote_ata.to_account_info(),
+                authority: market.to_account_info(),
+            };
+            let cpi_ctx =
+                CpiContext::new_with_signer(token_program_ai.clone(), cpi_accounts, signer_seeds)
+                    .with_remaining_accounts(ctx.remaining_accounts.to_vec());
+            transfer_checked_with_hook(
+                cpi_ctx,
+                &market.transfer_hook_programs,
+                proceeds_quote_fp,
+                ctx.accounts.quote_mint.decimals,
+            )?;
+        }
+
+        if unsold_base_fp > 0 {
+            let cpi_accounts = TransferChecked {
+                from: ctx.accounts.vault_base.to_account_info(),
+                mint: ctx.accounts.base_mint.to_account_info(),
+                to: ctx.accounts.beneficiary_base_ata.to_account_info(),
+                authority: market.to_account_info(),
+            };
+            let cpi_ctx = CpiContext::new_with_signer(token_program_ai, cpi_accounts, signer_seeds)
+                .with_remaining_accounts(ctx.remaining_accounts.to_vec());
+            transfer_checked_with_hook(
+                cpi_ctx,
+                &market.transfer_hook_programs,
+                unsold_base_fp,
+                ctx.accounts.base_mint.decimals,
+            )?;
+        }
+
+        market.launch_proceeds_quote_fp = 0;
+        market.launch_unsold_base_fp = market
+            .launch_unsold_base_fp
+            .checked_sub(unsold_base_fp)
+            .ok_or(AmmError::MathOverflow)?;
+
+        emit!(LaunchProceedsClaimed {
+            market: market.key(),
+            beneficiary: market.launch_beneficiary,
+            proceeds_quote_fp,
+            unsold_base_fp,
+        });
+
+        Ok(())
+    }
+
+    /// Release the vested launch base unlocked since the last claim. One tranche unlocks
+    /// per batch cleared after the fill's batch; the schedule closes once fully paid.
+    pub fn claim_tranche<'info>(
+        ctx: Context<'_, '_, '_, 'info, ClaimTranche<'info>>,
+    ) -> Result<()> {
+        let market = &ctx.accounts.market;
+        let vesting = &mut ctx.accounts.vesting;
+
+        let unlocked_base_fp = vesting.unlocked_base_fp(market.current_batch_id);
+        let claim_base_fp = unlocked_base_fp.saturating_sub(vesting.claimed_base_fp);
+        require!(claim_base_fp > 0, AmmError::NothingVested);
+
+        let authority_key = market.authority;
+        let base_mint_key = market.base_mint;
+        let quote_mint_key = market.quote_mint;
+        let bump = market.bump;
+        let market_seeds: &[&[u8]] = &[
+            MARKET_SEED,
+            authority_key.as_ref(),
+            base_mint_key.as_ref(),
+            quote_mint_key.as_ref(),
+            &[bump],
+        ];
+        let signer_seeds: &[&[&[u8]]] = &[market_seeds];
+
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.vault_base.to_account_info(),
+            mint: ctx.accounts.base_mint.to_account_info(),
+            to: ctx.accounts.user_base_ata.to_account_info(),
+            authority: market.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            cpi_accounts,
+            signer_seeds,
+        )
+        .with_remaining_accounts(ctx.remaining_accounts.to_vec());
+        transfer_checked_with_hook(
+            cpi_ctx,
+            &market.transfer_hook_programs,
+            claim_base_fp,
+            ctx.accounts.base_mint.decimals,
+        )?;
+
+        vesting.claimed_base_fp = unlocked_base_fp;
+
+        emit!(TrancheClaimed {
+            market: market.key(),
+            order: vesting.order,
+            user: vesting.user,
+            claimed_base_fp: claim_base_fp,
+            remaining_base_fp: vesting.total_base_fp - vesting.claimed_base_fp,
+        });
+
+        if vesting.claimed_base_fp == vesting.total_base_fp {
+            vesting.close(ctx.accounts.user.to_account_info())?;
+        }
+
+        Ok(())
+    }
+
+    /// Admin function to tweak per-user participation limits.
+    pub fn set_user_limits(
+        ctx: Context<SetParams>,
+        max_orders_per_user_per_batch: u32,
+        max_active_batches_per_user: u32,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+
+        market.max_orders_per_user_per_batch = max_orders_per_user_per_batch;
+        market.max_active_batches_per_user = max_active_batches_per_user;
+
+        emit!(UserLimitsUpdated {
+            market: market.key(),
+            max_orders_per_user_per_batch,
+            max_active_batches_per_user,
+        });
+
+        Ok(())
+    }
+
+    /// Admin function to configure keeper clear intents.
+    ///
+    /// `window_slots == 0` disables new intents.
+    pub fn set_clear_intent_params(
+        ctx: Context<SetParams>,
+        bond_lamports: u64,
+        window_slots: u64,
+        bonus_bps: u16,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+        require!(bonus_bps as u64 <= BPS_DENOM, AmmError::InvalidFeeBps);
+
+        market.intent_bond_lamports = bond_lamports;
+        market.intent_window_slots = window_slots;
+        market.intent_bonus_bps = bonus_bps;
+
+        emit!(ClearIntentParamsUpdated {
+            market: market.key(),
+            bond_lamports,
+            window_slots,
+            bonus_bps,
+        });
+
+        Ok(())
+    }
+
+    /// Keeper pre-commits to clear the current batch within `intent_window_slots` of it
+    /// becoming eligible, posting `intent_bond_lamports` as a bond.
+    ///
+    /// During the window only this keeper may clear (and earns `intent_bonus_bps` on top
+    /// of the keeper fee). After it, anyone may clear and collects the bond.
+    pub fn register_clear_intent(ctx: Context<RegisterClearIntent>) -> Result<()> {
+        let clock = ctx.accounts.market.clock()?;
+        let market = &mut ctx.accounts.market;
+        let keeper = &ctx.accounts.keeper;
+
+        require!(!market.is_paused(PauseModes::CLEARING), AmmError::MarketPaused);
+        require!(market.intent_window_slots > 0, AmmError::ClearIntentsDisabled);
+        if market.has_flag(MarketFlags::KEEPER_RESTRICTED) {
+            require_keys_eq!(market.only_keeper, keeper.key(), AmmError::KeeperNotAllowed);
+        }
+        require!(
+            market.intent_keeper == Pubkey::default()
+                || market.intent_batch_id != market.current_batch_id,
+            AmmError::ClearIntentAlreadyRegistered
+        );
+        // Must commit before the batch becomes clearable.
+        require!(
+            clock.slot < market.next_clear_slot(),
+            AmmError::BatchAlreadyClosed
+        );
+
+        if market.intent_bond_lamports > 0 {
+            let cpi_ctx = CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: keeper.to_account_info(),
+                    to: ctx.accounts.clear_intent.to_account_info(),
+                },
+            );
+            system_program::transfer(cpi_ctx, market.intent_bond_lamports)?;
+        }
+
+        let intent = &mut ctx.accounts.clear_intent;
+        intent.market = market.key();
+        intent.batch_id = market.current_batch_id;
+        intent.keeper = keeper.key();
+        intent.bond_lamports = market.intent_bond_lamports;
+        intent.registered_slot = clock.slot;
+        intent.bump = ctx.bumps.clear_intent;
+
+        market.intent_keeper = keeper.key();
+        market.intent_batch_id = market.current_batch_id;
+        market.intent_rebate_bps = 0;
+
+        emit!(ClearIntentRegistered {
+            market: market.key(),
+            batch_id: intent.batch_id,
+            keeper: intent.keeper,
+            bond_lamports: intent.bond_lamports,
+            window_end_slot: market
+                .next_clear_slot()
+                .saturating_add(market.intent_window_slots),
+        });
+
+        Ok(())
+    }
+
+    /// Take over the current batch's clear intent by rebating more of the keeper reward to
+    /// its traders: `rebate_bps` must beat the standing bid and is taken off both the
+    /// keeper reward and the batch's protocol fees. The outbid keeper gets its bond back
+    /// (not the intent's rent); the new one posts `intent_bond_lamports`.
+    pub fn outbid_clear_intent(ctx: Context<OutbidClearIntent>, rebate_bps: u16) -> Result<()> {
+        let clock = ctx.accounts.market.clock()?;
+        let market = &mut ctx.accounts.market;
+        let keeper = &ctx.accounts.keeper;
+
+        require!(!market.is_paused(PauseModes::CLEARING), AmmError::MarketPaused);
+        if market.has_flag(MarketFlags::KEEPER_RESTRICTED) {
+            require_keys_eq!(market.only_keeper, keeper.key(), AmmError::KeeperNotAllowed);
+        }
+        require!(
+            clock.slot < market.next_clear_slot(),
+            AmmError::BatchAlreadyClosed
+        );
+        require!(
+            rebate_bps > market.intent_rebate_bps
+                && rebate_bps <= market.keeper_fee_bps.saturating_add(market.intent_bonus_bps),
+            AmmError::ClearIntentBidTooLow
+        );
+
+        let intent = &mut ctx.accounts.clear_intent;
+        let previous_keeper = intent.keeper;
+        if intent.bond_lamports > 0 {
+            intent.to_account_info().sub_lamports(intent.bond_lamports)?;
+            ctx.accounts
+                .previous_keeper
+                .to_account_info()
+                .add_lamports(intent.bond_lamports)?;
+        }
+        if market.intent_bond_lamports > 0 {
+            let cpi_ctx = CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: keeper.to_account_info(),
+                    to: intent.to_account_info(),
+                },
+            );
+            system_program::transfer(cpi_ctx, market.intent_bond_lamports)?;
+        }
+
+        intent.keeper = keeper.key();
+        intent.bond_lamports = market.intent_bond_lamports;
+        intent.registered_slot = clock.slot;
+
+        market.intent_keeper = keeper.key();
+        market.intent_rebate_bps = rebate_bps;
+
+        emit!(ClearIntentOutbid {
+            market: market.key(),
+            batch_id: intent.batch_id,
+            previous_keeper,
+            keeper: intent.keeper,
+            rebate_bps,
+        });
+
+        Ok(())
+    }
+
+    /// Admin function to cap the clearing-price search. Batches with more than
+    /// `max_candidate_prices` distinct limit prices clear on the `tick_size_fp` grid instead;
+    /// if even the grid exceeds the cap (or no grid is set) the clear fails.
+    pub fn set_price_grid(
+        ctx: Context<SetParams>,
+        tick_size_fp: u64,
+        max_candidate_prices: u16,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+
+        market.tick_size_fp = tick_size_fp;
+        market.max_candidate_prices = max_candidate_prices;
+
+        emit!(PriceGridUpdated {
+            market: market.key(),
+            tick_size_fp,
+            max_candidate_prices,
+        });
+
+        Ok(())
+    }
+
+    /// Admin function to require a clear bond: each clearer posts `bond_lamports`, slashed
+    /// to whoever proves the clear wrong with `audit_batch` within `dispute_window_slots`.
+    /// The window must close before settlement opens (see `set_settlement_delay`).
+    pub fn set_clear_bond(
+        ctx: Context<SetParams>,
+        bond_lamports: u64,
+        dispute_window_slots: u64,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+        require!(
+            dispute_window_slots == 0 || market.settlement_delay_slots > dispute_window_slots,
+            AmmError::DisputeWindowOutlastsSettlementDelay
+        );
+
+        market.clear_bond_lamports = bond_lamports;
+        market.dispute_window_slots = dispute_window_slots;
+
+        emit!(ClearBondUpdated {
+            market: market.key(),
+            bond_lamports,
+            dispute_window_slots,
+        });
+
+        Ok(())
+    }
+
+    /// Admin function to quote prices as if the quote token had 6 decimals, so a 9-decimal
+    /// stable market reads the same as a 6-decimal one. Only while the open batch is
+    /// empty, since placed deposits were sized with the old scale.
+    pub fn set_quote_normalization(
+        ctx: Context<SetQuoteNormalization>,
+        enabled: bool,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+        require!(market.global_orders_in_batch == 0, AmmError::QuoteScaleLocked);
+
+        let quote_decimals = ctx.accounts.quote_mint.decimals;
+        market.quote_scale_fp = if enabled {
+            10u64
+                .checked_pow(quote_decimals as u32)
+                .ok_or(AmmError::MathOverflow)?
+        } else {
+            PRICE_SCALE
+        };
+
+        emit!(QuoteNormalizationUpdated {
+            market: market.key(),
+            quote_decimals,
+            quote_scale_fp: market.quote_scale_fp,
+        });
+
+        Ok(())
+    }
+
+    /// Admin function to hold settlement for `settlement_delay_slots` after each clear, so
+    /// `audit_batch` challenges can land before any funds move. `0` settles immediately.
+    /// With a dispute window set, the delay must outlast it.
+    pub fn set_settlement_delay(
+        ctx: Context<SetParams>,
+        settlement_delay_slots: u64,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+        require!(
+            market.dispute_window_slots == 0
+                || settlement_delay_slots > market.dispute_window_slots,
+            AmmError::DisputeWindowOutlastsSettlementDelay
+        );
+
+        market.settlement_delay_slots = settlement_delay_slots;
+
+        emit!(SettlementDelayUpdated {
+            market: market.key(),
+            settlement_delay_slots,
+        });
+
+        Ok(())
+    }
+
+    /// Admin function to arm the volume circuit breaker: a batch whose matched quote
+    /// exceeds `max_volume_multiple` times the trailing average needs `confirm_batch`
+    /// before it settles. `0` disables it.
+    pub fn set_volume_breaker(ctx: Context<SetParams>, max_volume_multiple: u16) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+
+        market.max_volume_multiple = max_volume_multiple;
+
+        emit!(VolumeBreakerUpdated {
+            market: market.key(),
+            max_volume_multiple,
+        });
+
+        Ok(())
+    }
+
+    /// Admin function to set the per-clear lamport reimbursement paid to clearers out of
+    /// the market's `ReimbursementVault`. `0` disables it.
+    pub fn set_clear_reimbursement(
+        ctx: Context<SetParams>,
+        lamports_per_clear: u64,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+
+        market.clear_reimbursement_lamports = lamports_per_clear;
+
+        emit!(ClearReimbursementUpdated {
+            market: market.key(),
+            lamports_per_clear,
+        });
+
+        Ok(())
+    }
+
+    /// Top up the market's `ReimbursementVault` (created on first use). Anyone may fund
+    /// it; typically the market authority.
+    pub fn fund_reimbursement_vault(
+        ctx: Context<FundReimbursementVault>,
+        lamports: u64,
+    ) -> Result<()> {
+        let vault = &mut ctx.accounts.reimbursement_vault;
+        vault.market = ctx.accounts.market.key();
+        vault.bump = ctx.bumps.reimbursement_vault;
+
+        let cpi_ctx = CpiContext::new(
+            ctx.accounts.system_program.to_account_info(),
+            system_program::Transfer {
+                from: ctx.accounts.funder.to_account_info(),
+                to: vault.to_account_info(),
+            },
+        );
+        system_program::transfer(cpi_ctx, lamports)?;
+
+        emit!(ReimbursementVaultFunded {
+            market: vault.market,
+            funder: ctx.accounts.funder.key(),
+            lamports,
+        });
+
+        Ok(())
+    }
+
+    /// Admin function to require bundle-based clears.
+    ///
+    /// When enabled, `clear_batch` must be in a transaction that also contains a System
+    /// Program transfer of at least `min_tip_lamports` to `tip_account` (e.g. a Jito tip
+    /// account), verified through the instructions sysvar.
+    pub fn set_mev_protection(
+        ctx: Context<SetParams>,
+        require_tip: bool,
+        tip_account: Pubkey,
+        min_tip_lamports: u64,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+        if require_tip {
+            require_keys_neq!(tip_account, Pubkey::default(), AmmError::InvalidTipAccount);
+        }
+
+        market.set_flag(MarketFlags::REQUIRE_CLEAR_TIP, require_tip);
+        market.clear_tip_account = tip_account;
+        market.min_clear_tip_lamports = min_tip_lamports;
+
+        emit!(MevProtectionUpdated {
+            market: market.key(),
+            require_tip,
+            tip_account,
+            min_tip_lamports,
+        });
+
+        Ok(())
+    }
+
+    /// Schedule a promotional fee window: batches `start_batch..=end_batch` accrue protocol
+    /// fees at `fee_bps_override` instead of `protocol_fee_bps`, then revert automatically.
+    pub fn schedule_fee_holiday(
+        ctx: Context<SetParams>,
+        start_batch: u64,
+        end_batch: u64,
+        fee_bps_override: u16,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+        require!(
+            start_batch <= end_batch && end_batch >= market.current_batch_id,
+            AmmError::InvalidFeeHoliday
+        );
+        require!(
+            fee_bps_override <= market.protocol_fee_bps,
+            AmmError::InvalidFeeBps
+        );
+
+        market.set_flag(MarketFlags::FEE_HOLIDAY_ACTIVE, true);
+        market.fee_holiday_start_batch = start_batch;
+        market.fee_holiday_end_batch = end_batch;
+        market.fee_holiday_bps = fee_bps_override;
+
+        emit!(FeeHolidayScheduled {
+            market: market.key(),
+            start_batch,
+            end_batch,
+            fee_bps_override,
+        });
+
+        Ok(())
+    }
+
+    /// Drop a scheduled (or running) fee holiday.
+    pub fn cancel_fee_holiday(ctx: Context<SetParams>) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+        market.set_flag(MarketFlags::FEE_HOLIDAY_ACTIVE, false);
+
+        emit!(FeeHolidayCancelled {
+            market: market.key(),
+        });
+
+        Ok(())
+    }
+
+    /// Admin function to enable the imbalance fee model.
+    ///
+    /// At settlement the heavier side at the clearing price (the one consuming scarce
+    /// liquidity) pays up to `imbalance_fee_bps` more and the scarce side up to that much
+    /// less, scaled by `|bid_vol - ask_vol| / (bid_vol + ask_vol)`. `0` disables it.
+    pub fn set_imbalance_fee(ctx: Context<SetParams>, imbalance_fee_bps: u16) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+        require!(imbalance_fee_bps <= market.fee_bps, AmmError::InvalidFeeBps);
+
+        market.imbalance_fee_bps = imbalance_fee_bps;
+
+        emit!(ImbalanceFeeUpdated {
+            market: market.key(),
+            imbalance_fee_bps,
+        });
+
+        Ok(())
+    }
+
+    /// Configure the late-cancel fee: cancellations within `window_slots` of the batch
+    /// close forfeit `fee_bps` of their deposit to the protocol (admin only).
+    /// A zero fee disables it.
+    pub fn set_late_cancel_fee(
+        ctx: Context<SetParams>,
+        window_slots: u64,
+        fee_bps: u16,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+        require!(fee_bps <= market.fee_bps, AmmError::InvalidFeeBps);
+        require!(
+            window_slots <= market.batch_duration_slots,
+            AmmError::InvalidLateCancelWindow
+        );
+
+        market.late_cancel_window_slots = window_slots;
+        market.late_cancel_fee_bps = fee_bps;
+
+        emit!(LateCancelFeeUpdated {
+            market: market.key(),
+            window_slots,
+            fee_bps,
+        });
+
+        Ok(())
+    }
+
+    /// Require a batch to reach `min_batch_orders` orders or `min_batch_notional_quote_fp`
+    /// notional before it clears (admin only; 0 turns a threshold off). Thin batches are
+    /// extended with `extend_batch` rather than setting a misleading reference price, at
+    /// most `max_batch_extensions` times (required with a threshold), so orders in an
+    /// illiquid market are never trapped.
+    pub fn set_participation_threshold(
+        ctx: Context<SetParams>,
+        min_batch_orders: u32,
+        min_batch_notional_quote_fp: u128,
+        max_batch_extensions: u32,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+        require!(
+            (min_batch_orders == 0 && min_batch_notional_quote_fp == 0)
+                || max_batch_extensions > 0,
+            AmmError::InvalidParticipationThreshold
+        );
+
+        market.min_batch_orders = min_batch_orders;
+        market.min_batch_notional_quote_fp = min_batch_notional_quote_fp;
+        market.max_batch_extensions = max_batch_extensions;
+
+        emit!(ParticipationThresholdUpdated {
+            market: market.key(),
+            min_batch_orders,
+            min_batch_notional_quote_fp,
+            max_batch_extensions,
+        });
+
+        Ok(())
+    }
+
+    /// Cap how many batches the market can clear per Solana epoch (admin only; 0 lifts
+    /// the cap). Further clears in the epoch fail with `ClearBudgetExhausted`.
+    pub fn set_clear_throttle(ctx: Context<SetParams>, max_clears_per_epoch: u32) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+
+        market.max_clears_per_epoch = max_clears_per_epoch;
+
+        emit!(ClearThrottleUpdated {
+            market: market.key(),
+            max_clears_per_epoch,
+        });
+
+        Ok(())
+    }
+
+    /// Report only a size bucket instead of the exact amount in `OrderPlaced` (admin
+    /// only). Exact fills still surface at clear and settlement.
+    pub fn set_size_bucketing(ctx: Context<SetParams>, enabled: bool) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+
+        market.set_flag(MarketFlags::BUCKET_ORDER_SIZES, enabled);
+
+        emit!(SizeBucketingUpdated {
+            market: market.key(),
+            enabled,
+        });
+
+        Ok(())
+    }
+
+    /// Admin function to configure the vault yield adapter.
+    ///
+    /// `yield_program` is the only program the market PDA will sign for in
+    /// `sweep_to_yield` / `recall_from_yield`; `sweep_bps` caps the share of the quote
+    /// vault that may be deployed. `sweep_bps == 0` disables new sweeps.
+    pub fn set_yield_config(
+        ctx: Context<SetParams>,
+        yield_program: Pubkey,
+        sweep_bps: u16,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+        require!(sweep_bps as u64 <= BPS_DENOM, AmmError::InvalidFeeBps);
+        // Can't swap adapters while funds are out.
+        require!(
+            market.yield_deployed_quote_fp == 0 || market.yield_program == yield_program,
+            AmmError::YieldNotRecalled
+        );
+
+        market.yield_program = yield_program;
+        market.yield_sweep_bps = sweep_bps;
+
+        emit!(YieldConfigUpdated {
+            market: market.key(),
+            yield_program,
+            sweep_bps,
+        });
+
+        Ok(())
+    }
+
+    /// Set the Token-2022 transfer-hook programs this market's mints may invoke (admin
+    /// only). `place_order`, `settle_order` and `cancel_order` refuse to move a hooked mint
+    /// whose hook program isn't listed, so an empty list admits only mints without hooks.
+    pub fn set_transfer_hook_programs(
+        ctx: Context<SetParams>,
+        programs: Vec<Pubkey>,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+        require!(
+            programs.len() <= MAX_TRANSFER_HOOK_PROGRAMS,
+            AmmError::TooManyTransferHookPrograms
+        );
+
+        market.transfer_hook_programs = programs;
+
+        emit!(TransferHookProgramsUpdated {
+            market: market.key(),
+            programs: market.transfer_hook_programs.clone(),
+        });
+
+        Ok(())
+    }
+
+    /// Pin the slot this market's handlers see to `slot` (admin only; 0 returns to the
+    /// Clock sysvar), so integration tests step batch boundaries deterministically instead
+    /// of warping the validator. Fails with `TestClockDisabled` unless built with the
+    /// `test-clock` feature, which must never be deployed.
+    pub fn set_test_clock(ctx: Context<SetParams>, slot: u64) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+        require!(cfg!(feature = "test-clock"), AmmError::TestClockDisabled);
+
+        market.test_clock_slot = slot;
+
+        emit!(TestClockSet {
+            market: market.key(),
+            slot,
+        });
+
+        Ok(())
+    }
+
+    /// Deposit idle quote into the configured lending program.
+    ///
+    /// `ix_data` is the adapter's deposit instruction; remaining_accounts are its accounts
+    /// (the market PDA signs). The vault balance drop is measured and must stay within
+    /// `yield_sweep_bps` of the vault.
+    pub fn sweep_to_yield<'info>(
+        ctx: Context<'_, '_, '_, 'info, YieldCpi<'info>>,
+        ix_data: Vec<u8>,
+    ) -> Result<()> {
+        let market = &ctx.accounts.market;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+        require!(market.yield_sweep_bps > 0, AmmError::YieldDisabled);
+
+        let before = ctx.accounts.vault_quote.amount;
+        let max_deployed = (before as u128 + market.yield_deployed_quote_fp as u128)
+            * market.yield_sweep_bps as u128
+            / BPS_DENOM as u128;
+
+        yield_cpi(
+            &ctx.accounts.market,
+            &ctx.accounts.yield_program,
+            ctx.remaining_accounts,
+            ix_data,
+        )?;
+
+        ctx.accounts.vault_quote.reload()?;
+        let after = ctx.accounts.vault_quote.amount;
+        let swept = before.checked_sub(after).ok_or(AmmError::YieldAccountingMismatch)?;
+
+        let market = &mut ctx.accounts.market;
+        let deployed = market
+            .yield_deployed_quote_fp
+            .checked_add(swept)
+            .ok_or(AmmError::MathOverflow)?;
+        require!(deployed as u128 <= max_deployed, AmmError::YieldSweepTooLarge);
+        market.yield_deployed_quote_fp = deployed;
+
+        emit!(YieldSwept {
+            market: market.key(),
+            amount_quote_fp: swept,
+            deployed_quote_fp: deployed,
+        });
+
+        Ok(())
+    }
+
+    /// Withdraw from the lending program back into the quote vault.
+    ///
+    /// Anything returned above the deployed principal is yield and accrues to the
+    /// insurance fund (`insurance_fund_quote_fp`, held in the quote vault).
+    pub fn recall_from_yield<'info>(
+        ctx: Context<'_, '_, '_, 'info, YieldCpi<'info>>,
+        ix_data: Vec<u8>,
+    ) -> Result<()> {
+        let market = &ctx.accounts.market;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+
+        let before = ctx.accounts.vault_quote.amount;
+
+        yield_cpi(
+            &ctx.accounts.market,
+            &ctx.accounts.yield_program,
+            ctx.remaining_accounts,
+            ix_data,
+        )?;
+
+        ctx.accounts.vault_quote.reload()?;
+        let after = ctx.accounts.vault_quote.amount;
+        let returned = after.checked_sub(before).ok_or(AmmError::YieldAccountingMismatch)?;
+
+        let market = &mut ctx.accounts.market;
+        let principal = returned.min(market.yield_deployed_quote_fp);
+        let yield_quote_fp = returned - principal;
+        market.yield_deployed_quote_fp -= principal;
+        market.insurance_fund_quote_fp = market
+            .insurance_fund_quote_fp
+            .checked_add(yield_quote_fp as u128)
+            .ok_or(AmmError::MathOverflow)?;
+
+        emit!(YieldRecalled {
+            market: market.key(),
+            principal_quote_fp: principal,
+            yield_quote_fp,
+            deployed_quote_fp: market.yield_deployed_quote_fp,
+        });
+
+        Ok(())
+    }
+
+    /// Register (or replace) the market's external liquidity adapter.
+    ///
+    /// `program` must implement the adapter interface (`get_quote`, see `LiquiditySource`);
+    /// `accounts` are the accounts it needs, passed by keepers after the order triplets.
+    pub fn register_liquidity_adapter(
+        ctx: Context<RegisterLiquidityAdapter>,
+        program: Pubkey,
+        accounts: Vec<Pubkey>,
+    ) -> Result<()> {
+        let market = &ctx.accounts.market;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+        require!(
+            accounts.len() <= MAX_ADAPTER_ACCOUNTS,
+            AmmError::InvalidLiquidityAdapter
+        );
+
+        let adapter = &mut ctx.accounts.liquidity_adapter;
+        adapter.market = market.key();
+        adapter.program = program;
+        adapter.accounts = accounts;
+        adapter.bump = ctx.bumps.liquidity_adapter;
+
+        emit!(LiquidityAdapterRegistered {
+            market: market.key(),
+            program,
+            account_count: adapter.accounts.len() as u8,
+        });
+
+        Ok(())
+    }
+
+    /// Swap the batch's residual imbalance through the registered liquidity adapter.
+    ///
+    /// Finalize step between `clear_batch` and settlement, callable once per batch by the
+    /// clearing keeper or the market authority. `ix_data` is the adapter's swap instruction;
+    /// remaining_accounts are its accounts (the market PDA signs). Vault deltas are
+    /// measured and must be no worse than the clearing price; unmatched orders on the
+    /// residual side then receive their pro-rata share in `settle_order`.
+    pub fn route_residual<'info>(
+        ctx: Context<'_, '_, '_, 'info, RouteResidual<'info>>,
+        ix_data: Vec<u8>,
+    ) -> Result<()> {
+        let market = &ctx.accounts.market;
+        let batch_state = &ctx.accounts.batch_state;
+        let signer = ctx.accounts.keeper.key();
+
+        require!(!market.is_paused(PauseModes::CLEARING), AmmError::MarketPaused);
+        require!(
+            signer == batch_state.keeper || signer == market.authority,
+            AmmError::Unauthorized
+        );
+        require!(
+            batch_state.market == market.key(),
+            AmmError::BatchMarketMismatch
+        );
+        require!(
+            batch_state.clearing_price_fp > 0,
+            AmmError::BatchNotCleared
+        );
+        require!(batch_state.routable_base_fp > 0, AmmError::NoResidualToRoute);
+        require!(!batch_state.residual_routed, AmmError::ResidualAlreadyRouted);
+        require!(!batch_state.disputed, AmmError::BatchDisputed);
+        require!(
+            market.clock()?.slot >= batch_state.settlement_open_slot,
+            AmmError::SettlementNotOpen
+        );
+
+        let base_before = ctx.accounts.vault_base.amount;
+        let quote_before = ctx.accounts.vault_quote.amount;
+
+        market_signed_cpi(
+            &ctx.accounts.market,
+            &ctx.accounts.adapter_program.to_account_info(),
+            ctx.remaining_accounts,
+            ix_data,
+        )?;
+
+        ctx.accounts.vault_base.reload()?;
+        ctx.accounts.vault_quote.reload()?;
+        let base_after = ctx.accounts.vault_base.amount;
+        let quote_after = ctx.accounts.vault_quote.amount;
+
+        let batch_state = &mut ctx.accounts.batch_state;
+        // Compared as `quote * scale <= base * price`, with the quote side normalized by
+        // the market's quote scale.
+        let price_fp = (batch_state.clearing_price_fp as u128)
+            .checked_mul(ctx.accounts.market.quote_scale_fp as u128)
+            .ok_or(AmmError::MathOverflow)?;
+        let scale = PRICE_SCALE as u128 * PRICE_SCALE as u128;
+        let (routed_base_fp, routed_quote_fp) = match batch_state.residual_side {
+            // Excess bids: quote out, base in, paying no more than the clearing price.
+            OrderSide::Bid => {
+                let base_in = base_after
+                    .checked_sub(base_before)
+                    .ok_or(AmmError::ResidualRouteMismatch)?;
+                let quote_out = quote_before
+                    .checked_sub(quote_after)
+                    .ok_or(AmmError::ResidualRouteMismatch)?;
+                require!(
+                    (quote_out as u128) * scale
+                        <= (base_in as u128)
+                            .checked_mul(price_fp)
+                            .ok_or(AmmError::MathOverflow)?,
+                    AmmError::ResidualRouteMismatch
+                );
+                (base_in, quote_out)
+            }
+            // Excess asks: base out, quote in, receiving at least the clearing price.
+            OrderSide::Ask => {
+                let base_out = base_before
+                    .checked_sub(base_after)
+                    .ok_or(AmmError::ResidualRouteMismatch)?;
+                let quote_in = quote_after
+                    .checked_sub(quote_before)
+                    .ok_or(AmmError::ResidualRouteMismatch)?;
+                require!(
+                    (quote_in as u128) * scale
+                        >= (base_out as u128)
+                            .checked_mul(price_fp)
+                            .ok_or(AmmError::MathOverflow)?,
+                    AmmError::ResidualRouteMismatch
+                );
+                (base_out, quote_in)
+            }
+        };
+        require!(
+            routed_base_fp <= batch_state.routable_base_fp,
+            AmmError::ResidualRouteMismatch
+        );
+
+        batch_state.residual_routed = true;
+        batch_state.routed_base_fp = routed_base_fp;
+        batch_state.routed_quote_fp = routed_quote_fp;
+        batch_state.routed_base_remaining_fp = routed_base_fp;
+        batch_state.routed_quote_remaining_fp = routed_quote_fp;
+
+        emit!(ResidualRouted {
+            market: batch_state.market,
+            batch_id: batch_state.batch_id,
+            residual_side: batch_state.residual_side,
+            routed_base_fp,
+            routed_quote_fp,
+        });
+
+        Ok(())
+    }
+
+    /// Admin function to set the market's display metadata (name, tags, off-chain URI).
+    pub fn set_market_metadata(
+        ctx: Context<SetMarketMetadata>,
+        name: String,
+        tags: Vec<String>,
+        uri: String,
+    ) -> Result<()> {
+        let market = &ctx.accounts.market;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+        require!(
+            name.len() <= MAX_MARKET_NAME_LEN
+                && uri.len() <= MAX_MARKET_URI_LEN
+                && tags.len() <= MAX_MARKET_TAGS
+                && tags.iter().all(|t| t.len() <= MAX_MARKET_TAG_LEN),
+            AmmError::MetadataTooLong
+        );
+
+        let metadata = &mut ctx.accounts.market_metadata;
+        metadata.market = market.key();
+        metadata.name = name;
+        metadata.tags = tags;
+        metadata.uri = uri;
+        metadata.bump = ctx.bumps.market_metadata;
+
+        emit!(MarketMetadataUpdated {
+            market: market.key(),
+            name: metadata.name.clone(),
+            tags: metadata.tags.clone(),
+            uri: metadata.uri.clone(),
+        });
+
+        Ok(())
+    }
+
+    /// Configure the user's settlement co-signer (e.g. a compliance key).
+    ///
+    /// Orders placed while a co-signer is set cannot settle a fill above
+    /// `threshold_quote_fp` until the co-signer calls `approve_settlement`. Once set,
+    /// changing or removing the co-signer needs its signature too.
+    pub fn set_settlement_cosigner(
+        ctx: Context<SetSettlementCosigner>,
+        cosigner: Pubkey,
+        threshold_quote_fp: u64,
+    ) -> Result<()> {
+        let user_stats = &mut ctx.accounts.user_stats;
+        if user_stats.settlement_cosigner != Pubkey::default() {
+            let current = ctx
+                .accounts
+                .current_cosigner
+                .as_ref()
+                .ok_or(AmmError::CosignerSignatureRequired)?;
+            require_keys_eq!(
+                current.key(),
+                user_stats.settlement_cosigner,
+                AmmError::CosignerSignatureRequired
+            );
+        }
+        user_stats.settlement_cosigner = cosigner;
+        user_stats.cosign_threshold_quote_fp = threshold_quote_fp;
+
+        emit!(SettlementCosignerUpdated {
+            market: ctx.accounts.market.key(),
+            user: ctx.accounts.user.key(),
+            cosigner,
+            threshold_quote_fp,
+        });
+
+        Ok(())
+    }
+
+    /// Co-signer approval for an order's settlement payout.
+    pub fn approve_settlement(ctx: Context<ApproveSettlement>) -> Result<()> {
+        let order = &mut ctx.accounts.order;
+        require!(
+            order.has_flag(OrderFlags::REQUIRES_COSIGN),
+            AmmError::CosignNotRequired
+        );
+        order.set_flag(OrderFlags::COSIGN_APPROVED, true);
+
+        emit!(SettlementApproved {
+            market: order.market,
+            order: order.key(),
+            user: order.user,
+            cosigner: ctx.accounts.cosigner.key(),
+        });
+
+        Ok(())
+    }
+
+    /// Admin function to name the compliance key that may `freeze_order`, and the longest
+    /// a freeze may last. The default key disables freezing.
+    pub fn set_compliance_authority(
+        ctx: Context<SetParams>,
+        compliance_authority: Pubkey,
+        max_freeze_slots: u64,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+
+        market.compliance_authority = compliance_authority;
+        market.max_freeze_slots = max_freeze_slots;
+
+        emit!(ComplianceAuthorityUpdated {
+            market: market.key(),
+            compliance_authority,
+            max_freeze_slots,
+        });
+
+        Ok(())
+    }
+
+    /// Compliance hold: block settlement and cancellation of an order for `freeze_slots`
+    /// (at most `max_freeze_slots`) pending review. It lifts itself when that runs out;
+    /// an order can only be frozen once, so a hold can't be renewed indefinitely.
+    pub fn freeze_order(ctx: Context<FreezeOrder>, freeze_slots: u64) -> Result<()> {
+        let market = &ctx.accounts.market;
+        let order = &mut ctx.accounts.order;
+        require!(
+            freeze_slots > 0 && freeze_slots <= market.max_freeze_slots,
+            AmmError::InvalidFreezeDuration
+        );
+        require!(order.frozen_until_slot == 0, AmmError::OrderAlreadyFrozen);
+
+        order.frozen_until_slot = market.clock()?
+            .slot
+            .checked_add(freeze_slots)
+            .ok_or(AmmError::MathOverflow)?;
+
+        emit!(OrderFrozen {
+            market: market.key(),
+            order: order.key(),
+            user: order.user,
+            frozen_until_slot: order.frozen_until_slot,
+        });
+
+        Ok(())
+    }
+
+    /// Lift a compliance hold before it runs out.
+    pub fn unfreeze_order(ctx: Context<FreezeOrder>) -> Result<()> {
+        let order = &mut ctx.accounts.order;
+        let slot = ctx.accounts.market.clock()?.slot;
+        require!(order.is_frozen(slot), AmmError::OrderNotFrozen);
+        order.frozen_until_slot = slot;
+
+        emit!(OrderUnfrozen {
+            market: ctx.accounts.market.key(),
+            order: order.key(),
+            user: order.user,
+        });
+
+        Ok(())
+    }
+
+    /// Admin function to require a travel-rule record on fills whose quote notional exceeds
+    /// `threshold_quote_fp`. `0` never requires one.
+    pub fn set_travel_rule_threshold(
+        ctx: Context<SetParams>,
+        threshold_quote_fp: u64,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+
+        market.travel_rule_threshold_quote_fp = threshold_quote_fp;
+
+        emit!(TravelRuleThresholdUpdated {
+            market: market.key(),
+            threshold_quote_fp,
+        });
+
+        Ok(())
+    }
+
+    /// Attach the hash of an off-chain travel-rule payload to an order, so a large fill
+    /// can settle without putting PII on-chain. Settlement copies it into the `OrderFill`.
+    pub fn attach_travel_rule(
+        ctx: Context<AttachTravelRule>,
+        payload_hash: [u8; 32],
+    ) -> Result<()> {
+        let order = &ctx.accounts.order;
+        require!(payload_hash != [0u8; 32], AmmError::InvalidAmount);
+
+        let record = &mut ctx.accounts.travel_rule;
+        record.market = order.market;
+        record.order = order.key();
+        record.payload_hash = payload_hash;
+        record.bump = ctx.bumps.travel_rule;
+
+        emit!(TravelRuleAttached {
+            market: order.market,
+            order: order.key(),
+            user: order.user,
+            payload_hash,
+        });
+
+        Ok(())
+    }
+
+    /// Designate where stranded refunds may be redirected by `execute_recovery` if the
+    /// user loses access to their wallet or token accounts. Default (zero) disables it.
+    pub fn set_recovery_address(
+        ctx: Context<SetRecoveryAddress>,
+        recovery_address: Pubkey,
+    ) -> Result<()> {
+        ctx.accounts.user_stats.recovery_address = recovery_address;
+
+        emit!(RecoveryAddressUpdated {
+            market: ctx.accounts.market.key(),
+            user: ctx.accounts.user_stats.user,
+            recovery_address,
+        });
+
+        Ok(())
+    }
+
+    /// Start the recovery timelock for an order of a closed batch (admin only).
+    ///
+    /// Snapshots the owner's designated recovery address; `execute_recovery` can pay the
+    /// refund there once `RECOVERY_TIMELOCK_SLOTS` have passed, unless the owner (or the
+    /// authority) calls `cancel_recovery` first.
+    pub fn request_recovery(ctx: Context<RequestRecovery>) -> Result<()> {
+        let clock = ctx.accounts.market.clock()?;
+        let market = &ctx.accounts.market;
+        let order = &ctx.accounts.order;
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+        require!(!order.has_flag(OrderFlags::CANCELLED), AmmError::OrderCancelled);
+        require!(!order.has_flag(OrderFlags::FILLED), AmmError::OrderAlreadySettled);
+        require!(!order.is_frozen(clock.slot), AmmError::OrderFrozen);
+        require!(order.batch_id < market.current_batch_id, AmmError::BatchNotReady);
+
+        let recovery_address = ctx.accounts.user_stats.recovery_address;
+        require!(
+            recovery_address != Pubkey::default(),
+            AmmError::RecoveryAddressNotSet
+        );
+
+        let request = &mut ctx.accounts.recovery_request;
+        request.market = market.key();
+        request.order = order.key();
+        request.user = order.user;
+        request.recovery_address = recovery_address;
+        request.requested_slot = clock.slot;
+        request.executable_slot = clock
+            .slot
+            .checked_add(RECOVERY_TIMELOCK_SLOTS)
+            .ok_or(AmmError::MathOverflow)?;
+        request.bump = ctx.bumps.recovery_request;
+
+        emit!(RecoveryRequested {
+            market: request.market,
+            order: request.order,
+            user: request.user,
+            recovery_address,
+            executable_slot: request.executable_slot,
+        });
+
+        Ok(())
+    }
+
+    /// Abort a pending recovery. Callable by the order owner or the market authority.
+    pub fn cancel_recovery(ctx: Context<CancelRecovery>) -> Result<()> {
+        let signer = ctx.accounts.signer.key();
+        let request = &ctx.accounts.recovery_request;
+        require!(
+            signer == ctx.accounts.market.authority || signer == request.user,
+            AmmError::Unauthorized
+        );
+
+        emit!(RecoveryCancelled {
+            market: ctx.accounts.market.key(),
+            order: request.order,
+            cancelled_by: signer,
+        });
+
+        Ok(())
+    }
+
+    /// Pay a stranded refund to the snapshotted recovery address once the timelock has
+    /// passed (admin only).
+    ///
+    /// Refund-only: the order must not have been matched, nor be owed a share of an
+    /// externally routed residual, so batch fill accounting is untouched. Matched orders
+    /// still settle through `settle_order`.
+    pub fn execute_recovery<'info>(
+        ctx: Context<'_, '_, '_, 'info, ExecuteRecovery<'info>>,
+    ) -> Result<()> {
+        let clock = ctx.accounts.market.clock()?;
+        let market = &ctx.accounts.market;
+        let batch_state = &mut ctx.accounts.batch_state;
+        let order = &mut ctx.accounts.order;
+        let request = &ctx.accounts.recovery_request;
+
+        require_keys_eq!(market.authority, ctx.accounts.authority.key(), AmmError::Unauthorized);
+        require!(!market.is_paused(PauseModes::SETTLEMENT), AmmError::MarketPaused);
+        require!(
+            clock.slot >= request.executable_slot,
+            AmmError::RecoveryTimelockActive
+        );
+        require!(!order.has_flag(OrderFlags::CANCELLED), AmmError::OrderCancelled);
+        require!(!order.has_flag(OrderFlags::FILLED), AmmError::OrderAlreadySettled);
+        require!(!order.is_frozen(clock.slot), AmmError::OrderFrozen);
+
+        take_staged_claim(order, batch_state, ctx.accounts.clearing_state.as_mut())?;
+        let matched = batch_state.clearing_price_fp > 0
+            && order.has_flag(OrderFlags::CLAIM_RECORDED)
+            && order.matched_base_fp > 0;
+        let owed_routed_share =
+            order.has_flag(OrderFlags::ALLOW_EXTERNAL_FILL) && batch_state.routed_base_fp > 0;
+        require!(!matched && !owed_routed_share, AmmError::RecoveryNotRefundOnly);
+
+        let market_seeds: &[&[u8]] = &[
+            MARKET_SEED,
+            market.authority.as_ref(),
+            market.base_mint.as_ref(),
+            market.quote_mint.as_ref(),
+            &[market.bump],
+        ];
+        let signer_seeds: &[&[&[u8]]] = &[market_seeds];
+
+        let (from, to, mint, refund_fp) = match order.side {
+            OrderSide::Bid => (
+                ctx.accounts.vault_quote.to_account_info(),
+                ctx.accounts.recovery_quote_ata.to_account_info(),
+                &ctx.accounts.quote_mint,
+                order.quote_deposit_fp,
+            ),
+            OrderSide::Ask => (
+                ctx.accounts.vault_base.to_account_info(),
+                ctx.accounts.recovery_base_ata.to_account_info(),
+                &ctx.accounts.base_mint,
+                order.amount_base_fp,
+            ),
+        };
+        if refund_fp > 0 {
+            let cpi_accounts = TransferChecked {
+                from,
+                mint: mint.to_account_info(),
+                to,
+                authority: market.to_account_info(),
+            };
+            let cpi_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                cpi_accounts,
+                signer_seeds,
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec());
+            transfer_checked_with_hook(
+                cpi_ctx,
+                &market.transfer_hook_programs,
+                refund_fp,
+                mint.decimals,
+            )?;
+        }
+
+        order.claim_base_fp = 0;
+        order.claim_quote_fp = 0;
+        order.set_flag(OrderFlags::FILLED, true);
+        order.settle_nonce = order
+            .settle_nonce
+            .checked_add(1)
+            .ok_or(AmmError::MathOverflow)?;
+        batch_state.settled_order_count = batch_state
+            .settled_order_count
+            .checked_add(1)
+            .ok_or(AmmError::MathOverflow)?;
+        ctx.accounts
+            .user_stats
+            .release_open_order(&mut ctx.accounts.user_batch_stats);
+
+        let (refund_base_fp, refund_quote_fp) = match order.side {
+            OrderSide::Bid => (0, refund_fp),
+            OrderSide::Ask => (refund_fp, 0),
+        };
+        emit_logged!(ctx.accounts.event_log, OrderSettled {
+            market: market.key(),
+            order: order.key(),
+            user: order.user,
+            batch_id: order.batch_id,
+            side: order.side,
+            clearing_price_fp: batch_state.clearing_price_fp,
+            filled_base_fp: 0,
+            filled_quote_fp: 0,
+            refund_base_fp,
+            refund_quote_fp,
+            memo: order.memo,
+        });
+        emit!(RecoveryExecuted {
+            market: market.key(),
+            order: order.key(),
+            user: order.user,
+            recovery_address: request.recovery_address,
+            refund_base_fp,
+            refund_quote_fp,
+        });
+
+        Ok(())
+    }
+
+    /// Open a custodial sub-account under the signing master wallet.
+    ///
+    /// The sub-account PDA owns its own token accounts (fund them with plain SPL
+    /// transfers) and trades through `place_order` / `settle_order` / `cancel_order`
+    /// by passing it as `sub_account`, so each strategy gets its own orders and limits.
+    pub fn open_sub_account(ctx: Context<OpenSubAccount>, sub_account_id: u16) -> Result<()> {
+        let sub = &mut ctx.accounts.sub_account;
+        sub.master = ctx.accounts.master.key();
+        sub.market = ctx.accounts.market.key();
+        sub.sub_account_id = sub_account_id;
+        sub.bump = ctx.bumps.sub_account;
+        sub.orders_placed = 0;
+        sub.filled_base_fp = 0;
+        sub.filled_quote_fp = 0;
+
+        emit!(SubAccountOpened {
+            market: sub.market,
+            master: sub.master,
+            sub_account: sub.key(),
+            sub_account_id,
+        });
+
+        Ok(())
+    }
+
+    /// Master-only: move tokens out of a sub-account, either to another sub-account of
+    /// the same master (pass `to_sub_account`) or back to the master's own token account.
+    pub fn sweep_sub_account(ctx: Context<SweepSubAccount>, amount: u64) -> Result<()> {
+        require!(amount > 0, AmmError::InvalidAmount);
+        let destination_owner = ctx.accounts.destination.owner;
+        let to_sub_account = match ctx.accounts.to_sub_account.as_ref() {
+            Some(to) => {
+                require_keys_eq!(destination_owner, to.key(), AmmError::InvalidSweepDestination);
+                Some(to.key())
+            }
+            None => {
+                require_keys_eq!(
+                    destination_owner,
+                    ctx.accounts.master.key(),
+                    AmmError::InvalidSweepDestination
+                );
+                None
+            }
+        };
+
+        let from = &ctx.accounts.from_sub_account;
+        let (market_key, master_key, id_bytes, bump) = from.seed_parts();
+        let seeds: &[&[u8]] = &[
+            SUB_ACCOUNT_SEED,
+            market_key.as_ref(),
+            master_key.as_ref(),
+            id_bytes.as_ref(),
+            bump.as_ref(),
+        ];
+        let signer_seeds: &[&[&[u8]]] = &[seeds];
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.source.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            to: ctx.accounts.destination.to_account_info(),
+            authority: from.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            cpi_accounts,
+            signer_seeds,
+        );
+        token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+
+        emit!(SubAccountSwept {
+            market: from.market,
+            master: from.master,
+            from_sub_account: from.key(),
+            to_sub_account,
+            mint: ctx.accounts.source.mint,
+            amount,
+        });
+
+        Ok(())
+    }
+
+    /// Opt in to (or out of) fill receipts for this market.
+    pub fn set_receipt_opt_in(ctx: Context<SetReceiptOptIn>, enabled: bool) -> Result<()> {
+        let user_stats = &mut ctx.accounts.user_stats;
+        if user_stats.user == Pubkey::default() {
+            user_stats.user = ctx.accounts.user.key();
+            user_stats.market = ctx.accounts.market.key();
+            user_stats.active_batch_count = 0;
+            user_stats.bump = ctx.bumps.user_stats;
+        }
+        user_stats.receipts_enabled = enabled;
+        Ok(())
+    }
+
+    /// Mint a non-transferable Token-2022 receipt for a settled fill.
+    ///
+    /// Meant to be bundled right after `settle_order` by users who opted in via
+    /// `set_receipt_opt_in`. The receipt mint is a PDA of the order (one per fill), has
+    /// supply fixed at 1 and carries batch, side, price and size as token metadata.
+    pub fn mint_fill_receipt(ctx: Context<MintFillReceipt>) -> Result<()> {
+        let market = &ctx.accounts.market;
+        let order = &ctx.accounts.order;
+        let order_fill = &ctx.accounts.order_fill;
+
+        require!(ctx.accounts.user_stats.receipts_enabled, AmmError::ReceiptsNotEnabled);
+        require!(
+            order_fill.claimed && order_fill.filled_base_fp > 0,
+            AmmError::NothingToReceipt
+        );
+
+        let order_key = order.key();
+        let mint_key = ctx.accounts.receipt_mint.key();
+        let market_key = market.key();
+        let side = match order.side {
+            OrderSide::Bid => "bid",
+            OrderSide::Ask => "ask",
+        };
+        let fields = [
+            ("market", market_key.to_string()),
+            ("batch_id", order_fill.batch_id.to_string()),
+            ("side", side.to_string()),
+            ("clearing_price_fp", ctx.accounts.batch_state.clearing_price_fp.to_string()),
+            ("filled_base_fp", order_fill.filled_base_fp.to_string()),
+            ("filled_quote_fp", order_fill.filled_quote_fp.to_string()),
+        ];
+        let metadata = TokenMetadata {
+            update_authority: OptionalNonZeroPubkey::try_from(Some(market_key))?,
+            mint: mint_key,
+            name: "Micro-Batch Fill Receipt".to_string(),
+            symbol: "MBFILL".to_string(),
+            uri: String::new(),
+            additional_metadata: fields
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.clone()))
+                .collect(),
+        };
+
+        // Allocate the mint for its extensions; metadata is appended by Token-2022 itself,
+        // so fund the final size up front.
+        let mint_len = ExtensionType::try_calculate_account_len::<spl_token_2022::state::Mint>(
+            &[ExtensionType::NonTransferable, ExtensionType::MetadataPointer],
+        )?;
+        let lamports = Rent::get()?.minimum_balance(mint_len + metadata.tlv_size_of()?);
+
+        let receipt_seeds: &[&[u8]] = &[
+            RECEIPT_SEED,
+            order_key.as_ref(),
+            &[ctx.bumps.receipt_mint],
+        ];
+        let market_seeds: &[&[u8]] = &[
+            MARKET_SEED,
+            market.authority.as_ref(),
+            market.base_mint.as_ref(),
+            market.quote_mint.as_ref(),
+            &[market.bump],
+        ];
+        let token_program_ai = ctx.accounts.token_2022_program.to_account_info();
+        let mint_ai = ctx.accounts.receipt_mint.to_account_info();
+        let market_ai = market.to_account_info();
+
+        system_program::create_account(
+            CpiContext::new_with_signer(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::CreateAccount {
+                    from: ctx.accounts.user.to_account_info(),
+                    to: mint_ai.clone(),
+                },
+                &[receipt_seeds],
+            ),
+            lamports,
+            mint_len as u64,
+            &token_2022::ID,
+        )?;
+        non_transferable_mint_initialize(CpiContext::new(
+            token_program_ai.clone(),
+            NonTransferableMintInitialize {
+                token_program_id: token_program_ai.clone(),
+                mint: mint_ai.clone(),
+            },
+        ))?;
+        metadata_pointer_initialize(
+            CpiContext::new(
+                token_program_ai.clone(),
+                MetadataPointerInitialize {
+                    token_program_id: token_program_ai.clone(),
+                    mint: mint_ai.clone(),
+                },
+            ),
+            Some(market_key),
+            Some(mint_key),
+        )?;
+        token_2022::initialize_mint2(
+            CpiContext::new(
+                token_program_ai.clone(),
+                token_2022::InitializeMint2 { mint: mint_ai.clone() },
+            ),
+            0,
+            &market_key,
+            None,
+        )?;
+        token_metadata_initialize(
+            CpiContext::new_with_signer(
+                token_program_ai.clone(),
+                TokenMetadataInitialize {
+                    token_program_id: token_program_ai.clone(),
+                    metadata: mint_ai.clone(),
+                    update_authority: market_ai.clone(),
+                    mint_authority: market_ai.clone(),
+                    mint: mint_ai.clone(),
+                },
+                &[market_seeds],
+            ),
+            metadata.name.clone(),
+            metadata.symbol.clone(),
+            metadata.uri.clone(),
+        )?;
+        for (key, value) in fields {
+            token_metadata_update_field(
+                CpiContext::new_with_signer(
+                    token_program_ai.clone(),
+                    TokenMetadataUpdateField {
+                        token_program_id: token_program_ai.clone(),
+                        metadata: mint_ai.clone(),
+                        update_authority: market_ai.clone(),
+                    },
+                    &[market_seeds],
+                ),
+                Field::Key(key.to_string()),
+                value,
+            )?;
+        }
+
+        associated_token::create(CpiContext::new(
+            ctx.accounts.associated_token_program.to_account_info(),
+            associated_token::Create {
+                payer: ctx.accounts.user.to_account_info(),
+                associated_token: ctx.accounts.receipt_ata.to_account_info(),
+                authority: ctx.accounts.user.to_account_info(),
+                mint: mint_ai.clone(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+                token_program: token_program_ai.clone(),
+            },
+        ))?;
+        token_2022::mint_to(
+            CpiContext::new_with_signer(
+                token_program_ai.clone(),
+                token_2022::MintTo {
+                    mint: mint_ai.clone(),
+                    to: ctx.accounts.receipt_ata.to_account_info(),
+                    authority: market_ai.clone(),
+                },
+                &[market_seeds],
+            ),
+            1,
+        )?;
+        // Fix supply at one.
+        token_2022::set_authority(
+            CpiContext::new_with_signer(
+                token_program_ai,
+                token_2022::SetAuthority {
+                    current_authority: market_ai,
+                    account_or_mint: mint_ai,
+                },
+                &[market_seeds],
+            ),
+            spl_token_2022::instruction::AuthorityType::MintTokens,
+            None,
+        )?;
+
+        emit!(ReceiptMinted {
+            market: market_key,
+            order: order_key,
+            user: order.user,
+            mint: mint_key,
+            batch_id: order_fill.batch_id,
+        });
+
+        Ok(())
+    }
+
+    /// Freeze a user's trading activity for a finished epoch into an immutable
+    /// `UserActivitySnapshot`, for airdrops and rebate programs to verify on-chain.
+    ///
+    /// Permissionless; the payer funds the snapshot. `UserStats` only keeps the user's
+    /// current and previous active epochs, so an epoch must be snapshotted before the
+    /// user trades in two later ones.
+    pub fn snapshot_user_activity(ctx: Context<SnapshotUserActivity>, epoch: u64) -> Result<()> {
+        let market = &ctx.accounts.market;
+        let user_stats = &ctx.accounts.user_stats;
+        let clock = market.clock()?;
+        require!(epoch < clock.epoch, AmmError::EpochNotFinished);
+        let (volume_quote_fp, fill_count) = user_stats
+            .epoch_activity(epoch)
+            .ok_or(AmmError::ActivityEpochUnavailable)?;
+
+        let snapshot = &mut ctx.accounts.snapshot;
+        snapshot.market = market.key();
+        snapshot.user = user_stats.user;
+        snapshot.epoch = epoch;
+        snapshot.volume_quote_fp = volume_quote_fp;
+        snapshot.fill_count = fill_count;
+        snapshot.snapshot_slot = clock.slot;
+        snapshot.bump = ctx.bumps.snapshot;
+
+        emit!(UserActivitySnapshotted {
+            market: snapshot.market,
+            user: snapshot.user,
+            epoch,
+            volume_quote_fp,
+            fill_count,
+        });
+
+        Ok(())
+    }
+
+    /// Create the `EventLog` for a market initialized before event logging existed.
+    pub fn init_event_log(ctx: Context<InitEventLog>) -> Result<()> {
+        let event_log = &mut ctx.accounts.event_log;
+        event_log.market = ctx.accounts.market.key();
+        event_log.next_seq = 0;
+        event_log.bump = ctx.bumps.event_log;
+        Ok(())
+    }
+
+    /// Create the `PriceHistory` for a market initialized before price history existed.
+    pub fn init_price_history(ctx: Context<InitPriceHistory>) -> Result<()> {
+        let price_history = &mut ctx.accounts.price_history;
+        price_history.market = ctx.accounts.market.key();
+        price_history.recorded = 0;
+        price_history.bump = ctx.bumps.price_history;
+        Ok(())
+    }
+
+    /// Read-only portfolio summary for wallet integrations, returned as return data.
+    ///
+    /// remaining_accounts = the `Market` accounts first (`market_count` of them), then
+    /// any mix of the user's `Order` accounts and `BatchState` accounts for closed batches.
+    /// Orders in the current batch are open; orders in a closed batch are pending
+    /// settlement, or claimable refunds when their `BatchState` shows they did not cross.
+    pub fn get_user_portfolio<'info>(
+        ctx: Context<'_, '_, '_, 'info, GetUserPortfolio<'info>>,
+        market_count: u8,
+    ) -> Result<UserPortfolio> {
+        let user = ctx.accounts.user.key();
+        let remaining = ctx.remaining_accounts;
+        let market_count = market_count as usize;
+        require!(
+            market_count <= MAX_PORTFOLIO_MARKETS && market_count <= remaining.len(),
+            AmmError::TooManyPortfolioMarkets
+        );
+
+        let mut markets: Vec<(Pubkey, u64)> = Vec::with_capacity(market_count);
+        let mut entries: Vec<PortfolioMarket> = Vec::with_capacity(market_count);
+        for ai in &remaining[..market_count] {
+            let market: Market = load_account(ai)?;
+            markets.push((ai.key(), market.current_batch_id));
+            entries.push(PortfolioMarket {
+                market: ai.key(),
+                ..Default::default()
+            });
+        }
+
+        // First pass: clearing prices of the closed batches that were passed in.
+        let mut cleared: Vec<(Pubkey, u64, u64)> = Vec::new();
+        for ai in &remaining[market_count..] {
+            if ai.try_borrow_data()?.get(..8) == Some(&BatchState::DISCRIMINATOR[..]) {
+                let batch: BatchState = load_account(ai)?;
+                cleared.push((batch.market, batch.batch_id, batch.clearing_price_fp));
+            }
+        }
+
+        for ai in &remaining[market_count..] {
+            if ai.try_borrow_data()?.get(..8) == Some(&BatchState::DISCRIMINATOR[..]) {
+                continue;
+            }
+            let order: Order = load_account(ai)?;
+            require_keys_eq!(order.user, user, AmmError::Unauthorized);
+            if order.has_flag(OrderFlags::FILLED) || order.has_flag(OrderFlags::CANCELLED) {
+                continue;
+            }
+            let idx = markets
+                .iter()
+                .position(|(key, _)| *key == order.market)
+                .ok_or(AmmError::PortfolioMarketMissing)?;
+            let (base_locked, quote_locked) = match order.side {
+                OrderSide::Bid => (0, order.quote_deposit_fp),
+                OrderSide::Ask => (order.amount_base_fp, 0),
+            };
+            let entry = &mut entries[idx];
+
+            if order.batch_id == markets[idx].1 {
+                entry.open_orders += 1;
+                entry.open_base_locked_fp += base_locked;
+                entry.open_quote_locked_fp += quote_locked;
+                continue;
+            }
+
+            let clearing_price_fp = cleared
+                .iter()
+                .find(|(market, batch_id, _)| *market == order.market && *batch_id == order.batch_id)
+                .map(|(_, _, price)| *price);
+            let crossed = match clearing_price_fp {
+                Some(0) => false,
+                Some(price) => {
+                    order.is_active()
+                        && match order.side {
+                            OrderSide::Bid => order.limit_price_fp >= price,
+                            OrderSide::Ask => order.limit_price_fp <= price,
+                        }
+                }
+                // Unknown outcome: report as pending.
+                None => true,
+            };
+            if crossed {
+                entry.pending_settlements += 1;
+                entry.pending_base_locked_fp += base_locked;
+                entry.pending_quote_locked_fp += quote_locked;
+            } else {
+                entry.claimable_refund_base_fp += base_locked;
+                entry.claimable_refund_quote_fp += quote_locked;
+            }
+        }
+
+        Ok(UserPortfolio {
+            user,
+            markets: entries,
+        })
+    }
+
+    /// Clearing price of `batch_id`, returned as return data, so integrators need not keep
+    /// `BatchState` accounts around. `None` if the batch did not cross or has already been
+    /// evicted from the ring (only the last `PRICE_HISTORY_CAPACITY` crossed batches are kept).
+    pub fn get_clearing_price(
+        ctx: Context<GetClearingPrice>,
+        batch_id: u64,
+    ) -> Result<Option<u64>> {
+        Ok(ctx
+            .accounts
+            .price_history
+            .find(batch_id)
+            .map(|entry| entry.clearing_price_fp))
+    }
+
+    /// Canonical Borsh result of a cleared batch, returned as return data for settlement
+    /// bridges and reporting. Remaining accounts are the batch's `OrderIndex` pages then
+    /// every indexed order, as for `audit_batch`; `fill_digest` chains each order's key,
+    /// side and recorded claim in index order, so identical batches export identical bytes.
+    pub fn export_batch_result<'info>(
+        ctx: Context<'_, '_, '_, 'info, ExportBatchResult<'info>>,
+    ) -> Result<BatchResult> {
+        let market = &ctx.accounts.market;
+        let batch_state = &ctx.accounts.batch_state;
+        let market_pk = market.key();
+        let batch_id = batch_state.batch_id;
+        let remaining = ctx.remaining_accounts;
+
+        let order_count = batch_state.order_count as usize;
+        let page_count = order_count.div_ceil(ORDER_INDEX_PAGE_SIZE);
+        require!(
+            remaining.len() == page_count + order_count,
+            AmmError::InvalidRemainingAccountsLayout
+        );
+        let (page_ais, order_ais) = remaining.split_at(page_count);
+
+        let base_fee_bps = market.effective_protocol_fee_bps(batch_id);
+        let mut fill_digest = [0u8; 32];
+        let mut filled_order_count: u32 = 0;
+        let mut protocol_fee_quote_fp: u128 = 0;
+        let mut indexed = 0usize;
+        for (page_no, page_ai) in page_ais.iter().enumerate() {
+            let page: OrderIndex = load_account(page_ai)?;
+            require!(
+                page.market == market_pk
+                    && page.batch_id == batch_id
+                    && page.page as usize == page_no,
+                AmmError::InvalidOrderIndex
+            );
+            for order_key in page.orders.iter() {
+                let order_ai = order_ais
+                    .get(indexed)
+                    .ok_or(AmmError::InvalidOrderIndex)?;
+                require_keys_eq!(*order_ai.key, *order_key, AmmError::InvalidOrderIndex);
+                indexed += 1;
+
+                let order: Order = load_account(order_ai)?;
+                let (matched_base_fp, matched_quote_fp) =
+                    if order.has_flag(OrderFlags::CLAIM_RECORDED) {
+                        (order.matched_base_fp, order.matched_quote_fp)
+                    } else {
+                        (0, 0)
+                    };
+                if matched_base_fp > 0 {
+                    filled_order_count += 1;
+                    let fee_bps = batch_state
+                        .imbalance_adjusted_fee_bps(
+                            base_fee_bps,
+                            order.side,
+                            market.imbalance_fee_bps,
+                        )
+                        .saturating_sub(batch_state.keeper_rebate_bps);
+                    protocol_fee_quote_fp +=
+                        matched_quote_fp as u128 * fee_bps as u128 / BPS_DENOM as u128;
+                }
+                fill_digest = hashv(&[
+                    &fill_digest,
+                    order_key.as_ref(),
+                    &[order.side as u8],
+                    &matched_base_fp.to_le_bytes(),
+                    &matched_quote_fp.to_le_bytes(),
+                ])
+                .to_bytes();
+            }
+        }
+        require!(indexed == order_count, AmmError::InvalidOrderIndex);
+
+        Ok(BatchResult {
+            market: market_pk,
+            batch_id,
+            cleared_slot: batch_state.cleared_slot,
+            clearing_price_fp: batch_state.clearing_price_fp,
+            total_base_traded_fp: batch_state.total_base_traded_fp,
+            total_quote_traded_fp: batch_state.total_quote_traded_fp,
+            bid_volume_at_clear_fp: batch_state.bid_volume_at_clear_fp,
+            ask_volume_at_clear_fp: batch_state.ask_volume_at_clear_fp,
+            order_count: batch_state.order_count,
+            filled_order_count,
+            protocol_fee_quote_fp,
+            keeper_reward_quote_fp: batch_state.keeper_reward_quote_fp,
+            keeper_rebate_bps: batch_state.keeper_rebate_bps,
+            fill_digest,
+        })
+    }
+
+    /// Settlement progress of cleared batch `batch_id`, returned as return data for
+    /// dashboards. Cancelled orders count towards `order_count` but never settle, so the
+    /// outstanding amounts reaching zero, not the order counts meeting, marks completion.
+    pub fn get_batch_settlement_status(
+        ctx: Context<GetBatchSettlementStatus>,
+        batch_id: u64,
+    ) -> Result<BatchSettlementStatus> {
+        let batch_state = &ctx.accounts.batch_state;
+        let slot = ctx.accounts.market.clock()?.slot;
+        Ok(BatchSettlementStatus {
+            market: batch_state.market,
+            batch_id,
+            settled: batch_state.settled,
+            order_count: batch_state.order_count,
+            settled_order_count: batch_state.settled_order_count,
+            remaining_base_to_settle_fp: batch_state.remaining_base_to_settle_fp,
+            remaining_quote_to_settle_fp: batch_state.remaining_quote_to_settle_fp,
+            slots_since_clear: slot.saturating_sub(batch_state.cleared_slot),
+        })
+    }
+
+    /// The market's PDAs, plus `trader`'s accounts for the open batch, returned as return
+    /// data so clients derive addresses from the program instead of copying seed logic.
+    /// `order_nonce` is the trader's next `UserStats::order_nonce`.
+    pub fn derive_addresses(
+        ctx: Context<DeriveAddresses>,
+        trader: Pubkey,
+        order_nonce: u64,
+    ) -> Result<MarketAddresses> {
+        let market = &ctx.accounts.market;
+        let market_pk = market.key();
+        let batch_id = market.current_batch_id.to_le_bytes();
+        let pda = |seeds: &[&[u8]]| Pubkey::find_program_address(seeds, &crate::ID).0;
+
+        let order = pda(&[
+            ORDER_SEED,
+            market_pk.as_ref(),
+            trader.as_ref(),
+            &order_nonce.to_le_bytes(),
+            &market.batch_salt,
+        ]);
+        let index_page = market.global_orders_in_batch / ORDER_INDEX_PAGE_SIZE as u32;
+
+        Ok(MarketAddresses {
+            market: market_pk,
+            vault_base: pda(&[VAULT_BASE_SEED, market_pk.as_ref()]),
+            vault_quote: pda(&[VAULT_QUOTE_SEED, market_pk.as_ref()]),
+            batch_state: pda(&[BATCH_STATE_SEED, market_pk.as_ref(), &batch_id]),
+            event_log: pda(&[EVENT_LOG_SEED, market_pk.as_ref()]),
+            price_history: pda(&[PRICE_HISTORY_SEED, market_pk.as_ref()]),
+            order_index: pda(&[
+                ORDER_INDEX_SEED,
+                market_pk.as_ref(),
+                &batch_id,
+                &index_page.to_le_bytes(),
+            ]),
+            user_stats: pda(&[USER_STATS_SEED, market_pk.as_ref(), trader.as_ref()]),
+            user_batch_stats: pda(&[
+                USER_BATCH_SEED,
+                market_pk.as_ref(),
+                trader.as_ref(),
+                &batch_id,
+            ]),
+            order,
+            order_fill: pda(&[ORDER_FILL_SEED, order.as_ref()]),
+        })
+    }
+
+    /// Simple read helper: emit key market params for off-chain UIs.
+    pub fn view_market(ctx: Context<ViewMarket>) -> Result<()> {
+        let market = &ctx.accounts.market;
+
+        emit!(MarketView {
+            market: market.key(),
+            authority: market.authority,
+            base_mint: market.base_mint,
+            quote_mint: market.quote_mint,
+            batch_duration_slots: market.batch_duration_slots,
+            last_batch_slot: market.last_batch_slot,
+            current_batch_id: market.current_batch_id,
+            next_order_id: market.next_order_id,
+            fee_bps: market.fee_bps,
+            max_orders_per_user_per_batch: market.max_orders_per_user_per_batch,
+            max_active_batches_per_user: market.max_active_batches_per_user,
+            pause_modes: market.pause_modes,
+            max_notional_per_batch_quote_fp: market.max_notional_per_batch_quote_fp,
+            max_notional_per_user_per_batch_quote_fp: market.max_notional_per_user_per_batch_quote_fp,
+            batch_notional_quote_fp: market.batch_notional_quote_fp,
+            max_orders_global_per_batch: market.max_orders_global_per_batch,
+            global_orders_in_batch: market.global_orders_in_batch,
+            max_price_move_bps: market.max_price_move_bps,
+            last_clearing_price_fp: market.last_clearing_price_fp,
+            keeper_fee_bps: market.keeper_fee_bps,
+            min_base_order_fp: market.min_base_order_fp,
+            min_quote_order_fp: market.min_quote_order_fp,
+            max_base_order_fp: market.max_base_order_fp,
+            max_quote_order_fp: market.max_quote_order_fp,
+            protocol_fee_bps: market.protocol_fee_bps,
+            referral_fee_bps: market.referral_fee_bps,
+            protocol_fees_accrued_fp: market.protocol_fees_accrued_fp,
+            pause_reason: market.pause_reason,
+            mode: market.mode,
+        });
+
+        Ok(())
+    }
+}
+
+/// Shared by `initialize_market` and `create_market_from_template`: checks the
+/// program-wide bounds, then writes the market's defaults and its event log.
+fn init_market(
+    accounts: &mut InitializeMarket,
+    bumps: &InitializeMarketBumps,
+    batch_duration_slots: u64,
+    fee_bps: u16,
+    max_orders_per_user_per_batch: u32,
+) -> Result<()> {
+    require!(fee_bps as u64 <= BPS_DENOM, AmmError::InvalidFeeBps);
+    let config = &accounts.program_config;
+    config.check_fees(fee_bps, 0)?;
+    require!(
+        batch_duration_slots >= config.min_batch_duration_slots,
+        AmmError::BatchDurationTooShort
+    );
+
+    let market = &mut accounts.market;
+    market.authority = accounts.authority.key();
+    market.base_mint = accounts.base_mint.key();
+    market.quote_mint = accounts.quote_mint.key();
+    market.vault_base = accounts.vault_base.key();
+    market.vault_quote = accounts.vault_quote.key();
+    market.batch_duration_slots = batch_duration_slots;
+    market.last_batch_slot = 0;
+    market.current_batch_id = 0;
+    market.next_order_id = 0;
+    market.fee_bps = fee_bps;
+    market.max_orders_per_user_per_batch = max_orders_per_user_per_batch;
+    market.max_active_batches_per_user = u32::MAX;
+    market.flags = 0;
+
+    market.bump = bumps.market;
+    market.vault_base_bump = bumps.vault_base;
+    market.vault_quote_bump = bumps.vault_quote;
+
+    // --- New risk / fee / keeper defaults ---
+
+    // Notional caps (quote-side, fixed point 1e6)
+    market.max_notional_per_batch_quote_fp = u128::MAX;
+    market.max_notional_per_user_per_batch_quote_fp = u128::MAX;
+    market.batch_notional_quote_fp = 0;
+
+    market.max_orders_global_per_batch = u32::MAX;
+    market.global_orders_in_batch = 0;
+    market.cancelled_orders_in_batch = 0;
+    market.batch_keeper_tips_fp = 0;
+
+    // Price band (bps) & last price
+    market.max_price_move_bps = 0; // 0 = disabled
+    market.last_clearing_price_fp = 0;
+
+    // Keeper incentives
+    market.keeper_fee_bps = 0;
+    market.keeper_treasury = accounts.authority.key();
+    market.min_slots_between_clears = batch_duration_slots;
+    market.only_keeper = Pubkey::default();
+    market.max_clears_per_epoch = 0;
+    market.clear_epoch = 0;
+    market.clears_in_epoch = 0;
+
+    // Protocol treasury / fees
+    market.protocol_treasury = accounts.authority.key();
+    market.referral_fee_bps = 0;
+    market.protocol_fee_bps = fee_bps;
+    market.protocol_fees_accrued_fp = 0;
+    market.keeper_reward_shortfall_fp = 0;
+
+    // Dust / min order sizes
+    market.min_base_order_fp = 1;
+    market.min_quote_order_fp = 1;
+
+    // Max order sizes (fat-finger guard)
+    market.max_base_order_fp = u64::MAX;
+    market.max_quote_order_fp = u64::MAX;
+
+    // Pause reason code
+    market.pause_modes = 0;
+    market.pause_reason = 0;
+
+    // Clear intents (disabled until set_clear_intent_params)
+    market.intent_keeper = Pubkey::default();
+    market.intent_batch_id = 0;
+    market.intent_bond_lamports = 0;
+    market.intent_window_slots = 0;
+    market.intent_bonus_bps = 0;
+    market.intent_rebate_bps = 0;
+
+    // MEV protection (bundle tip requirement off by default)
+    market.clear_tip_account = Pubkey::default();
+    market.min_clear_tip_lamports = 0;
+
+    // Fee holiday (none scheduled)
+    market.fee_holiday_start_batch = 0;
+    market.fee_holiday_end_batch = 0;
+    market.fee_holiday_bps = 0;
+
+    // Imbalance fee (disabled)
+    market.imbalance_fee_bps = 0;
+
+    // Candidate price guard (unlimited, no grid)
+    market.tick_size_fp = 0;
+    market.max_candidate_prices = 0;
+
+    // Keeper compute reimbursement (disabled)
+    market.clear_reimbursement_lamports = 0;
+
+    // Volume circuit breaker (disabled)
+    market.max_volume_multiple = 0;
+    market.trailing_volume_quote_fp = 0;
+
+    // Keeper clear bond (disabled)
+    market.clear_bond_lamports = 0;
+    market.dispute_window_slots = 0;
+
+    // Settlement delay (settle right after the clear)
+    market.settlement_delay_slots = 0;
+
+    // Quote normalization (off: prices in raw quote units)
+    market.quote_scale_fp = PRICE_SCALE;
+
+    // Compliance freeze (disabled)
+    market.compliance_authority = Pubkey::default();
+    market.max_freeze_slots = 0;
+
+    // Travel-rule metadata (never required)
+    market.travel_rule_threshold_quote_fp = 0;
+
+    // Synchronized clears (unclustered)
+    market.cluster = Pubkey::default();
+    market.cluster_period_slots = 0;
+    market.cluster_clear_window_slots = 0;
+
+    // Parameter ramp (none running)
+    market.ramp_param = RampParam::None;
+    market.ramp_start = 0;
+    market.ramp_target = 0;
+    market.ramp_start_batch_id = 0;
+    market.ramp_batches = 0;
+
+    // Crank hints (no work queue)
+    market.work_queue = Pubkey::default();
+
+    // Token-2022 (no transfer hooks allowed)
+    market.transfer_hook_programs = Vec::new();
+
+    // Participation threshold (none)
+    market.min_batch_orders = 0;
+    market.min_batch_notional_quote_fp = 0;
+    market.max_batch_extensions = 0;
+    market.batch_extensions = 0;
+
+    // Simulation (sysvar clock)
+    market.test_clock_slot = 0;
+
+    // Order PDA salt (rolled with every batch)
+    let slot = Clock::get()?.slot;
+    market.batch_salt = hashv(&[market.key().as_ref(), &slot.to_le_bytes()]).to_bytes();
+
+    // Late-cancel fee (disabled)
+    market.late_cancel_window_slots = 0;
+    market.late_cancel_fee_bps = 0;
+    market.cancel_fees_accrued_base_fp = 0;
+    market.cancel_fees_accrued_quote_fp = 0;
+
+    // Vault yield (disabled) / insurance fund
+    market.yield_program = Pubkey::default();
+    market.yield_sweep_bps = 0;
+    market.yield_deployed_quote_fp = 0;
+    market.insurance_fund_quote_fp = 0;
+
+    // Auction mode
+    market.mode = MarketMode::Standard;
+
+    // Launch auction (inactive until configure_launch)
+    market.launch_beneficiary = Pubkey::default();
+    market.launch_reserve_price_fp = 0;
+    market.launch_unsold_base_fp = 0;
+    market.launch_batches_remaining = 0;
+    market.launch_proceeds_quote_fp = 0;
+    market.launch_vesting_batches = 0;
+
+    let event_log = &mut accounts.event_log;
+    event_log.market = market.key();
+    event_log.next_seq = 0;
+    event_log.bump = bumps.event_log;
+
+    let price_history = &mut accounts.price_history;
+    price_history.market = market.key();
+    price_history.recorded = 0;
+    price_history.bump = bumps.price_history;
+
+    emit!(MarketInitialized {
+        market: market.key(),
+        authority: market.authority,
+        base_mint: market.base_mint,
+        quote_mint: market.quote_mint,
+        batch_duration_slots,
+        fee_bps,
+    });
+
+    Ok(())
+}
+
+/// CPI into the market's yield adapter with the market PDA as signer.
+fn yield_cpi<'info>(
+    market: &Account<'info, Market>,
+    yield_program: &AccountInfo<'info>,
+    remaining: &[AccountInfo<'info>],
+    ix_data: Vec<u8>,
+) -> Result<()> {
+    // The market PDA signs this CPI: never hand the adapter the base vault.
+    require!(
+        remaining.iter().all(|ai| *ai.key != market.vault_base),
+        AmmError::YieldAccountingMismatch
+    );
+    market_signed_cpi(market, yield_program, remaining, ix_data)
+}
+
+/// Key that owns an order: the sub-account when trading through one, else the signer.
+fn trader_key(user: &Pubkey, sub_account: &Option<Account<SubAccount>>) -> Pubkey {
+    sub_account.as_ref().map_or(*user, |s| s.key())
+}
+
+/// Uniform-price discovery: the candidate maximizing `min(bid_volume, ask_volume)`, the
+/// lowest one winning ties so the result does not depend on the order accounts were
+/// passed in. Returns `(price, traded, bid_volume, ask_volume)`, all zero if nothing crosses.
+fn discover_clearing_price(
+    orders: &[TempOrder],
+    candidate_prices: &[u64],
+) -> Result<(u64, u128, u128, u128)> {
+    let mut best = (0u64, 0u128, 0u128, 0u128);
+    for &p in candidate_prices.iter() {
+        let mut bid_vol: u128 = 0;
+        let mut ask_vol: u128 = 0;
+
+        for o in orders.iter() {
+            match o.side {
+                OrderSide::Bid => {
+                    if o.limit_price_fp >= p {
+                        bid_vol = bid_vol
+                            .checked_add(o.original_base_fp)
+                            .ok_or(AmmError::MathOverflow)?;
+                    }
+                }
+                OrderSide::Ask => {
+                    if o.limit_price_fp <= p {
+                        ask_vol = ask_vol
+                            .checked_add(o.original_base_fp)
+                            .ok_or(AmmError::MathOverflow)?;
+                    }
+                }
+            }
+        }
+
+        let traded = bid_vol.min(ask_vol);
+        if traded > best.1 || (traded > 0 && traded == best.1 && p < best.0) {
+            best = (p, traded, bid_vol, ask_vol);
+        }
+    }
+    Ok(best)
+}
+
+/// Round candidate prices down to the `tick` grid (at least one tick), sorted and deduped.
+fn bucket_to_tick(candidate_prices: &mut Vec<u64>, tick: u64) {
+    for p in candidate_prices.iter_mut() {
+        *p = (*p / tick).max(1) * tick;
+    }
+    candidate_prices.sort_unstable();
+    candidate_prices.dedup();
+}
+
+/// Decimal order of magnitude of an order size in whole base units: bucket `k` covers
+/// `[10^(k-1), 10^k)` units, bucket 0 anything below one unit.
+fn size_bucket(amount_base_fp: u64) -> u8 {
+    match amount_base_fp / PRICE_SCALE {
+        0 => 0,
+        units => (units.ilog10() + 1) as u8,
+    }
+}
+
+/// Deserialize a program account passed through remaining_accounts.
+///
+/// Anchor's `try_deserialize` only checks the discriminator, so the owner is verified
+/// first: a look-alike account owned by another program is `ForeignAccount`, while a
+/// program-owned account of the wrong type or shape is `AccountLayoutMismatch`.
+fn load_account<T: AccountDeserialize + Discriminator>(ai: &AccountInfo) -> Result<T> {
+    require_keys_eq!(*ai.owner, crate::ID, AmmError::ForeignAccount);
+    let data = ai.try_borrow_data()?;
+    require!(
+        data.len() >= 8 && data[..8] == T::DISCRIMINATOR,
+        AmmError::AccountLayoutMismatch
+    );
+    let mut data_slice: &[u8] = &data;
+    T::try_deserialize_unchecked(&mut data_slice)
+        .map_err(|_| AmmError::AccountLayoutMismatch.into())
+}
+
+/// Persist a program account that was deserialized by hand from remaining_accounts.
+fn write_account<T: AccountSerialize>(ai: &AccountInfo, acc: &T) -> Result<()> {
+    require!(ai.is_writable, AmmError::InvalidRemainingAccountsLayout);
+    let mut data = ai.try_borrow_mut_data()?;
+    let mut writer: &mut [u8] = &mut data;
+    acc.try_serialize(&mut writer)
+}
+
+/// Close a program account passed through remaining_accounts, sending its rent to
+/// `recipient`.
+fn close_program_account(ai: &AccountInfo, recipient: &AccountInfo) -> Result<()> {
+    require!(ai.is_writable, AmmError::InvalidRemainingAccountsLayout);
+    recipient.add_lamports(ai.lamports())?;
+    ai.sub_lamports(ai.lamports())?;
+    ai.assign(&system_program::ID);
+    ai.realloc(0, false)?;
+    Ok(())
+}
+
+/// Split the optional trailing [Order, Claimables] pairs off a clear's remaining_accounts.
+fn split_claimables_pairs<'a, 'info>(
+    remaining: &'a [AccountInfo<'info>],
+) -> Result<(&'a [AccountInfo<'info>], &'a [AccountInfo<'info>])> {
+    let mut split = remaining.len();
+    while split >= 2
+        && *remaining[split - 1].owner == crate::ID
+        && remaining[split - 1].try_borrow_data()?.get(..8)
+            == Some(&Claimables::DISCRIMINATOR[..])
+    {
+        split -= 2;
+    }
+    Ok(remaining.split_at(split))
+}
+
+/// List each paired order that the clear gave a claim (on the order, or staged on
+/// `clearing_state`) in its owner's `Claimables` index. Orders without a claim, e.g.
+/// conditionals that didn't trigger, and orders already listed are skipped.
+fn list_claims(
+    pairs: &[AccountInfo],
+    market_pk: Pubkey,
+    batch_id: u64,
+    clearing_state: Option<&ClearingState>,
+) -> Result<()> {
+    for pair in pairs.chunks_exact(2) {
+        let (order_ai, claimables_ai) = (&pair[0], &pair[1]);
+        let order: Order = load_account(order_ai)?;
+        let mut claimables: Claimables = load_account(claimables_ai)?;
+        require!(
+            order.market == market_pk
+                && order.batch_id == batch_id
+                && claimables.market == market_pk
+                && claimables.user == order.user,
+            AmmError::InvalidRemainingAccountsLayout
+        );
+        let claimed = match clearing_state {
+            Some(state) => state
+                .entries
+                .iter()
+                .any(|staged| staged.order == *order_ai.key && staged.active),
+            None => order.has_flag(OrderFlags::CLAIM_RECORDED),
+        };
+        if !claimed || claimables.entries.iter().any(|entry| entry.order == *order_ai.key) {
+            continue;
+        }
+        require!(
+            claimables.entries.len() < MAX_CLAIMABLES,
+            AmmError::ClaimablesFull
+        );
+        claimables.entries.push(ClaimableEntry {
+            batch_id,
+            order: *order_ai.key,
+        });
+        write_account(claimables_ai, &claimables)?;
+    }
+    Ok(())
+}
+
+/// For a batch cleared in chunks, copy the order's claim off the batch's `ClearingState`
+/// the first time it is needed. An order the stage never held keeps no claim and is
+/// refunded in full, as with `clear_batch`.
+fn take_staged_claim(
+    order: &mut Account<Order>,
+    batch_state: &BatchState,
+    clearing_state: Option<&mut Box<Account<ClearingState>>>,
+) -> Result<()> {
+    if batch_state.clearing_state == Pubkey::default()
+        || order.has_flag(OrderFlags::CLAIM_RECORDED)
+    {
+        return Ok(());
+    }
+    let state = clearing_state.ok_or(AmmError::ClearingStateRequired)?;
+    let order_key = order.key();
+    if let Some(staged) = state.entries.iter().find(|staged| staged.order == order_key) {
+        // Inactive entries have no claim: the order settles as a plain refund.
+        if staged.active {
+            order.record_claim(&staged.claim);
+        }
+        state.claims_taken = state
+            .claims_taken
+            .checked_add(1)
+            .ok_or(AmmError::MathOverflow)?;
+    }
+    Ok(())
+}
+
+/// The program a Token-2022 mint's transfer hook invokes, if it has one.
+fn transfer_hook_program(mint: &AccountInfo) -> Result<Option<Pubkey>> {
+    if *mint.owner != spl_token_2022::ID {
+        return Ok(None);
+    }
+    let data = mint.try_borrow_data()?;
+    let state =
+        spl_token_2022::extension::StateWithExtensions::<spl_token_2022::state::Mint>::unpack(
+            &data,
+        )?;
+    Ok(spl_token_2022::extension::transfer_hook::get_program_id(&state))
+}
+
+/// `transfer_checked` that forwards the CPI context's remaining accounts to the mint's
+/// transfer hook (its program, its extra-account-metas PDA and the accounts that lists).
+/// The hook program must be on `allowed_hook_programs`.
+fn transfer_checked_with_hook<'info>(
+    ctx: CpiContext<'_, '_, '_, 'info, TransferChecked<'info>>,
+    allowed_hook_programs: &[Pubkey],
+    amount: u64,
+    decimals: u8,
+) -> Result<()> {
+    if let Some(hook_program) = transfer_hook_program(&ctx.accounts.mint)? {
+        require!(
+            allowed_hook_programs.contains(&hook_program),
+            AmmError::TransferHookNotAllowed
+        );
+    }
+    spl_token_2022::onchain::invoke_transfer_checked(
+        ctx.program.key,
+        ctx.accounts.from,
+        ctx.accounts.mint,
+        ctx.accounts.to,
+        ctx.accounts.authority,
+        &ctx.remaining_accounts,
+        amount,
+        decimals,
+        ctx.signer_seeds,
+    )?;
+    Ok(())
+}
+
+/// Invoke `program` with `remaining` as its accounts, the market PDA signing.
+fn market_signed_cpi<'info>(
+    market: &Account<'info, Market>,
+    program: &AccountInfo<'info>,
+    remaining: &[AccountInfo<'info>],
+    ix_data: Vec<u8>,
+) -> Result<()> {
+    let market_key = market.key();
+    let metas: Vec<AccountMeta> = remaining
+        .iter()
+        .map(|ai| AccountMeta {
+            pubkey: *ai.key,
+            is_signer: ai.is_signer || *ai.key == market_key,
+            is_writable: ai.is_writable,
+        })
+        .collect();
+    let ix = Instruction {
+        program_id: *program.key,
+        accounts: metas,
+        data: ix_data,
+    };
+
+    let mut infos: Vec<AccountInfo<'info>> = remaining.to_vec();
+    infos.push(program.clone());
+
+    let market_seeds: &[&[u8]] = &[
+        MARKET_SEED,
+        market.authority.as_ref(),
+        market.base_mint.as_ref(),
+        market.quote_mint.as_ref(),
+        &[market.bump],
+    ];
+    invoke_signed(&ix, &infos, &[market_seeds])?;
+    Ok(())
+}
+
+// -------------------------------
+// External liquidity adapters
+// -------------------------------
+
+/// Request sent to an adapter for the residual left after the uniform-price match.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct QuoteRequest {
+    pub market: Pubkey,
+    /// Side with unmatched size (Bid = excess buyers, Ask = excess sellers).
+    pub residual_side: OrderSide,
+    pub amount_base_fp: u64,
+    /// Clearing price; quotes must be at least as good for the residual side.
+    pub limit_price_fp: u64,
+}
+
+/// Firm quote returned by an adapter via return data.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct FirmQuote {
+    pub price_fp: u64,
+    pub max_base_fp: u64,
+}
+
+/// Source of firm quotes for residual imbalance after the uniform-price match.
+pub trait LiquiditySource {
+    /// Returns a quote that honors `request.limit_price_fp`, or `None` if there is none.
+    fn firm_quote(&self, request: &QuoteRequest) -> Result<Option<FirmQuote>>;
+}
+
+/// Adapter program reached via CPI.
+///
+/// Interface: instruction `get_quote(request: QuoteRequest)` (Anchor sighash
+/// `global:get_quote`) that sets a borsh `FirmQuote` as return data.
+struct CpiLiquiditySource<'a, 'info> {
+    program: &'a AccountInfo<'info>,
+    accounts: &'a [AccountInfo<'info>],
+}
+
+impl<'a, 'info> LiquiditySource for CpiLiquiditySource<'a, 'info> {
+    fn firm_quote(&self, request: &QuoteRequest) -> Result<Option<FirmQuote>> {
+        let mut data = anchor_lang::solana_program::hash::hash(b"global:get_quote").to_bytes()
+            [..8]
+            .to_vec();
+        request.serialize(&mut data)?;
+
+        let ix = Instruction {
+            program_id: *self.program.key,
+            accounts: self
+                .accounts
+                .iter()
+                .map(|ai| AccountMeta {
+                    pubkey: *ai.key,
+                    is_signer: false,
+                    is_writable: ai.is_writable,
+                })
+                .collect(),
+            data,
+        };
+        let mut infos: Vec<AccountInfo<'info>> = self.accounts.to_vec();
+        infos.push(self.program.clone());
+        anchor_lang::solana_program::program::invoke(&ix, &infos)?;
+
+        let quote = match anchor_lang::solana_program::program::get_return_data() {
+            Some((program_id, ret)) if program_id == *self.program.key => {
+                FirmQuote::try_from_slice(&ret)?
+            }
+            _ => return Ok(None),
+        };
+        let honors_limit = match request.residual_side {
+            // Excess buyers need an external seller at or below the clearing price.
+            OrderSide::Bid => quote.price_fp <= request.limit_price_fp,
+            // Excess sellers need an external buyer at or above it.
+            OrderSide::Ask => quote.price_fp >= request.limit_price_fp,
+        };
+        if quote.price_fp == 0 || quote.max_base_fp == 0 || !honors_limit {
+            return Ok(None);
+        }
+        Ok(Some(FirmQuote {
+            price_fp: quote.price_fp,
+            max_base_fp: quote.max_base_fp.min(request.amount_base_fp),
+        }))
+    }
+}
+
+/// Scan the transaction (via the instructions sysvar) for a System Program transfer of at
+/// least `min_lamports` into `tip_account`.
+fn tx_has_tip(ix_sysvar: &AccountInfo, tip_account: &Pubkey, min_lamports: u64) -> Result<bool> {
+    // SystemInstruction::Transfer = u32 tag 2, followed by u64 lamports.
+    const SYSTEM_TRANSFER_TAG: u32 = 2;
+
+    let mut i = 0usize;
+    while let Ok(ix) = sysvar_instructions::load_instruction_at_checked(i, ix_sysvar) {
+        i += 1;
+        if ix.program_id != system_program::ID || ix.data.len() < 12 || ix.accounts.len() < 2 {
+            continue;
+        }
+        let tag = u32::from_le_bytes(ix.data[0..4].try_into().unwrap());
+        let lamports = u64::from_le_bytes(ix.data[4..12].try_into().unwrap());
+        if tag == SYSTEM_TRANSFER_TAG
+            && ix.accounts[1].pubkey == *tip_account
+            && lamports >= min_lamports
+        {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Hash of the most recent slot recorded in the SlotHashes sysvar. The sysvar is too large
+/// to deserialize on-chain, so the first entry is read straight from the account data
+/// (u64 length prefix, then `(slot: u64, hash: [u8; 32])` entries, newest first).
+fn most_recent_slot_hash(slot_hashes: &AccountInfo) -> Result<[u8; 32]> {
+    let data = slot_hashes.try_borrow_data()?;
+    require!(data.len() >= 48, AmmError::SlotHashUnavailable);
+    let len = u64::from_le_bytes(data[0..8].try_into().unwrap());
+    require!(len > 0, AmmError::SlotHashUnavailable);
+    Ok(data[16..48].try_into().unwrap())
+}
+
+// -------------------------------
+// Accounts
+// -------------------------------
+
+#[derive(Accounts)]
+#[instruction(batch_duration_slots: u64, fee_bps: u16, max_orders_per_user_per_batch: u32)]
+pub struct InitializeMarket<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// Both mints must belong to `token_program` (SPL Token or Token-2022).
+    #[account(mint::token_program = token_program)]
+    pub base_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_program)]
+    pub quote_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [
+            MARKET_SEED,
+            authority.key().as_ref(),
+            base_mint.key().as_ref(),
+            quote_mint.key().as_ref()
+        ],
+        bump,
+        space = 8 + Market::INIT_SPACE
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [VAULT_BASE_SEED, market.key().as_ref()],
+        bump,
+        token::mint = base_mint,
+        token::authority = market,
+        token::token_program = token_program
+    )]
+    pub vault_base: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [VAULT_QUOTE_SEED, market.key().as_ref()],
+        bump,
+        token::mint = quote_mint,
+        token::authority = market,
+        token::token_program = token_program
+    )]
+    pub vault_quote: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [EVENT_LOG_SEED, market.key().as_ref()],
+        bump,
+        space = 8 + EventLog::INIT_SPACE
+    )]
+    pub event_log: Box<Account<'info, EventLog>>,
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [PRICE_HISTORY_SEED, market.key().as_ref()],
+        bump,
+        space = 8 + PriceHistory::INIT_SPACE
+    )]
+    pub price_history: Box<Account<'info, PriceHistory>>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Account<'info, ProgramConfig>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct PlaceOrder<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        has_one = base_mint,
+        has_one = quote_mint,
+        constraint = !market.is_paused(PauseModes::PLACEMENT) @ AmmError::MarketPaused
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    pub base_mint: InterfaceAccount<'info, Mint>,
+    pub quote_mint: InterfaceAccount<'info, Mint>,
+
+    /// Trade through this sub-account of `user` instead of the wallet itself.
+    #[account(
+        mut,
+        constraint = sub_account.master == user.key() @ AmmError::InvalidSubAccount,
+        constraint = sub_account.market == market.key() @ AmmError::InvalidSubAccount
+    )]
+    pub sub_account: Option<Account<'info, SubAccount>>,
+
+    #[account(
+        mut,
+        constraint = vault_base.key() == market.vault_base
+    )]
+    pub vault_base: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = vault_quote.key() == market.vault_quote
+    )]
+    pub vault_quote: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = user_base_ata.owner == trader_key(&user.key(), &sub_account),
+        constraint = user_base_ata.mint == base_mint.key()
+    )]
+    pub user_base_ata: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = user_quote_ata.owner == trader_key(&user.key(), &sub_account),
+        constraint = user_quote_ata.mint == quote_mint.key()
+    )]
+    pub user_quote_ata: InterfaceAccount<'info, TokenAccount>,
+
+    // Declared before `order`: its nonce is part of the order seeds.
+    #[account(
+        init_if_needed,
+        payer = user,
+        seeds = [
+            USER_STATS_SEED,
+            market.key().as_ref(),
+            trader_key(&user.key(), &sub_account).as_ref()
+        ],
+        bump,
+        space = 8 + UserStats::INIT_SPACE
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    // Seeded per user so concurrent placements by different users never collide; the
+    // per-batch salt keeps addresses unpredictable until the batch opens.
+    #[account(
+        init,
+        payer = user,
+        seeds = [
+            ORDER_SEED,
+            market.key().as_ref(),
+            trader_key(&user.key(), &sub_account).as_ref(),
+            &user_stats.order_nonce.to_le_bytes(),
+            &market.batch_salt
+        ],
+        bump,
+        space = 8 + Order::INIT_SPACE
+    )]
+    pub order: Account<'info, Order>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        seeds = [
+            USER_BATCH_SEED,
+            market.key().as_ref(),
+            trader_key(&user.key(), &sub_account).as_ref(),
+            &market.current_batch_id.to_le_bytes()
+        ],
+        bump,
+        space = 8 + UserBatchStats::INIT_SPACE
+    )]
+    pub user_batch_stats: Account<'info, UserBatchStats>,
+
+    // Page holding this order's `batch_seq` in the batch's order index.
+    #[account(
+        init_if_needed,
+        payer = user,
+        seeds = [
+            ORDER_INDEX_SEED,
+            market.key().as_ref(),
+            &market.current_batch_id.to_le_bytes(),
+            &(market.global_orders_in_batch / ORDER_INDEX_PAGE_SIZE as u32).to_le_bytes()
+        ],
+        bump,
+        space = 8 + OrderIndex::INIT_SPACE
+    )]
+    pub order_index: Account<'info, OrderIndex>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub rent: Sysvar<'info, Rent>,
+
+    /// Required when the market is in RFQ mode.
+    pub rfq_whitelist: Option<Account<'info, RfqWhitelist>>,
+
+    #[account(
+        mut,
+        seeds = [EVENT_LOG_SEED, market.key().as_ref()],
+        bump = event_log.bump
+    )]
+    pub event_log: Box<Account<'info, EventLog>>,
+}
+
+#[derive(Accounts)]
+pub struct ClearBatch<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        has_one = base_mint,
+        has_one = quote_mint
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    pub base_mint: InterfaceAccount<'info, Mint>,
+    pub quote_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = vault_base.key() == market.vault_base
+    )]
+    pub vault_base: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = vault_quote.key() == market.vault_quote
+    )]
+    pub vault_quote: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        seeds = [BATCH_STATE_SEED, market.key().as_ref(), &market.current_batch_id.to_le_bytes()],
+        bump,
+        space = 8 + BatchState::INIT_SPACE
+    )]
+    pub batch_state: Account<'info, BatchState>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    // no #[account] attribute: avoids AccountDeserialize requirement
+    pub system_program: Program<'info, System>,
+
+    /// Optional reference market used to activate conditional orders in this clear.
+    pub trigger_market: Option<Account<'info, Market>>,
+
+    /// Required when a keeper registered a clear intent for this batch.
+    #[account(mut)]
+    pub clear_intent: Option<Account<'info, ClearIntent>>,
+
+    /// CHECK: address-constrained to the instructions sysvar; required when the market
+    /// enforces bundle tips.
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions_sysvar: Option<UncheckedAccount<'info>>,
+
+    /// Registered external liquidity adapter (optional).
+    pub liquidity_adapter: Option<Account<'info, LiquidityAdapter>>,
+
+    /// CHECK: address-constrained to the SlotHashes sysvar; read raw for the batch salt.
+    #[account(address = slot_hashes::ID)]
+    pub slot_hashes: UncheckedAccount<'info>,
+
+    /// The clearing keeper's record; routes the keeper reward to its registered recipient.
+    #[account(
+        seeds = [KEEPER_SEED, market.key().as_ref(), authority.key().as_ref()],
+        bump = keeper_record.bump
+    )]
+    pub keeper_record: Option<Account<'info, KeeperRecord>>,
+
+    /// Pays `clear_reimbursement_lamports` to the clearer when passed.
+    #[account(
+        mut,
+        seeds = [REIMBURSEMENT_VAULT_SEED, market.key().as_ref()],
+        bump = reimbursement_vault.bump
+    )]
+    pub reimbursement_vault: Option<Account<'info, ReimbursementVault>>,
+
+    /// CHECK: must equal `liquidity_adapter.program`; checked in the handler.
+    pub adapter_program: Option<UncheckedAccount<'info>>,
+
+    #[account(
+        mut,
+        seeds = [EVENT_LOG_SEED, market.key().as_ref()],
+        bump = event_log.bump
+    )]
+    pub event_log: Box<Account<'info, EventLog>>,
+
+    #[account(
+        mut,
+        seeds = [PRICE_HISTORY_SEED, market.key().as_ref()],
+        bump = price_history.bump
+    )]
+    pub price_history: Box<Account<'info, PriceHistory>>,
+
+    /// The market's cluster and the keeper's share of its reward pool; pass both to book
+    /// this clear's volume to the keeper.
+    #[account(
+        mut,
+        address = market.cluster
+    )]
+    pub cluster: Option<Box<Account<'info, MarketCluster>>>,
+
+    #[account(
+        mut,
+        seeds = [CLUSTER_KEEPER_SEED, market.cluster.as_ref(), authority.key().as_ref()],
+        bump = cluster_keeper_share.bump
+    )]
+    pub cluster_keeper_share: Option<Account<'info, KeeperClusterShare>>,
+
+    /// Required when the market publishes to a work queue.
+    #[account(
+        mut,
+        address = market.work_queue
+    )]
+    pub work_queue: Option<Box<Account<'info, WorkQueue>>>,
+
+    /// The batch's stage from `begin_clear`; finalizes it (`finalize_clear`).
+    #[account(
+        mut,
+        seeds = [
+            CLEARING_STATE_SEED,
+            market.key().as_ref(),
+            &market.current_batch_id.to_le_bytes()
+        ],
+        bump = clearing_state.bump
+    )]
+    pub clearing_state: Option<Box<Account<'info, ClearingState>>>,
+}
+
+#[derive(Accounts)]
+pub struct BeginClear<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [
+            CLEARING_STATE_SEED,
+            market.key().as_ref(),
+            &market.current_batch_id.to_le_bytes()
+        ],
+        bump,
+        space = 8 + ClearingState::INIT_SPACE
+    )]
+    pub clearing_state: Box<Account<'info, ClearingState>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClearChunk<'info> {
+    pub authority: Signer<'info>,
+
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        mut,
+        has_one = market,
+        constraint = clearing_state.keeper == authority.key() @ AmmError::Unauthorized
+    )]
+    pub clearing_state: Box<Account<'info, ClearingState>>,
+}
+
+#[derive(Accounts)]
+pub struct CloseClearingState<'info> {
+    pub closer: Signer<'info>,
+
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        mut,
+        has_one = market,
+        has_one = keeper,
+        close = keeper
+    )]
+    pub clearing_state: Box<Account<'info, ClearingState>>,
+
+    /// CHECK: rent recipient, matched against `clearing_state.keeper`.
+    #[account(mut)]
+    pub keeper: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AuditBatch<'info> {
+    #[account(mut)]
+    pub auditor: Signer<'info>,
+
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        mut,
+        has_one = market
+    )]
+    pub batch_state: Account<'info, BatchState>,
+}
+
+#[derive(Accounts)]
+pub struct ExtendBatch<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    /// Required when the market publishes to a work queue; its clear item is rescheduled.
+    #[account(
+        mut,
+        address = market.work_queue
+    )]
+    pub work_queue: Option<Box<Account<'info, WorkQueue>>>,
+}
+
+#[derive(Accounts)]
+pub struct ReleaseClearBond<'info> {
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        mut,
+        has_one = market,
+        has_one = keeper
+    )]
+    pub batch_state: Account<'info, BatchState>,
+
+    /// CHECK: the batch's clearing keeper; only receives lamports.
+    #[account(mut)]
+    pub keeper: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct TipOrder<'info> {
+    pub tipper: Signer<'info>,
+
+    #[account(mut, has_one = quote_mint)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(mut, has_one = market)]
+    pub order: Account<'info, Order>,
+
+    pub quote_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = vault_quote.key() == market.vault_quote
+    )]
+    pub vault_quote: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = tipper_quote_ata.owner == tipper.key(),
+        constraint = tipper_quote_ata.mint == quote_mint.key()
+    )]
+    pub tipper_quote_ata: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimKeeperTips<'info> {
+    #[account(mut, has_one = quote_mint)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(mut, has_one = market)]
+    pub batch_state: Account<'info, BatchState>,
+
+    pub quote_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = vault_quote.key() == market.vault_quote
+    )]
+    pub vault_quote: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = recipient_quote_ata.owner == batch_state.keeper_reward_recipient,
+        constraint = recipient_quote_ata.mint == quote_mint.key()
+    )]
+    pub recipient_quote_ata: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ConfirmBatch<'info> {
+    pub authority: Signer<'info>,
+
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        mut,
+        has_one = market
+    )]
+    pub batch_state: Account<'info, BatchState>,
+}
+
+#[derive(Accounts)]
+pub struct SettleOrder<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        mut,
+        has_one = market
+    )]
+    pub batch_state: Account<'info, BatchState>,
+
+    /// Trade through this sub-account of `user` instead of the wallet itself.
+    #[account(
+        mut,
+        constraint = sub_account.master == user.key() @ AmmError::InvalidSubAccount,
+        constraint = sub_account.market == market.key() @ AmmError::InvalidSubAccount
+    )]
+    pub sub_account: Option<Account<'info, SubAccount>>,
+
+    #[account(
+        mut,
+        constraint = order.user == trader_key(&user.key(), &sub_account),
+        constraint = order.market == market.key()
+    )]
+    pub order: Account<'info, Order>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        seeds = [ORDER_FILL_SEED, order.key().as_ref()],
+        bump,
+        space = 8 + OrderFill::INIT_SPACE
+    )]
+    pub order_fill: Account<'info, OrderFill>,
+
+    #[account(
+        mut,
+        seeds = [
+            USER_BATCH_SEED,
+            market.key().as_ref(),
+            order.user.as_ref(),
+            &order.batch_id.to_le_bytes()
+        ],
+        bump = user_batch_stats.bump
+    )]
+    pub user_batch_stats: Account<'info, UserBatchStats>,
+
+    #[account(
+        mut,
+        seeds = [USER_STATS_SEED, market.key().as_ref(), order.user.as_ref()],
+        bump = user_stats.bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    // `strict-seeds` re-derives the vaults from their stored bumps instead of trusting
+    // the recorded keys.
+    #[cfg_attr(
+        not(feature = "strict-seeds"),
+        account(mut, constraint = vault_base.key() == market.vault_base)
+    )]
+    #[cfg_attr(
+        feature = "strict-seeds",
+        account(
+            mut,
+            seeds = [VAULT_BASE_SEED, market.key().as_ref()],
+            bump = market.vault_base_bump
+        )
+    )]
+    pub vault_base: InterfaceAccount<'info, TokenAccount>,
+
+    #[cfg_attr(
+        not(feature = "strict-seeds"),
+        account(mut, constraint = vault_quote.key() == market.vault_quote)
+    )]
+    #[cfg_attr(
+        feature = "strict-seeds",
+        account(
+            mut,
+            seeds = [VAULT_QUOTE_SEED, market.key().as_ref()],
+            bump = market.vault_quote_bump
+        )
+    )]
+    pub vault_quote: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: the order's owner (wallet or sub-account); only authorizes the payout ATAs.
+    #[account(address = order.user)]
+    pub order_owner: UncheckedAccount<'info>,
+
+    #[account(address = market.base_mint)]
+    pub base_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(address = market.quote_mint)]
+    pub quote_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    // Recreated (paid by `user`) if the owner closed it after placing the order.
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = base_mint,
+        associated_token::authority = order_owner,
+        associated_token::token_program = token_program
+    )]
+    pub user_base_ata: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = quote_mint,
+        associated_token::authority = order_owner,
+        associated_token::token_program = token_program
+    )]
+    pub user_quote_ata: InterfaceAccount<'info, TokenAccount>,
+
+    /// Vesting schedule for a bid filled in a vesting launch batch; required only then.
+    #[account(
+        init_if_needed,
+        payer = user,
+        seeds = [VESTING_SEED, order.key().as_ref()],
+        bump,
+        space = 8 + VestingSchedule::INIT_SPACE
+    )]
+    pub vesting: Option<Box<Account<'info, VestingSchedule>>>,
+
+    /// The order's travel-rule record; required only for fills above the market threshold.
+    #[account(
+        seeds = [TRAVEL_RULE_SEED, order.key().as_ref()],
+        bump = travel_rule.bump
+    )]
+    pub travel_rule: Option<Box<Account<'info, TravelRuleRecord>>>,
+
+    /// The order owner's `Claimables` index, if opened; the order is dropped from it.
+    #[account(
+        mut,
+        seeds = [CLAIMABLES_SEED, market.key().as_ref(), order.user.as_ref()],
+        bump = claimables.bump
+    )]
+    pub claimables: Option<Box<Account<'info, Claimables>>>,
+
+    /// The batch's `ClearingState`; required when it was cleared in chunks.
+    #[account(mut, address = batch_state.clearing_state)]
+    pub clearing_state: Option<Box<Account<'info, ClearingState>>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    // no #[account] attribute
+    pub system_program: Program<'info, System>,
+
+    #[account(
+        mut,
+        seeds = [EVENT_LOG_SEED, market.key().as_ref()],
+        bump = event_log.bump
+    )]
+    pub event_log: Box<Account<'info, EventLog>>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimTranche<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    /// Claim for this sub-account of `user` instead of the wallet itself.
+    #[account(
+        constraint = sub_account.master == user.key() @ AmmError::InvalidSubAccount,
+        constraint = sub_account.market == market.key() @ AmmError::InvalidSubAccount
+    )]
+    pub sub_account: Option<Account<'info, SubAccount>>,
+
+    #[account(
+        mut,
+        seeds = [VESTING_SEED, vesting.order.as_ref()],
+        bump = vesting.bump,
+        constraint = vesting.user == trader_key(&user.key(), &sub_account),
+        constraint = vesting.market == market.key()
+    )]
+    pub vesting: Account<'info, VestingSchedule>,
+
+    #[account(
+        mut,
+        constraint = vault_base.key() == market.vault_base
+    )]
+    pub vault_base: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: the vesting owner (wallet or sub-account); only authorizes the payout ATA.
+    #[account(address = vesting.user)]
+    pub order_owner: UncheckedAccount<'info>,
+
+    #[account(address = market.base_mint)]
+    pub base_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = base_mint,
+        associated_token::authority = order_owner,
+        associated_token::token_program = token_program
+    )]
+    pub user_base_ata: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct LinkOcoOrders<'info> {
+    pub user: Signer<'info>,
+
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    /// Link orders of this sub-account of `user` instead of the wallet itself.
+    #[account(
+        constraint = sub_account.master == user.key() @ AmmError::InvalidSubAccount,
+        constraint = sub_account.market == market.key() @ AmmError::InvalidSubAccount
+    )]
+    pub sub_account: Option<Account<'info, SubAccount>>,
+
+    #[account(
+        mut,
+        constraint = order_a.user == trader_key(&user.key(), &sub_account),
+        constraint = order_a.market == market.key()
+    )]
+    pub order_a: Account<'info, Order>,
+
+    #[account(
+        mut,
+        constraint = order_b.user == trader_key(&user.key(), &sub_account),
+        constraint = order_b.market == market.key()
+    )]
+    pub order_b: Account<'info, Order>,
+}
+
+#[derive(Accounts)]
+pub struct SetCancelAuthority<'info> {
+    pub user: Signer<'info>,
+
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    /// Delegate for an order of this sub-account of `user` instead of the wallet itself.
+    #[account(
+        constraint = sub_account.master == user.key() @ AmmError::InvalidSubAccount,
+        constraint = sub_account.market == market.key() @ AmmError::InvalidSubAccount
+    )]
+    pub sub_account: Option<Account<'info, SubAccount>>,
+
+    #[account(
+        mut,
+        constraint = order.user == trader_key(&user.key(), &sub_account),
+        constraint = order.market == market.key()
+    )]
+    pub order: Account<'info, Order>,
+}
+
+#[derive(Accounts)]
+pub struct CancelOrder<'info> {
+    /// The order's owner, or its `cancel_authority`.
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    /// Trade through this sub-account of `user` instead of the wallet itself.
+    #[account(
+        mut,
+        constraint = sub_account.master == user.key() @ AmmError::InvalidSubAccount,
+        constraint = sub_account.market == market.key() @ AmmError::InvalidSubAccount
+    )]
+    pub sub_account: Option<Account<'info, SubAccount>>,
+
+    #[account(
+        mut,
+        constraint = order.user == trader_key(&user.key(), &sub_account)
+            || order.cancel_authority == user.key(),
+        constraint = order.market == market.key()
+    )]
+    pub order: Account<'info, Order>,
+
+    #[account(
+        mut,
+        seeds = [
+            USER_BATCH_SEED,
+            market.key().as_ref(),
+            order.user.as_ref(),
+            &order.batch_id.to_le_bytes()
+        ],
+        bump = user_batch_stats.bump
+    )]
+    pub user_batch_stats: Account<'info, UserBatchStats>,
+
+    #[account(
+        mut,
+        seeds = [USER_STATS_SEED, market.key().as_ref(), order.user.as_ref()],
+        bump = user_stats.bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    // `strict-seeds` re-derives the vaults from their stored bumps instead of trusting
+    // the recorded keys.
+    #[cfg_attr(
+        not(feature = "strict-seeds"),
+        account(mut, constraint = vault_base.key() == market.vault_base)
+    )]
+    #[cfg_attr(
+        feature = "strict-seeds",
+        account(
+            mut,
+            seeds = [VAULT_BASE_SEED, market.key().as_ref()],
+            bump = market.vault_base_bump
+        )
+    )]
+    pub vault_base: InterfaceAccount<'info, TokenAccount>,
+
+    #[cfg_attr(
+        not(feature = "strict-seeds"),
+        account(mut, constraint = vault_quote.key() == market.vault_quote)
+    )]
+    #[cfg_attr(
+        feature = "strict-seeds",
+        account(
+            mut,
+            seeds = [VAULT_QUOTE_SEED, market.key().as_ref()],
+            bump = market.vault_quote_bump
+        )
+    )]
+    pub vault_quote: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: the order's owner (wallet or sub-account); only authorizes the payout ATAs.
+    #[account(address = order.user)]
+    pub order_owner: UncheckedAccount<'info>,
+
+    #[account(address = market.base_mint)]
+    pub base_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(address = market.quote_mint)]
+    pub quote_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    // Recreated (paid by `user`) if the owner closed it after placing the order.
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = base_mint,
+        associated_token::authority = order_owner,
+        associated_token::token_program = token_program
+    )]
+    pub user_base_ata: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = quote_mint,
+        associated_token::authority = order_owner,
+        associated_token::token_program = token_program
+    )]
+    pub user_quote_ata: InterfaceAccount<'info, TokenAccount>,
+
+    /// The order owner's `Claimables` index, if opened; the order is dropped from it.
+    #[account(
+        mut,
+        seeds = [CLAIMABLES_SEED, market.key().as_ref(), order.user.as_ref()],
+        bump = claimables.bump
+    )]
+    pub claimables: Option<Box<Account<'info, Claimables>>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+
+    #[account(
+        mut,
+        seeds = [EVENT_LOG_SEED, market.key().as_ref()],
+        bump = event_log.bump
+    )]
+    pub event_log: Box<Account<'info, EventLog>>,
+}
+
+#[derive(Accounts)]
+pub struct SetPaused<'info> {
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+}
+
+#[derive(Accounts)]
+pub struct SetParams<'info> {
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Account<'info, ProgramConfig>,
+}
+
+#[derive(Accounts)]
+pub struct SetQuoteNormalization<'info> {
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+    #[account(address = market.quote_mint)]
+    pub quote_mint: InterfaceAccount<'info, Mint>,
+}
+
+#[derive(Accounts)]
+pub struct InitProgramConfig<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump,
+        space = 8 + ProgramConfig::INIT_SPACE
+    )]
+    pub program_config: Account<'info, ProgramConfig>,
+
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()))]
+    pub program: Program<'info, crate::program::MicroBatchAmm>,
+
+    #[account(
+        constraint = program_data.upgrade_authority_address == Some(authority.key())
+            @ AmmError::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetProgramConfig<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        has_one = admin @ AmmError::Unauthorized,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Account<'info, ProgramConfig>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateProgramState<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    /// CHECK: deserialized by hand, since a config from before the upgrade may be short
+    /// of the current layout; admin is checked once it has been grown.
+    #[account(mut, seeds = [PROGRAM_CONFIG_SEED], bump)]
+    pub program_config: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterKeeper<'info> {
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        init_if_needed,
+        payer = keeper,
+        seeds = [KEEPER_SEED, market.key().as_ref(), keeper.key().as_ref()],
+        bump,
+        space = 8 + KeeperRecord::INIT_SPACE
+    )]
+    pub keeper_record: Account<'info, KeeperRecord>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(cluster_id: u16)]
+pub struct CreateMarketCluster<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [CLUSTER_SEED, authority.key().as_ref(), &cluster_id.to_le_bytes()],
+        bump,
+        space = 8 + MarketCluster::INIT_SPACE
+    )]
+    pub cluster: Account<'info, MarketCluster>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct JoinMarketCluster<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        mut,
+        has_one = authority
+    )]
+    pub cluster: Account<'info, MarketCluster>,
+}
+
+#[derive(Accounts)]
+pub struct FundClusterRewards<'info> {
+    #[account(mut)]
+    pub funder: Signer<'info>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(mut)]
+    pub cluster: Account<'info, MarketCluster>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterClusterKeeper<'info> {
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    pub cluster: Account<'info, MarketCluster>,
+
+    #[account(
+        init,
+        payer = keeper,
+        seeds = [CLUSTER_KEEPER_SEED, cluster.key().as_ref(), keeper.key().as_ref()],
+        bump,
+        space = 8 + KeeperClusterShare::INIT_SPACE
+    )]
+    pub cluster_keeper_share: Account<'info, KeeperClusterShare>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseClusterRewardEpoch<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        mut,
+        has_one = authority
+    )]
+    pub cluster: Account<'info, MarketCluster>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimClusterRewards<'info> {
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(mut)]
+    pub cluster: Account<'info, MarketCluster>,
+
+    #[account(
+        mut,
+        seeds = [CLUSTER_KEEPER_SEED, cluster.key().as_ref(), keeper.key().as_ref()],
+        bump = cluster_keeper_share.bump
+    )]
+    pub cluster_keeper_share: Account<'info, KeeperClusterShare>,
+}
+
+#[derive(Accounts)]
+pub struct OpenClaimables<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    /// Open the index of this sub-account of `user` instead of the wallet's.
+    #[account(
+        constraint = sub_account.master == user.key() @ AmmError::InvalidSubAccount,
+        constraint = sub_account.market == market.key() @ AmmError::InvalidSubAccount
+    )]
+    pub sub_account: Option<Account<'info, SubAccount>>,
+
+    #[account(
+        init,
+        payer = user,
+        seeds = [
+            CLAIMABLES_SEED,
+            market.key().as_ref(),
+            trader_key(&user.key(), &sub_account).as_ref()
+        ],
+        bump,
+        space = 8 + Claimables::INIT_SPACE
+    )]
+    pub claimables: Box<Account<'info, Claimables>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseUserBatchStats<'info> {
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    /// Set when the stats belong to this sub-account; the rent then goes to its master.
+    #[account(
+        constraint = sub_account.key() == user_batch_stats.user @ AmmError::InvalidSubAccount
+    )]
+    pub sub_account: Option<Account<'info, SubAccount>>,
+
+    /// CHECK: the trader's wallet (or the sub-account's master); only receives the rent.
+    #[account(
+        mut,
+        constraint = rent_recipient.key()
+            == sub_account.as_ref().map_or(user_batch_stats.user, |s| s.master)
+            @ AmmError::Unauthorized
+    )]
+    pub rent_recipient: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        has_one = market,
+        close = rent_recipient
+    )]
+    pub user_batch_stats: Account<'info, UserBatchStats>,
+}
+
+#[derive(Accounts)]
+pub struct SweepUserBatchStats<'info> {
+    pub market: Account<'info, Market>,
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+}
+
+#[derive(Accounts)]
+pub struct PruneClaimables<'info> {
+    #[account(mut)]
+    pub claimables: Box<Account<'info, Claimables>>,
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+}
+
+#[derive(Accounts)]
+#[instruction(queue_id: u16)]
+pub struct CreateWorkQueue<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [WORK_QUEUE_SEED, authority.key().as_ref(), &queue_id.to_le_bytes()],
+        bump,
+        space = 8 + WorkQueue::INIT_SPACE
+    )]
+    pub work_queue: Box<Account<'info, WorkQueue>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AttachWorkQueue<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        mut,
+        has_one = authority
+    )]
+    pub work_queue: Box<Account<'info, WorkQueue>>,
+}
+
+#[derive(Accounts)]
+pub struct PruneWorkQueue<'info> {
+    #[account(mut)]
+    pub work_queue: Box<Account<'info, WorkQueue>>,
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+}
+
+#[derive(Accounts)]
+pub struct ConfigureRfq<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        seeds = [RFQ_SEED, market.key().as_ref()],
+        bump,
+        space = 8 + RfqWhitelist::INIT_SPACE
+    )]
+    pub rfq_whitelist: Account<'info, RfqWhitelist>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ConfigureLaunch<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        mut,
+        constraint = vault_base.key() == market.vault_base
+    )]
+    pub vault_base: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = authority_base_ata.owner == authority.key(),
+        constraint = authority_base_ata.mint == market.base_mint
+    )]
+    pub authority_base_ata: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = market.base_mint)]
+    pub base_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimLaunchProceeds<'info> {
+    pub payer: Signer<'info>,
+
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        mut,
+        constraint = vault_base.key() == market.vault_base
+    )]
+    pub vault_base: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = vault_quote.key() == market.vault_quote
+    )]
+    pub vault_quote: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = beneficiary_base_ata.owner == market.launch_beneficiary,
+        constraint = beneficiary_base_ata.mint == market.base_mint
+    )]
+    pub beneficiary_base_ata: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = beneficiary_quote_ata.owner == market.launch_beneficiary,
+        constraint = beneficiary_quote_ata.mint == market.quote_mint
+    )]
+    pub beneficiary_quote_ata: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = market.base_mint)]
+    pub base_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(address = market.quote_mint)]
+    pub quote_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterClearIntent<'info> {
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        init,
+        payer = keeper,
+        seeds = [CLEAR_INTENT_SEED, market.key().as_ref(), &market.current_batch_id.to_le_bytes()],
+        bump,
+        space = 8 + ClearIntent::INIT_SPACE
+    )]
+    pub clear_intent: Account<'info, ClearIntent>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct OutbidClearIntent<'info> {
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        mut,
+        seeds = [CLEAR_INTENT_SEED, market.key().as_ref(), &market.current_batch_id.to_le_bytes()],
+        bump = clear_intent.bump
+    )]
+    pub clear_intent: Account<'info, ClearIntent>,
+
+    /// CHECK: the keeper holding the intent; only receives its bond back.
+    #[account(
+        mut,
+        address = clear_intent.keeper
+    )]
+    pub previous_keeper: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct FundReimbursementVault<'info> {
+    #[account(mut)]
+    pub funder: Signer<'info>,
+
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        init_if_needed,
+        payer = funder,
+        seeds = [REIMBURSEMENT_VAULT_SEED, market.key().as_ref()],
+        bump,
+        space = 8 + ReimbursementVault::INIT_SPACE
+    )]
+    pub reimbursement_vault: Account<'info, ReimbursementVault>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct YieldCpi<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        mut,
+        constraint = vault_quote.key() == market.vault_quote
+    )]
+    pub vault_quote: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: must be the market's configured yield adapter program.
+    #[account(
+        executable,
+        constraint = yield_program.key() == market.yield_program @ AmmError::YieldDisabled
+    )]
+    pub yield_program: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterLiquidityAdapter<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        seeds = [LIQUIDITY_ADAPTER_SEED, market.key().as_ref()],
+        bump,
+        space = 8 + LiquidityAdapter::INIT_SPACE
+    )]
+    pub liquidity_adapter: Account<'info, LiquidityAdapter>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RouteResidual<'info> {
+    pub keeper: Signer<'info>,
+
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(mut)]
+    pub batch_state: Account<'info, BatchState>,
+
+    #[account(
+        mut,
+        constraint = vault_base.key() == market.vault_base
+    )]
+    pub vault_base: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = vault_quote.key() == market.vault_quote
+    )]
+    pub vault_quote: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        seeds = [LIQUIDITY_ADAPTER_SEED, market.key().as_ref()],
+        bump = liquidity_adapter.bump
+    )]
+    pub liquidity_adapter: Account<'info, LiquidityAdapter>,
+
+    /// CHECK: must be the registered adapter program.
+    #[account(
+        executable,
+        constraint = adapter_program.key() == liquidity_adapter.program @ AmmError::InvalidLiquidityAdapter
+    )]
+    pub adapter_program: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RefundUncrossedBatch<'info> {
+    pub cranker: Signer<'info>,
+
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(mut)]
+    pub batch_state: Account<'info, BatchState>,
+
+    #[account(
+        mut,
+        constraint = vault_base.key() == market.vault_base
+    )]
+    pub vault_base: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = vault_quote.key() == market.vault_quote
+    )]
+    pub vault_quote: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = market.base_mint)]
+    pub base_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(address = market.quote_mint)]
+    pub quote_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    #[account(
+        mut,
+        seeds = [EVENT_LOG_SEED, market.key().as_ref()],
+        bump = event_log.bump
+    )]
+    pub event_log: Box<Account<'info, EventLog>>,
+}
+
+#[derive(Accounts)]
+pub struct RefundOrder<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        mut,
+        has_one = market,
+        constraint = batch_state.batch_id == order.batch_id @ AmmError::BatchIdMismatch
+    )]
+    pub batch_state: Account<'info, BatchState>,
+
+    #[account(
+        mut,
+        constraint = order.market == market.key()
+    )]
+    pub order: Account<'info, Order>,
+
+    #[account(
+        mut,
+        seeds = [
+            USER_BATCH_SEED,
+            market.key().as_ref(),
+            order.user.as_ref(),
+            &order.batch_id.to_le_bytes()
+        ],
+        bump = user_batch_stats.bump
+    )]
+    pub user_batch_stats: Account<'info, UserBatchStats>,
+
+    #[account(
+        mut,
+        seeds = [USER_STATS_SEED, market.key().as_ref(), order.user.as_ref()],
+        bump = user_stats.bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    #[account(
+        mut,
+        constraint = vault_base.key() == market.vault_base
+    )]
+    pub vault_base: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = vault_quote.key() == market.vault_quote
+    )]
+    pub vault_quote: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: the order's owner (wallet or sub-account); only authorizes the payout ATAs.
+    #[account(address = order.user)]
+    pub order_owner: UncheckedAccount<'info>,
+
+    #[account(address = market.base_mint)]
+    pub base_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(address = market.quote_mint)]
+    pub quote_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    // Created (paid by `payer`) if the owner has none.
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = base_mint,
+        associated_token::authority = order_owner,
+        associated_token::token_program = token_program
+    )]
+    pub user_base_ata: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = quote_mint,
+        associated_token::authority = order_owner,
+        associated_token::token_program = token_program
+    )]
+    pub user_quote_ata: InterfaceAccount<'info, TokenAccount>,
+
+    /// The order owner's `Claimables` index, if opened; the order is dropped from it.
+    #[account(
+        mut,
+        seeds = [CLAIMABLES_SEED, market.key().as_ref(), order.user.as_ref()],
+        bump = claimables.bump
+    )]
+    pub claimables: Option<Box<Account<'info, Claimables>>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+
+    #[account(
+        mut,
+        seeds = [EVENT_LOG_SEED, market.key().as_ref()],
+        bump = event_log.bump
+    )]
+    pub event_log: Box<Account<'info, EventLog>>,
+}
+
+#[derive(Accounts)]
+pub struct SetMarketMetadata<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        seeds = [MARKET_METADATA_SEED, market.key().as_ref()],
+        bump,
+        space = 8 + MarketMetadata::INIT_SPACE
+    )]
+    pub market_metadata: Account<'info, MarketMetadata>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetSettlementCosigner<'info> {
+    pub user: Signer<'info>,
+
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        mut,
+        seeds = [USER_STATS_SEED, market.key().as_ref(), user.key().as_ref()],
+        bump = user_stats.bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    /// Required when a co-signer is already configured.
+    pub current_cosigner: Option<Signer<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct ApproveSettlement<'info> {
+    pub cosigner: Signer<'info>,
+
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        mut,
+        constraint = order.market == market.key()
+    )]
+    pub order: Account<'info, Order>,
+
+    #[account(
+        seeds = [USER_STATS_SEED, market.key().as_ref(), order.user.as_ref()],
+        bump = user_stats.bump,
+        constraint = user_stats.settlement_cosigner == cosigner.key() @ AmmError::Unauthorized
+    )]
+    pub user_stats: Account<'info, UserStats>,
+}
+
+#[derive(Accounts)]
+pub struct FreezeOrder<'info> {
+    #[account(
+        constraint = compliance_authority.key() == market.compliance_authority
+            @ AmmError::Unauthorized
+    )]
+    pub compliance_authority: Signer<'info>,
+
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        mut,
+        constraint = order.market == market.key()
+    )]
+    pub order: Account<'info, Order>,
+}
+
+#[derive(Accounts)]
+pub struct AttachTravelRule<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    /// Attach to an order of this sub-account of `user` instead of the wallet itself.
+    #[account(
+        constraint = sub_account.master == user.key() @ AmmError::InvalidSubAccount,
+        constraint = sub_account.market == market.key() @ AmmError::InvalidSubAccount
+    )]
+    pub sub_account: Option<Account<'info, SubAccount>>,
+
+    #[account(
+        constraint = order.user == trader_key(&user.key(), &sub_account),
+        constraint = order.market == market.key()
+    )]
+    pub order: Account<'info, Order>,
+
+    #[account(
+        init,
+        payer = user,
+        seeds = [TRAVEL_RULE_SEED, order.key().as_ref()],
+        bump,
+        space = 8 + TravelRuleRecord::INIT_SPACE
+    )]
+    pub travel_rule: Account<'info, TravelRuleRecord>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetRecoveryAddress<'info> {
+    pub user: Signer<'info>,
+
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    /// Set the address for this sub-account of `user` instead of the wallet itself.
+    #[account(
+        constraint = sub_account.master == user.key() @ AmmError::InvalidSubAccount,
+        constraint = sub_account.market == market.key() @ AmmError::InvalidSubAccount
+    )]
+    pub sub_account: Option<Account<'info, SubAccount>>,
+
+    #[account(
+        mut,
+        seeds = [
+            USER_STATS_SEED,
+            market.key().as_ref(),
+            trader_key(&user.key(), &sub_account).as_ref()
+        ],
+        bump = user_stats.bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+}
+
+#[derive(Accounts)]
+pub struct RequestRecovery<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(constraint = order.market == market.key())]
+    pub order: Account<'info, Order>,
+
+    #[account(
+        seeds = [USER_STATS_SEED, market.key().as_ref(), order.user.as_ref()],
+        bump = user_stats.bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [RECOVERY_SEED, order.key().as_ref()],
+        bump,
+        space = 8 + RecoveryRequest::INIT_SPACE
+    )]
+    pub recovery_request: Account<'info, RecoveryRequest>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelRecovery<'info> {
+    pub signer: Signer<'info>,
+
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    /// CHECK: receives the request's rent; must be the market authority that paid it.
+    #[account(mut, address = market.authority)]
+    pub market_authority: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [RECOVERY_SEED, recovery_request.order.as_ref()],
+        bump = recovery_request.bump,
+        constraint = recovery_request.market == market.key(),
+        close = market_authority
+    )]
+    pub recovery_request: Account<'info, RecoveryRequest>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteRecovery<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        mut,
+        has_one = market,
+        constraint = batch_state.batch_id == order.batch_id @ AmmError::BatchMarketMismatch
+    )]
+    pub batch_state: Account<'info, BatchState>,
+
+    #[account(
+        mut,
+        constraint = order.market == market.key()
+    )]
+    pub order: Account<'info, Order>,
+
+    #[account(
+        mut,
+        seeds = [RECOVERY_SEED, order.key().as_ref()],
+        bump = recovery_request.bump,
+        close = authority
+    )]
+    pub recovery_request: Account<'info, RecoveryRequest>,
+
+    /// The batch's `ClearingState`; required when it was cleared in chunks.
+    #[account(mut, address = batch_state.clearing_state)]
+    pub clearing_state: Option<Box<Account<'info, ClearingState>>>,
+
+    #[account(
+        mut,
+        seeds = [
+            USER_BATCH_SEED,
+            market.key().as_ref(),
+            order.user.as_ref(),
+            &order.batch_id.to_le_bytes()
+        ],
+        bump = user_batch_stats.bump
+    )]
+    pub user_batch_stats: Account<'info, UserBatchStats>,
+
+    #[account(
+        mut,
+        seeds = [USER_STATS_SEED, market.key().as_ref(), order.user.as_ref()],
+        bump = user_stats.bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    #[account(
+        mut,
+        constraint = vault_base.key() == market.vault_base
+    )]
+    pub vault_base: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = vault_quote.key() == market.vault_quote
+    )]
+    pub vault_quote: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = recovery_base_ata.owner == recovery_request.recovery_address,
+        constraint = recovery_base_ata.mint == market.base_mint
+    )]
+    pub recovery_base_ata: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = recovery_quote_ata.owner == recovery_request.recovery_address,
+        constraint = recovery_quote_ata.mint == market.quote_mint
+    )]
+    pub recovery_quote_ata: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = market.base_mint)]
+    pub base_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(address = market.quote_mint)]
+    pub quote_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    #[account(
+        mut,
+        seeds = [EVENT_LOG_SEED, market.key().as_ref()],
+        bump = event_log.bump
+    )]
+    pub event_log: Box<Account<'info, EventLog>>,
+}
+
+#[derive(Accounts)]
+#[instruction(sub_account_id: u16)]
+pub struct OpenSubAccount<'info> {
+    #[account(mut)]
+    pub master: Signer<'info>,
+
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        init,
+        payer = master,
+        seeds = [
+            SUB_ACCOUNT_SEED,
+            market.key().as_ref(),
+            master.key().as_ref(),
+            &sub_account_id.to_le_bytes()
+        ],
+        bump,
+        space = 8 + SubAccount::INIT_SPACE
+    )]
+    pub sub_account: Account<'info, SubAccount>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SweepSubAccount<'info> {
+    pub master: Signer<'info>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        has_one = master @ AmmError::InvalidSubAccount
+    )]
+    pub from_sub_account: Account<'info, SubAccount>,
+
+    #[account(
+        mut,
+        constraint = source.owner == from_sub_account.key() @ AmmError::InvalidSubAccount
+    )]
+    pub source: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = destination.mint == source.mint @ AmmError::InvalidSweepDestination
+    )]
+    pub destination: InterfaceAccount<'info, TokenAccount>,
+
+    /// Destination sub-account; omit to sweep back to the master wallet.
+    #[account(
+        has_one = master @ AmmError::InvalidSubAccount,
+        constraint = to_sub_account.market == from_sub_account.market @ AmmError::InvalidSubAccount
+    )]
+    pub to_sub_account: Option<Account<'info, SubAccount>>,
+
+    #[account(address = source.mint)]
+    pub mint: Box<InterfaceAccount<'info, Mint>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct SetReceiptOptIn<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        seeds = [USER_STATS_SEED, market.key().as_ref(), user.key().as_ref()],
+        bump,
+        space = 8 + UserStats::INIT_SPACE
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MintFillReceipt<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(has_one = market)]
+    pub batch_state: Account<'info, BatchState>,
+
+    #[account(
+        constraint = order.user == user.key(),
+        constraint = order.market == market.key(),
+        constraint = order.batch_id == batch_state.batch_id @ AmmError::BatchIdMismatch
+    )]
+    pub order: Account<'info, Order>,
+
+    #[account(
+        seeds = [ORDER_FILL_SEED, order.key().as_ref()],
+        bump
+    )]
+    pub order_fill: Account<'info, OrderFill>,
+
+    #[account(
+        seeds = [USER_STATS_SEED, market.key().as_ref(), user.key().as_ref()],
+        bump = user_stats.bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    /// CHECK: receipt mint PDA, created and initialized in the handler.
+    #[account(
+        mut,
+        seeds = [RECEIPT_SEED, order.key().as_ref()],
+        bump
+    )]
+    pub receipt_mint: UncheckedAccount<'info>,
+
+    /// CHECK: the user's Token-2022 ATA for the receipt mint, created by the ATA program.
+    #[account(mut)]
+    pub receipt_ata: UncheckedAccount<'info>,
+
+    pub token_2022_program: Program<'info, Token2022>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(epoch: u64)]
+pub struct SnapshotUserActivity<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(has_one = market)]
+    pub user_stats: Account<'info, UserStats>,
+
+    #[account(
+        init,
+        payer = payer,
+        seeds = [
+            ACTIVITY_SNAPSHOT_SEED,
+            user_stats.key().as_ref(),
+            &epoch.to_le_bytes()
+        ],
+        bump,
+        space = 8 + UserActivitySnapshot::INIT_SPACE
+    )]
+    pub snapshot: Account<'info, UserActivitySnapshot>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitEventLog<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        init,
+        payer = payer,
+        seeds = [EVENT_LOG_SEED, market.key().as_ref()],
+        bump,
+        space = 8 + EventLog::INIT_SPACE
+    )]
+    pub event_log: Box<Account<'info, EventLog>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitPriceHistory<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        init,
+        payer = payer,
+        seeds = [PRICE_HISTORY_SEED, market.key().as_ref()],
+        bump,
+        space = 8 + PriceHistory::INIT_SPACE
+    )]
+    pub price_history: Box<Account<'info, PriceHistory>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ViewMarket<'info> {
+    pub market: Account<'info, Market>,
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+}
+
+#[derive(Accounts)]
+pub struct DeriveAddresses<'info> {
+    pub market: Account<'info, Market>,
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+}
+
+#[derive(Accounts)]
+pub struct GetClearingPrice<'info> {
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        seeds = [PRICE_HISTORY_SEED, market.key().as_ref()],
+        bump = price_history.bump
+    )]
+    pub price_history: Box<Account<'info, PriceHistory>>,
+}
+
+#[derive(Accounts)]
+#[instruction(batch_id: u64)]
+pub struct GetBatchSettlementStatus<'info> {
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(
+        seeds = [BATCH_STATE_SEED, market.key().as_ref(), &batch_id.to_le_bytes()],
+        bump
+    )]
+    pub batch_state: Account<'info, BatchState>,
+}
+
+#[derive(Accounts)]
+pub struct ExportBatchResult<'info> {
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+
+    #[account(has_one = market)]
+    pub batch_state: Account<'info, BatchState>,
+}
+
+#[derive(Accounts)]
+pub struct GetUserPortfolio<'info> {
+    /// CHECK: only the key is used, to match order ownership.
+    pub user: UncheckedAccount<'info>,
+    #[account(
+        seeds = [PROGRAM_CONFIG_SEED],
+        bump = program_config.bump,
+        constraint = program_config.is_current() @ AmmError::MigrationPending
+    )]
+    pub program_config: Box<Account<'info, ProgramConfig>>,
+}
+
+/// Per-market slice of `UserPortfolio`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct PortfolioMarket {
+    pub market: Pubkey,
+    pub open_orders: u32,
+    pub open_base_locked_fp: u64,
+    pub open_quote_locked_fp: u64,
+    pub pending_settlements: u32,
+    pub pending_base_locked_fp: u64,
+    pub pending_quote_locked_fp: u64,
+    pub claimable_refund_base_fp: u64,
+    pub claimable_refund_quote_fp: u64,
+}
+
+/// Return data of `get_user_portfolio`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct UserPortfolio {
+    pub user: Pubkey,
+    pub markets: Vec<PortfolioMarket>,
+}
+
+/// Return data of `export_batch_result`. Fees are the protocol fees settlement charges on
+/// the auction fills at current market fee settings.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct BatchResult {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    pub cleared_slot: u64,
+    pub clearing_price_fp: u64,
+    pub total_base_traded_fp: u64,
+    pub total_quote_traded_fp: u64,
+    pub bid_volume_at_clear_fp: u128,
+    pub ask_volume_at_clear_fp: u128,
+    pub order_count: u32,
+    pub filled_order_count: u32,
+    pub protocol_fee_quote_fp: u128,
+    pub keeper_reward_quote_fp: u128,
+    pub keeper_rebate_bps: u16,
+    pub fill_digest: [u8; 32],
+}
+
+/// Return data of `get_batch_settlement_status`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct BatchSettlementStatus {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    pub settled: bool,
+    pub order_count: u32,
+    pub settled_order_count: u32,
+    pub remaining_base_to_settle_fp: u128,
+    pub remaining_quote_to_settle_fp: u128,
+    pub slots_since_clear: u64,
+}
+
+/// Return data of `derive_addresses`; batch-scoped accounts are for the open batch.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct MarketAddresses {
+    pub market: Pubkey,
+    pub vault_base: Pubkey,
+    pub vault_quote: Pubkey,
+    pub batch_state: Pubkey,
+    pub event_log: Pubkey,
+    pub price_history: Pubkey,
+    /// Page the trader's next order is appended to.
+    pub order_index: Pubkey,
+    pub user_stats: Pubkey,
+    pub user_batch_stats: Pubkey,
+    pub order: Pubkey,
+    pub order_fill: Pubkey,
+}
+
+// -------------------------------
+// Data structs
+// -------------------------------
+
+/// Program-wide bounds every market's parameters must respect.
+#[account]
+#[derive(InitSpace)]
+pub struct ProgramConfig {
+    pub admin: Pubkey,
+    pub max_fee_bps: u16,
+    pub max_keeper_fee_bps: u16,
+    pub min_batch_duration_slots: u64,
+    pub bump: u8,
+    /// State version last brought up to date by `migrate_program_state`; every other
+    /// handler refuses to run until it equals `PROGRAM_VERSION`.
+    pub program_version: u16,
+}
+
+impl ProgramConfig {
+    pub fn is_current(&self) -> bool {
+        self.program_version == PROGRAM_VERSION
+    }
+
+    fn apply(
+        &mut self,
+        max_fee_bps: u16,
+        max_keeper_fee_bps: u16,
+        min_batch_duration_slots: u64,
+    ) -> Result<()> {
+        require!(max_fee_bps as u64 <= BPS_DENOM, AmmError::InvalidFeeBps);
+        require!(max_keeper_fee_bps as u64 <= BPS_DENOM, AmmError::InvalidFeeBps);
+        self.max_fee_bps = max_fee_bps;
+        self.max_keeper_fee_bps = max_keeper_fee_bps;
+        self.min_batch_duration_slots = min_batch_duration_slots;
+
+        emit!(ProgramConfigUpdated {
+            admin: self.admin,
+            max_fee_bps,
+            max_keeper_fee_bps,
+            min_batch_duration_slots,
+        });
+        Ok(())
+    }
+
+    pub fn check_fees(&self, fee_bps: u16, keeper_fee_bps: u16) -> Result<()> {
+        require!(fee_bps <= self.max_fee_bps, AmmError::FeeAboveProgramCap);
+        require!(
+            keeper_fee_bps <= self.max_keeper_fee_bps,
+            AmmError::FeeAboveProgramCap
+        );
+        Ok(())
+    }
+}
+
+/// Bits of `Market::flags`.
+pub struct MarketFlags;
+
+impl MarketFlags {
+    pub const KEEPER_RESTRICTED: u8 = 1 << 1;
+    pub const REQUIRE_CLEAR_TIP: u8 = 1 << 2;
+    pub const FEE_HOLIDAY_ACTIVE: u8 = 1 << 3;
+    pub const BUCKET_ORDER_SIZES: u8 = 1 << 4;
+}
+
+/// Bits of `Market::pause_modes`, each halting one part of the market.
+pub struct PauseModes;
+
+impl PauseModes {
+    /// `place_order`.
+    pub const PLACEMENT: u8 = 1 << 0;
+    /// `clear_batch`, `extend_batch`, clear intents and residual routing.
+    pub const CLEARING: u8 = 1 << 1;
+    /// Everything paying out of the vaults: settlement, cancels, reductions, refunds and
+    /// recovery.
+    pub const SETTLEMENT: u8 = 1 << 2;
+    pub const ALL: u8 = Self::PLACEMENT | Self::CLEARING | Self::SETTLEMENT;
+}
+
+/// Bits of `Order::flags`.
+pub struct OrderFlags;
+
+impl OrderFlags {
+    pub const FILLED: u8 = 1 << 0;
+    pub const CANCELLED: u8 = 1 << 1;
+    /// Conditional order whose trigger fired at clear time.
+    pub const TRIGGERED: u8 = 1 << 2;
+    /// Opted in to external residual filling (`route_residual`).
+    pub const ALLOW_EXTERNAL_FILL: u8 = 1 << 3;
+    /// Placed while the user had a settlement co-signer configured.
+    pub const REQUIRES_COSIGN: u8 = 1 << 4;
+    /// The co-signer approved this order's settlement (`approve_settlement`).
+    pub const COSIGN_APPROVED: u8 = 1 << 5;
+    /// `clear_batch` recorded this order's claim (`matched_*` / `claim_*`).
+    pub const CLAIM_RECORDED: u8 = 1 << 6;
+    /// Placed after the user cancelled in the same batch; queued behind other orders.
+    pub const REQUEUED: u8 = 1 << 7;
+}
+
+// Layout audit: account sizes are pinned so a layout change is always deliberate
+// (bump the size here and in the README), and must stay creatable via CPI.
+macro_rules! assert_account_space {
+    ($account:ty, $space:expr) => {
+        const _: () = assert!(<$account>::INIT_SPACE == $space);
+        const _: () = assert!(8 + <$account>::INIT_SPACE <= MAX_PERMITTED_DATA_INCREASE);
+    };
+}
+
+assert_account_space!(ProgramConfig, 47);
+assert_account_space!(Market, 1218);
+assert_account_space!(Order, 315);
+assert_account_space!(UserBatchStats, 101);
+assert_account_space!(UserStats, 222);
+assert_account_space!(UserActivitySnapshot, 101);
+assert_account_space!(RecoveryRequest, 145);
+assert_account_space!(SubAccount, 91);
+assert_account_space!(BatchState, 427);
+assert_account_space!(OrderFill, 105);
+assert_account_space!(
+    ClearingState,
+    32 + 8 + 32 + 8 + 1 + 4 + 1 + 4 + 107 * MAX_STAGED_ORDERS
+);
+assert_account_space!(RfqWhitelist, 32 + 32 + 4 + 32 * MAX_RFQ_RESPONDERS + 1);
+assert_account_space!(LiquidityAdapter, 32 + 32 + 4 + 32 * MAX_ADAPTER_ACCOUNTS + 1);
+assert_account_space!(ClearIntent, 89);
+assert_account_space!(KeeperRecord, 97);
+assert_account_space!(
+    MarketCluster,
+    32 + 2 + 8 + 8 + 32 + 4 + 16 + 8 + 16 + 4 + 32 * MAX_CLUSTER_MARKETS + 1
+);
+assert_account_space!(KeeperClusterShare, 85);
+assert_account_space!(WorkQueue, 32 + 2 + 4 + 49 * MAX_WORK_ITEMS + 1);
+assert_account_space!(Claimables, 32 + 32 + 4 + 40 * MAX_CLAIMABLES + 1);
+assert_account_space!(ReimbursementVault, 33);
+assert_account_space!(VestingSchedule, 125);
+assert_account_space!(TravelRuleRecord, 97);
+assert_account_space!(EventLog, 32 + 8 + 1 + 56 * EVENT_LOG_CAPACITY);
+assert_account_space!(PriceHistory, 32 + 8 + 1 + 24 * PRICE_HISTORY_CAPACITY);
+assert_account_space!(
+    MarketMetadata,
+    32 + 4
+        + MAX_MARKET_NAME_LEN
+        + 4
+        + MAX_MARKET_TAGS * (4 + MAX_MARKET_TAG_LEN)
+        + 4
+        + MAX_MARKET_URI_LEN
+        + 1
+);
+assert_account_space!(OrderIndex, 32 + 8 + 4 + 1 + 4 + 32 * ORDER_INDEX_PAGE_SIZE);
+
+#[account]
+#[derive(InitSpace)]
+pub struct Market {
+    pub authority: Pubkey,
+    pub base_mint: Pubkey,
+    pub quote_mint: Pubkey,
+    pub vault_base: Pubkey,
+    pub vault_quote: Pubkey,
+
+    pub batch_duration_slots: u64,
+    pub last_batch_slot: u64,
+    pub current_batch_id: u64,
+    pub next_order_id: u64,
+
+    pub fee_bps: u16,
+    pub max_orders_per_user_per_batch: u32,
+    pub max_active_batches_per_user: u32,
+    /// `MarketFlags` bits.
+    pub flags: u8,
+
+    pub bump: u8,
+    pub vault_base_bump: u8,
+    pub vault_quote_bump: u8,
+
+    // --- Risk caps / notional ---
+    pub max_notional_per_batch_quote_fp: u128,
+    pub max_notional_per_user_per_batch_quote_fp: u128,
+    pub batch_notional_quote_fp: u128,
+
+    pub max_orders_global_per_batch: u32,
+    pub global_orders_in_batch: u32,
+    /// Orders of the open batch cancelled since placement; `clear_batch` must be passed
+    /// every other one.
+    pub cancelled_orders_in_batch: u32,
+    /// Quote tipped to orders of the open batch (`tip_order`), paid to whoever clears it.
+    pub batch_keeper_tips_fp: u64,
+
+    // --- Price band / last price ---
+    pub max_price_move_bps: u16,
+    pub last_clearing_price_fp: u64,
+
+    // --- Keeper ---
+    pub keeper_fee_bps: u16,
+    pub keeper_treasury: Pubkey,
+    pub min_slots_between_clears: u64,
+    pub only_keeper: Pubkey,
+    /// Clears allowed per Solana epoch (0 = unlimited); `clears_in_epoch` counts them
+    /// for `clear_epoch` and restarts when the epoch changes.
+    pub max_clears_per_epoch: u32,
+    pub clear_epoch: u64,
+    pub clears_in_epoch: u32,
+
+    // --- Fees / treasury ---
+    pub protocol_treasury: Pubkey,
+    pub referral_fee_bps: u16,
+    pub protocol_fee_bps: u16,
+    pub protocol_fees_accrued_fp: u128,
+    /// Keeper rewards the accrued protocol fees could not cover at clear time; owed by the
+    /// insurance fund (a ledger entry, like the fees themselves).
+    pub keeper_reward_shortfall_fp: u128,
+
+    // --- Dust limits ---
+    pub min_base_order_fp: u64,
+    pub min_quote_order_fp: u64,
+
+    // --- Size bands ---
+    pub max_base_order_fp: u64,
+    pub max_quote_order_fp: u64,
+
+    // --- Pause ---
+    /// `PauseModes` bits currently in force (0 = fully open).
+    pub pause_modes: u8,
+    pub pause_reason: u8,
+
+    // --- Keeper clear intents ---
+    pub intent_keeper: Pubkey,
+    pub intent_batch_id: u64,
+    pub intent_bond_lamports: u64,
+    pub intent_window_slots: u64,
+    pub intent_bonus_bps: u16,
+    /// Keeper reward the committed keeper rebates to traders of its batch
+    /// (`outbid_clear_intent`); the lowest-reward bid holds the intent.
+    pub intent_rebate_bps: u16,
+
+    // --- Auction mode ---
+    pub mode: MarketMode,
+
+    // --- Launch auction ---
+    pub launch_beneficiary: Pubkey,
+    pub launch_reserve_price_fp: u64,
+    pub launch_unsold_base_fp: u64,
+    pub launch_batches_remaining: u32,
+    pub launch_proceeds_quote_fp: u128,
+    /// Batches over which launch fills vest (0 = paid in full at settlement).
+    pub launch_vesting_batches: u32,
+
+    // --- MEV protection ---
+    pub clear_tip_account: Pubkey,
+    pub min_clear_tip_lamports: u64,
+
+    // --- Fee holiday ---
+    pub fee_holiday_start_batch: u64,
+    pub fee_holiday_end_batch: u64,
+    pub fee_holiday_bps: u16,
+
+    // --- Dynamic (imbalance) fee ---
+    pub imbalance_fee_bps: u16,
+
+    // --- Vault yield / insurance ---
+    pub yield_program: Pubkey,
+    pub yield_sweep_bps: u16,
+    pub yield_deployed_quote_fp: u64,
+    pub insurance_fund_quote_fp: u128,
+
+    // --- Late-cancel fee (anti-spoofing) ---
+    pub late_cancel_window_slots: u64,
+    pub late_cancel_fee_bps: u16,
+    pub cancel_fees_accrued_base_fp: u64,
+    pub cancel_fees_accrued_quote_fp: u64,
+
+    // --- Candidate price guard ---
+    /// Price grid used when a batch has more than `max_candidate_prices` distinct prices.
+    pub tick_size_fp: u64,
+    /// Max clearing-price candidates searched directly (0 = unlimited).
+    pub max_candidate_prices: u16,
+
+    // --- Keeper compute reimbursement ---
+    /// Lamports paid from the `ReimbursementVault` to the clearer per successful clear.
+    pub clear_reimbursement_lamports: u64,
+
+    // --- Volume circuit breaker ---
+    /// Matched-volume multiple of the trailing average that holds a batch (0 = off).
+    pub max_volume_multiple: u16,
+    /// Moving average of matched quote per crossed batch.
+    pub trailing_volume_quote_fp: u64,
+
+    // --- Keeper clear bond ---
+    /// Lamports the clearer bonds on each batch's correctness (0 = no bond).
+    pub clear_bond_lamports: u64,
+    /// Slots after a clear during which `audit_batch` can slash the bond.
+    pub dispute_window_slots: u64,
+    /// Slots after a clear before settlement opens (0 = immediately).
+    pub settlement_delay_slots: u64,
+
+    // --- Quote normalization ---
+    /// Quote token units per `PRICE_SCALE` price units: `PRICE_SCALE` prices in raw quote
+    /// units, `10^quote_decimals` prices as if the quote token had 6 decimals.
+    pub quote_scale_fp: u64,
+
+    // --- Compliance freeze ---
+    /// Key allowed to `freeze_order` (default = freezing disabled).
+    pub compliance_authority: Pubkey,
+    /// Longest a single freeze may hold an order.
+    pub max_freeze_slots: u64,
+    /// Fills above this quote notional need a `TravelRuleRecord` (0 = never).
+    pub travel_rule_threshold_quote_fp: u64,
+
+    // --- Synchronized clears ---
+    /// `MarketCluster` this market clears in step with (default = unclustered).
+    pub cluster: Pubkey,
+    /// Cluster boundary period: batches close at multiples of it.
+    pub cluster_period_slots: u64,
+    /// Slots after a boundary in which the closed batch may be cleared.
+    pub cluster_clear_window_slots: u64,
+
+    // --- Parameter ramp ---
+    /// Parameter moving towards `ramp_target` one step per batch (`None` = no ramp).
+    pub ramp_param: RampParam,
+    pub ramp_start: u128,
+    pub ramp_target: u128,
+    /// Batch the ramp started in; the target takes effect `ramp_batches` batches later.
+    pub ramp_start_batch_id: u64,
+    pub ramp_batches: u32,
+
+    // --- Crank hints ---
+    /// `WorkQueue` this market publishes its due work to (default = none).
+    pub work_queue: Pubkey,
+
+    // --- Token-2022 ---
+    /// Transfer-hook programs the market's mints may invoke (empty = no hooked mints).
+    #[max_len(MAX_TRANSFER_HOOK_PROGRAMS)]
+    pub transfer_hook_programs: Vec<Pubkey>,
+
+    // --- Participation threshold ---
+    /// A batch may clear once it has this many orders or this much notional (0 = no
+    /// threshold); until then `extend_batch` pushes its close out by another duration.
+    pub min_batch_orders: u32,
+    pub min_batch_notional_quote_fp: u128,
+    /// After this many extensions the batch clears (or refunds) regardless.
+    pub max_batch_extensions: u32,
+    /// Times the open batch has been extended.
+    pub batch_extensions: u32,
+
+    // --- Chunked clearing ---
+    /// `ClearingState` staging the open batch (default = none). Placement, extensions and
+    /// single-transaction clears wait until it is finalized or closed.
+    pub clearing_state: Pubkey,
+
+    // --- Simulation ---
+    /// Slot handlers see instead of the Clock sysvar's (0 = sysvar). Only honored, and
+    /// only settable, in builds with the `test-clock` feature.
+    pub test_clock_slot: u64,
+
+    // --- Order PDA privacy ---
+    /// Mixed into order PDA seeds; re-derived from the slot hashes each time a batch rolls.
+    pub batch_salt: [u8; 32],
+}
+
+impl Market {
+    /// The Clock sysvar, with its slot replaced by `test_clock_slot` when that is set in a
+    /// `test-clock` build.
+    pub fn clock(&self) -> Result<Clock> {
+        #[allow(unused_mut)]
+        let mut clock = Clock::get()?;
+        #[cfg(feature = "test-clock")]
+        if self.test_clock_slot != 0 {
+            clock.slot = self.test_clock_slot;
+        }
+        Ok(clock)
+    }
+
+    pub fn has_flag(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    pub fn set_flag(&mut self, flag: u8, on: bool) {
+        if on {
+            self.flags |= flag;
+        } else {
+            self.flags &= !flag;
+        }
+    }
+
+    pub fn is_paused(&self, mode: u8) -> bool {
+        self.pause_modes & mode != 0
+    }
+
+    /// Protocol fee charged on fills from `batch_id`, honoring any fee holiday.
+    pub fn effective_protocol_fee_bps(&self, batch_id: u64) -> u16 {
+        if self.has_flag(MarketFlags::FEE_HOLIDAY_ACTIVE)
+            && batch_id >= self.fee_holiday_start_batch
+            && batch_id <= self.fee_holiday_end_batch
+        {
+            self.fee_holiday_bps
+        } else {
+            self.protocol_fee_bps
+        }
+    }
+
+    /// Late-cancel fee on `deposit_fp` pulled from a `side` order at `slot` (0 outside the
+    /// window), booked to the matching cancel-fee bucket.
+    fn charge_late_cancel_fee(
+        &mut self,
+        side: OrderSide,
+        deposit_fp: u64,
+        slot: u64,
+    ) -> Result<u64> {
+        let close_slot = self.last_batch_slot + self.batch_duration_slots;
+        if self.late_cancel_fee_bps == 0
+            || slot.saturating_add(self.late_cancel_window_slots) < close_slot
+        {
+            return Ok(0);
+        }
+        let fee_fp = ((deposit_fp as u128)
+            .checked_mul(self.late_cancel_fee_bps as u128)
+            .ok_or(AmmError::MathOverflow)?
+            / BPS_DENOM as u128) as u64;
+        let accrued = match side {
+            OrderSide::Bid => &mut self.cancel_fees_accrued_quote_fp,
+            OrderSide::Ask => &mut self.cancel_fees_accrued_base_fp,
+        };
+        *accrued = accrued.checked_add(fee_fp).ok_or(AmmError::MathOverflow)?;
+        Ok(fee_fp)
+    }
+
+    /// Whether the open batch has enough participation to clear: no threshold is set, or
+    /// it meets the order-count or the notional one.
+    pub fn meets_participation(&self) -> bool {
+        (self.min_batch_orders == 0 && self.min_batch_notional_quote_fp == 0)
+            || (self.min_batch_orders > 0 && self.global_orders_in_batch >= self.min_batch_orders)
+            || (self.min_batch_notional_quote_fp > 0
+                && self.batch_notional_quote_fp >= self.min_batch_notional_quote_fp)
+    }
+
+    /// Orders placed in the open batch and not cancelled; `clear_batch` must see them all.
+    pub fn live_orders_in_batch(&self) -> u32 {
+        self.global_orders_in_batch.saturating_sub(self.cancelled_orders_in_batch)
+    }
+
+    /// Whether the open batch may clear: it meets the participation threshold, or has
+    /// used up its extensions.
+    pub fn may_clear(&self) -> bool {
+        self.meets_participation() || self.batch_extensions >= self.max_batch_extensions
+    }
+
+    /// First slot at which the current batch may be cleared.
+    pub fn next_clear_slot(&self) -> u64 {
+        self.last_batch_slot
+            .saturating_add(self.batch_duration_slots.max(self.min_slots_between_clears))
+    }
+
+    /// Quote token units owed for `base_fp` at `price_fp`. Every base-to-quote conversion
+    /// goes through here so `quote_scale_fp` applies uniformly.
+    pub fn quote_for_base(&self, base_fp: u128, price_fp: u64) -> Result<u128> {
+        let quote_fp = base_fp
+            .checked_mul(price_fp as u128)
+            .ok_or(AmmError::MathOverflow)?
+            / PRICE_SCALE as u128;
+        Ok(quote_fp
+            .checked_mul(self.quote_scale_fp as u128)
+            .ok_or(AmmError::MathOverflow)?
+            / PRICE_SCALE as u128)
+    }
+
+    /// Fold a crossed batch's matched quote into the trailing average and report whether
+    /// it tripped the volume circuit breaker (judged against the average before it).
+    fn record_volume(&mut self, quote_traded_fp: u128) -> bool {
+        if quote_traded_fp == 0 {
+            return false;
+        }
+        let average = self.trailing_volume_quote_fp as u128;
+        let spike = self.max_volume_multiple > 0
+            && average > 0
+            && quote_traded_fp > average.saturating_mul(self.max_volume_multiple as u128);
+        self.trailing_volume_quote_fp = if average == 0 {
+            quote_traded_fp
+        } else {
+            (average * (VOLUME_AVERAGE_WINDOW as u128 - 1) + quote_traded_fp)
+                / VOLUME_AVERAGE_WINDOW as u128
+        }
+        .min(u64::MAX as u128) as u64;
+        spike
+    }
+
+    /// Whether the open batch has passed a cluster boundary and waits for its
+    /// synchronized clear. Always false for unclustered markets.
+    pub fn awaiting_cluster_clear(&self, slot: u64) -> bool {
+        self.cluster_period_slots > 0
+            && self.last_batch_slot < slot - slot % self.cluster_period_slots
+    }
+
+    /// Whether `slot` is inside the clear window of the last cluster boundary.
+    pub fn in_cluster_clear_window(&self, slot: u64) -> bool {
+        slot % self.cluster_period_slots < self.cluster_clear_window_slots
+    }
+
+    /// Close the current batch at `slot` and open the next one, emitting `BatchOpened`.
+    /// Returns the id of the batch that just closed.
+    fn roll_batch(&mut self, market_pk: Pubkey, slot: u64, slot_hash: &[u8]) -> Result<u64> {
+        let cleared_batch_id = self.current_batch_id;
+        self.last_batch_slot = slot;
+        self.batch_salt = hashv(&[
+            &self.batch_salt,
+            market_pk.as_ref(),
+            &slot.to_le_bytes(),
+            slot_hash,
+        ])
+        .to_bytes();
+        self.current_batch_id = self
+            .current_batch_id
+            .checked_add(1)
+            .ok_or(AmmError::MathOverflow)?;
+        self.batch_notional_quote_fp = 0;
+        self.global_orders_in_batch = 0;
+        self.cancelled_orders_in_batch = 0;
+        self.batch_extensions = 0;
+        self.step_param_ramp();
+
+        emit!(BatchOpened {
+            market: market_pk,
+            batch_id: self.current_batch_id,
+            open_slot: slot,
+            scheduled_close_slot: self.next_clear_slot(),
+        });
+
+        Ok(cleared_batch_id)
+    }
+
+    pub fn ramp_param_value(&self, param: RampParam) -> u128 {
+        match param {
+            RampParam::None => 0,
+            RampParam::FeeBps => self.fee_bps as u128,
+            RampParam::ProtocolFeeBps => self.protocol_fee_bps as u128,
+            RampParam::KeeperFeeBps => self.keeper_fee_bps as u128,
+            RampParam::MaxNotionalPerBatch => self.max_notional_per_batch_quote_fp,
+            RampParam::MaxNotionalPerUserPerBatch => self.max_notional_per_user_per_batch_quote_fp,
+        }
+    }
+
+    /// Values are checked against the parameter's range by `start_param_ramp`, and every
+    /// step lies between the start and the target.
+    fn set_ramp_param_value(&mut self, param: RampParam, value: u128) {
+        match param {
+            RampParam::None => {}
+            RampParam::FeeBps => self.fee_bps = value as u16,
+            RampParam::ProtocolFeeBps => self.protocol_fee_bps = value as u16,
+            RampParam::KeeperFeeBps => self.keeper_fee_bps = value as u16,
+            RampParam::MaxNotionalPerBatch => self.max_notional_per_batch_quote_fp = value,
+            RampParam::MaxNotionalPerUserPerBatch => {
+                self.max_notional_per_user_per_batch_quote_fp = value
+            }
+        }
+    }
+
+    /// Move a running ramp to its linearly interpolated value for the batch just opened,
+    /// ending it once the target is reached.
+    fn step_param_ramp(&mut self) {
+        if self.ramp_param == RampParam::None {
+            return;
+        }
+        let elapsed = self.current_batch_id.saturating_sub(self.ramp_start_batch_id);
+        let value = if elapsed >= self.ramp_batches as u64 {
+            self.ramp_target
+        } else {
+            let (lo, hi) = if self.ramp_target >= self.ramp_start {
+                (self.ramp_start, self.ramp_target)
+            } else {
+                (self.ramp_target, self.ramp_start)
+            };
+            let step = pro_rata_u128(hi - lo, elapsed, self.ramp_batches as u64);
+            if self.ramp_target >= self.ramp_start {
+                self.ramp_start + step
+            } else {
+                self.ramp_start - step
+            }
+        };
+        self.set_ramp_param_value(self.ramp_param, value);
+        if value == self.ramp_target {
+            self.ramp_param = RampParam::None;
+        }
+    }
+
+    /// Base offered by the launch supply in the current batch (0 if no launch is running).
+    pub fn launch_offer_for_batch(&self) -> u64 {
+        if self.mode != MarketMode::Launch || self.launch_batches_remaining == 0 {
+            return 0;
+        }
+        self.launch_unsold_base_fp / self.launch_batches_remaining as u64
+    }
+
+    /// Book a cleared launch batch: supply sold, quote raised, one fewer batch to go.
+    fn record_launch_clear(&mut self, base_sold_fp: u128, quote_raised_fp: u128) -> Result<()> {
+        if self.mode != MarketMode::Launch || self.launch_batches_remaining == 0 {
+            return Ok(());
+        }
+        self.launch_unsold_base_fp = self
+            .launch_unsold_base_fp
+            .checked_sub(base_sold_fp as u64)
+            .ok_or(AmmError::MathOverflow)?;
+        self.launch_proceeds_quote_fp = self
+            .launch_proceeds_quote_fp
+            .checked_add(quote_raised_fp)
+            .ok_or(AmmError::MathOverflow)?;
+        self.launch_batches_remaining -= 1;
+        Ok(())
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Order {
+    pub user: Pubkey,
+    pub market: Pubkey,
+    pub side: OrderSide,
+    pub limit_price_fp: u64,
+    pub amount_base_fp: u64,
+    pub batch_id: u64,
+    /// `OrderFlags` bits.
+    pub flags: u8,
+    pub quote_deposit_fp: u64,
+    pub id: u64,
+    pub user_nonce: u64,
+    pub batch_seq: u32,
+
+    // --- Conditional trigger (cross-market) ---
+    pub trigger_market: Pubkey,
+    pub trigger_condition: TriggerCondition,
+    pub trigger_price_fp: u64,
+    /// Other leg of a one-cancels-the-other pair (`link_oco_orders`); default if unlinked.
+    pub oco_link: Pubkey,
+    /// Settlement and cancellation are blocked before this slot (`freeze_order`);
+    /// 0 = never frozen. An order can be frozen once.
+    pub frozen_until_slot: u64,
+    /// Caller's tag from `place_order`, echoed in events (zero if none).
+    pub memo: [u8; 32],
+    /// Bumped whenever the order's claim is consumed (settled, refunded or cancelled);
+    /// `settle_order` must quote the current value.
+    pub settle_nonce: u32,
+    /// Quote tipped to this order's clearing keeper (`tip_order`); a hint of its urgency.
+    pub keeper_tip_fp: u64,
+    /// Extra key allowed to cancel the order (`set_cancel_authority`), e.g. a risk bot's
+    /// kill switch; it cannot place, reduce or settle. Default = none.
+    pub cancel_authority: Pubkey,
+
+    // --- Settlement claim (written by clear_batch) ---
+    pub matched_base_fp: u64,
+    pub matched_quote_fp: u64,
+    /// Net vault-to-user obligations; zeroed once `settle_order` pays them.
+    pub claim_base_fp: u64,
+    pub claim_quote_fp: u64,
+    /// Unmatched size still funded at the clearing price (a bid's leftover deposit may
+    /// not cover all of it); caps the order's share of a routed residual.
+    pub fillable_residual_base_fp: u64,
+}
+
+impl Order {
+    pub fn has_flag(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    pub fn set_flag(&mut self, flag: u8, on: bool) {
+        if on {
+            self.flags |= flag;
+        } else {
+            self.flags &= !flag;
+        }
+    }
+
+    /// True if the trigger condition holds for the given reference clearing price.
+    pub fn trigger_fires(&self, reference_price_fp: u64) -> bool {
+        if reference_price_fp == 0 {
+            return false;
+        }
+        match self.trigger_condition {
+            TriggerCondition::None => true,
+            TriggerCondition::PriceAtOrAbove => reference_price_fp >= self.trigger_price_fp,
+            TriggerCondition::PriceAtOrBelow => reference_price_fp <= self.trigger_price_fp,
+        }
+    }
+
+    /// Effective queue position within a price level: arrival order, except that
+    /// `REQUEUED` orders rank after every order that was not. Only decides who gets
+    /// the rounding dust when a level is filled pro-rata.
+    pub fn queue_seq(&self) -> u64 {
+        ((self.has_flag(OrderFlags::REQUEUED) as u64) << 32) | self.batch_seq as u64
+    }
+
+    /// Whether `freeze_order` still blocks settlement and cancellation at `slot`.
+    pub fn is_frozen(&self, slot: u64) -> bool {
+        slot < self.frozen_until_slot
+    }
+
+    /// Unconditional orders are always active; conditional ones only once triggered.
+    pub fn is_active(&self) -> bool {
+        self.trigger_condition == TriggerCondition::None || self.has_flag(OrderFlags::TRIGGERED)
+    }
+
+    /// What the order escrowed: quote for a bid, base for an ask.
+    pub fn deposit_fp(&self) -> u64 {
+        match self.side {
+            OrderSide::Bid => self.quote_deposit_fp,
+            OrderSide::Ask => self.amount_base_fp,
+        }
+    }
+
+    pub fn record_claim(&mut self, claim: &OrderClaim) {
+        self.matched_base_fp = claim.matched_base_fp;
+        self.matched_quote_fp = claim.matched_quote_fp;
+        self.claim_base_fp = claim.claim_base_fp;
+        self.claim_quote_fp = claim.claim_quote_fp;
+        self.fillable_residual_base_fp = claim.fillable_residual_base_fp;
+        self.set_flag(OrderFlags::CLAIM_RECORDED, true);
+    }
+}
+
+/// An order's settlement claim as the clear computed it (see the `Order` fields of the
+/// same names).
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct OrderClaim {
+    pub matched_base_fp: u64,
+    pub matched_quote_fp: u64,
+    pub claim_base_fp: u64,
+    pub claim_quote_fp: u64,
+    pub fillable_residual_base_fp: u64,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct UserBatchStats {
+    pub user: Pubkey,
+    pub market: Pubkey,
+    pub batch_id: u64,
+    pub order_count: u32,
+    pub bump: u8,
+    pub notional_quote_fp: u128,
+    pub open_order_count: u32,
+    /// Orders cancelled in this batch; later placements are `REQUEUED`.
+    pub cancel_count: u32,
+}
+
+impl UserBatchStats {
+    /// Nothing needs the account any more: its batch closed and none of its orders await
+    /// settlement or refund.
+    pub fn is_closable(&self, current_batch_id: u64) -> bool {
+        self.batch_id < current_batch_id && self.open_order_count == 0
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct UserStats {
+    pub user: Pubkey,
+    pub market: Pubkey,
+    pub active_batch_count: u32,
+    pub bump: u8,
+    pub order_nonce: u64,
+    /// Opted in to fill receipts (`mint_fill_receipt`).
+    pub receipts_enabled: bool,
+
+    // --- Institutional settlement controls ---
+    pub settlement_cosigner: Pubkey,
+    pub cosign_threshold_quote_fp: u64,
+
+    /// Where `execute_recovery` may redirect stranded refunds (default = disabled).
+    pub recovery_address: Pubkey,
+
+    // --- Activity (see `snapshot_user_activity`) ---
+    /// Epoch the `epoch_*` counters accumulate settled fills for.
+    pub activity_epoch: u64,
+    pub epoch_volume_quote_fp: u128,
+    pub epoch_fill_count: u32,
+    /// The user's previous active epoch, kept until another epoch displaces it.
+    pub prev_activity_epoch: u64,
+    pub prev_epoch_volume_quote_fp: u128,
+    pub prev_epoch_fill_count: u32,
+    pub lifetime_volume_quote_fp: u128,
+}
+
+impl UserStats {
+    /// An order left the book (settled or cancelled). Once the user has no open
+    /// orders left in that batch, the batch stops counting against their limit.
+    /// Saturating so orders placed before this tracking existed can still close.
+    pub fn release_open_order(&mut self, user_batch: &mut UserBatchStats) {
+        if user_batch.open_order_count == 0 {
+            return;
+        }
+        user_batch.open_order_count -= 1;
+        if user_batch.open_order_count == 0 {
+            self.active_batch_count = self.active_batch_count.saturating_sub(1);
+        }
+    }
+
+    /// Count a settled fill towards `epoch`, rolling the current counters into the
+    /// previous-epoch slot when the epoch changes.
+    pub fn record_fill(&mut self, epoch: u64, quote_fp: u128) {
+        if epoch != self.activity_epoch {
+            if self.epoch_fill_count > 0 {
+                self.prev_activity_epoch = self.activity_epoch;
+                self.prev_epoch_volume_quote_fp = self.epoch_volume_quote_fp;
+                self.prev_epoch_fill_count = self.epoch_fill_count;
+            }
+            self.activity_epoch = epoch;
+            self.epoch_volume_quote_fp = 0;
+            self.epoch_fill_count = 0;
+        }
+        self.epoch_volume_quote_fp = self.epoch_volume_quote_fp.saturating_add(quote_fp);
+        self.epoch_fill_count = self.epoch_fill_count.saturating_add(1);
+        self.lifetime_volume_quote_fp = self.lifetime_volume_quote_fp.saturating_add(quote_fp);
+    }
+
+    /// `(volume, fill count)` for `epoch`, if it is still held.
+    pub fn epoch_activity(&self, epoch: u64) -> Option<(u128, u32)> {
+        if self.epoch_fill_count > 0 && self.activity_epoch == epoch {
+            Some((self.epoch_volume_quote_fp, self.epoch_fill_count))
+        } else if self.prev_epoch_fill_count > 0 && self.prev_activity_epoch == epoch {
+            Some((self.prev_epoch_volume_quote_fp, self.prev_epoch_fill_count))
+        } else {
+            None
+        }
+    }
+}
+
+/// Immutable record of one user's settled trading activity in one epoch.
+/// Seeds: `["activity_snapshot", user_stats, epoch]`; never modified or closed.
+#[account]
+#[derive(InitSpace)]
+pub struct UserActivitySnapshot {
+    pub market: Pubkey,
+    pub user: Pubkey,
+    pub epoch: u64,
+    /// Quote notional of the fills settled in `epoch`.
+    pub volume_quote_fp: u128,
+    pub fill_count: u32,
+    pub snapshot_slot: u64,
+    pub bump: u8,
+}
+
+/// Pending authority-mediated refund redirect for one order.
+/// Seeds: `["recovery", order]`; closed on execution or cancellation.
+#[account]
+#[derive(InitSpace)]
+pub struct RecoveryRequest {
+    pub market: Pubkey,
+    pub order: Pubkey,
+    pub user: Pubkey,
+    /// Snapshot of `UserStats::recovery_address` at request time.
+    pub recovery_address: Pubkey,
+    pub requested_slot: u64,
+    pub executable_slot: u64,
+    pub bump: u8,
+}
+
+/// Base a launch bid bought, released in equal tranches as later batches clear.
+/// Seeds: `["vesting", order]`; closed once fully claimed.
+#[account]
+#[derive(InitSpace)]
+pub struct VestingSchedule {
+    pub market: Pubkey,
+    pub order: Pubkey,
+    pub user: Pubkey,
+    /// Batch the fill cleared in; tranche `k` unlocks once batch `batch_id + k` clears.
+    pub batch_id: u64,
+    pub tranches: u32,
+    pub total_base_fp: u64,
+    pub claimed_base_fp: u64,
+    pub bump: u8,
+}
+
+/// Hash of an off-chain travel-rule (KYC) payload, attached to an order by its owner.
+/// Seeds: `["travel_rule", order]`.
+#[account]
+#[derive(InitSpace)]
+pub struct TravelRuleRecord {
+    pub market: Pubkey,
+    pub order: Pubkey,
+    pub payload_hash: [u8; 32],
+    pub bump: u8,
+}
+
+impl VestingSchedule {
+    /// Base unlocked so far, given the market's open batch id.
+    pub fn unlocked_base_fp(&self, current_batch_id: u64) -> u64 {
+        let elapsed = current_batch_id
+            .saturating_sub(self.batch_id)
+            .saturating_sub(1)
+            .min(self.tranches as u64);
+        ((self.total_base_fp as u128 * elapsed as u128) / self.tranches as u128) as u64
+    }
+}
+
+/// Custodial sub-account of a master wallet (one per strategy).
+///
+/// Acts as the order owner for anything placed through it, so its `UserStats` /
+/// `UserBatchStats` track it separately from the master and from sibling sub-accounts.
+#[account]
+#[derive(InitSpace)]
+pub struct SubAccount {
+    pub master: Pubkey,
+    pub market: Pubkey,
+    pub sub_account_id: u16,
+    pub bump: u8,
+
+    // --- Per-sub-account stats ---
+    pub orders_placed: u64,
+    pub filled_base_fp: u64,
+    pub filled_quote_fp: u64,
+}
+
+impl SubAccount {
+    /// Owned seed parts for signing as this sub-account.
+    pub fn seed_parts(&self) -> (Pubkey, Pubkey, [u8; 2], [u8; 1]) {
+        (
+            self.market,
+            self.master,
+            self.sub_account_id.to_le_bytes(),
+            [self.bump],
+        )
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct BatchState {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    pub clearing_price_fp: u64,
+    pub total_base_traded_fp: u64,
+    pub total_quote_traded_fp: u64,
+    pub created_slot: u64,
+    pub cleared_slot: u64,
+    pub settled: bool,
+    pub keeper: Pubkey,
+    /// Destination for the keeper reward (from the keeper's `KeeperRecord`, else `keeper`).
+    pub keeper_reward_recipient: Pubkey,
+    /// Keeper reward booked against the protocol fees accrued before the clear.
+    pub keeper_reward_quote_fp: u128,
+    /// Part of the keeper reward those fees could not cover.
+    pub keeper_reward_shortfall_fp: u128,
+    pub remaining_base_to_settle_fp: u128,
+    pub remaining_quote_to_settle_fp: u128,
+    pub bid_volume_at_clear_fp: u128,
+    pub ask_volume_at_clear_fp: u128,
+
+    // --- Residual imbalance / external quote ---
+    pub residual_side: OrderSide,
+    pub residual_base_fp: u64,
+    pub external_price_fp: u64,
+    pub external_base_fp: u64,
+
+    // --- External residual routing ---
+    pub routable_base_fp: u64,
+    pub residual_routed: bool,
+    pub routed_base_fp: u64,
+    pub routed_quote_fp: u64,
+    pub routed_base_remaining_fp: u64,
+    pub routed_quote_remaining_fp: u64,
+
+    // --- Conservation tracking ---
+    /// Cumulative base paid to bids as fills.
+    pub settled_bid_base_fp: u64,
+    /// Cumulative quote paid to asks as fills.
+    pub settled_ask_quote_fp: u64,
+
+    // --- Launch vesting ---
+    /// Tranches bid fills from this batch vest over (0 = paid at settlement).
+    pub vesting_tranches: u32,
+
+    // --- Volume circuit breaker ---
+    /// Held by a volume spike; settlement waits for `confirm_batch`.
+    pub awaiting_confirmation: bool,
+
+    // --- Audit replay inputs ---
+    /// Orders placed in the batch (entries across its `OrderIndex` pages).
+    pub order_count: u32,
+    /// `Market::next_order_id` when the batch closed; its orders all have lower ids.
+    pub closing_order_id: u64,
+    /// Synthetic launch ask offered in the batch.
+    pub launch_offer_base_fp: u64,
+    /// Keeper reward rebated to the batch's traders as lower protocol fees, from the
+    /// winning clear-intent bid.
+    pub keeper_rebate_bps: u16,
+    /// Tick candidate prices were bucketed to (0 = not bucketed).
+    pub price_tick_fp: u64,
+
+    // --- Keeper accountability ---
+    /// Clearing keeper's bond, held in this account's lamports until released or slashed.
+    pub keeper_bond_lamports: u64,
+    /// Last slot an `audit_batch` challenge can slash the bond.
+    pub dispute_deadline_slot: u64,
+    /// A challenge proved the clear wrong; the batch no longer settles.
+    pub disputed: bool,
+    /// First slot settlement (and residual routing) may run.
+    pub settlement_open_slot: u64,
+
+    // --- Settlement progress ---
+    /// Orders settled or refunded since the clear.
+    pub settled_order_count: u32,
+
+    /// Order tips collected while the batch was open, owed to `keeper_reward_recipient`
+    /// (`claim_keeper_tips`).
+    pub keeper_tips_quote_fp: u64,
+
+    /// `ClearingState` holding the orders' claims when the batch was cleared in chunks
+    /// (default = the claims were written to the orders themselves).
+    pub clearing_state: Pubkey,
+}
+
+impl BatchState {
+    /// Whether orders are simply refunded their deposits: the batch cleared at price 0, or
+    /// a successful `audit_batch` challenge disputed it before anything was paid out.
+    pub fn refunds_deposits(&self) -> bool {
+        let voided = self.disputed
+            && self.settled_bid_base_fp == 0
+            && self.settled_ask_quote_fp == 0
+            && !self.residual_routed;
+        self.clearing_price_fp == 0 || voided
+    }
+
+    /// Whether settling an order with these matched / filled amounts keeps the batch
+    /// within its books: matched volume still outstanding, and cumulative fills per side
+    /// no larger than the traded totals plus what `route_residual` brought in.
+    pub fn settlement_conserves(
+        &self,
+        side: OrderSide,
+        matched_base_fp: u128,
+        matched_quote_fp: u128,
+        filled_base_fp: u128,
+        filled_quote_fp: u128,
+    ) -> bool {
+        let outstanding = match side {
+            OrderSide::Bid => matched_base_fp <= self.remaining_base_to_settle_fp,
+            OrderSide::Ask => matched_quote_fp <= self.remaining_quote_to_settle_fp,
+        };
+        if !outstanding {
+            return false;
+        }
+        let routed_in = |routed_side: OrderSide, amount: u64| -> u128 {
+            if self.residual_routed && self.residual_side == routed_side {
+                amount as u128
+            } else {
+                0
+            }
+        };
+        match side {
+            OrderSide::Bid => {
+                self.settled_bid_base_fp as u128 + filled_base_fp
+                    <= self.total_base_traded_fp as u128
+                        + routed_in(OrderSide::Bid, self.routed_base_fp)
+            }
+            OrderSide::Ask => {
+                self.settled_ask_quote_fp as u128 + filled_quote_fp
+                    <= self.total_quote_traded_fp as u128
+                        + routed_in(OrderSide::Ask, self.routed_quote_fp)
+            }
+        }
+    }
+
+    /// Fee for `side` after the imbalance adjustment: the heavier side at the clearing
+    /// price pays more, the scarce side pays less, proportional to the imbalance ratio.
+    pub fn imbalance_adjusted_fee_bps(
+        &self,
+        base_fee_bps: u16,
+        side: OrderSide,
+        imbalance_fee_bps: u16,
+    ) -> u16 {
+        let total = self.bid_volume_at_clear_fp.saturating_add(self.ask_volume_at_clear_fp);
+        if imbalance_fee_bps == 0 || total == 0 {
+            return base_fee_bps;
+        }
+        let (heavy, light) = if self.bid_volume_at_clear_fp >= self.ask_volume_at_clear_fp {
+            (OrderSide::Bid, self.ask_volume_at_clear_fp)
+        } else {
+            (OrderSide::Ask, self.bid_volume_at_clear_fp)
+        };
+        let imbalance = total - 2 * light; // |bid - ask|
+        let adj = (imbalance_fee_bps as u128 * imbalance / total) as u16;
+        if side == heavy {
+            base_fee_bps.saturating_add(adj).min(BPS_DENOM as u16)
+        } else {
+            base_fee_bps.saturating_sub(adj)
+        }
+    }
+}
+
+/// A batch being cleared across transactions: `clear_chunk` stages its orders, then
+/// `finalize_clear` prices them and writes each one's claim back here.
+/// Seeds: `["clearing_state", market, batch_id]`; closed by `close_clearing_state`.
+#[account]
+#[derive(InitSpace)]
+pub struct ClearingState {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    /// Keeper that began the stage; only it may add chunks and finalize.
+    pub keeper: Pubkey,
+    pub begun_slot: u64,
+    /// The batch cleared; `entries` now carry the orders' claims.
+    pub finalized: bool,
+    /// Claims settlement has copied out to their orders so far.
+    pub claims_taken: u32,
+    pub bump: u8,
+    #[max_len(MAX_STAGED_ORDERS)]
+    pub entries: Vec<StagedOrder>,
+}
+
+/// What the clear needs of one order, copied from it by `clear_chunk`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, InitSpace)]
+pub struct StagedOrder {
+    pub order: Pubkey,
+    pub side: OrderSide,
+    pub limit_price_fp: u64,
+    pub amount_base_fp: u64,
+    pub quote_deposit_fp: u64,
+    pub queue_seq: u64,
+    pub allow_external_fill: bool,
+    /// False for conditional orders, which sit out a chunked clear and are refunded.
+    pub active: bool,
+    /// Written by `finalize_clear`.
+    pub claim: OrderClaim,
+}
+
+impl StagedOrder {
+    pub fn deposit_fp(&self) -> u64 {
+        match self.side {
+            OrderSide::Bid => self.quote_deposit_fp,
+            OrderSide::Ask => self.amount_base_fp,
+        }
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct OrderFill {
+    pub order: Pubkey,
+    pub batch_id: u64,
+    pub filled_base_fp: u64,
+    pub filled_quote_fp: u64,
+    pub refund_quote_fp: u64,
+    pub refund_base_fp: u64,
+    pub claimed: bool,
+    /// Travel-rule payload hash the fill settled with (zero if none was attached).
+    pub travel_rule_hash: [u8; 32],
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct RfqWhitelist {
+    pub market: Pubkey,
+    pub requester: Pubkey,
+    #[max_len(MAX_RFQ_RESPONDERS)]
+    pub responders: Vec<Pubkey>,
+    pub bump: u8,
+}
+
+impl RfqWhitelist {
+    pub fn is_allowed(&self, user: &Pubkey) -> bool {
+        *user == self.requester || self.responders.contains(user)
+    }
+}
+
+/// Market parameter a `start_param_ramp` can move gradually.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum RampParam {
+    None,
+    FeeBps,
+    ProtocolFeeBps,
+    KeeperFeeBps,
+    MaxNotionalPerBatch,
+    MaxNotionalPerUserPerBatch,
+}
+
+/// Settlement check a recorded fill failed in `clear_batch` (see `FillNotSettleable`).
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum FillCheck {
+    /// The order's fill plus its refund exceeds what it deposited.
+    ExceedsDeposit,
+    /// The batch's fills on the order's side exceed the batch's traded total.
+    ExceedsBatchTotal,
+    /// The vault balance cannot cover the batch's payouts up to this order.
+    VaultShortfall,
+}
+
+/// Market parameter named in a `ParamsChanged` diff (one per `set_params` argument).
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum MarketParam {
+    FeeBps,
+    MaxNotionalPerBatch,
+    MaxNotionalPerUserPerBatch,
+    MaxOrdersGlobalPerBatch,
+    MaxPriceMoveBps,
+    KeeperFeeBps,
+    MinBaseOrder,
+    MinQuoteOrder,
+    ProtocolFeeBps,
+    ReferralFeeBps,
+    MaxBaseOrder,
+    MaxQuoteOrder,
+}
+
+/// One changed parameter in `ParamsChanged`, widened to u128.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct ParamChange {
+    pub param: MarketParam,
+    pub old: u128,
+    pub new: u128,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum MarketMode {
+    Standard,
+    Rfq,
+    Launch,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct LiquidityAdapter {
+    pub market: Pubkey,
+    pub program: Pubkey,
+    #[max_len(MAX_ADAPTER_ACCOUNTS)]
+    pub accounts: Vec<Pubkey>,
+    pub bump: u8,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct ClearIntent {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    pub keeper: Pubkey,
+    pub bond_lamports: u64,
+    pub registered_slot: u64,
+    pub bump: u8,
+}
+
+/// Lamports set aside by the market to reimburse clearers' transaction fees.
+/// Seeds: `["reimbursement_vault", market]`; the balance above rent is spendable.
+#[account]
+#[derive(InitSpace)]
+pub struct ReimbursementVault {
+    pub market: Pubkey,
+    pub bump: u8,
+}
+
+/// Keeper registration for a market (`register_keeper`).
+#[account]
+#[derive(InitSpace)]
+pub struct KeeperRecord {
+    pub market: Pubkey,
+    pub keeper: Pubkey,
+    pub reward_recipient: Pubkey,
+    pub bump: u8,
+}
+
+/// One cleared order in a `Claimables` index.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, InitSpace)]
+pub struct ClaimableEntry {
+    pub batch_id: u64,
+    pub order: Pubkey,
+}
+
+/// A trader's cleared orders on a market that hold a claim still awaiting settlement,
+/// oldest first.
+/// Seeds: `["claimables", market, user]`.
+#[account]
+#[derive(InitSpace)]
+pub struct Claimables {
+    pub market: Pubkey,
+    pub user: Pubkey,
+    #[max_len(MAX_CLAIMABLES)]
+    pub entries: Vec<ClaimableEntry>,
+    pub bump: u8,
+}
+
+impl Claimables {
+    pub fn remove(&mut self, order: &Pubkey) {
+        self.entries.retain(|entry| entry.order != *order);
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum WorkKind {
+    /// `clear_batch` the market's open batch.
+    Clear,
+    /// Settle the orders of a crossed batch.
+    Settle,
+}
+
+/// One piece of crank work, doable from `due_slot`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, InitSpace)]
+pub struct WorkItem {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    pub kind: WorkKind,
+    pub due_slot: u64,
+}
+
+/// Due work of the markets attached to it, for crank networks to poll.
+/// Seeds: `["work_queue", authority, queue_id]`.
+#[account]
+#[derive(InitSpace)]
+pub struct WorkQueue {
+    pub authority: Pubkey,
+    pub queue_id: u16,
+    /// One clear item per attached market, plus settle items for crossed batches.
+    #[max_len(MAX_WORK_ITEMS)]
+    pub items: Vec<WorkItem>,
+    pub bump: u8,
+}
+
+impl WorkQueue {
+    /// Point `market`'s clear item at its open batch.
+    pub fn schedule_clear(&mut self, market: Pubkey, batch_id: u64, due_slot: u64) {
+        self.items
+            .retain(|item| !(item.kind == WorkKind::Clear && item.market == market));
+        self.make_room();
+        self.items.push(WorkItem {
+            market,
+            batch_id,
+            kind: WorkKind::Clear,
+            due_slot,
+        });
+    }
+
+    /// List a crossed batch for settlement.
+    pub fn schedule_settle(&mut self, market: Pubkey, batch_id: u64, due_slot: u64) {
+        self.make_room();
+        self.items.push(WorkItem {
+            market,
+            batch_id,
+            kind: WorkKind::Settle,
+            due_slot,
+        });
+    }
+
+    /// When the queue is full the earliest-due settle item makes room: these are hints,
+    /// and the oldest batches are the likeliest done. Clear items never exceed
+    /// `MAX_WORK_QUEUE_MARKETS`, so there always is one.
+    fn make_room(&mut self) {
+        if self.items.len() < MAX_WORK_ITEMS {
+            return;
+        }
+        if let Some(oldest) = self
+            .items
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| item.kind == WorkKind::Settle)
+            .min_by_key(|(_, item)| item.due_slot)
+            .map(|(i, _)| i)
+        {
+            self.items.remove(oldest);
+        }
+    }
+}
+
+/// Markets whose batches close and clear on shared slot boundaries.
+/// Seeds: `["cluster", authority, cluster_id]`.
+#[account]
+#[derive(InitSpace)]
+pub struct MarketCluster {
+    pub authority: Pubkey,
+    pub cluster_id: u16,
+    /// Boundary period: member batches close at multiples of it.
+    pub period_slots: u64,
+    /// Slots after a boundary in which members may clear.
+    pub clear_window_slots: u64,
+    /// Quote mint every member shares, so cleared volumes compare (set by the first join).
+    pub quote_mint: Pubkey,
+
+    // --- Shared keeper reward pool (lamports held in this account) ---
+    /// Epoch keepers are currently accruing cleared volume in.
+    pub reward_epoch: u32,
+    pub epoch_volume_quote_fp: u128,
+    /// Pool paid out pro-rata for epoch `reward_epoch - 1`; unclaimed lamports roll into
+    /// the next close.
+    pub closed_epoch_reward_lamports: u64,
+    pub closed_epoch_volume_quote_fp: u128,
+
+    #[max_len(MAX_CLUSTER_MARKETS)]
+    pub members: Vec<Pubkey>,
+    pub bump: u8,
+}
+
+impl MarketCluster {
+    /// Move `share` to the current epoch, returning the lamports it earned in the closed
+    /// epoch (its volume's pro-rata part of the pool). Older volume has expired.
+    pub fn roll_share(&self, share: &mut KeeperClusterShare) -> u64 {
+        if share.epoch == self.reward_epoch {
+            return 0;
+        }
+        let owed = if share.epoch + 1 == self.reward_epoch {
+            pro_rata(
+                self.closed_epoch_reward_lamports,
+                share.volume_quote_fp,
+                self.closed_epoch_volume_quote_fp,
+            )
+        } else {
+            0
+        };
+        share.epoch = self.reward_epoch;
+        share.volume_quote_fp = 0;
+        owed
+    }
+}
+
+/// `amount * part / whole` for `part < whole`, splitting `amount` so the product can't
+/// overflow u128.
+fn pro_rata_u128(amount: u128, part: u64, whole: u64) -> u128 {
+    let (part, whole) = (part as u128, whole as u128);
+    (amount / whole) * part + (amount % whole) * part / whole
+}
+
+/// Running totals of the claims `clear_batch` records, against the limits settlement
+/// will hold them to.
+struct FillTotals {
+    total_base_traded: u128,
+    total_quote_traded: u128,
+    vault_base: u128,
+    vault_quote: u128,
+    bid_base_fp: u128,
+    ask_quote_fp: u128,
+    payout_base_fp: u128,
+    payout_quote_fp: u128,
+}
+
+impl FillTotals {
+    fn new(
+        total_base_traded: u128,
+        total_quote_traded: u128,
+        vault_base: u64,
+        vault_quote: u64,
+    ) -> Self {
+        Self {
+            total_base_traded,
+            total_quote_traded,
+            vault_base: vault_base as u128,
+            vault_quote: vault_quote as u128,
+            bid_base_fp: 0,
+            ask_quote_fp: 0,
+            payout_base_fp: 0,
+            payout_quote_fp: 0,
+        }
+    }
+
+    /// Add one order's recorded claim, failing with the first check `settle_order` (or
+    /// the vault transfer) would trip on: the order's own deposit, the batch totals it
+    /// settles against, then the vault balances that must cover every payout so far.
+    fn record(
+        &mut self,
+        side: OrderSide,
+        deposit_fp: u64,
+        claim: &OrderClaim,
+    ) -> std::result::Result<(), FillCheck> {
+        let (matched_base_fp, matched_quote_fp) =
+            (claim.matched_base_fp as u128, claim.matched_quote_fp as u128);
+        let (claim_base_fp, claim_quote_fp) =
+            (claim.claim_base_fp as u128, claim.claim_quote_fp as u128);
+        match side {
+            OrderSide::Bid => {
+                if matched_quote_fp + claim_quote_fp > deposit_fp as u128 {
+                    return Err(FillCheck::ExceedsDeposit);
+                }
+                self.bid_base_fp += matched_base_fp;
+                if self.bid_base_fp > self.total_base_traded {
+                    return Err(FillCheck::ExceedsBatchTotal);
+                }
+            }
+            OrderSide::Ask => {
+                if matched_base_fp + claim_base_fp > deposit_fp as u128 {
+                    return Err(FillCheck::ExceedsDeposit);
+                }
+                self.ask_quote_fp += matched_quote_fp;
+                if self.ask_quote_fp > self.total_quote_traded {
+                    return Err(FillCheck::ExceedsBatchTotal);
+                }
+            }
+        }
+        self.payout_base_fp += claim_base_fp;
+        self.payout_quote_fp += claim_quote_fp;
+        if self.payout_base_fp > self.vault_base || self.payout_quote_fp > self.vault_quote {
+            return Err(FillCheck::VaultShortfall);
+        }
+        Ok(())
+    }
+}
+
+/// Split `volume_fp` over one side's crossed orders (`indices`, best price first): whole
+/// price levels fill in full until the level where the volume runs out, which shares the
+/// rest pro-rata to each order's fillable size; the rounding dust goes to that level's
+/// orders in queue order. Returns `(order index, base fill)` for every order given some.
+fn allocate_side(
+    temp_orders: &[TempOrder],
+    indices: &[usize],
+    volume_fp: u128,
+    price_fp: u64,
+    quote_scale_fp: u64,
+) -> Vec<(usize, u128)> {
+    let fillable = |i: usize| temp_orders[i].fillable_base_fp(price_fp, quote_scale_fp);
+    let mut fills: Vec<(usize, u128)> = Vec::with_capacity(indices.len());
+    let mut left_fp = volume_fp;
+    let mut start = 0usize;
+    while start < indices.len() && left_fp > 0 {
+        let level_price_fp = temp_orders[indices[start]].limit_price_fp;
+        let len = indices[start..]
+            .iter()
+            .take_while(|&&i| temp_orders[i].limit_price_fp == level_price_fp)
+            .count();
+        let level = &indices[start..start + len];
+        let level_fp: u128 = level.iter().map(|&i| fillable(i)).sum();
+
+        if level_fp <= left_fp {
+            fills.extend(level.iter().map(|&i| (i, fillable(i))));
+            left_fp -= level_fp;
+        } else {
+            // Marginal level.
+            let first = fills.len();
+            for &i in level {
+                fills.push((i, pro_rata(left_fp as u64, fillable(i), level_fp) as u128));
+            }
+            let mut dust_fp = left_fp - fills[first..].iter().map(|f| f.1).sum::<u128>();
+            for fill in fills[first..].iter_mut() {
+                let extra_fp = (fillable(fill.0) - fill.1).min(dust_fp);
+                fill.1 += extra_fp;
+                dust_fp -= extra_fp;
+            }
+            left_fp = 0;
+        }
+        start += len;
+    }
+    fills.retain(|f| f.1 > 0);
+    fills
+}
+
+/// `amount * part / whole` for `part <= whole`, without overflowing u128: large volumes
+/// are shifted down to 64 bits first.
+fn pro_rata(amount: u64, part: u128, whole: u128) -> u64 {
+    if whole == 0 {
+        return 0;
+    }
+    let shift = 64u32.saturating_sub(whole.leading_zeros());
+    let (part, whole) = (part >> shift, whole >> shift);
+    if whole == 0 {
+        return 0;
+    }
+    ((amount as u128 * part) / whole).min(amount as u128) as u64
+}
+
+/// A keeper's cleared volume in a cluster's reward epoch.
+/// Seeds: `["cluster_keeper", cluster, keeper]`.
+#[account]
+#[derive(InitSpace)]
+pub struct KeeperClusterShare {
+    pub cluster: Pubkey,
+    pub keeper: Pubkey,
+    pub epoch: u32,
+    pub volume_quote_fp: u128,
+    pub bump: u8,
+}
+
+/// Human-readable market info for front-ends, set by `set_market_metadata`.
+#[account]
+#[derive(InitSpace)]
+pub struct MarketMetadata {
+    pub market: Pubkey,
+    #[max_len(MAX_MARKET_NAME_LEN)]
+    pub name: String,
+    #[max_len(MAX_MARKET_TAGS, MAX_MARKET_TAG_LEN)]
+    pub tags: Vec<String>,
+    #[max_len(MAX_MARKET_URI_LEN)]
+    pub uri: String,
+    pub bump: u8,
+}
+
+/// One logged event: its sequence number, slot, event discriminator and
+/// `hash(discriminator || borsh(event))`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct EventLogEntry {
+    pub seq: u64,
+    pub slot: u64,
+    pub discriminator: [u8; 8],
+    pub digest: [u8; 32],
+}
+
+/// Ring buffer of the market's last `EVENT_LOG_CAPACITY` order-lifecycle events.
+/// Indexers that missed logs compare `next_seq` and the digests against what they
+/// saw to find gaps and backfill those transactions.
+#[account]
+#[derive(InitSpace)]
+pub struct EventLog {
+    pub market: Pubkey,
+    /// Sequence number of the next event; entry `seq` lives at `seq % EVENT_LOG_CAPACITY`.
+    pub next_seq: u64,
+    pub bump: u8,
+    pub entries: [EventLogEntry; EVENT_LOG_CAPACITY],
+}
+
+impl EventLog {
+    pub fn record<E: AnchorSerialize + Discriminator>(&mut self, event: &E) -> Result<u64> {
+        let seq = self.next_seq;
+        let data = event.try_to_vec()?;
+        self.entries[(seq % EVENT_LOG_CAPACITY as u64) as usize] = EventLogEntry {
+            seq,
+            slot: Clock::get()?.slot,
+            discriminator: E::DISCRIMINATOR,
+            digest: hashv(&[&E::DISCRIMINATOR, &data]).to_bytes(),
+        };
+        self.next_seq = seq.checked_add(1).ok_or(AmmError::MathOverflow)?;
+        Ok(seq)
+    }
+}
+
+/// Clearing price of one crossed batch.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct PriceHistoryEntry {
+    pub batch_id: u64,
+    pub clearing_price_fp: u64,
+    pub cleared_slot: u64,
+}
+
+/// Ring buffer of the market's last `PRICE_HISTORY_CAPACITY` clearing prices, so
+/// historical prices outlive closed `BatchState` accounts.
+#[account]
+#[derive(InitSpace)]
+pub struct PriceHistory {
+    pub market: Pubkey,
+    /// Batches recorded so far; the next entry goes to `recorded % PRICE_HISTORY_CAPACITY`.
+    pub recorded: u64,
+    pub bump: u8,
+    pub entries: [PriceHistoryEntry; PRICE_HISTORY_CAPACITY],
+}
+
+impl PriceHistory {
+    pub fn record(&mut self, batch_id: u64, clearing_price_fp: u64, cleared_slot: u64) {
+        self.entries[(self.recorded % PRICE_HISTORY_CAPACITY as u64) as usize] =
+            PriceHistoryEntry {
+                batch_id,
+                clearing_price_fp,
+                cleared_slot,
+            };
+        self.recorded = self.recorded.saturating_add(1);
+    }
+
+    /// The entry for `batch_id`, if it is still in the ring.
+    pub fn find(&self, batch_id: u64) -> Option<&PriceHistoryEntry> {
+        let filled = (self.recorded as usize).min(PRICE_HISTORY_CAPACITY);
+        self.entries[..filled].iter().find(|e| e.batch_id == batch_id)
+    }
+}
+
+/// One page of a batch's order pubkeys, appended by `place_order` in `batch_seq` order.
+/// Seeds: `["order_index", market, batch_id, page]`; keepers read pages 0.. until one
+/// is missing or not full.
+#[account]
+#[derive(InitSpace)]
+pub struct OrderIndex {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    pub page: u32,
+    pub bump: u8,
+    #[max_len(ORDER_INDEX_PAGE_SIZE)]
+    pub orders: Vec<Pubkey>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum OrderSide {
+    Bid,
+    Ask,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum TriggerCondition {
+    None,
+    PriceAtOrAbove,
+    PriceAtOrBelow,
+}
+
+/// Cross-market activation condition supplied at placement.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct OrderTrigger {
+    pub market: Pubkey,
+    pub condition: TriggerCondition,
+    pub price_fp: u64,
+}
+
+/// Full set of risk and fee parameters taken by `set_params`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct RiskFeeParams {
+    pub fee_bps: u16,
+    pub max_notional_per_batch_quote_fp: u128,
+    pub max_notional_per_user_per_batch_quote_fp: u128,
+    pub max_orders_global_per_batch: u32,
+    pub max_price_move_bps: u16,
+    pub keeper_fee_bps: u16,
+    pub min_base_order_fp: u64,
+    pub min_quote_order_fp: u64,
+    pub protocol_fee_bps: u16,
+    pub referral_fee_bps: u16,
+    pub max_base_order_fp: u64,
+    pub max_quote_order_fp: u64,
+}
+
+/// Local helper for in-memory order matching during batch clear.
+struct TempOrder {
+    pub account_index: usize, // index into remaining_accounts
+    pub side: OrderSide,
+    pub limit_price_fp: u64,
+    pub original_base_fp: u128,
+    pub remaining_base_fp: u128,
+    /// Quote exchanged so far (paid by a bid, owed to an ask).
+    pub matched_quote_fp: u128,
+    /// Bids: deposit not yet spent on matches.
+    pub quote_deposit_fp: u128,
+    /// `Order::queue_seq` (rounding-dust priority within a price level).
+    pub queue_seq: u64,
+    pub allow_external_fill: bool,
+}
+
+impl TempOrder {
+    /// Unmatched size the order could still fill at `price_fp`: bids are capped by what
+    /// their leftover deposit affords, exactly as in the matching loop. `quote_scale_fp`
+    /// is the market's `Market::quote_scale_fp`.
+    fn fillable_base_fp(&self, price_fp: u64, quote_scale_fp: u64) -> u128 {
+        match self.side {
+            OrderSide::Bid => {
+                let deposit_fp = self.quote_deposit_fp * PRICE_SCALE as u128
+                    / (quote_scale_fp as u128).max(1);
+                self.remaining_base_fp
+                    .min(deposit_fp * PRICE_SCALE as u128 / (price_fp as u128).max(1))
+            }
+            OrderSide::Ask => self.remaining_base_fp,
+        }
+    }
+}
+
+// -------------------------------
+// Events
+// -------------------------------
+
+#[event]
+pub struct MarketInitialized {
+    pub market: Pubkey,
+    pub authority: Pubkey,
+    pub base_mint: Pubkey,
+    pub quote_mint: Pubkey,
+    pub batch_duration_slots: u64,
+    pub fee_bps: u16,
+}
+
+#[event]
+pub struct OrderPlaced {
+    pub market: Pubkey,
+    pub order: Pubkey,
+    pub user: Pubkey,
+    pub side: OrderSide,
+    pub limit_price_fp: u64,
+    /// 0 when the market buckets order sizes.
+    pub amount_base_fp: u64,
+    pub size_bucket: u8,
+    pub batch_id: u64,
+    pub batch_seq: u32,
+    pub allow_external_fill: bool,
+    pub memo: [u8; 32],
+}
+
+/// Per-clear sizes that drive compute usage.
+#[event]
+pub struct ClearMetrics {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    /// Order triplets passed in remaining_accounts (including skipped ones).
+    pub orders_passed: u32,
+    /// Orders that joined the batch (including a launch auction's synthetic ask).
+    pub order_count: u32,
+    /// Distinct limit prices in the batch.
+    pub unique_price_count: u32,
+    /// Clearing-price candidates actually searched (fewer when bucketed to the tick grid).
+    pub evaluated_price_count: u32,
+    pub prices_bucketed: bool,
+    /// Iterations of the bid/ask matching loop.
+    pub match_iterations: u32,
+    /// `OrderIndex` pages spanned by the orders passed.
+    pub index_pages: u32,
+}
+
+#[event]
+pub struct BatchOpened {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    pub open_slot: u64,
+    pub scheduled_close_slot: u64,
+}
+
+/// Emitted just before `clear_batch` fails with `FillNotSettleable`, so it only shows
+/// in the logs of a simulated or failed clear.
+#[event]
+pub struct FillNotSettleable {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    pub order: Pubkey,
+    pub check: FillCheck,
+}
+
+#[event]
+pub struct BatchCleared {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    pub clearing_price_fp: u64,
+    pub total_base_traded_fp: u64,
+    pub total_quote_traded_fp: u64,
+}
+
+/// Accounting summary of a crossed clear, emitted alongside `BatchCleared`.
+#[event]
+pub struct BatchReport {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    pub clearing_price_fp: u64,
+    pub bid_filled_base_fp: u128,
+    pub bid_filled_quote_fp: u128,
+    pub ask_filled_base_fp: u128,
+    pub ask_filled_quote_fp: u128,
+    /// Protocol fees the batch's fills accrue as they settle.
+    pub protocol_fee_quote_fp: u128,
+    pub keeper_reward_quote_fp: u128,
+    /// Crossed size left unmatched at the clearing price, carried to routing or refunds.
+    pub residual_side: OrderSide,
+    pub residual_base_fp: u64,
+}
 
-    #[account(
-        mut,
-        constraint = vault_quote.key() == market.vault_quote
-    )]
-    pub vault_quote: Account<'info, TokenAccount>,
+/// Doubles as the cancellation receipt: what came back, what the fee kept, and how far
+/// into the batch the cancel landed.
+#[event]
+pub struct OrderCancelled {
+    pub market: Pubkey,
+    pub order: Pubkey,
+    pub user: Pubkey,
+    pub batch_id: u64,
+    pub side: OrderSide,
+    pub late_cancel_fee_fp: u64,
+    pub refund_base_fp: u64,
+    pub refund_quote_fp: u64,
+    pub slot: u64,
+    /// Slots left before the batch could clear when the cancel landed.
+    pub slots_before_close: u64,
+}
 
-    #[account(
-        mut,
-        constraint = user_base_ata.owner == user.key(),
-        constraint = user_base_ata.mint == market.base_mint
-    )]
-    pub user_base_ata: Account<'info, TokenAccount>,
+#[event]
+pub struct CancelAuthoritySet {
+    pub market: Pubkey,
+    pub order: Pubkey,
+    pub cancel_authority: Pubkey,
+}
 
-    #[account(
-        mut,
-        constraint = user_quote_ata.owner == user.key(),
-        constraint = user_quote_ata.mint == market.quote_mint
-    )]
-    pub user_quote_ata: Account<'info, TokenAccount>,
+#[event]
+pub struct OcoLinked {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    pub order_a: Pubkey,
+    pub order_b: Pubkey,
+}
 
-    pub token_program: Program<'info, Token>,
+#[event]
+pub struct OcoLegCancelled {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    pub order: Pubkey,
+    /// The leg that activated first and stays in the batch.
+    pub linked_order: Pubkey,
 }
 
-#[derive(Accounts)]
-pub struct SetPaused<'info> {
-    pub authority: Signer<'info>,
-    #[account(mut)]
-    pub market: Account<'info, Market>,
+#[event]
+pub struct OrderReduced {
+    pub market: Pubkey,
+    pub order: Pubkey,
+    pub user: Pubkey,
+    pub batch_id: u64,
+    pub side: OrderSide,
+    /// Order size after the reduction.
+    pub amount_base_fp: u64,
+    pub refund_fp: u64,
+    pub late_cancel_fee_fp: u64,
 }
 
-#[derive(Accounts)]
-pub struct SetParams<'info> {
-    pub authority: Signer<'info>,
-    #[account(mut)]
-    pub market: Account<'info, Market>,
+#[event]
+pub struct OrderSettled {
+    pub market: Pubkey,
+    pub order: Pubkey,
+    pub user: Pubkey,
+    pub batch_id: u64,
+    pub side: OrderSide,
+    pub clearing_price_fp: u64,
+    pub filled_base_fp: u64,
+    pub filled_quote_fp: u64,
+    pub refund_base_fp: u64,
+    pub refund_quote_fp: u64,
+    pub memo: [u8; 32],
 }
 
-#[derive(Accounts)]
-pub struct ViewMarket<'info> {
-    pub market: Account<'info, Market>,
+#[event]
+pub struct PausedSet {
+    pub market: Pubkey,
+    pub pause_modes: u8,
+    pub reason: u8,
+}
+
+#[event]
+pub struct ParamsUpdated {
+    pub market: Pubkey,
+    pub fee_bps: u16,
+    pub max_notional_per_batch_quote_fp: u128,
+    pub max_notional_per_user_per_batch_quote_fp: u128,
+    pub max_orders_global_per_batch: u32,
+    pub max_price_move_bps: u16,
+    pub keeper_fee_bps: u16,
+    pub min_base_order_fp: u64,
+    pub min_quote_order_fp: u64,
+    pub protocol_fee_bps: u16,
+    pub referral_fee_bps: u16,
+    pub max_base_order_fp: u64,
+    pub max_quote_order_fp: u64,
+}
+
+/// Only the parameters a `set_params` call actually changed; not emitted for a no-op.
+#[event]
+pub struct ParamsChanged {
+    pub market: Pubkey,
+    pub changes: Vec<ParamChange>,
+}
+
+#[event]
+pub struct ParamRampStarted {
+    pub market: Pubkey,
+    pub param: RampParam,
+    pub start: u128,
+    pub target: u128,
+    pub start_batch_id: u64,
+    pub ramp_batches: u32,
+}
+
+#[event]
+pub struct RfqConfigured {
+    pub market: Pubkey,
+    pub requester: Pubkey,
+    pub enabled: bool,
+    pub responder_count: u8,
+    pub batch_duration_slots: u64,
+}
+
+#[event]
+pub struct LaunchConfigured {
+    pub market: Pubkey,
+    pub beneficiary: Pubkey,
+    pub total_supply_base_fp: u64,
+    pub num_batches: u32,
+    pub reserve_price_fp: u64,
+    pub vesting_batches: u32,
+}
+
+#[event]
+pub struct VestingScheduled {
+    pub market: Pubkey,
+    pub order: Pubkey,
+    pub user: Pubkey,
+    pub batch_id: u64,
+    pub total_base_fp: u64,
+    pub tranches: u32,
+}
+
+#[event]
+pub struct TrancheClaimed {
+    pub market: Pubkey,
+    pub order: Pubkey,
+    pub user: Pubkey,
+    pub claimed_base_fp: u64,
+    pub remaining_base_fp: u64,
+}
+
+#[event]
+pub struct LaunchProceedsClaimed {
+    pub market: Pubkey,
+    pub beneficiary: Pubkey,
+    pub proceeds_quote_fp: u64,
+    pub unsold_base_fp: u64,
+}
+
+#[event]
+pub struct UserLimitsUpdated {
+    pub market: Pubkey,
+    pub max_orders_per_user_per_batch: u32,
+    pub max_active_batches_per_user: u32,
+}
+
+#[event]
+pub struct ClearIntentParamsUpdated {
+    pub market: Pubkey,
+    pub bond_lamports: u64,
+    pub window_slots: u64,
+    pub bonus_bps: u16,
+}
+
+#[event]
+pub struct ClearIntentRegistered {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    pub keeper: Pubkey,
+    pub bond_lamports: u64,
+    pub window_end_slot: u64,
+}
+
+#[event]
+pub struct ClearIntentOutbid {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    pub previous_keeper: Pubkey,
+    pub keeper: Pubkey,
+    pub rebate_bps: u16,
+}
+
+#[event]
+pub struct ClearIntentResolved {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    pub committed_keeper: Pubkey,
+    pub clearer: Pubkey,
+    pub honored: bool,
+}
+
+#[event]
+pub struct MevProtectionUpdated {
+    pub market: Pubkey,
+    pub require_tip: bool,
+    pub tip_account: Pubkey,
+    pub min_tip_lamports: u64,
+}
+
+#[event]
+pub struct FeeHolidayScheduled {
+    pub market: Pubkey,
+    pub start_batch: u64,
+    pub end_batch: u64,
+    pub fee_bps_override: u16,
+}
+
+#[event]
+pub struct FeeHolidayCancelled {
+    pub market: Pubkey,
+}
+
+#[event]
+pub struct ImbalanceFeeUpdated {
+    pub market: Pubkey,
+    pub imbalance_fee_bps: u16,
+}
+
+#[event]
+pub struct LateCancelFeeUpdated {
+    pub market: Pubkey,
+    pub window_slots: u64,
+    pub fee_bps: u16,
+}
+
+#[event]
+pub struct PriceGridUpdated {
+    pub market: Pubkey,
+    pub tick_size_fp: u64,
+    pub max_candidate_prices: u16,
+}
+
+#[event]
+pub struct VolumeBreakerUpdated {
+    pub market: Pubkey,
+    pub max_volume_multiple: u16,
+}
+
+#[event]
+pub struct VolumeSpikeDetected {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    pub total_quote_traded_fp: u64,
+    pub trailing_average_quote_fp: u64,
+    pub max_volume_multiple: u16,
+}
+
+#[event]
+pub struct AuditResult {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    pub auditor: Pubkey,
+    pub recorded_price_fp: u64,
+    pub recomputed_price_fp: u64,
+    /// Volume crossing at the recomputed price.
+    pub recomputed_base_fp: u64,
+    pub order_count: u32,
+    pub consistent: bool,
+}
+
+#[event]
+pub struct ClearBondUpdated {
+    pub market: Pubkey,
+    pub bond_lamports: u64,
+    pub dispute_window_slots: u64,
+}
+
+#[event]
+pub struct QuoteNormalizationUpdated {
+    pub market: Pubkey,
+    pub quote_decimals: u8,
+    pub quote_scale_fp: u64,
+}
+
+#[event]
+pub struct SettlementDelayUpdated {
+    pub market: Pubkey,
+    pub settlement_delay_slots: u64,
+}
+
+#[event]
+pub struct KeeperSlashed {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    pub keeper: Pubkey,
+    pub challenger: Pubkey,
+    pub bond_lamports: u64,
+}
+
+#[event]
+pub struct OrderTipped {
+    pub market: Pubkey,
+    pub order: Pubkey,
+    pub batch_id: u64,
+    pub tipper: Pubkey,
+    pub amount_quote_fp: u64,
+    /// The order's total tip, and the open batch's, after this one.
+    pub order_tip_fp: u64,
+    pub batch_tips_fp: u64,
+}
+
+#[event]
+pub struct KeeperRewardBooked {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    pub reward_due_quote_fp: u128,
+    /// Taken off the accrued protocol fees: `min(protocol_fees_accrued_fp, reward_due)`.
+    pub booked_quote_fp: u128,
+    pub shortfall_quote_fp: u128,
+    /// Market's cumulative `keeper_reward_shortfall_fp`.
+    pub total_shortfall_quote_fp: u128,
+}
+
+#[event]
+pub struct KeeperTipsClaimed {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    pub recipient: Pubkey,
+    pub tips_quote_fp: u64,
+    /// The batch was disputed; the tips went to protocol fees instead.
+    pub forfeited: bool,
+}
+
+#[event]
+pub struct UserBatchStatsClosed {
+    pub market: Pubkey,
+    pub user: Pubkey,
+    pub batch_id: u64,
+}
+
+#[event]
+pub struct ClearBegun {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    pub keeper: Pubkey,
+    pub clearing_state: Pubkey,
+}
+
+#[event]
+pub struct ClearChunkStaged {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    /// Orders this chunk added.
+    pub staged_count: u32,
+    pub total_staged: u32,
+}
+
+#[event]
+pub struct ClearingStateClosed {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    pub clearing_state: Pubkey,
+    /// Closed before finalizing, unfreezing the batch.
+    pub aborted: bool,
+}
+
+#[event]
+pub struct ClearBondReleased {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    pub keeper: Pubkey,
+    pub bond_lamports: u64,
+}
+
+#[event]
+pub struct BatchConfirmed {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    pub confirmed_by: Pubkey,
 }
 
-// -------------------------------
-// Data structs
-// -------------------------------
+#[event]
+pub struct ClearReimbursementUpdated {
+    pub market: Pubkey,
+    pub lamports_per_clear: u64,
+}
 
-#[account]
-pub struct Market {
-    pub authority: Pubkey,
-    pub base_mint: Pubkey,
-    pub quote_mint: Pubkey,
-    pub vault_base: Pubkey,
-    pub vault_quote: Pubkey,
+#[event]
+pub struct ReimbursementVaultFunded {
+    pub market: Pubkey,
+    pub funder: Pubkey,
+    pub lamports: u64,
+}
 
-    pub batch_duration_slots: u64,
-    pub last_batch_slot: u64,
-    pub current_batch_id: u64,
-    pub next_order_id: u64,
+#[event]
+pub struct ClearReimbursed {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    pub clearer: Pubkey,
+    pub lamports: u64,
+}
 
-    pub fee_bps: u16,
-    pub max_orders_per_user_per_batch: u32,
-    pub paused: bool,
+#[event]
+pub struct ParticipationThresholdUpdated {
+    pub market: Pubkey,
+    pub min_batch_orders: u32,
+    pub min_batch_notional_quote_fp: u128,
+    pub max_batch_extensions: u32,
+}
 
-    pub bump: u8,
-    pub vault_base_bump: u8,
-    pub vault_quote_bump: u8,
+#[event]
+pub struct BatchExtended {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    pub extensions: u32,
+    pub orders: u32,
+    pub notional_quote_fp: u128,
+    pub scheduled_close_slot: u64,
+}
 
-    // --- Risk caps / notional ---
-    pub max_notional_per_batch_quote_fp: u128,
-    pub max_notional_per_user_per_batch_quote_fp: u128,
-    pub batch_notional_quote_fp: u128,
+#[event]
+pub struct ClearThrottleUpdated {
+    pub market: Pubkey,
+    pub max_clears_per_epoch: u32,
+}
 
-    pub max_orders_global_per_batch: u32,
-    pub global_orders_in_batch: u32,
+#[event]
+pub struct SizeBucketingUpdated {
+    pub market: Pubkey,
+    pub enabled: bool,
+}
 
-    // --- Price band / last price ---
-    pub max_price_move_bps: u16,
-    pub last_clearing_price_fp: u64,
+#[event]
+pub struct TestClockSet {
+    pub market: Pubkey,
+    pub slot: u64,
+}
 
-    // --- Keeper ---
-    pub keeper_fee_bps: u16,
-    pub keeper_treasury: Pubkey,
-    pub min_slots_between_clears: u64,
-    pub keeper_restricted: bool,
-    pub only_keeper: Pubkey,
+#[event]
+pub struct TransferHookProgramsUpdated {
+    pub market: Pubkey,
+    pub programs: Vec<Pubkey>,
+}
 
-    // --- Fees / treasury ---
-    pub protocol_treasury: Pubkey,
-    pub referral_fee_bps: u16,
-    pub protocol_fee_bps: u16,
-    pub protocol_fees_accrued_fp: u128,
+#[event]
+pub struct YieldConfigUpdated {
+    pub market: Pubkey,
+    pub yield_program: Pubkey,
+    pub sweep_bps: u16,
+}
 
-    // --- Dust limits ---
-    pub min_base_order_fp: u64,
-    pub min_quote_order_fp: u64,
+#[event]
+pub struct YieldSwept {
+    pub market: Pubkey,
+    pub amount_quote_fp: u64,
+    pub deployed_quote_fp: u64,
+}
 
-    // --- Pause reason ---
-    pub pause_reason: u8,
+#[event]
+pub struct YieldRecalled {
+    pub market: Pubkey,
+    pub principal_quote_fp: u64,
+    pub yield_quote_fp: u64,
+    pub deployed_quote_fp: u64,
 }
 
-impl Market {
-    pub const LEN: usize = 412;
+#[event]
+pub struct LiquidityAdapterRegistered {
+    pub market: Pubkey,
+    pub program: Pubkey,
+    pub account_count: u8,
 }
 
-#[account]
-pub struct Order {
-    pub user: Pubkey,
+#[event]
+pub struct ResidualRouted {
     pub market: Pubkey,
-    pub side: OrderSide,
-    pub limit_price_fp: u64,
-    pub amount_base_fp: u64,
     pub batch_id: u64,
-    pub filled: bool,
-    pub cancelled: bool,
-    pub quote_deposit_fp: u64,
-    pub id: u64,
+    pub residual_side: OrderSide,
+    pub routed_base_fp: u64,
+    pub routed_quote_fp: u64,
 }
 
-impl Order {
-    pub const LEN: usize = 107;
+#[event]
+pub struct UncrossedBatchRefunded {
+    pub market: Pubkey,
+    pub batch_id: u64,
+    pub orders_refunded: u32,
 }
 
-#[account]
-pub struct UserBatchStats {
-    pub user: Pubkey,
+#[event]
+pub struct ReceiptMinted {
     pub market: Pubkey,
+    pub order: Pubkey,
+    pub user: Pubkey,
+    pub mint: Pubkey,
     pub batch_id: u64,
-    pub order_count: u32,
-    pub bump: u8,
-    pub notional_quote_fp: u128,
 }
 
-impl UserBatchStats {
-    pub const LEN: usize = 93;
+#[event]
+pub struct UserActivitySnapshotted {
+    pub market: Pubkey,
+    pub user: Pubkey,
+    pub epoch: u64,
+    pub volume_quote_fp: u128,
+    pub fill_count: u32,
 }
 
-#[account]
-pub struct BatchState {
+#[event]
+pub struct MarketMetadataUpdated {
     pub market: Pubkey,
-    pub batch_id: u64,
-    pub clearing_price_fp: u64,
-    pub total_base_traded_fp: u64,
-    pub total_quote_traded_fp: u64,
-    pub created_slot: u64,
-    pub cleared_slot: u64,
-    pub settled: bool,
-    pub keeper: Pubkey,
-    pub keeper_reward_quote_fp: u128,
-    pub remaining_base_to_settle_fp: u128,
-    pub remaining_quote_to_settle_fp: u128,
+    pub name: String,
+    pub tags: Vec<String>,
+    pub uri: String,
 }
 
-impl BatchState {
-    pub const LEN: usize = 161;
+#[event]
+pub struct SettlementCosignerUpdated {
+    pub market: Pubkey,
+    pub user: Pubkey,
+    pub cosigner: Pubkey,
+    pub threshold_quote_fp: u64,
 }
 
-#[account]
-pub struct OrderFill {
+#[event]
+pub struct SettlementApproved {
+    pub market: Pubkey,
     pub order: Pubkey,
-    pub batch_id: u64,
-    pub filled_base_fp: u64,
-    pub filled_quote_fp: u64,
-    pub refund_quote_fp: u64,
-    pub refund_base_fp: u64,
-    pub claimed: bool,
+    pub user: Pubkey,
+    pub cosigner: Pubkey,
 }
 
-impl OrderFill {
-    pub const LEN: usize = 73;
+#[event]
+pub struct ComplianceAuthorityUpdated {
+    pub market: Pubkey,
+    pub compliance_authority: Pubkey,
+    pub max_freeze_slots: u64,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
-pub enum OrderSide {
-    Bid,
-    Ask,
+#[event]
+pub struct OrderFrozen {
+    pub market: Pubkey,
+    pub order: Pubkey,
+    pub user: Pubkey,
+    pub frozen_until_slot: u64,
 }
 
-/// Local helper for in-memory order matching during batch clear.
-struct TempOrder {
-    pub account_index: usize, // index into remaining_accounts
-    pub side: OrderSide,
-    pub limit_price_fp: u64,
-    pub original_base_fp: u128,
-    pub remaining_base_fp: u128,
-    pub quote_deposit_fp: u128,
+#[event]
+pub struct OrderUnfrozen {
+    pub market: Pubkey,
+    pub order: Pubkey,
+    pub user: Pubkey,
 }
 
-// -------------------------------
-// Events
-// -------------------------------
-
 #[event]
-pub struct MarketInitialized {
+pub struct TravelRuleThresholdUpdated {
     pub market: Pubkey,
-    pub authority: Pubkey,
-    pub base_mint: Pubkey,
-    pub quote_mint: Pubkey,
-    pub batch_duration_slots: u64,
-    pub fee_bps: u16,
+    pub threshold_quote_fp: u64,
 }
 
 #[event]
-pub struct OrderPlaced {
+pub struct TravelRuleAttached {
     pub market: Pubkey,
     pub order: Pubkey,
     pub user: Pubkey,
-    pub side: OrderSide,
-    pub limit_price_fp: u64,
-    pub amount_base_fp: u64,
-    pub batch_id: u64,
+    pub payload_hash: [u8; 32],
 }
 
 #[event]
-pub struct BatchCleared {
+pub struct RecoveryAddressUpdated {
     pub market: Pubkey,
-    pub batch_id: u64,
-    pub clearing_price_fp: u64,
-    pub total_base_traded_fp: u64,
-    pub total_quote_traded_fp: u64,
+    pub user: Pubkey,
+    pub recovery_address: Pubkey,
 }
 
 #[event]
-pub struct OrderCancelled {
+pub struct RecoveryRequested {
     pub market: Pubkey,
     pub order: Pubkey,
     pub user: Pubkey,
-    pub batch_id: u64,
-    pub side: OrderSide,
+    pub recovery_address: Pubkey,
+    pub executable_slot: u64,
 }
 
 #[event]
-pub struct OrderSettled {
+pub struct RecoveryCancelled {
+    pub market: Pubkey,
+    pub order: Pubkey,
+    pub cancelled_by: Pubkey,
+}
+
+#[event]
+pub struct RecoveryExecuted {
     pub market: Pubkey,
     pub order: Pubkey,
     pub user: Pubkey,
+    pub recovery_address: Pubkey,
+    pub refund_base_fp: u64,
+    pub refund_quote_fp: u64,
+}
+
+#[event]
+pub struct SubAccountOpened {
+    pub market: Pubkey,
+    pub master: Pubkey,
+    pub sub_account: Pubkey,
+    pub sub_account_id: u16,
+}
+
+#[event]
+pub struct SubAccountSwept {
+    pub market: Pubkey,
+    pub master: Pubkey,
+    pub from_sub_account: Pubkey,
+    pub to_sub_account: Option<Pubkey>,
+    pub mint: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct ConservationViolated {
+    pub market: Pubkey,
     pub batch_id: u64,
-    pub side: OrderSide,
-    pub clearing_price_fp: u64,
+    pub order: Pubkey,
     pub filled_base_fp: u64,
     pub filled_quote_fp: u64,
-    pub refund_base_fp: u64,
-    pub refund_quote_fp: u64,
+    pub refund_fp: u64,
 }
 
 #[event]
-pub struct PausedSet {
+pub struct ProgramConfigUpdated {
+    pub admin: Pubkey,
+    pub max_fee_bps: u16,
+    pub max_keeper_fee_bps: u16,
+    pub min_batch_duration_slots: u64,
+}
+
+#[event]
+pub struct ProgramStateMigrated {
+    pub admin: Pubkey,
+    pub from_version: u16,
+    pub to_version: u16,
+}
+
+#[event]
+pub struct MarketTemplateApplied {
     pub market: Pubkey,
-    pub paused: bool,
-    pub reason: u8,
+    pub template_id: u8,
+    pub name: String,
 }
 
 #[event]
-pub struct ParamsUpdated {
+pub struct MarketClusterCreated {
+    pub cluster: Pubkey,
+    pub authority: Pubkey,
+    pub period_slots: u64,
+    pub clear_window_slots: u64,
+}
+
+#[event]
+pub struct MarketJoinedCluster {
+    pub cluster: Pubkey,
     pub market: Pubkey,
-    pub fee_bps: u16,
-    pub max_notional_per_batch_quote_fp: u128,
-    pub max_notional_per_user_per_batch_quote_fp: u128,
-    pub max_orders_global_per_batch: u32,
-    pub max_price_move_bps: u16,
-    pub keeper_fee_bps: u16,
-    pub min_base_order_fp: u64,
-    pub min_quote_order_fp: u64,
-    pub protocol_fee_bps: u16,
-    pub referral_fee_bps: u16,
+    pub member_count: u8,
+}
+
+#[event]
+pub struct ClusterRewardsFunded {
+    pub cluster: Pubkey,
+    pub funder: Pubkey,
+    pub lamports: u64,
+}
+
+#[event]
+pub struct ClusterKeeperRegistered {
+    pub cluster: Pubkey,
+    pub keeper: Pubkey,
+}
+
+#[event]
+pub struct ClusterRewardEpochClosed {
+    pub cluster: Pubkey,
+    pub epoch: u32,
+    pub reward_lamports: u64,
+    pub volume_quote_fp: u128,
+}
+
+#[event]
+pub struct ClusterRewardsClaimed {
+    pub cluster: Pubkey,
+    pub keeper: Pubkey,
+    pub epoch: u32,
+    pub lamports: u64,
+}
+
+#[event]
+pub struct WorkQueueAttached {
+    pub work_queue: Pubkey,
+    pub market: Pubkey,
+}
+
+#[event]
+pub struct KeeperRegistered {
+    pub market: Pubkey,
+    pub keeper: Pubkey,
+    pub reward_recipient: Pubkey,
 }
 
 #[event]
@@ -1589,7 +11415,8 @@ pub struct MarketView {
     pub next_order_id: u64,
     pub fee_bps: u16,
     pub max_orders_per_user_per_batch: u32,
-    pub paused: bool,
+    pub max_active_batches_per_user: u32,
+    pub pause_modes: u8,
     pub max_notional_per_batch_quote_fp: u128,
     pub max_notional_per_user_per_batch_quote_fp: u128,
     pub batch_notional_quote_fp: u128,
@@ -1600,10 +11427,13 @@ pub struct MarketView {
     pub keeper_fee_bps: u16,
     pub min_base_order_fp: u64,
     pub min_quote_order_fp: u64,
+    pub max_base_order_fp: u64,
+    pub max_quote_order_fp: u64,
     pub protocol_fee_bps: u16,
     pub referral_fee_bps: u16,
     pub protocol_fees_accrued_fp: u128,
     pub pause_reason: u8,
+    pub mode: MarketMode,
 }
 
 // -------------------------------
@@ -1658,4 +11488,204 @@ pub enum AmmError {
     BatchMarketMismatch,
     #[msg("Batch id mismatch")]
     BatchIdMismatch,
+    #[msg("Invalid order trigger")]
+    InvalidTrigger,
+    #[msg("RFQ whitelist account required")]
+    RfqWhitelistRequired,
+    #[msg("User is not an RFQ participant")]
+    NotRfqParticipant,
+    #[msg("Too many RFQ responders")]
+    TooManyRfqResponders,
+    #[msg("Operation not allowed in the current market mode")]
+    InvalidMarketMode,
+    #[msg("Launch auction is not active")]
+    LaunchNotActive,
+    #[msg("Launch auctions only accept bids")]
+    LaunchBidsOnly,
+    #[msg("Order exceeds max order size")]
+    OrderTooLarge,
+    #[msg("Max order size below min order size")]
+    InvalidOrderSizeBand,
+    #[msg("Too many concurrently active batches for this user")]
+    TooManyActiveBatches,
+    #[msg("Clear intents are disabled for this market")]
+    ClearIntentsDisabled,
+    #[msg("A clear intent is already registered for this batch")]
+    ClearIntentAlreadyRegistered,
+    #[msg("Registered keeper still has exclusive clear rights")]
+    ClearIntentWindowActive,
+    #[msg("Clear intent account required")]
+    ClearIntentRequired,
+    #[msg("Clear transaction is missing the required bundle tip")]
+    ClearTipMissing,
+    #[msg("Invalid tip account")]
+    InvalidTipAccount,
+    #[msg("Invalid fee holiday window")]
+    InvalidFeeHoliday,
+    #[msg("Vault yield is disabled or adapter mismatch")]
+    YieldDisabled,
+    #[msg("Swept quote must be recalled first")]
+    YieldNotRecalled,
+    #[msg("Sweep exceeds the configured vault fraction")]
+    YieldSweepTooLarge,
+    #[msg("Unexpected vault balance change during yield CPI")]
+    YieldAccountingMismatch,
+    #[msg("Invalid liquidity adapter")]
+    InvalidLiquidityAdapter,
+    #[msg("No residual imbalance to route")]
+    NoResidualToRoute,
+    #[msg("Residual already routed for this batch")]
+    ResidualAlreadyRouted,
+    #[msg("External swap result outside residual bounds")]
+    ResidualRouteMismatch,
+    #[msg("Batch crossed; settle orders individually")]
+    BatchCrossed,
+    #[msg("Account is not owned by this program")]
+    ForeignAccount,
+    #[msg("Account discriminator or layout does not match the expected type")]
+    AccountLayoutMismatch,
+    #[msg("Order index page is full")]
+    OrderIndexFull,
+    #[msg("Fill receipts not enabled for this user")]
+    ReceiptsNotEnabled,
+    #[msg("Order has no settled fill to receipt")]
+    NothingToReceipt,
+    #[msg("Market metadata exceeds size limits")]
+    MetadataTooLong,
+    #[msg("Fill above the co-sign threshold needs approve_settlement")]
+    SettlementApprovalRequired,
+    #[msg("Current co-signer must sign this change")]
+    CosignerSignatureRequired,
+    #[msg("Order does not require co-signing")]
+    CosignNotRequired,
+    #[msg("Sub-account does not belong to this master and market")]
+    InvalidSubAccount,
+    #[msg("Sweep destination must belong to the master or one of its sub-accounts")]
+    InvalidSweepDestination,
+    #[msg("Too many markets for a single portfolio view")]
+    TooManyPortfolioMarkets,
+    #[msg("Order's market was not passed to the portfolio view")]
+    PortfolioMarketMissing,
+    #[msg("Settlement accounting drifted; market auto-paused pending review")]
+    ConservationViolation,
+    #[msg("Late-cancel window cannot exceed the batch duration")]
+    InvalidLateCancelWindow,
+    #[msg("Fee exceeds the program-wide cap")]
+    FeeAboveProgramCap,
+    #[msg("Batch duration below the program-wide minimum")]
+    BatchDurationTooShort,
+    #[msg("Unknown market template")]
+    UnknownMarketTemplate,
+    #[msg("SlotHashes sysvar has no entries")]
+    SlotHashUnavailable,
+    #[msg("Order owner has not designated a recovery address")]
+    RecoveryAddressNotSet,
+    #[msg("Recovery timelock has not elapsed")]
+    RecoveryTimelockActive,
+    #[msg("Recovery only redirects refunds; matched orders must settle")]
+    RecoveryNotRefundOnly,
+    #[msg("Too many distinct prices to clear, even on the tick grid")]
+    TooManyCandidatePrices,
+    #[msg("Fill vests: pass the order's vesting account")]
+    VestingAccountRequired,
+    #[msg("No vested tranche to claim yet")]
+    NothingVested,
+    #[msg("Volume spike: batch needs confirm_batch before settlement")]
+    BatchAwaitingConfirmation,
+    #[msg("Batch is not awaiting confirmation")]
+    BatchNotAwaitingConfirmation,
+    #[msg("Order index pages or orders do not match the batch")]
+    InvalidOrderIndex,
+    #[msg("Batch clear was successfully challenged; it no longer settles")]
+    BatchDisputed,
+    #[msg("Dispute window has not elapsed")]
+    DisputeWindowActive,
+    #[msg("Settlement for this batch has not opened yet")]
+    SettlementNotOpen,
+    #[msg("OCO legs must be two distinct, unlinked, open conditional orders in the open batch")]
+    InvalidOcoLink,
+    #[msg("Quote scale can only change while the open batch has no orders")]
+    QuoteScaleLocked,
+    #[msg("Order is frozen pending compliance review")]
+    OrderFrozen,
+    #[msg("Freeze duration must be between 1 and the market's max_freeze_slots")]
+    InvalidFreezeDuration,
+    #[msg("Order has already been frozen once")]
+    OrderAlreadyFrozen,
+    #[msg("Order is not frozen")]
+    OrderNotFrozen,
+    #[msg("Fill exceeds the travel-rule threshold: pass the order's travel-rule record")]
+    TravelRuleRecordRequired,
+    #[msg("Cluster clear window must be non-zero and shorter than its period")]
+    InvalidClusterParams,
+    #[msg("Market already belongs to a cluster")]
+    AlreadyInCluster,
+    #[msg("Cluster has no room for another market")]
+    ClusterFull,
+    #[msg("Open batch must be empty")]
+    OpenBatchNotEmpty,
+    #[msg("Clustered batch can only clear just after a cluster boundary")]
+    ClusterClearWindowClosed,
+    #[msg("Batch closed at the cluster boundary; orders resume after its clear")]
+    BatchAwaitingClusterClear,
+    #[msg("Cluster members must share one quote mint")]
+    ClusterQuoteMismatch,
+    #[msg("No cluster rewards to claim for the last closed epoch")]
+    NoClusterRewards,
+    #[msg("Parameter ramp needs a parameter and at least one batch")]
+    InvalidParamRamp,
+    #[msg("Rebate must beat the standing bid and stay within the keeper reward")]
+    ClearIntentBidTooLow,
+    #[msg("Market publishes to a work queue: pass it")]
+    WorkQueueRequired,
+    #[msg("Market already publishes to a work queue")]
+    WorkQueueAlreadySet,
+    #[msg("Work queue has no room for another market")]
+    WorkQueueFull,
+    #[msg("Mint's transfer-hook program is not allowed on this market")]
+    TransferHookNotAllowed,
+    #[msg("Too many transfer-hook programs")]
+    TooManyTransferHookPrograms,
+    #[msg("Claimables index is full: settle or prune it first")]
+    ClaimablesFull,
+    #[msg("Settle nonce is stale: the order changed since it was read")]
+    StaleSettleNonce,
+    #[msg("Test clock is only available in test-clock builds")]
+    TestClockDisabled,
+    #[msg("Program state needs migrate_program_state after the upgrade")]
+    MigrationPending,
+    #[msg("Program state is already at this version")]
+    AlreadyMigrated,
+    #[msg("Unknown pause mode bits")]
+    InvalidPauseModes,
+    #[msg("Market has used its clears for this epoch")]
+    ClearBudgetExhausted,
+    #[msg("User batch stats are still in use")]
+    UserBatchStatsInUse,
+    #[msg("Batch has not met the participation threshold; extend it instead")]
+    BatchBelowParticipation,
+    #[msg("Batch may clear (threshold met or extensions used up); clear it instead")]
+    BatchMeetsParticipation,
+    #[msg("A participation threshold needs a non-zero extension cap")]
+    InvalidParticipationThreshold,
+    #[msg("Epoch has not finished yet")]
+    EpochNotFinished,
+    #[msg("UserStats no longer holds (or never had) activity for this epoch")]
+    ActivityEpochUnavailable,
+    #[msg("A recorded fill would not settle; see the FillNotSettleable event")]
+    FillNotSettleable,
+    #[msg("The batch is being cleared in chunks; finalize or close its ClearingState first")]
+    ClearInProgress,
+    #[msg("The batch's ClearingState must be passed")]
+    ClearingStateRequired,
+    #[msg("ClearingState is already finalized")]
+    ClearingStateFinalized,
+    #[msg("ClearingState has no room for more orders")]
+    ClearingStateFull,
+    #[msg("ClearingState cannot be closed yet")]
+    ClearingStateNotClosable,
+    #[msg("clear_batch must be passed every live order in the batch")]
+    IncompleteOrderSet,
+    #[msg("Settlement delay must outlast the dispute window")]
+    DisputeWindowOutlastsSettlementDelay,
 }
@@ -10,6 +10,7 @@
      failed to send transaction: Transaction simulation failed: Error processing Instruction 0: Program failed to complete*/ 
 
 import * as splToken from "@solana/spl-token";
+import * as fx from "./fixtures";
 
 describe("micro_batch_amm", () => {
   const BN = anchor.BN;
@@ -105,6 +106,16 @@ describe("micro_batch_amm", () => {
       programId
     );
 
+    const [eventLogPda] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("event_log"), marketPda.toBuffer()],
+      programId
+    );
+
+    const [priceHistoryPda] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("price_history"), marketPda.toBuffer()],
+      programId
+    );
+
     // ----------------------------------------
     // 4) initializeMarket
     // ----------------------------------------
@@ -113,6 +124,31 @@ describe("micro_batch_amm", () => {
     const feeBps = 50;                    // 0.50%
     const maxOrdersPerUserPerBatch = 10;
 
+    // Program-wide bounds (created once by the upgrade authority)
+    const [programConfigPda] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("program_config")],
+      programId
+    );
+    const existingConfig = await pg.program.account.programConfig.fetchNullable(
+      programConfigPda
+    );
+    if (existingConfig === null) {
+      const [programDataPda] = web3.PublicKey.findProgramAddressSync(
+        [programId.toBuffer()],
+        new web3.PublicKey("BPFLoaderUpgradeab1e11111111111111111111111")
+      );
+      await pg.program.methods
+        .initProgramConfig(1_000, 1_000, new BN(1))
+        .accounts({
+          authority: wallet.publicKey,
+          programConfig: programConfigPda,
+          program: programId,
+          programData: programDataPda,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .rpc();
+    }
+
     const txInit = await pg.program.methods
       .initializeMarket(batchDurationSlots, feeBps, maxOrdersPerUserPerBatch)
       .accounts({
@@ -122,6 +158,9 @@ describe("micro_batch_amm", () => {
         market: marketPda,
         vaultBase: vaultBasePda,
         vaultQuote: vaultQuotePda,
+        eventLog: eventLogPda,
+        priceHistory: priceHistoryPda,
+        programConfig: programConfigPda,
         systemProgram: web3.SystemProgram.programId,
         tokenProgram: splToken.TOKEN_PROGRAM_ID,
         rent: web3.SYSVAR_RENT_PUBKEY,
@@ -141,14 +180,19 @@ describe("micro_batch_amm", () => {
     // ----------------------------------------
 
     const marketBeforeBid = await pg.program.account.market.fetch(marketPda);
-    const nextOrderIdBid: anchor.BN = marketBeforeBid.nextOrderId;
     const currentBatchId: anchor.BN = marketBeforeBid.currentBatchId;
 
+    // Orders are seeded by (market, user, user_stats.order_nonce, market.batch_salt).
+    // UserStats doesn't exist before the user's first order, so the nonce starts at 0.
+    const nonceBid = new BN(0);
+
     const [orderBidPda] = web3.PublicKey.findProgramAddressSync(
       [
         Buffer.from("order"),
         marketPda.toBuffer(),
-        nextOrderIdBid.toArrayLike(Buffer, "le", 8),
+        wallet.publicKey.toBuffer(),
+        nonceBid.toArrayLike(Buffer, "le", 8),
+        Buffer.from(marketBeforeBid.batchSalt),
       ],
       programId
     );
@@ -163,12 +207,32 @@ describe("micro_batch_amm", () => {
       programId
     );
 
+    const [userStatsPda] = web3.PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("user_stats"),
+        marketPda.toBuffer(),
+        wallet.publicKey.toBuffer(),
+      ],
+      programId
+    );
+
+    // Both orders land on page 0 of this batch's order index.
+    const [orderIndexPda] = web3.PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("order_index"),
+        marketPda.toBuffer(),
+        currentBatchId.toArrayLike(Buffer, "le", 8),
+        new BN(0).toArrayLike(Buffer, "le", 4),
+      ],
+      programId
+    );
+
     const sideBid = { bid: {} };
     const limitPriceFp = new BN(1_000_000); // price = 1.0
     const amountBaseFp = new BN(1_000_000); // 1 base unit (fp)
 
     const txPlaceBid = await pg.program.methods
-      .placeOrder(sideBid, limitPriceFp, amountBaseFp)
+      .placeOrder(sideBid, limitPriceFp, amountBaseFp, null, false, null)
       .accounts({
         user: wallet.publicKey,
         market: marketPda,
@@ -180,9 +244,14 @@ describe("micro_batch_amm", () => {
         userQuoteAta: userQuoteAta.address,
         order: orderBidPda,
         userBatchStats: userBatchStatsPda,
+        userStats: userStatsPda,
+        orderIndex: orderIndexPda,
+        eventLog: eventLogPda,
         systemProgram: web3.SystemProgram.programId,
         tokenProgram: splToken.TOKEN_PROGRAM_ID,
         rent: web3.SYSVAR_RENT_PUBKEY,
+        rfqWhitelist: null,
+        subAccount: null,
       })
       .rpc();
 
@@ -194,22 +263,24 @@ describe("micro_batch_amm", () => {
     assert.deepEqual(orderBidAccount.side, sideBid);
     assert.ok(orderBidAccount.amountBaseFp.eq(amountBaseFp));
     assert.ok(orderBidAccount.limitPriceFp.eq(limitPriceFp));
-    assert.equal(orderBidAccount.filled, false);
-    assert.equal(orderBidAccount.cancelled, false);
+    assert.equal(orderBidAccount.flags, 0); // not filled / cancelled
 
     // ----------------------------------------
     // 6) placeOrder - Ask (same user, same price/size)
     //    This ensures there's an actual crossing trade.
     // ----------------------------------------
 
-    const marketBeforeAsk = await pg.program.account.market.fetch(marketPda);
-    const nextOrderIdAsk: anchor.BN = marketBeforeAsk.nextOrderId;
+    const userStatsBeforeAsk = await pg.program.account.userStats.fetch(userStatsPda);
+    const nonceAsk: anchor.BN = userStatsBeforeAsk.orderNonce;
+    assert.equal(nonceAsk.toNumber(), 1);
 
     const [orderAskPda] = web3.PublicKey.findProgramAddressSync(
       [
         Buffer.from("order"),
         marketPda.toBuffer(),
-        nextOrderIdAsk.toArrayLike(Buffer, "le", 8),
+        wallet.publicKey.toBuffer(),
+        nonceAsk.toArrayLike(Buffer, "le", 8),
+        Buffer.from(marketBeforeBid.batchSalt),
       ],
       programId
     );
@@ -217,7 +288,7 @@ describe("micro_batch_amm", () => {
     const sideAsk = { ask: {} };
 
     const txPlaceAsk = await pg.program.methods
-      .placeOrder(sideAsk, limitPriceFp, amountBaseFp)
+      .placeOrder(sideAsk, limitPriceFp, amountBaseFp, null, false, null)
       .accounts({
         user: wallet.publicKey,
         market: marketPda,
@@ -229,9 +300,14 @@ describe("micro_batch_amm", () => {
         userQuoteAta: userQuoteAta.address,
         order: orderAskPda,
         userBatchStats: userBatchStatsPda, // same user_batch PDA, already initialized
+        userStats: userStatsPda,
+        orderIndex: orderIndexPda,
+        eventLog: eventLogPda,
         systemProgram: web3.SystemProgram.programId,
         tokenProgram: splToken.TOKEN_PROGRAM_ID,
         rent: web3.SYSVAR_RENT_PUBKEY,
+        rfqWhitelist: null,
+        subAccount: null,
       })
       .rpc();
 
@@ -239,6 +315,12 @@ describe("micro_batch_amm", () => {
 
     const orderAskAccount = await pg.program.account.order.fetch(orderAskPda);
     assert.deepEqual(orderAskAccount.side, sideAsk);
+
+    const orderIndex = await pg.program.account.orderIndex.fetch(orderIndexPda);
+    assert.deepEqual(
+      orderIndex.orders.map((k) => k.toBase58()),
+      [orderBidPda.toBase58(), orderAskPda.toBase58()]
+    );
     assert.ok(orderAskAccount.amountBaseFp.eq(amountBaseFp));
     assert.ok(orderAskAccount.limitPriceFp.eq(limitPriceFp));
 
@@ -258,7 +340,7 @@ describe("micro_batch_amm", () => {
       programId
     );
 
-    // We only need to pass one triplet of remaining accounts for now
+    // One triplet per order; orders are writable because clear_batch records claims on them
     const txClear = await pg.program.methods
       .clearBatch()
       .accounts({
@@ -271,6 +353,20 @@ describe("micro_batch_amm", () => {
         batchState: batchStatePda,
         tokenProgram: splToken.TOKEN_PROGRAM_ID,
         systemProgram: web3.SystemProgram.programId,
+        triggerMarket: null,
+        clearIntent: null,
+        instructionsSysvar: null,
+        liquidityAdapter: null,
+        adapterProgram: null,
+        keeperRecord: null,
+        reimbursementVault: null,
+        slotHashes: web3.SYSVAR_SLOT_HASHES_PUBKEY,
+        eventLog: eventLogPda,
+        priceHistory: priceHistoryPda,
+        cluster: null,
+        clusterKeeperShare: null,
+        workQueue: null,
+        clearingState: null,
       })
       .remainingAccounts([
         {
@@ -288,9 +384,21 @@ describe("micro_batch_amm", () => {
           isSigner: false,
           isWritable: false,
         },
-        // (Optionally  could also add the ask order triplet if you extend
-        // clearBatch logic to use them, but current Rust code only reads the
-        // Order account itself.)
+        {
+          pubkey: orderAskPda,
+          isSigner: false,
+          isWritable: true,
+        },
+        {
+          pubkey: userBaseAta.address,
+          isSigner: false,
+          isWritable: false,
+        },
+        {
+          pubkey: userQuoteAta.address,
+          isSigner: false,
+          isWritable: false,
+        },
       ])
       .rpc();
 
@@ -305,6 +413,13 @@ describe("micro_batch_amm", () => {
     assert.ok(batchStateAccount.clearingPriceFp.gt(new BN(0)));
     assert.ok(batchStateAccount.totalBaseTradedFp.gt(new BN(0)));
 
+    // The price stays queryable after the BatchState is gone.
+    const historicalPrice = await pg.program.methods
+      .getClearingPrice(batchIdForClear)
+      .accounts({ market: marketPda, priceHistory: priceHistoryPda })
+      .view();
+    assert.ok(historicalPrice.eq(batchStateAccount.clearingPriceFp));
+
     // ----------------------------------------
     // 8) settleOrder (for the bid)
     // ----------------------------------------
@@ -315,19 +430,31 @@ describe("micro_batch_amm", () => {
     );
 
     const txSettle = await pg.program.methods
-      .settleOrder()
+      .settleOrder(0)
       .accounts({
         user: wallet.publicKey,
         market: marketPda,
         batchState: batchStatePda,
         order: orderBidPda,
         orderFill: orderFillPda,
+        userBatchStats: userBatchStatsPda,
+        userStats: userStatsPda,
         vaultBase: vaultBasePda,
         vaultQuote: vaultQuotePda,
+        orderOwner: wallet.publicKey,
+        baseMint,
+        quoteMint,
         userBaseAta: userBaseAta.address,
         userQuoteAta: userQuoteAta.address,
         tokenProgram: splToken.TOKEN_PROGRAM_ID,
+        associatedTokenProgram: splToken.ASSOCIATED_TOKEN_PROGRAM_ID,
         systemProgram: web3.SystemProgram.programId,
+        eventLog: eventLogPda,
+        subAccount: null,
+        vesting: null,
+        travelRule: null,
+        claimables: null,
+        clearingState: null,
       })
       .rpc();
 
@@ -343,6 +470,17 @@ describe("micro_batch_amm", () => {
       batchStateAccount.batchId.toNumber()
     );
     assert.equal(orderFillAccount.claimed, true);
+    assert.ok(orderFillAccount.filledBaseFp.eq(amountBaseFp));
+
+    // The recorded claim was debited in full.
+    const settledBid = await pg.program.account.order.fetch(orderBidPda);
+    assert.ok(settledBid.claimBaseFp.isZero());
+    assert.ok(settledBid.claimQuoteFp.isZero());
+
+    // Two placements, one clear and one settlement were sequenced.
+    const eventLog = await pg.program.account.eventLog.fetch(eventLogPda);
+    assert.equal(eventLog.nextSeq.toNumber(), 4);
+    assert.equal(eventLog.entries[3].seq.toNumber(), 3);
 
     console.log("OrderFill (bid):", {
       filledBase: orderFillAccount.filledBaseFp.toString(),
@@ -351,4 +489,313 @@ describe("micro_batch_amm", () => {
       refundQuote: orderFillAccount.refundQuoteFp.toString(),
     });
   });
+
+  it("underfunded bid: clear and settle agree on the affordable fill", async () => {
+    const connection = pg.connection;
+    const wallet = pg.wallet;
+    // @ts-ignore - Playground exposes an underlying Keypair
+    const payer: web3.Keypair = wallet.payer ?? wallet.keypair;
+    const programId = pg.program.programId;
+
+    const baseMint = await splToken.createMint(connection, payer, wallet.publicKey, null, 6);
+    const quoteMint = await splToken.createMint(connection, payer, wallet.publicKey, null, 6);
+    const userBaseAta = await splToken.getOrCreateAssociatedTokenAccount(
+      connection, payer, baseMint, wallet.publicKey
+    );
+    const userQuoteAta = await splToken.getOrCreateAssociatedTokenAccount(
+      connection, payer, quoteMint, wallet.publicKey
+    );
+    await splToken.mintTo(connection, payer, baseMint, userBaseAta.address, wallet.publicKey, BigInt(1_000_000));
+    await splToken.mintTo(connection, payer, quoteMint, userQuoteAta.address, wallet.publicKey, BigInt(1_000_000));
+
+    const pda = (...seeds: Buffer[]) =>
+      web3.PublicKey.findProgramAddressSync(seeds, programId)[0];
+    const marketPda = pda(
+      Buffer.from("market"), wallet.publicKey.toBuffer(), baseMint.toBuffer(), quoteMint.toBuffer()
+    );
+    const vaultBasePda = pda(Buffer.from("vault_base"), marketPda.toBuffer());
+    const vaultQuotePda = pda(Buffer.from("vault_quote"), marketPda.toBuffer());
+    const eventLogPda = pda(Buffer.from("event_log"), marketPda.toBuffer());
+    const priceHistoryPda = pda(Buffer.from("price_history"), marketPda.toBuffer());
+    const programConfigPda = pda(Buffer.from("program_config"));
+    const userStatsPda = pda(Buffer.from("user_stats"), marketPda.toBuffer(), wallet.publicKey.toBuffer());
+
+    await pg.program.methods
+      .initializeMarket(new BN(5), 50, 10)
+      .accounts({
+        authority: wallet.publicKey,
+        baseMint,
+        quoteMint,
+        market: marketPda,
+        vaultBase: vaultBasePda,
+        vaultQuote: vaultQuotePda,
+        eventLog: eventLogPda,
+        priceHistory: priceHistoryPda,
+        programConfig: programConfigPda,
+        systemProgram: web3.SystemProgram.programId,
+        tokenProgram: splToken.TOKEN_PROGRAM_ID,
+        rent: web3.SYSVAR_RENT_PUBKEY,
+      })
+      .rpc();
+
+    const market = await pg.program.account.market.fetch(marketPda);
+    const batchId: anchor.BN = market.currentBatchId;
+    const userBatchStatsPda = pda(
+      Buffer.from("user_batch"), marketPda.toBuffer(), wallet.publicKey.toBuffer(),
+      batchId.toArrayLike(Buffer, "le", 8)
+    );
+    const orderIndexPda = pda(
+      Buffer.from("order_index"), marketPda.toBuffer(),
+      batchId.toArrayLike(Buffer, "le", 8), new BN(0).toArrayLike(Buffer, "le", 4)
+    );
+    const orderPda = (nonce: number) =>
+      pda(
+        Buffer.from("order"), marketPda.toBuffer(), wallet.publicKey.toBuffer(),
+        new BN(nonce).toArrayLike(Buffer, "le", 8), Buffer.from(market.batchSalt)
+      );
+
+    // 1 base fp at 1.5: the bid deposits floor(1.5) = 1 quote fp, which cannot pay for
+    // its 1 base fp at the 1.5 clearing price.
+    const priceFp = new BN(1_500_000);
+    const amountFp = new BN(1);
+    const orders = [orderPda(0), orderPda(1)];
+    for (const [i, side] of [{ bid: {} }, { ask: {} }].entries()) {
+      await pg.program.methods
+        .placeOrder(side, priceFp, amountFp, null, false, null)
+        .accounts({
+          user: wallet.publicKey,
+          market: marketPda,
+          baseMint,
+          quoteMint,
+          vaultBase: vaultBasePda,
+          vaultQuote: vaultQuotePda,
+          userBaseAta: userBaseAta.address,
+          userQuoteAta: userQuoteAta.address,
+          order: orders[i],
+          userBatchStats: userBatchStatsPda,
+          userStats: userStatsPda,
+          orderIndex: orderIndexPda,
+          eventLog: eventLogPda,
+          systemProgram: web3.SystemProgram.programId,
+          tokenProgram: splToken.TOKEN_PROGRAM_ID,
+          rent: web3.SYSVAR_RENT_PUBKEY,
+          rfqWhitelist: null,
+          subAccount: null,
+        })
+        .rpc();
+    }
+
+    // Wait out the batch.
+    const openSlot = await connection.getSlot();
+    while ((await connection.getSlot()) < openSlot + 6) {
+      await new Promise((r) => setTimeout(r, 400));
+    }
+
+    const batchStatePda = pda(
+      Buffer.from("batch_state"), marketPda.toBuffer(), batchId.toArrayLike(Buffer, "le", 8)
+    );
+    await pg.program.methods
+      .clearBatch()
+      .accounts({
+        authority: wallet.publicKey,
+        market: marketPda,
+        baseMint,
+        quoteMint,
+        vaultBase: vaultBasePda,
+        vaultQuote: vaultQuotePda,
+        batchState: batchStatePda,
+        tokenProgram: splToken.TOKEN_PROGRAM_ID,
+        systemProgram: web3.SystemProgram.programId,
+        triggerMarket: null,
+        clearIntent: null,
+        instructionsSysvar: null,
+        liquidityAdapter: null,
+        adapterProgram: null,
+        keeperRecord: null,
+        reimbursementVault: null,
+        slotHashes: web3.SYSVAR_SLOT_HASHES_PUBKEY,
+        eventLog: eventLogPda,
+        priceHistory: priceHistoryPda,
+        cluster: null,
+        clusterKeeperShare: null,
+        workQueue: null,
+        clearingState: null,
+      })
+      .remainingAccounts(
+        orders.flatMap((order) => [
+          { pubkey: order, isSigner: false, isWritable: true },
+          { pubkey: userBaseAta.address, isSigner: false, isWritable: false },
+          { pubkey: userQuoteAta.address, isSigner: false, isWritable: false },
+        ])
+      )
+      .rpc();
+
+    // The bid is crossed but unaffordable: nothing matched and no fillable residual.
+    const clearedBid = await pg.program.account.order.fetch(orders[0]);
+    assert.ok(clearedBid.matchedBaseFp.isZero());
+    assert.ok(clearedBid.fillableResidualBaseFp.isZero());
+    assert.ok(clearedBid.claimQuoteFp.eq(new BN(1)));
+
+    // Settlement consumes exactly that claim: a full refund, no revert.
+    const orderFillPda = pda(Buffer.from("order_fill"), orders[0].toBuffer());
+    await pg.program.methods
+      .settleOrder(0)
+      .accounts({
+        user: wallet.publicKey,
+        market: marketPda,
+        batchState: batchStatePda,
+        order: orders[0],
+        orderFill: orderFillPda,
+        userBatchStats: userBatchStatsPda,
+        userStats: userStatsPda,
+        vaultBase: vaultBasePda,
+        vaultQuote: vaultQuotePda,
+        orderOwner: wallet.publicKey,
+        baseMint,
+        quoteMint,
+        userBaseAta: userBaseAta.address,
+        userQuoteAta: userQuoteAta.address,
+        tokenProgram: splToken.TOKEN_PROGRAM_ID,
+        associatedTokenProgram: splToken.ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: web3.SystemProgram.programId,
+        eventLog: eventLogPda,
+        subAccount: null,
+        vesting: null,
+        travelRule: null,
+        claimables: null,
+        clearingState: null,
+      })
+      .rpc();
+
+    const fill = await pg.program.account.orderFill.fetch(orderFillPda);
+    assert.ok(fill.filledBaseFp.isZero());
+    assert.ok(fill.refundQuoteFp.eq(new BN(1)));
+  });
+
+  it("seeded order flow: clearing respects limit prices and conserves base", async () => {
+    // Change the seed to explore other flows; failures are reproducible from it.
+    const seed = 2216;
+    console.log("order flow seed:", seed);
+
+    const tm = await fx.setupMarket();
+    const traders = [await fx.createTrader(tm), await fx.createTrader(tm)];
+    const placed: fx.PlacedOrder[] = [];
+    for (const spec of fx.generateOrderFlow(seed, traders.length, 6)) {
+      placed.push(await fx.placeOrder(tm, traders[spec.trader], spec));
+    }
+
+    await fx.waitForClear(tm);
+    const batchStatePda = await fx.clearBatch(tm, placed);
+    const batchState = await pg.program.account.batchState.fetch(batchStatePda);
+    const priceFp: anchor.BN = batchState.clearingPriceFp;
+
+    let bidMatched = new BN(0);
+    let askMatched = new BN(0);
+    let demand = new BN(0);
+    let supply = new BN(0);
+    for (const { order, spec } of placed) {
+      const acc = await pg.program.account.order.fetch(order);
+      const isBid = "bid" in spec.side;
+      assert.ok(acc.matchedBaseFp.lte(spec.amountBaseFp));
+      if (priceFp.isZero()) {
+        assert.ok(acc.matchedBaseFp.isZero());
+        continue;
+      }
+      // Nobody trades through their limit.
+      const crosses = isBid ? spec.limitPriceFp.gte(priceFp) : spec.limitPriceFp.lte(priceFp);
+      if (!crosses) assert.ok(acc.matchedBaseFp.isZero());
+      if (isBid) {
+        bidMatched = bidMatched.add(acc.matchedBaseFp);
+        if (crosses) demand = demand.add(spec.amountBaseFp);
+      } else {
+        askMatched = askMatched.add(acc.matchedBaseFp);
+        if (crosses) supply = supply.add(spec.amountBaseFp);
+      }
+    }
+
+    // Both sides trade the same base, no more than the short side offered at the price.
+    assert.ok(bidMatched.eq(askMatched));
+    assert.ok(bidMatched.eq(batchState.totalBaseTradedFp));
+    assert.ok(bidMatched.lte(BN.min(demand, supply)));
+  });
+
+  it("a cleared order cannot be cancelled in the following batch", async () => {
+    const tm = await fx.setupMarket();
+    const traders = [await fx.createTrader(tm), await fx.createTrader(tm)];
+    const priceFp = new BN(1_000_000);
+    const amountFp = new BN(1_000_000);
+    const bid = await fx.placeOrder(tm, traders[0], {
+      trader: 0, side: { bid: {} }, limitPriceFp: priceFp, amountBaseFp: amountFp,
+    });
+    const ask = await fx.placeOrder(tm, traders[1], {
+      trader: 1, side: { ask: {} }, limitPriceFp: priceFp, amountBaseFp: amountFp,
+    });
+    await fx.waitForClear(tm);
+    await fx.clearBatch(tm, [bid, ask]);
+
+    // The bid's deposit now belongs to the cleared batch's settlement.
+    await fx.expectError(fx.cancelOrder(tm, bid), "BatchAlreadyClosed");
+    const acc = await pg.program.account.order.fetch(bid.order);
+    assert.ok(acc.matchedBaseFp.eq(amountFp));
+    assert.ok(acc.claimBaseFp.eq(amountFp));
+  });
+
+  it("a cancel delegate can cancel the order, anyone else is refused", async () => {
+    const tm = await fx.setupMarket();
+    const trader = await fx.createTrader(tm);
+    const bid = await fx.placeOrder(tm, trader, {
+      trader: 0, side: { bid: {} }, limitPriceFp: new BN(1_000_000),
+      amountBaseFp: new BN(1_000_000),
+    });
+    const delegate = web3.Keypair.generate();
+    const stranger = web3.Keypair.generate();
+    await fx.setCancelAuthority(tm, bid, delegate.publicKey);
+
+    // Neither the owner nor its delegate: the order's signer constraint rejects it.
+    await fx.expectError(fx.cancelOrder(tm, bid, stranger), "ConstraintRaw");
+    await fx.cancelOrder(tm, bid, delegate);
+    const acc = await pg.program.account.order.fetch(bid.order);
+    assert.equal(acc.flags & 0b10, 0b10); // cancelled
+    assert.ok(acc.cancelAuthority.equals(delegate.publicKey));
+  });
+
+  it("clear_batch fails when a live order is left out", async () => {
+    const tm = await fx.setupMarket();
+    const traders = [await fx.createTrader(tm), await fx.createTrader(tm)];
+    const priceFp = new BN(1_000_000);
+    const bid = await fx.placeOrder(tm, traders[0], {
+      trader: 0, side: { bid: {} }, limitPriceFp: priceFp, amountBaseFp: new BN(1_000_000),
+    });
+    const ask = await fx.placeOrder(tm, traders[1], {
+      trader: 1, side: { ask: {} }, limitPriceFp: priceFp, amountBaseFp: new BN(1_000_000),
+    });
+    await fx.waitForClear(tm);
+
+    // A keeper leaving the ask out would starve the bid of its match.
+    await fx.expectError(fx.clearBatch(tm, [bid]), "IncompleteOrderSet");
+    await fx.clearBatch(tm, [bid, ask]);
+    const acc = await pg.program.account.order.fetch(bid.order);
+    assert.ok(acc.matchedBaseFp.eq(new BN(1_000_000)));
+  });
+
+  it("a dispute window must close before settlement opens", async () => {
+    const tm = await fx.setupMarket();
+    const admin = { authority: pg.wallet.publicKey, market: tm.market };
+    const setClearBond = (windowSlots: number) =>
+      pg.program.methods.setClearBond(new BN(0), new BN(windowSlots)).accounts(admin).rpc();
+
+    // With no delay, a challenge could land after orders were paid.
+    await fx.expectError(setClearBond(10), "DisputeWindowOutlastsSettlementDelay");
+    await pg.program.methods.setSettlementDelay(new BN(10)).accounts(admin).rpc();
+    await fx.expectError(setClearBond(10), "DisputeWindowOutlastsSettlementDelay");
+    await setClearBond(9);
+    await fx.expectError(
+      pg.program.methods.setSettlementDelay(new BN(9)).accounts(admin).rpc(),
+      "DisputeWindowOutlastsSettlementDelay"
+    );
+
+    const market = await pg.program.account.market.fetch(tm.market);
+    assert.ok(market.disputeWindowSlots.eq(new BN(9)));
+    assert.ok(market.settlementDelaySlots.eq(new BN(10)));
+  });
 });
@@ -0,0 +1,354 @@
+// Test support: market fixtures and seeded order-flow generation.
+// In Solana Playground, web3, anchor, pg, BN are globally available.
+//
+// Scenarios are deterministic for a given seed, so a failing invariant can be replayed
+// by rerunning with the seed printed in the test output.
+
+import * as splToken from "@solana/spl-token";
+
+export type Side = { bid: {} } | { ask: {} };
+
+export interface TestMarket {
+  baseMint: web3.PublicKey;
+  quoteMint: web3.PublicKey;
+  market: web3.PublicKey;
+  vaultBase: web3.PublicKey;
+  vaultQuote: web3.PublicKey;
+  eventLog: web3.PublicKey;
+  priceHistory: web3.PublicKey;
+}
+
+export interface Trader {
+  keypair: web3.Keypair;
+  baseAta: web3.PublicKey;
+  quoteAta: web3.PublicKey;
+}
+
+export interface OrderSpec {
+  trader: number;
+  side: Side;
+  limitPriceFp: anchor.BN;
+  amountBaseFp: anchor.BN;
+}
+
+export interface PlacedOrder {
+  order: web3.PublicKey;
+  trader: Trader;
+  spec: OrderSpec;
+}
+
+// @ts-ignore - Playground exposes an underlying Keypair
+const payer = (): web3.Keypair => pg.wallet.payer ?? pg.wallet.keypair;
+
+export const pda = (...seeds: Buffer[]) =>
+  web3.PublicKey.findProgramAddressSync(seeds, pg.program.programId)[0];
+const u64 = (n: anchor.BN | number) => new BN(n).toArrayLike(Buffer, "le", 8);
+const u32 = (n: number) => new BN(n).toArrayLike(Buffer, "le", 4);
+
+/** Small seeded PRNG (mulberry32); returns floats in [0, 1). */
+export function seededRandom(seed: number): () => number {
+  let a = seed >>> 0;
+  return () => {
+    a = (a + 0x6d2b79f5) >>> 0;
+    let t = a;
+    t = Math.imul(t ^ (t >>> 15), t | 1);
+    t ^= t + Math.imul(t ^ (t >>> 7), t | 61);
+    return ((t ^ (t >>> 14)) >>> 0) / 4294967296;
+  };
+}
+
+/** Fresh 6-decimal mints and a market on them, authority = the Playground wallet. */
+export async function setupMarket(
+  batchDurationSlots = 5,
+  feeBps = 50,
+  maxOrdersPerUserPerBatch = 10
+): Promise<TestMarket> {
+  const connection = pg.connection;
+  const authority = pg.wallet.publicKey;
+  const baseMint = await splToken.createMint(connection, payer(), authority, null, 6);
+  const quoteMint = await splToken.createMint(connection, payer(), authority, null, 6);
+
+  const market = pda(
+    Buffer.from("market"), authority.toBuffer(), baseMint.toBuffer(), quoteMint.toBuffer()
+  );
+  const tm: TestMarket = {
+    baseMint,
+    quoteMint,
+    market,
+    vaultBase: pda(Buffer.from("vault_base"), market.toBuffer()),
+    vaultQuote: pda(Buffer.from("vault_quote"), market.toBuffer()),
+    eventLog: pda(Buffer.from("event_log"), market.toBuffer()),
+    priceHistory: pda(Buffer.from("price_history"), market.toBuffer()),
+  };
+
+  // Program-wide bounds (created once by the upgrade authority)
+  const programConfig = pda(Buffer.from("program_config"));
+  if ((await pg.program.account.programConfig.fetchNullable(programConfig)) === null) {
+    await pg.program.methods
+      .initProgramConfig(1_000, 1_000, new BN(1))
+      .accounts({
+        authority,
+        programConfig,
+        program: pg.program.programId,
+        programData: web3.PublicKey.findProgramAddressSync(
+          [pg.program.programId.toBuffer()],
+          new web3.PublicKey("BPFLoaderUpgradeab1e11111111111111111111111")
+        )[0],
+        systemProgram: web3.SystemProgram.programId,
+      })
+      .rpc();
+  }
+
+  await pg.program.methods
+    .initializeMarket(new BN(batchDurationSlots), feeBps, maxOrdersPerUserPerBatch)
+    .accounts({
+      authority,
+      baseMint,
+      quoteMint,
+      market,
+      vaultBase: tm.vaultBase,
+      vaultQuote: tm.vaultQuote,
+      eventLog: tm.eventLog,
+      priceHistory: tm.priceHistory,
+      programConfig,
+      systemProgram: web3.SystemProgram.programId,
+      tokenProgram: splToken.TOKEN_PROGRAM_ID,
+      rent: web3.SYSVAR_RENT_PUBKEY,
+    })
+    .rpc();
+  return tm;
+}
+
+/** A new keypair funded with SOL for rent and minted base/quote test tokens. */
+export async function createTrader(
+  tm: TestMarket,
+  lamports = 0.1 * web3.LAMPORTS_PER_SOL,
+  baseAmount = 100_000_000,
+  quoteAmount = 100_000_000
+): Promise<Trader> {
+  const connection = pg.connection;
+  const keypair = web3.Keypair.generate();
+  await web3.sendAndConfirmTransaction(
+    connection,
+    new web3.Transaction().add(
+      web3.SystemProgram.transfer({
+        fromPubkey: pg.wallet.publicKey,
+        toPubkey: keypair.publicKey,
+        lamports,
+      })
+    ),
+    [payer()]
+  );
+  const baseAta = await splToken.getOrCreateAssociatedTokenAccount(
+    connection, payer(), tm.baseMint, keypair.publicKey
+  );
+  const quoteAta = await splToken.getOrCreateAssociatedTokenAccount(
+    connection, payer(), tm.quoteMint, keypair.publicKey
+  );
+  await splToken.mintTo(
+    connection, payer(), tm.baseMint, baseAta.address, pg.wallet.publicKey, BigInt(baseAmount)
+  );
+  await splToken.mintTo(
+    connection, payer(), tm.quoteMint, quoteAta.address, pg.wallet.publicKey, BigInt(quoteAmount)
+  );
+  return { keypair, baseAta: baseAta.address, quoteAta: quoteAta.address };
+}
+
+/**
+ * `count` random orders spread over `traderCount` traders, priced on a `tickFp` grid
+ * within `spreadTicks` ticks of `midPriceFp` and sized in whole `lotFp` lots.
+ */
+export function generateOrderFlow(
+  seed: number,
+  traderCount: number,
+  count: number,
+  midPriceFp = 1_000_000,
+  tickFp = 10_000,
+  spreadTicks = 10,
+  lotFp = 100_000,
+  maxLots = 5
+): OrderSpec[] {
+  const rand = seededRandom(seed);
+  const pick = (n: number) => Math.floor(rand() * n);
+  return [...Array(count).keys()].map(() => ({
+    trader: pick(traderCount),
+    side: rand() < 0.5 ? { bid: {} } : { ask: {} },
+    limitPriceFp: new BN(midPriceFp + (pick(2 * spreadTicks + 1) - spreadTicks) * tickFp),
+    amountBaseFp: new BN((1 + pick(maxLots)) * lotFp),
+  }));
+}
+
+/** Place `spec` for `trader` in the market's open batch. */
+export async function placeOrder(
+  tm: TestMarket,
+  trader: Trader,
+  spec: OrderSpec
+): Promise<PlacedOrder> {
+  const user = trader.keypair.publicKey;
+  const market = await pg.program.account.market.fetch(tm.market);
+  const userStats = pda(Buffer.from("user_stats"), tm.market.toBuffer(), user.toBuffer());
+  const stats = await pg.program.account.userStats.fetchNullable(userStats);
+  const nonce = stats === null ? new BN(0) : stats.orderNonce;
+  const order = pda(
+    Buffer.from("order"), tm.market.toBuffer(), user.toBuffer(), u64(nonce),
+    Buffer.from(market.batchSalt)
+  );
+
+  await pg.program.methods
+    .placeOrder(spec.side, spec.limitPriceFp, spec.amountBaseFp, null, false, null)
+    .accounts({
+      user,
+      market: tm.market,
+      baseMint: tm.baseMint,
+      quoteMint: tm.quoteMint,
+      vaultBase: tm.vaultBase,
+      vaultQuote: tm.vaultQuote,
+      userBaseAta: trader.baseAta,
+      userQuoteAta: trader.quoteAta,
+      order,
+      userBatchStats: pda(
+        Buffer.from("user_batch"), tm.market.toBuffer(), user.toBuffer(),
+        u64(market.currentBatchId)
+      ),
+      userStats,
+      orderIndex: pda(
+        Buffer.from("order_index"), tm.market.toBuffer(), u64(market.currentBatchId),
+        u32(Math.floor(market.globalOrdersInBatch / 64))
+      ),
+      eventLog: tm.eventLog,
+      systemProgram: web3.SystemProgram.programId,
+      tokenProgram: splToken.TOKEN_PROGRAM_ID,
+      rent: web3.SYSVAR_RENT_PUBKEY,
+      rfqWhitelist: null,
+      subAccount: null,
+    })
+    .signers([trader.keypair])
+    .rpc();
+  return { order, trader, spec };
+}
+
+/** Wait until the open batch may be cleared. */
+export async function waitForClear(tm: TestMarket) {
+  const market = await pg.program.account.market.fetch(tm.market);
+  const nextClearSlot = market.lastBatchSlot
+    .add(BN.max(market.batchDurationSlots, market.minSlotsBetweenClears))
+    .toNumber();
+  while ((await pg.connection.getSlot()) < nextClearSlot) {
+    await new Promise((r) => setTimeout(r, 400));
+  }
+}
+
+/** Clear the open batch with `orders` as triplets; returns its BatchState address. */
+export async function clearBatch(
+  tm: TestMarket,
+  orders: PlacedOrder[]
+): Promise<web3.PublicKey> {
+  const market = await pg.program.account.market.fetch(tm.market);
+  const batchState = pda(
+    Buffer.from("batch_state"), tm.market.toBuffer(), u64(market.currentBatchId)
+  );
+  await pg.program.methods
+    .clearBatch()
+    .accounts({
+      authority: pg.wallet.publicKey,
+      market: tm.market,
+      baseMint: tm.baseMint,
+      quoteMint: tm.quoteMint,
+      vaultBase: tm.vaultBase,
+      vaultQuote: tm.vaultQuote,
+      batchState,
+      tokenProgram: splToken.TOKEN_PROGRAM_ID,
+      systemProgram: web3.SystemProgram.programId,
+      triggerMarket: null,
+      clearIntent: null,
+      instructionsSysvar: null,
+      liquidityAdapter: null,
+      adapterProgram: null,
+      keeperRecord: null,
+      reimbursementVault: null,
+      slotHashes: web3.SYSVAR_SLOT_HASHES_PUBKEY,
+      eventLog: tm.eventLog,
+      priceHistory: tm.priceHistory,
+      cluster: null,
+      clusterKeeperShare: null,
+      workQueue: null,
+      clearingState: null,
+    })
+    .preInstructions([web3.ComputeBudgetProgram.setComputeUnitLimit({ units: 1_400_000 })])
+    .remainingAccounts(
+      orders.flatMap(({ order, trader }) => [
+        { pubkey: order, isSigner: false, isWritable: true },
+        { pubkey: trader.baseAta, isSigner: false, isWritable: false },
+        { pubkey: trader.quoteAta, isSigner: false, isWritable: false },
+      ])
+    )
+    .rpc();
+  return batchState;
+}
+
+/** Let `cancelAuthority` cancel `placed` on its trader's behalf. */
+export async function setCancelAuthority(
+  tm: TestMarket,
+  placed: PlacedOrder,
+  cancelAuthority: web3.PublicKey
+) {
+  await pg.program.methods
+    .setCancelAuthority(cancelAuthority)
+    .accounts({
+      user: placed.trader.keypair.publicKey,
+      market: tm.market,
+      subAccount: null,
+      order: placed.order,
+    })
+    .signers([placed.trader.keypair])
+    .rpc();
+}
+
+/** Cancel `placed`, signed by its trader unless another `signer` is given. */
+export async function cancelOrder(
+  tm: TestMarket,
+  placed: PlacedOrder,
+  signer: web3.Keypair = placed.trader.keypair
+) {
+  const user = signer.publicKey;
+  const order = await pg.program.account.order.fetch(placed.order);
+  await pg.program.methods
+    .cancelOrder()
+    .accounts({
+      user,
+      market: tm.market,
+      programConfig: pda(Buffer.from("program_config")),
+      subAccount: null,
+      order: placed.order,
+      userBatchStats: pda(
+        Buffer.from("user_batch"), tm.market.toBuffer(), order.user.toBuffer(),
+        u64(order.batchId)
+      ),
+      userStats: pda(Buffer.from("user_stats"), tm.market.toBuffer(), order.user.toBuffer()),
+      vaultBase: tm.vaultBase,
+      vaultQuote: tm.vaultQuote,
+      orderOwner: order.user,
+      baseMint: tm.baseMint,
+      quoteMint: tm.quoteMint,
+      userBaseAta: placed.trader.baseAta,
+      userQuoteAta: placed.trader.quoteAta,
+      claimables: null,
+      tokenProgram: splToken.TOKEN_PROGRAM_ID,
+      associatedTokenProgram: splToken.ASSOCIATED_TOKEN_PROGRAM_ID,
+      systemProgram: web3.SystemProgram.programId,
+      eventLog: tm.eventLog,
+    })
+    .signers([signer])
+    .rpc();
+}
+
+/** Await `tx` and require it to fail with the program error `code`. */
+export async function expectError(tx: Promise<unknown>, code: string) {
+  try {
+    await tx;
+  } catch (err) {
+    if (String(err).includes(code)) return;
+    throw err;
+  }
+  throw new Error(`expected ${code}`);
+}